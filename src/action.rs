@@ -2,7 +2,7 @@
 //
 // SPDX-License-Identifier: GPL-3.0-only
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::hash::{Hash, Hasher};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
@@ -13,9 +13,12 @@ use serde::{Deserialize, Serialize};
 use xxhash_rust::xxh3::Xxh3;
 
 use crate::config::ArcHermitConfig;
+use crate::config::schedule::Schedule;
 use crate::file_ops::dirs::BASE_DIRS;
+use crate::file_ops::{write_atomic, write_private};
 use crate::hermitgrab_error::ActionError;
 use crate::{HermitConfig, RequireTag};
+pub mod fetch;
 pub mod install;
 pub mod link;
 pub mod patch;
@@ -25,6 +28,12 @@ pub struct ActionOutput {
     pub output_order: Vec<String>,
     standard_output: HashMap<String, String>,
     error_output: HashMap<String, String>,
+    /// Files the output was streamed to while the commands ran
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    log_files: BTreeMap<String, PathBuf>,
+    /// Files an install added, changed or removed, if they were compared
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub changes: Option<Vec<String>>,
 }
 
 impl ActionOutput {
@@ -52,6 +61,14 @@ impl ActionOutput {
         }
     }
 
+    fn add_log_file(&mut self, name: String, path: PathBuf) {
+        self.log_files.insert(name, path);
+    }
+
+    pub fn log_files(&self) -> &BTreeMap<String, PathBuf> {
+        &self.log_files
+    }
+
     pub fn is_empty(&self) -> bool {
         self.output_order.is_empty()
     }
@@ -88,8 +105,17 @@ impl PreprocessingType {
 #[derive(Serialize, Deserialize, Debug, Hash, PartialEq, Clone)]
 #[serde(untagged)]
 pub enum FileOrText {
-    File { file: PathBuf },
-    Text { text: String },
+    File {
+        file: PathBuf,
+    },
+    Text {
+        #[serde(alias = "content")]
+        text: String,
+    },
+    /// A named snippet, rendered with handlebars
+    Snippet {
+        snippet: String,
+    },
 }
 
 #[derive(Serialize, Deserialize, Debug, Hash, PartialEq, Default, Clone, Copy)]
@@ -117,20 +143,110 @@ impl ContentType {
     pub fn is_default(&self) -> bool {
         *self == ContentType::default()
     }
+
+    /// The content type by the extension of the file, Unknown if not supported
+    pub fn from_extension(file: &Path) -> Self {
+        file.extension()
+            .and_then(|ext| ext.to_str())
+            .map(|s| s.to_lowercase())
+            .map(|ext| match ext.as_str() {
+                "json" | "jsonc" => ContentType::Json,
+                "yaml" | "yml" => ContentType::Yaml,
+                "toml" => ContentType::Toml,
+                _ => ContentType::Unknown,
+            })
+            .unwrap_or(ContentType::Unknown)
+    }
+}
+
+/// Unicode byte order mark
+pub const BOM: char = '\u{feff}';
+
+#[derive(Serialize, Deserialize, Debug, Hash, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Newline {
+    Lf,
+    Crlf,
+}
+
+/// Line ending and byte order mark policy for written text files
+#[derive(Serialize, Deserialize, Debug, Hash, PartialEq, Eq, Default, Clone, Copy)]
+pub struct TextEncoding {
+    /// Line endings of the written file, unchanged if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub newline: Option<Newline>,
+    /// Adds (true) or removes (false) the UTF-8 byte order mark, unchanged if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bom: Option<bool>,
+}
+
+impl TextEncoding {
+    pub fn is_default(&self) -> bool {
+        *self == TextEncoding::default()
+    }
+
+    /// Fills unset values with the encoding of the existing content, so that
+    /// rewriting a file keeps its line endings and byte order mark
+    pub fn or_detected(&self, existing: &str) -> TextEncoding {
+        TextEncoding {
+            newline: self
+                .newline
+                .or_else(|| existing.contains("\r\n").then_some(Newline::Crlf)),
+            bom: self
+                .bom
+                .or_else(|| existing.starts_with(BOM).then_some(true)),
+        }
+    }
+
+    pub fn apply(&self, content: &str) -> String {
+        let (has_bom, body) = match content.strip_prefix(BOM) {
+            Some(body) => (true, body),
+            None => (false, content),
+        };
+        let body = match self.newline {
+            Some(Newline::Lf) => body.replace("\r\n", "\n"),
+            Some(Newline::Crlf) => body.replace("\r\n", "\n").replace('\n', "\r\n"),
+            None => body.to_string(),
+        };
+        if self.bom.unwrap_or(has_bom) {
+            format!("{BOM}{body}")
+        } else {
+            body
+        }
+    }
+}
+
+/// The file in the data dir a source file without `rendered_file` is rendered to
+fn rendered_path(src: &Path) -> PathBuf {
+    let name = src
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    BASE_DIRS
+        .data_dir()
+        .join("hermitgrab")
+        .join("rendered")
+        .join(format!(
+            "{}_{name}",
+            blake3::hash(src.to_string_lossy().as_bytes())
+        ))
 }
 
 #[derive(Serialize, Deserialize, Debug, Hash, PartialEq, Clone)]
 pub struct SourceSpec {
-    /// The source can either be a file path or a text string.
+    /// The source can either be a file path, a text string or a snippet.
     /// If it's a file path, it will be read and used as the content.
     /// If it's a text string, it will be used directly as the content.
+    /// If it's a snippet, the rendered snippet will be used as the content.
     #[serde(flatten)]
     pub source: FileOrText,
     /// The pre-processing type to apply to the source content before usage.
     #[serde(default, skip_serializing_if = "PreprocessingType::is_default")]
     pub pre_processing: PreprocessingType,
     /// This is the output file path after pot-processing the source content.
-    /// If not set a temporary file will be created for text sources.
+    /// If not set a file in the data dir will be created for text sources and for file
+    /// sources with pre-processing, so the source in the hermit directory is not modified.
+    /// The file is written when the action is executed and only readable by the owner.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub rendered_file: Option<PathBuf>,
     /// The content type of the source, which can be used to determine how to parse it.
@@ -142,19 +258,31 @@ pub struct SourceSpec {
     // internal fields for providing references
     #[serde(skip)]
     rel_path: String,
+    /// The file that is rendered into the used file on execution, if they differ
+    #[serde(skip)]
+    template: Option<PathBuf>,
     #[serde(skip)]
     normalized: bool,
 }
 
 impl SourceSpec {
     pub fn raw_path(path: PathBuf) -> Self {
-        let rel_path = path.display().to_string();
+        Self::from_source(FileOrText::File { file: path })
+    }
+
+    pub fn from_source(source: FileOrText) -> Self {
+        let rel_path = match &source {
+            FileOrText::File { file } => file.display().to_string(),
+            FileOrText::Text { .. } => "inline content".to_string(),
+            FileOrText::Snippet { snippet } => format!("snippet {snippet}"),
+        };
         Self {
-            source: FileOrText::File { file: path },
+            source,
             rel_path,
             pre_processing: PreprocessingType::default(),
             rendered_file: None,
             content_type: ContentType::default(),
+            template: None,
             normalized: false,
         }
     }
@@ -167,79 +295,164 @@ impl SourceSpec {
         if self.normalized {
             return Ok(self.clone());
         }
-        let src = match &self.source {
+        // Nothing is rendered here, plans are also created by status, so the rendered file
+        // is only written on execution
+        let (src, template) = match &self.source {
             FileOrText::File { file } => {
                 let src = cfg.canonicalize_source_path::<E>(file, true)?;
-                if let Some(render_file) = self.rendered_file.as_ref() {
-                    let pre_render = cfg.canonicalize_source_path::<E>(render_file, false)?;
-                    if src != pre_render {
-                        std::fs::create_dir_all(
-                            pre_render.parent().unwrap_or_else(|| cfg.directory()),
-                        )?;
-                        std::fs::copy(&src, &pre_render)?;
+                let rendered = match self.rendered_file.as_ref() {
+                    Some(render_file) => {
+                        Some(cfg.canonicalize_source_path::<E>(render_file, false)?)
+                    }
+                    // Rendering in place would write the values, e.g. secrets, into the
+                    // hermit directory
+                    None if self.pre_processing == PreprocessingType::Handlebars => {
+                        Some(rendered_path(&src))
                     }
-                    pre_render
-                } else {
-                    src
+                    None => None,
+                };
+                match rendered {
+                    Some(rendered) if src != rendered => (rendered, Some(src)),
+                    _ => (src, None),
                 }
             }
-            FileOrText::Text { text } => {
-                let temp_file_path = if let Some(rendered_file) = &self.rendered_file {
-                    cfg.canonicalize_source_path::<E>(rendered_file, false)?
-                } else {
-                    let temp_dir = BASE_DIRS.data_dir().join("hermitgrab").join("sources");
-                    temp_dir.join(format!(
-                        "text_{}.{}",
-                        blake3::hash(text.as_bytes()).to_string(),
-                        dst.extension()
-                            .and_then(|ext| ext.to_str())
-                            .unwrap_or("rendered")
-                    ))
-                };
-                std::fs::create_dir_all(
-                    temp_file_path.parent().unwrap_or_else(|| cfg.directory()),
-                )?;
-                std::fs::write(&temp_file_path, text)?;
-                cfg.canonicalize_source_path::<E>(&temp_file_path, true)?
+            FileOrText::Text { text } => self.text_source::<E>(cfg, dst, text)?,
+            FileOrText::Snippet { snippet } => {
+                let text = cfg.get_snippet(snippet, &BTreeMap::new())?;
+                self.text_source::<E>(cfg, dst, &text)?
             }
         };
         let content_type = if matches!(self.content_type, ContentType::Auto) {
-            src.extension()
-                .or_else(|| dst.extension())
-                .and_then(|ext| ext.to_str())
-                .map(|s| s.to_lowercase())
-                .map(|ext| match ext.as_str() {
-                    "json" | "jsonc" => ContentType::Json,
-                    "yaml" | "yml" => ContentType::Yaml,
-                    "toml" => ContentType::Toml,
-                    _ => ContentType::Unknown,
-                })
-                .unwrap_or(ContentType::Unknown)
+            match src.extension() {
+                Some(_) => ContentType::from_extension(&src),
+                None => ContentType::from_extension(dst),
+            }
         } else {
             self.content_type
         };
 
-        let rel_path = src
-            .strip_prefix(cfg.directory())
-            .unwrap_or(&src)
-            .display()
-            .to_string();
+        let rel_path = match &self.source {
+            FileOrText::File { .. } => {
+                let src = template.as_ref().unwrap_or(&src);
+                src.strip_prefix(cfg.directory())
+                    .unwrap_or(src)
+                    .display()
+                    .to_string()
+            }
+            FileOrText::Text { .. } => "inline content".to_string(),
+            FileOrText::Snippet { snippet } => format!("snippet {snippet}"),
+        };
         Ok(Self {
             source: FileOrText::File { file: src },
             rel_path,
             pre_processing: self.pre_processing,
             rendered_file: None,
-            content_type: content_type,
+            content_type,
+            template,
             normalized: true,
         })
     }
 
+    /// The file with the text content and the template it is rendered from
+    fn text_source<E>(
+        &self,
+        cfg: &HermitConfig,
+        dst: &Path,
+        text: &str,
+    ) -> Result<(PathBuf, Option<PathBuf>), E>
+    where
+        E: From<std::io::Error>,
+        E: From<RenderError>,
+    {
+        if self.pre_processing != PreprocessingType::Handlebars {
+            let src = self.write_text_source::<E>(cfg, dst, text, self.rendered_file.as_ref())?;
+            return Ok((src, None));
+        }
+        // The text is kept apart from the rendered content, so writing it again does not
+        // replace the rendered values
+        let template = self.write_text_source::<E>(cfg, dst, text, None)?;
+        let rendered = match self.rendered_file.as_ref() {
+            Some(render_file) => cfg.canonicalize_source_path::<E>(render_file, false)?,
+            None => rendered_path(&template),
+        };
+        Ok((rendered, Some(template)))
+    }
+
+    /// Writes text content to the rendered file or a content addressed file in the data dir
+    fn write_text_source<E>(
+        &self,
+        cfg: &HermitConfig,
+        dst: &Path,
+        text: &str,
+        rendered_file: Option<&PathBuf>,
+    ) -> Result<PathBuf, E>
+    where
+        E: From<std::io::Error>,
+        E: From<RenderError>,
+    {
+        let temp_file_path = if let Some(rendered_file) = rendered_file {
+            cfg.canonicalize_source_path::<E>(rendered_file, false)?
+        } else {
+            let temp_dir = BASE_DIRS.data_dir().join("hermitgrab").join("sources");
+            temp_dir.join(format!(
+                "text_{}.{}",
+                blake3::hash(text.as_bytes()),
+                dst.extension()
+                    .and_then(|ext| ext.to_str())
+                    .unwrap_or("rendered")
+            ))
+        };
+        std::fs::create_dir_all(temp_file_path.parent().unwrap_or_else(|| cfg.directory()))?;
+        write_atomic(&temp_file_path, text)?;
+        cfg.canonicalize_source_path::<E>(&temp_file_path, true)
+    }
+
     pub fn file(&self) -> &PathBuf {
         match &self.source {
             FileOrText::File { file } => file,
             FileOrText::Text { text: _ } => {
                 panic!("SourceSpec with text source does not have a file path")
             }
+            FileOrText::Snippet { snippet: _ } => {
+                panic!("SourceSpec with snippet source does not have a file path")
+            }
+        }
+    }
+
+    /// Writes the used file from its template, rendered with Handlebars if the source is
+    /// pre-processed. The written file is only readable by the owner, the rendered values
+    /// may be secrets. Returns false if there is nothing to render.
+    pub fn render<E>(&self, cfg: &HermitConfig, encoding: &TextEncoding) -> Result<bool, E>
+    where
+        E: From<std::io::Error>,
+        E: From<RenderError>,
+    {
+        let handlebars = self.pre_processing == PreprocessingType::Handlebars;
+        let template = match &self.template {
+            Some(template) => template,
+            None if handlebars => self.file(),
+            None => return Ok(false),
+        };
+        if handlebars {
+            let content = std::fs::read_to_string(template)?;
+            let rendered = cfg.render_handlebars(&content, &BTreeMap::new())?;
+            write_private(self.file(), encoding.apply(&rendered))?;
+        } else {
+            write_private(self.file(), std::fs::read(template)?)?;
+        }
+        Ok(true)
+    }
+
+    /// The file the content is read from before rendering, the used file without a template
+    pub fn template_file(&self) -> &Path {
+        self.template.as_deref().unwrap_or(self.file())
+    }
+
+    /// The file path of file sources, None for text and snippet sources
+    pub fn file_path(&self) -> Option<&Path> {
+        match &self.source {
+            FileOrText::File { file } => Some(file),
+            _ => None,
         }
     }
 }
@@ -252,11 +465,29 @@ pub enum Status {
     NotSupported,
 }
 
+/// Outcome of an action that did not fail
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum ActionOutcome {
+    /// The action changed the system
+    Done,
+    /// Nothing had to be done, with the reason
+    Skipped(String),
+}
+
+impl ActionOutcome {
+    pub fn skip_reason(&self) -> Option<&str> {
+        match self {
+            ActionOutcome::Done => None,
+            ActionOutcome::Skipped(reason) => Some(reason),
+        }
+    }
+}
+
 pub trait ActionObserver {
     fn action_started(&self, action: &ArcAction);
     fn action_output(&self, action_id: &str, output: &ActionOutput);
     fn action_progress(&self, action_id: &str, current: u64, total: u64, msg: &str);
-    fn action_finished(&self, action: &ArcAction, result: &Result<(), ActionError>);
+    fn action_finished(&self, action: &ArcAction, result: &Result<ActionOutcome, ActionError>);
 }
 
 #[enum_dispatch]
@@ -272,9 +503,13 @@ pub trait Action: Send + Sync {
         &self,
         observer: &Arc<impl ActionObserver>,
         cfg: &ArcHermitConfig,
-    ) -> Result<(), ActionError>;
+    ) -> Result<ActionOutcome, ActionError>;
     fn get_status(&self, cfg: &HermitConfig, quick: bool) -> Status;
     fn get_order(&self) -> u64;
+    /// Scheduled actions only run when they are due
+    fn schedule(&self) -> Option<Schedule> {
+        None
+    }
 }
 
 pub fn id_from_hash<T: Hash>(item: &T) -> String {
@@ -289,5 +524,48 @@ pub enum Actions {
     Install(install::InstallAction),
     Link(link::LinkAction),
     Patch(patch::PatchAction),
+    Fetch(fetch::FetchAction),
 }
 pub type ArcAction = std::sync::Arc<Actions>;
+
+/// The kind of an action, used to select actions with `--types` and `--skip-types`
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Hash, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ActionType {
+    Install,
+    Link,
+    Patch,
+    Fetch,
+}
+
+impl std::fmt::Display for ActionType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Install => write!(f, "install"),
+            Self::Link => write!(f, "link"),
+            Self::Patch => write!(f, "patch"),
+            Self::Fetch => write!(f, "fetch"),
+        }
+    }
+}
+
+impl ActionType {
+    /// All types in the order of the plan output
+    pub const ALL: [ActionType; 4] = [Self::Install, Self::Link, Self::Patch, Self::Fetch];
+
+    /// Whether actions of this type are selected, an empty `types` selects all types
+    pub fn is_selected(self, types: &[ActionType], skip_types: &[ActionType]) -> bool {
+        (types.is_empty() || types.contains(&self)) && !skip_types.contains(&self)
+    }
+}
+
+impl Actions {
+    pub fn action_type(&self) -> ActionType {
+        match self {
+            Actions::Install(_) => ActionType::Install,
+            Actions::Link(_) => ActionType::Link,
+            Actions::Patch(_) => ActionType::Patch,
+            Actions::Fetch(_) => ActionType::Fetch,
+        }
+    }
+}
@@ -0,0 +1,481 @@
+// SPDX-FileCopyrightText: 2025 Karsten Becker
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use itertools::Itertools;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::action::{Action, ActionObserver, ActionOutcome, Status};
+use crate::config::{ArcHermitConfig, ConfigItem, FetchConfig, UnpackType};
+use crate::file_ops::dirs::BASE_DIRS;
+use crate::hermitgrab_error::{ActionError, FetchActionError};
+use crate::integrations::http;
+use crate::{HermitConfig, RequireTag};
+
+/// Name of the marker file written into unpacked target directories
+pub const FETCH_MARKER: &str = ".hermitgrab-fetch";
+
+#[derive(Serialize, Debug, Hash, PartialEq)]
+pub struct FetchAction {
+    #[serde(skip)]
+    rel_dst: String,
+    url: String,
+    dst: PathBuf,
+    sha256: Option<String>,
+    unpack: Option<UnpackType>,
+    strip_components: usize,
+    requires: Vec<RequireTag>,
+    order: u64,
+}
+
+impl FetchAction {
+    pub fn new(
+        fetch: &FetchConfig,
+        cfg: &HermitConfig,
+        target_prefix: Option<&Path>,
+    ) -> Result<Self, FetchActionError> {
+        let url = cfg.render_handlebars(&fetch.url, &Default::default())?;
+        let (dst, rel_dst) = cfg.expand_target(&fetch.target, target_prefix)?;
+        let requires = fetch.get_all_requires(cfg);
+        Ok(Self {
+            url,
+            dst,
+            rel_dst,
+            sha256: fetch.sha256.as_ref().map(|s| s.to_lowercase()),
+            unpack: fetch.unpack,
+            strip_components: fetch.strip_components,
+            requires: requires.into_iter().collect(),
+            order: fetch.total_order(cfg),
+        })
+    }
+
+    /// The marker stored next to the unpacked content, identifies the version that was installed
+    fn marker_content(&self) -> String {
+        self.sha256.clone().unwrap_or_else(|| self.url.clone())
+    }
+
+    /// Marker next to a fetched file, identifies the version that was fetched
+    fn file_marker(&self) -> PathBuf {
+        let name = self
+            .dst
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        self.dst.with_file_name(format!(".{name}{FETCH_MARKER}"))
+    }
+
+    fn cache_file(&self) -> PathBuf {
+        BASE_DIRS
+            .cache_dir()
+            .join("hermitgrab")
+            .join("fetch")
+            .join(blake3::hash(self.url.as_bytes()).to_string())
+    }
+
+    fn check(&self) -> Result<bool, FetchActionError> {
+        if !self.dst.exists() {
+            return Ok(false);
+        }
+        if self.unpack.is_some() {
+            let marker = self.dst.join(FETCH_MARKER);
+            if !marker.exists() {
+                return Ok(false);
+            }
+            let content = std::fs::read_to_string(&marker)?;
+            return Ok(content.trim() == self.marker_content());
+        }
+        match &self.sha256 {
+            Some(sha256) => Ok(&sha256_file(&self.dst)? == sha256),
+            None => {
+                let marker = self.file_marker();
+                if !marker.exists() {
+                    return Ok(false);
+                }
+                Ok(std::fs::read_to_string(&marker)?.trim() == self.marker_content())
+            }
+        }
+    }
+
+    fn download(&self) -> Result<PathBuf, FetchActionError> {
+        let cache_file = self.cache_file();
+        if cache_file.exists()
+            && let Some(sha256) = &self.sha256
+            && &sha256_file(&cache_file)? == sha256
+        {
+            return Ok(cache_file);
+        }
+        if let Some(parent) = cache_file.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // The blocking client must not be used from within the async runtime
+        let url = self.url.clone();
+        let bytes = std::thread::spawn(move || -> Result<Vec<u8>, FetchActionError> {
+            let response = http::blocking_client()?
+                .get(&url)
+                .send()?
+                .error_for_status()?;
+            Ok(response.bytes()?.to_vec())
+        })
+        .join()
+        .map_err(|_| FetchActionError::DownloadPanicked(self.url.clone()))??;
+        std::fs::write(&cache_file, bytes)?;
+        if let Some(sha256) = &self.sha256 {
+            let actual = sha256_file(&cache_file)?;
+            if &actual != sha256 {
+                std::fs::remove_file(&cache_file)?;
+                return Err(FetchActionError::ChecksumMismatch(
+                    self.url.clone(),
+                    sha256.clone(),
+                    actual,
+                ));
+            }
+        }
+        Ok(cache_file)
+    }
+
+    fn install(&self, cache_file: &Path) -> Result<(), FetchActionError> {
+        match self.unpack {
+            None => {
+                if let Some(parent) = self.dst.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::copy(cache_file, &self.dst)?;
+                if self.sha256.is_none() {
+                    std::fs::write(self.file_marker(), self.marker_content())?;
+                }
+            }
+            Some(unpack) => {
+                let parent = self
+                    .dst
+                    .parent()
+                    .filter(|p| !p.as_os_str().is_empty())
+                    .unwrap_or(Path::new("."));
+                std::fs::create_dir_all(parent)?;
+                // Unpacked next to the target and renamed, so files of an older version do
+                // not remain and a failed unpack keeps the old content
+                let temp = tempfile::Builder::new()
+                    .prefix(".hermitgrab-unpack")
+                    .tempdir_in(parent)?;
+                match unpack {
+                    UnpackType::TarGz => {
+                        unpack_tar_gz(cache_file, temp.path(), self.strip_components)?
+                    }
+                    UnpackType::Zip => unpack_zip(cache_file, temp.path(), self.strip_components)?,
+                }
+                std::fs::write(temp.path().join(FETCH_MARKER), self.marker_content())?;
+                if self.dst.is_dir() && !self.dst.is_symlink() {
+                    std::fs::remove_dir_all(&self.dst)?;
+                } else if self.dst.exists() || self.dst.is_symlink() {
+                    std::fs::remove_file(&self.dst)?;
+                }
+                std::fs::rename(temp.keep(), &self.dst)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Action for FetchAction {
+    fn short_description(&self) -> String {
+        format!("Fetch {} -> {}", self.url, self.rel_dst)
+    }
+
+    fn long_description(&self) -> String {
+        format!(
+            "Fetch {} into {} (sha256: {}, unpack: {}, tags: {:?})",
+            self.url,
+            self.dst.display(),
+            self.sha256.as_deref().unwrap_or("none"),
+            self.unpack
+                .map(|u| u.to_string())
+                .unwrap_or_else(|| "none".to_string()),
+            self.requires
+        )
+    }
+
+    fn requires(&self) -> &[RequireTag] {
+        &self.requires
+    }
+
+    fn id(&self) -> String {
+        format!(
+            "FetchAction:{}:{}:{}",
+            self.url,
+            self.rel_dst,
+            self.requires.iter().join(",")
+        )
+    }
+
+    fn execute(
+        &self,
+        observer: &Arc<impl ActionObserver>,
+        _cfg: &ArcHermitConfig,
+    ) -> Result<ActionOutcome, ActionError> {
+        observer.action_progress(&self.id(), 0, 3, "Checking existing content");
+        if self.check()? {
+            observer.action_progress(&self.id(), 3, 3, "Content is up to date");
+            return Ok(ActionOutcome::Skipped(format!(
+                "{} is up to date",
+                self.rel_dst
+            )));
+        }
+        observer.action_progress(&self.id(), 1, 3, "Downloading");
+        let cache_file = self.download()?;
+        observer.action_progress(&self.id(), 2, 3, "Installing");
+        self.install(&cache_file)?;
+        observer.action_progress(&self.id(), 3, 3, "Fetch completed");
+        Ok(ActionOutcome::Done)
+    }
+
+    fn get_status(&self, _cfg: &HermitConfig, _quick: bool) -> Status {
+        match self.check() {
+            Ok(true) => Status::Ok(format!("{} is up to date", self.rel_dst)),
+            Ok(false) => Status::NotOk(format!("{} is missing or outdated", self.rel_dst)),
+            Err(e) => Status::Error(format!("Failed to check {}: {e}", self.rel_dst)),
+        }
+    }
+
+    fn get_order(&self) -> u64 {
+        self.order
+    }
+}
+
+pub fn sha256_file(path: &Path) -> Result<String, std::io::Error> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut hasher = Sha256::new();
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Removes the first `strip` components of the path, returns None if nothing remains
+/// or the path tries to escape the target directory.
+fn strip_path(path: &Path, strip: usize) -> Option<PathBuf> {
+    let mut result = PathBuf::new();
+    for component in path.components().skip(strip) {
+        match component {
+            Component::Normal(c) => result.push(c),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    if result.as_os_str().is_empty() {
+        None
+    } else {
+        Some(result)
+    }
+}
+
+/// Creates the parent directories of the entry and returns its target. Fails if the target
+/// is outside of `dst`, e.g. because an earlier entry was a symlink to another directory.
+fn entry_target(dst: &Path, entry: &Path, stripped: &Path) -> Result<PathBuf, FetchActionError> {
+    let target = dst.join(stripped);
+    let parent = target.parent().unwrap_or(dst);
+    std::fs::create_dir_all(parent)?;
+    if !parent.canonicalize()?.starts_with(dst.canonicalize()?) {
+        return Err(FetchActionError::EntryOutsideTarget(
+            entry.display().to_string(),
+        ));
+    }
+    // An entry replaces an earlier symlink instead of writing through it
+    if target.is_symlink() {
+        std::fs::remove_file(&target)?;
+    }
+    Ok(target)
+}
+
+fn unpack_tar_gz(archive: &Path, dst: &Path, strip: usize) -> Result<(), FetchActionError> {
+    let decoder = flate2::read::GzDecoder::new(File::open(archive)?);
+    let mut archive = tar::Archive::new(decoder);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_path_buf();
+        let Some(stripped) = strip_path(&path, strip) else {
+            continue;
+        };
+        let target = entry_target(dst, &path, &stripped)?;
+        if entry.header().entry_type().is_hard_link() {
+            // The link name is resolved relative to the current directory by unpack
+            let source = entry
+                .link_name()?
+                .and_then(|name| strip_path(&name, strip))
+                .ok_or_else(|| FetchActionError::EntryOutsideTarget(path.display().to_string()))?;
+            std::fs::hard_link(entry_target(dst, &path, &source)?, &target)?;
+            continue;
+        }
+        entry.unpack(&target)?;
+    }
+    Ok(())
+}
+
+fn unpack_zip(archive: &Path, dst: &Path, strip: usize) -> Result<(), FetchActionError> {
+    let mut archive = zip::ZipArchive::new(File::open(archive)?)?;
+    for idx in 0..archive.len() {
+        let mut file = archive.by_index(idx)?;
+        let Some(path) = file.enclosed_name() else {
+            continue;
+        };
+        let Some(stripped) = strip_path(&path, strip) else {
+            continue;
+        };
+        let target = entry_target(dst, &path, &stripped)?;
+        if file.is_dir() {
+            std::fs::create_dir_all(&target)?;
+            continue;
+        }
+        let mut out = File::create(&target)?;
+        std::io::copy(&mut file, &mut out)?;
+        #[cfg(unix)]
+        if let Some(mode) = file.unix_mode() {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&target, std::fs::Permissions::from_mode(mode))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_path() {
+        assert_eq!(
+            strip_path(Path::new("font-1.0/ttf/a.ttf"), 1),
+            Some(PathBuf::from("ttf/a.ttf"))
+        );
+        assert_eq!(strip_path(Path::new("font-1.0/"), 1), None);
+        assert_eq!(strip_path(Path::new("../escape"), 0), None);
+    }
+
+    #[test]
+    fn test_unpack_tar_gz_strips_components() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let archive_path = temp.path().join("archive.tar.gz");
+        {
+            let encoder = flate2::write::GzEncoder::new(
+                File::create(&archive_path).unwrap(),
+                flate2::Compression::default(),
+            );
+            let mut builder = tar::Builder::new(encoder);
+            let content = b"hello";
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, "pkg-1.0/share/hello.txt", &content[..])
+                .unwrap();
+            builder.into_inner().unwrap().finish().unwrap();
+        }
+        let dst = temp.path().join("out");
+        unpack_tar_gz(&archive_path, &dst, 1).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dst.join("share/hello.txt")).unwrap(),
+            "hello"
+        );
+    }
+
+    /// Writes a tar.gz archive with the given files, a content starting with `->` is a symlink
+    fn write_tar_gz(path: &Path, files: &[(&str, &str)]) {
+        let encoder = flate2::write::GzEncoder::new(
+            File::create(path).unwrap(),
+            flate2::Compression::default(),
+        );
+        let mut builder = tar::Builder::new(encoder);
+        for (name, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_mode(0o755);
+            match content.strip_prefix("->") {
+                Some(target) => {
+                    header.set_entry_type(tar::EntryType::Symlink);
+                    header.set_size(0);
+                    builder.append_link(&mut header, name, target).unwrap();
+                }
+                None => {
+                    header.set_size(content.len() as u64);
+                    header.set_cksum();
+                    builder
+                        .append_data(&mut header, name, content.as_bytes())
+                        .unwrap();
+                }
+            }
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unpack_rejects_entries_outside_target() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let outside = temp.path().join("outside");
+        std::fs::create_dir_all(&outside).unwrap();
+        let archive = temp.path().join("evil.tar.gz");
+        let link_target = format!("->{}", outside.display());
+        write_tar_gz(&archive, &[("lib", &link_target), ("lib/evil", "x")]);
+        let dst = temp.path().join("out");
+        std::fs::create_dir_all(&dst).unwrap();
+        assert!(matches!(
+            unpack_tar_gz(&archive, &dst, 0),
+            Err(FetchActionError::EntryOutsideTarget(_))
+        ));
+        assert!(!outside.join("evil").exists());
+    }
+
+    #[test]
+    fn test_install_replaces_old_content() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let action = |url: &str, dst: PathBuf, unpack| FetchAction {
+            rel_dst: String::new(),
+            url: url.to_string(),
+            dst,
+            sha256: None,
+            unpack,
+            strip_components: 0,
+            requires: vec![],
+            order: 0,
+        };
+        let dst = temp.path().join("font");
+        let v1 = temp.path().join("v1.tar.gz");
+        write_tar_gz(&v1, &[("a.ttf", "a1"), ("b.ttf", "b1")]);
+        let v2 = temp.path().join("v2.tar.gz");
+        write_tar_gz(&v2, &[("a.ttf", "a2")]);
+        let old = action(
+            "https://example.com/v1",
+            dst.clone(),
+            Some(UnpackType::TarGz),
+        );
+        old.install(&v1).unwrap();
+        assert!(old.check().unwrap());
+        let new = action(
+            "https://example.com/v2",
+            dst.clone(),
+            Some(UnpackType::TarGz),
+        );
+        assert!(!new.check().unwrap());
+        new.install(&v2).unwrap();
+        assert!(new.check().unwrap());
+        assert_eq!(std::fs::read_to_string(dst.join("a.ttf")).unwrap(), "a2");
+        assert!(!dst.join("b.ttf").exists());
+
+        let file = temp.path().join("tool");
+        let old = action("https://example.com/tool-1", file.clone(), None);
+        assert!(!old.check().unwrap());
+        old.install(&v1).unwrap();
+        assert!(old.check().unwrap());
+        let new = action("https://example.com/tool-2", file.clone(), None);
+        assert!(!new.check().unwrap());
+    }
+}
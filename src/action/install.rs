@@ -2,15 +2,25 @@
 //
 // SPDX-License-Identifier: GPL-3.0-only
 
-use std::io::Write;
-use std::process::Output;
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
 use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
 use derive_where::derive_where;
 use serde::Serialize;
 
-use crate::action::{Action, ActionObserver, ActionOutput, Status, id_from_hash};
-use crate::config::{ArcHermitConfig, ConfigItem};
+use crate::action::{Action, ActionObserver, ActionOutcome, ActionOutput, Status, id_from_hash};
+use crate::config::sandbox::SandboxSpec;
+use crate::config::schedule::Schedule;
+use crate::config::secrets::redact;
+use crate::config::{
+    ArcHermitConfig, Check, CheckMode, ConfigItem, DirMode, PostInstallCheck, Shell,
+};
+use crate::file_ops::dirs::XDG_CONFIG_HOME;
+use crate::file_ops::{Snapshot, create_parents};
 use crate::hermitgrab_error::{ActionError, ConfigError, InstallActionError};
 use crate::{HermitConfig, InstallConfig, RequireTag};
 
@@ -19,49 +29,275 @@ use crate::{HermitConfig, InstallConfig, RequireTag};
 pub struct InstallAction {
     name: String,
     requires: Vec<RequireTag>,
-    check_cmd: Option<String>,
+    check_cmd: Option<Check>,
+    #[serde(skip_serializing_if = "CheckMode::is_default")]
+    #[derive_where(skip(Hash))]
+    check_mode: CheckMode,
     install_cmd: String,
+    // Not part of the hash to keep the ids of existing installs stable
+    #[serde(skip_serializing_if = "Shell::is_default")]
+    #[derive_where(skip(Hash))]
+    shell: Shell,
     order: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[derive_where(skip(Hash))]
+    schedule: Option<Schedule>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[derive_where(skip(Hash))]
+    sandbox: Option<SandboxSpec>,
+    #[serde(skip_serializing_if = "PostInstallCheck::is_default")]
+    #[derive_where(skip(Hash))]
+    post_install_check: PostInstallCheck,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    #[derive_where(skip(Hash))]
+    report_changes: bool,
+    /// Commands still running at this point in time are killed
+    #[serde(skip)]
+    #[derive_where(skip)]
+    deadline: Option<Instant>,
+    #[serde(skip)]
+    #[derive_where(skip)]
+    output_log: Option<OutputLog>,
     #[derive_where(skip)]
     output: Mutex<Option<ActionOutput>>,
 }
+
+/// Directory the output of install and check commands is streamed to while they run
+#[derive(Debug, Clone)]
+pub struct OutputLog {
+    pub dir: PathBuf,
+    /// Bytes kept per stream, later output is dropped and marked as truncated
+    pub limit: usize,
+}
+
+/// The files of a single command, see [OutputLog]
+pub struct LogFiles {
+    pub stdout: PathBuf,
+    pub stderr: PathBuf,
+    pub limit: usize,
+}
+
+impl OutputLog {
+    fn files(&self, action: &str, name: &str) -> LogFiles {
+        let dir = self.dir.join(file_name(action));
+        let name = file_name(name);
+        LogFiles {
+            stdout: dir.join(format!("{name}.stdout")),
+            stderr: dir.join(format!("{name}.stderr")),
+            limit: self.limit,
+        }
+    }
+}
+
+/// Directories that installers usually write to, with the depth they are compared at
+fn snapshot_roots() -> Vec<(PathBuf, usize)> {
+    let mut roots: Vec<_> = std::env::var_os("PATH")
+        .map(|path| std::env::split_paths(&path).map(|dir| (dir, 1)).collect())
+        .unwrap_or_default();
+    roots.push((PathBuf::from(XDG_CONFIG_HOME.as_str()), 2));
+    roots
+}
+
+fn file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
 impl InstallAction {
-    pub fn new(install_entry: &InstallConfig, cfg: &HermitConfig) -> Result<Self, ConfigError> {
+    pub fn new(
+        install_entry: &InstallConfig,
+        cfg: &HermitConfig,
+        deadline: Option<Instant>,
+        output_log: Option<OutputLog>,
+    ) -> Result<Self, ConfigError> {
         let mut variables = install_entry.variables.clone();
         variables.insert("name".to_string(), install_entry.name.clone());
         let check_cmd = install_entry
             .check
-            .as_deref()
-            .map(|cmd| cfg.render_handlebars(cmd, &variables))
+            .as_ref()
+            .map(|check| check.try_map(|cmd| cfg.render_handlebars(cmd, &variables)))
             .transpose()?;
         let install_cmd = cfg.render_handlebars(&install_entry.install, &variables)?;
         let requires = install_entry.get_all_requires(cfg);
+        let sandbox = match &install_entry.sandbox {
+            Some(sandbox) if sandbox.is_enabled() => Some(SandboxSpec::new(
+                sandbox
+                    .writable()
+                    .iter()
+                    .map(|path| cfg.expand_directory(path))
+                    .collect::<Result<_, _>>()?,
+            )),
+            _ => None,
+        };
+        let post_install_check = match &install_entry.post_install_check {
+            PostInstallCheck::Source(files) => PostInstallCheck::Source(
+                files
+                    .iter()
+                    .map(|file| cfg.expand_directory(file))
+                    .collect::<Result<_, _>>()?,
+            ),
+            other => other.clone(),
+        };
         Ok(Self {
             name: install_entry.name.clone(),
             requires: requires.into_iter().collect(),
             check_cmd,
+            check_mode: install_entry.check_mode,
             install_cmd,
+            shell: install_entry.shell,
             order: install_entry.total_order(cfg),
+            schedule: install_entry.schedule,
+            sandbox,
+            post_install_check,
+            report_changes: install_entry.report_changes,
+            deadline,
+            output_log,
             output: Mutex::new(None),
         })
     }
 
     fn install_required(&self) -> Result<bool, ActionError> {
-        if let Some(check_cmd) = &self.check_cmd {
-            let status = execute_script(check_cmd);
-            // We ignore errors here which may be caused by the command not being found
-            // or other issues, as we only care about successful execution.
-            if let Ok(output) = status
-                && output.status.success()
-            {
-                self.update_output(check_cmd, output, "check_cmd")?;
-                return Ok(false);
+        let Some(check) = &self.check_cmd else {
+            return Ok(true);
+        };
+        if !self.checks_fail(check, "check_cmd", |cmd| cmd.to_string())? {
+            return Ok(false);
+        }
+        match self.post_install_check {
+            PostInstallCheck::LoginShell | PostInstallCheck::Source(_) => {
+                self.checks_fail(check, "check_cmd fresh shell", |cmd| self.fresh_shell(cmd))
+            }
+            _ => Ok(true),
+        }
+    }
+
+    /// Runs the command in a shell that picked up the changes of the rc files
+    fn fresh_shell(&self, cmd: &str) -> String {
+        if cmd.starts_with("#!") {
+            return cmd.to_string();
+        }
+        match &self.post_install_check {
+            PostInstallCheck::LoginShell => {
+                format!("exec \"${{SHELL:-sh}}\" -l -c {}", quote(cmd))
+            }
+            PostInstallCheck::Source(files) => files
+                .iter()
+                .map(|file| format!(". {}\n", quote(&file.to_string_lossy())))
+                .chain([cmd.to_string()])
+                .collect(),
+            _ => cmd.to_string(),
+        }
+    }
+
+    /// Whether the checks decide that an install is required
+    fn checks_fail(
+        &self,
+        check: &Check,
+        prefix: &str,
+        wrap: impl Fn(&str) -> String,
+    ) -> Result<bool, ActionError> {
+        let commands = check.commands();
+        for (idx, check_cmd) in commands.iter().enumerate() {
+            let name = if commands.len() == 1 {
+                prefix.to_string()
+            } else {
+                format!("{prefix} {}", idx + 1)
+            };
+            let passed = self.run_check(&wrap(check_cmd), &name)?;
+            match self.check_mode {
+                CheckMode::All if !passed => return Ok(true),
+                CheckMode::Any if passed => return Ok(false),
+                _ => {}
+            }
+        }
+        Ok(self.check_mode == CheckMode::Any)
+    }
+
+    fn run(&self, cmd: &str, name: &str) -> Result<Output, std::io::Error> {
+        let Some(output_log) = &self.output_log else {
+            return execute_script_in(cmd, self.shell, self.deadline, self.sandbox.as_ref(), None);
+        };
+        let hash = self.id().rsplit(':').next().unwrap_or_default().to_string();
+        let files = output_log.files(&format!("{}-{hash}", self.name), name);
+        let result = execute_script_in(
+            cmd,
+            self.shell,
+            self.deadline,
+            self.sandbox.as_ref(),
+            Some(&files),
+        );
+        let mut guard = self.output.lock().expect("Expected to unlock output mutex");
+        for (stream, path) in [("stdout", files.stdout), ("stderr", files.stderr)] {
+            if path.exists() {
+                guard
+                    .get_or_insert_with(ActionOutput::default)
+                    .add_log_file(format!("{name} {stream}"), path);
             }
         }
-        Ok(true)
+        result
+    }
+
+    fn run_check(&self, check_cmd: &str, name: &str) -> Result<bool, ActionError> {
+        let status = self.run(check_cmd, name);
+        // We ignore errors here which may be caused by the command not being found
+        // or other issues, as we only care about successful execution.
+        if let Ok(output) = status
+            && output.status.success()
+        {
+            self.update_output(check_cmd, output, name)?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Files the installation changed, if [InstallConfig::report_changes] is set and it ran
+    pub fn changes(&self) -> Option<Vec<String>> {
+        self.output
+            .lock()
+            .expect("Expected to unlock output mutex")
+            .as_ref()
+            .and_then(|output| output.changes.clone())
+    }
+
+    /// The rendered check commands
+    pub fn check_commands(&self) -> Vec<&str> {
+        self.check_cmd
+            .as_ref()
+            .map(|check| check.commands())
+            .unwrap_or_default()
+    }
+
+    /// The rendered install command
+    pub fn install_command(&self) -> &str {
+        &self.install_cmd
+    }
+
+    /// Runs every check command and returns whether it succeeded
+    pub fn check_results(&self) -> Vec<(String, bool)> {
+        let commands = self.check_commands();
+        commands
+            .iter()
+            .enumerate()
+            .map(|(idx, cmd)| {
+                // Every check gets its own log files, like in checks_fail
+                let name = if commands.len() == 1 {
+                    "check_cmd".to_string()
+                } else {
+                    format!("check_cmd {}", idx + 1)
+                };
+                let passed = self
+                    .run(cmd, &name)
+                    .is_ok_and(|output| output.status.success());
+                (cmd.to_string(), passed)
+            })
+            .collect()
     }
 
-    fn update_output(&self, cmd: &String, output: Output, name: &str) -> Result<(), ActionError> {
+    fn update_output(&self, cmd: &str, output: Output, name: &str) -> Result<(), ActionError> {
         let stdout = String::from_utf8_lossy(&output.stdout).to_string();
         let stderr = String::from_utf8_lossy(&output.stderr).to_string();
         let stdout = stdout.trim();
@@ -96,7 +332,12 @@ impl Action for InstallAction {
         format!("Install {}", self.name)
     }
     fn long_description(&self) -> String {
-        format!("Install {} with cmd: {:?})", self.name, self.install_cmd)
+        let sandboxed = if self.sandbox.is_some() {
+            " (sandboxed)"
+        } else {
+            ""
+        };
+        format!("Install {}{sandboxed}", self.name)
     }
     fn requires(&self) -> &[RequireTag] {
         &self.requires
@@ -105,25 +346,51 @@ impl Action for InstallAction {
         &self,
         observer: &Arc<impl ActionObserver>,
         _cfg: &ArcHermitConfig,
-    ) -> Result<(), ActionError> {
-        observer.action_progress(&self.id(), 0, 2, "Checking installation");
+    ) -> Result<ActionOutcome, ActionError> {
+        let steps = if self.post_install_check.is_default() {
+            2
+        } else {
+            3
+        };
+        observer.action_progress(&self.id(), 0, steps, "Checking installation");
         if !self.install_required()? {
-            observer.action_progress(&self.id(), 2, 2, "Installation not required");
-            return Ok(()); // Installation not required
+            observer.action_progress(&self.id(), steps, steps, "Installation not required");
+            return Ok(ActionOutcome::Skipped(
+                "the check reports it as installed".to_string(),
+            ));
+        }
+        observer.action_progress(&self.id(), 1, steps, "Executing installation command");
+        let before = self
+            .report_changes
+            .then(|| Snapshot::take(&snapshot_roots()));
+        let output = self.run(&self.install_cmd, "install_cmd");
+        if let Some(before) = before {
+            let changes = before.changes(&Snapshot::take(&snapshot_roots()));
+            let mut output = self.output.lock().expect("Expected to unlock output mutex");
+            output.get_or_insert_with(ActionOutput::default).changes = Some(changes);
         }
-        observer.action_progress(&self.id(), 1, 2, "Executing installation command");
-        let output = execute_script(&self.install_cmd);
         match output {
             Ok(output) => {
                 self.update_output(&self.install_cmd, output, "install_cmd")?;
             }
+            Err(e) if e.kind() == ErrorKind::TimedOut => {
+                Err(InstallActionError::TimeBudgetExceeded(self.name.clone()))?
+            }
             Err(e) => Err(InstallActionError::CommandFailedLaunch(
                 self.install_cmd.clone(),
                 e,
             ))?,
         }
-        observer.action_progress(&self.id(), 2, 2, "Installation completed");
-        Ok(())
+        if !self.post_install_check.is_default() && self.check_cmd.is_some() {
+            observer.action_progress(&self.id(), 2, steps, "Checking the installation");
+            if self.install_required()? {
+                Err(InstallActionError::PostInstallCheckFailed(
+                    self.name.clone(),
+                ))?
+            }
+        }
+        observer.action_progress(&self.id(), steps, steps, "Installation completed");
+        Ok(ActionOutcome::Done)
     }
     fn get_output(&self) -> Option<ActionOutput> {
         self.output
@@ -146,29 +413,208 @@ impl Action for InstallAction {
     fn get_order(&self) -> u64 {
         self.order
     }
+
+    fn schedule(&self) -> Option<Schedule> {
+        self.schedule
+    }
+}
+
+/// Quotes an argument for POSIX shells
+fn quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+pub fn execute_script(cmd: &str, shell: Shell) -> Result<Output, std::io::Error> {
+    execute_script_until(cmd, shell, None)
+}
+
+/// Like [execute_script], but kills the command if it is still running at the deadline.
+/// This is reported as an error of kind [ErrorKind::TimedOut].
+pub fn execute_script_until(
+    cmd: &str,
+    shell: Shell,
+    deadline: Option<Instant>,
+) -> Result<Output, std::io::Error> {
+    execute_script_in(cmd, shell, deadline, None, None)
 }
 
-pub fn execute_script(cmd: &str) -> Result<Output, std::io::Error> {
+/// Like [execute_script_until], but runs the command inside the sandbox if one is given
+/// and streams its output to the log files as it arrives
+pub fn execute_script_in(
+    cmd: &str,
+    shell: Shell,
+    deadline: Option<Instant>,
+    sandbox: Option<&SandboxSpec>,
+    log: Option<&LogFiles>,
+) -> Result<Output, std::io::Error> {
     let path = if which::which("ubi").is_err() {
         insert_ubi_into_path()?
     } else {
         std::env::var("PATH").unwrap_or_default()
     };
+    crate::debug!(
+        "Running with {}{}: {cmd}",
+        shell.program(),
+        if sandbox.is_some() {
+            " (sandboxed)"
+        } else {
+            ""
+        }
+    );
+    crate::debug!("Environment: PATH={path}");
     if !cmd.starts_with("#!") {
-        return std::process::Command::new("sh")
+        let mut command = Command::new(shell.program());
+        command
             .env("PATH", path)
-            .arg("-c")
-            .arg(cmd)
-            .output();
+            .args(shell.command_args())
+            .arg(cmd);
+        return run_until(sandboxed(command, sandbox)?, deadline, log);
     };
-    let mut file = tempfile::NamedTempFile::new()?;
+    let mut file = tempfile::Builder::new()
+        .suffix(shell.script_extension())
+        .tempfile()?;
     writeln!(file, "{cmd}")?;
     file.flush()?;
-    let cmd_name = file.path();
-    std::process::Command::new("sh")
-        .arg(cmd_name)
-        .env("PATH", path)
-        .output()
+    // Close the handle, Windows does not allow executing files that are still open
+    let cmd_name = file.into_temp_path();
+    let mut command = Command::new(shell.program());
+    command
+        .args(shell.script_args())
+        .arg(&cmd_name)
+        .env("PATH", path);
+    run_until(sandboxed(command, sandbox)?, deadline, log)
+}
+
+fn sandboxed(command: Command, sandbox: Option<&SandboxSpec>) -> Result<Command, std::io::Error> {
+    match sandbox {
+        Some(sandbox) => sandbox.wrap(&command),
+        None => Ok(command),
+    }
+}
+
+fn run_until(
+    mut command: Command,
+    deadline: Option<Instant>,
+    log: Option<&LogFiles>,
+) -> Result<Output, std::io::Error> {
+    const POLL_INTERVAL: Duration = Duration::from_millis(50);
+    if deadline.is_none() && log.is_none() {
+        return command.output();
+    }
+    if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+        return Err(ErrorKind::TimedOut.into());
+    }
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let stdout = drain(
+        child.stdout.take(),
+        log.map(|log| (log.stdout.clone(), log.limit)),
+    );
+    let stderr = drain(
+        child.stderr.take(),
+        log.map(|log| (log.stderr.clone(), log.limit)),
+    );
+    let status = match deadline {
+        None => child.wait()?,
+        Some(deadline) => loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if Instant::now() >= deadline {
+                // The readers are not joined, as grand children may still hold the pipes open
+                let _ = child.kill();
+                child.wait()?;
+                return Err(ErrorKind::TimedOut.into());
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        },
+    };
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+fn drain(
+    pipe: Option<impl Read + Send + 'static>,
+    log: Option<(PathBuf, usize)>,
+) -> JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        match (pipe, log) {
+            (Some(pipe), Some((path, limit))) => buf = stream_to(pipe, &path, limit),
+            (Some(mut pipe), None) => {
+                let _ = pipe.read_to_end(&mut buf);
+            }
+            (None, _) => {}
+        }
+        buf
+    })
+}
+
+/// Mode of the directories the output is streamed to, only the owner can read the output
+const LOG_DIR_MODE: u32 = 0o700;
+
+/// Appends everything read from the pipe to the file as it arrives, with secret values
+/// redacted. Output is redacted by complete lines, so that a secret split across two reads
+/// is still found. Beyond `limit` bytes a marker is written and the rest is read but
+/// dropped. The file is only created once there is output. Returns what was written.
+fn stream_to(mut pipe: impl Read, path: &Path, limit: usize) -> Vec<u8> {
+    let mut written = Vec::new();
+    let mut file = None;
+    let mut chunk = [0u8; 8192];
+    let mut pending = Vec::new();
+    let mut truncated = false;
+    let mut append = |data: &[u8], written: &mut Vec<u8>| {
+        let redacted = redact(&String::from_utf8_lossy(data)).into_bytes();
+        let keep = redacted.len().min(limit - written.len());
+        let mut data = redacted[..keep].to_vec();
+        let truncated = keep < redacted.len();
+        if truncated {
+            data.extend_from_slice(
+                format!("\n[output truncated after {limit} bytes]\n").as_bytes(),
+            );
+        }
+        let file = file.get_or_insert_with(|| {
+            create_parents(path, Some(DirMode(LOG_DIR_MODE)))
+                .map_err(std::io::Error::other)
+                .and_then(|_| std::fs::File::create(path))
+                .inspect_err(|e| crate::warn!("Failed to create {}: {e}", path.display()))
+        });
+        if let Ok(file) = file {
+            let _ = file.write_all(&data);
+        }
+        written.extend_from_slice(&data);
+        truncated
+    };
+    loop {
+        let read = match pipe.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        };
+        if truncated {
+            continue;
+        }
+        pending.extend_from_slice(&chunk[..read]);
+        let complete = match pending.iter().rposition(|b| *b == b'\n') {
+            Some(pos) => pos + 1,
+            // Very long lines are written without waiting for their end
+            None if pending.len() >= chunk.len() => pending.len(),
+            None => continue,
+        };
+        let data = pending.drain(..complete).collect::<Vec<_>>();
+        truncated = append(&data, &mut written);
+    }
+    if !truncated && !pending.is_empty() {
+        append(&pending, &mut written);
+    }
+    written
 }
 
 #[cfg(not(feature = "ubi"))]
@@ -184,9 +630,13 @@ fn insert_ubi_into_path() -> Result<String, std::io::Error> {
     static UBI_EXE_DIR: OnceLock<TempDir> = OnceLock::new();
     let temp_dir =
         UBI_EXE_DIR.get_or_init(|| TempDir::new().expect("Failed to create temporary directory"));
-    let mut path = std::env::var("PATH").unwrap_or_default();
-    path.push(':');
-    path.push_str(temp_dir.path().to_str().unwrap());
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    let path = std::env::join_paths(
+        std::env::split_paths(&path).chain(std::iter::once(temp_dir.path().to_path_buf())),
+    )
+    .map_err(std::io::Error::other)?
+    .to_string_lossy()
+    .to_string();
     let ubi_exe = temp_dir.path().join("ubi");
     if !ubi_exe.exists() {
         use crate::file_ops::dirs::HERMIT_EXE;
@@ -209,7 +659,7 @@ fn insert_ubi_into_path() -> Result<String, std::io::Error> {
 
 #[cfg(test)]
 mod tests {
-    use std::path::PathBuf;
+    use std::path::{Path, PathBuf};
 
     use super::*;
     use crate::config::GlobalConfig;
@@ -220,7 +670,7 @@ mod tests {
             echo "Hello, World!"
             exit 0
         "#;
-        let status = execute_script(script);
+        let status = execute_script(script, Shell::Auto);
         assert!(status.is_ok());
         let output = status.unwrap();
         assert!(output.status.success());
@@ -233,6 +683,182 @@ mod tests {
         );
     }
 
+    #[cfg(unix)]
+    #[test]
+    fn test_script_execution_with_shell() {
+        if which::which("bash").is_err() {
+            return;
+        }
+        let output = execute_script("echo ${BASH_VERSION:+bash}", Shell::Bash).unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "bash");
+        let output = execute_script("#!/bin/bash\n[[ 1 == 1 ]] && echo ok", Shell::Bash).unwrap();
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "ok");
+        assert_eq!(Shell::Auto.resolve(), Shell::Sh);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_script_execution_deadline() {
+        let deadline = Instant::now() + Duration::from_millis(200);
+        let output = execute_script_until("echo fast", Shell::Sh, Some(deadline)).unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "fast");
+        let started = Instant::now();
+        let err = execute_script_until("sleep 10", Shell::Sh, Some(deadline)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_stream_to_truncates() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let path = temp.path().join("action").join("install_cmd.stdout");
+        let written = stream_to(&b""[..], &path, 10);
+        assert!(written.is_empty());
+        assert!(!path.exists(), "No file without output");
+        let written = stream_to(&b"0123456789abcdef"[..], &path, 10);
+        let expected = "0123456789\n[output truncated after 10 bytes]\n";
+        assert_eq!(String::from_utf8_lossy(&written), expected);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_stream_to_redacts() {
+        crate::config::secrets::register_secret("test://stream", "stream-s3cret");
+        let temp = tempfile::TempDir::new().unwrap();
+        let path = temp.path().join("action").join("install_cmd.stdout");
+        // The secret is split across two reads
+        let pipe = (&b"token=stream-s"[..]).chain(&b"3cret\ndone"[..]);
+        let written = stream_to(pipe, &path, 1024);
+        let expected = format!("token={}\ndone", crate::config::secrets::REDACTED);
+        assert_eq!(String::from_utf8_lossy(&written), expected);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), expected);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(path.parent().unwrap())
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o700);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    #[ignore = "requires bubblewrap with user namespaces, run with --ignored"]
+    fn test_sandboxed_script() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let sandbox = SandboxSpec {
+            read_only: temp.path().to_path_buf(),
+            writable: vec![temp.path().join("allowed")],
+        };
+        let write = |file: &str| {
+            let cmd = format!("echo x > {}", temp.path().join(file).display());
+            execute_script_in(&cmd, Shell::Sh, None, Some(&sandbox), None).unwrap()
+        };
+        let denied = write("denied");
+        assert!(
+            !String::from_utf8_lossy(&denied.stderr).contains("bwrap:"),
+            "bubblewrap failed: {}",
+            String::from_utf8_lossy(&denied.stderr)
+        );
+        assert!(!denied.status.success());
+        assert!(write("allowed/file").status.success());
+        assert!(!temp.path().join("denied").exists());
+    }
+
+    #[test]
+    fn test_check_modes() {
+        let global_cfg = Arc::new(GlobalConfig::default());
+        let config =
+            HermitConfig::create_new(Path::new("hermit.toml"), Arc::downgrade(&global_cfg));
+        let install_config = |check_mode| InstallConfig {
+            name: "checks".to_string(),
+            check: Some(Check::List(vec![
+                "exit 0".to_string(),
+                "exit 1".to_string(),
+            ])),
+            check_mode,
+            ..Default::default()
+        };
+        let all = InstallAction::new(&install_config(CheckMode::All), &config, None, None).unwrap();
+        assert!(all.install_required().unwrap());
+        let any = InstallAction::new(&install_config(CheckMode::Any), &config, None, None).unwrap();
+        assert!(!any.install_required().unwrap());
+        assert_eq!(
+            any.check_results(),
+            vec![("exit 0".to_string(), true), ("exit 1".to_string(), false)]
+        );
+        let temp = tempfile::TempDir::new().unwrap();
+        let output_log = OutputLog {
+            dir: temp.path().to_path_buf(),
+            limit: 1024,
+        };
+        let logged = InstallConfig {
+            check: Some(Check::List(vec![
+                "echo first".to_string(),
+                "echo second".to_string(),
+            ])),
+            ..install_config(CheckMode::All)
+        };
+        let logged = InstallAction::new(&logged, &config, None, Some(output_log)).unwrap();
+        logged.check_results();
+        let mut logs = std::fs::read_dir(
+            temp.path()
+                .read_dir()
+                .unwrap()
+                .next()
+                .unwrap()
+                .unwrap()
+                .path(),
+        )
+        .unwrap()
+        .map(|e| std::fs::read_to_string(e.unwrap().path()).unwrap())
+        .collect::<Vec<_>>();
+        logs.sort();
+        assert_eq!(logs, vec!["first\n", "second\n"]);
+        let parsed: InstallConfig = toml::from_str(
+            "name = \"a\"\ninstall = \"b\"\ncheck = [\"c\", \"d\"]\ncheck_mode = \"any\"",
+        )
+        .unwrap();
+        assert_eq!(parsed.check_mode, CheckMode::Any);
+        assert_eq!(parsed.check.unwrap().commands(), vec!["c", "d"]);
+    }
+
+    #[test]
+    fn test_post_install_check_sources_files() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let env = temp.path().join("it's env");
+        std::fs::write(&env, "HERMIT_TEST_INSTALLED=1\n").unwrap();
+        let global_cfg = Arc::new(GlobalConfig::default());
+        let config =
+            HermitConfig::create_new(Path::new("hermit.toml"), Arc::downgrade(&global_cfg));
+        let install_config = |post_install_check| InstallConfig {
+            name: "env".to_string(),
+            check: Some("test -n \"$HERMIT_TEST_INSTALLED\"".into()),
+            shell: Shell::Sh,
+            post_install_check,
+            ..Default::default()
+        };
+        let plain =
+            InstallAction::new(&install_config(PostInstallCheck::None), &config, None, None)
+                .unwrap();
+        assert!(plain.install_required().unwrap());
+        let sourced = InstallAction::new(
+            &install_config(PostInstallCheck::Source(vec![env])),
+            &config,
+            None,
+            None,
+        )
+        .unwrap();
+        assert!(!sourced.install_required().unwrap());
+        let parsed: InstallConfig =
+            toml::from_str("name = \"a\"\ninstall = \"b\"\npost_install_check = \"login_shell\"")
+                .unwrap();
+        assert_eq!(parsed.post_install_check, PostInstallCheck::LoginShell);
+    }
+
     #[test]
     fn test_stable_hash_generation() {
         let global_cfg = Arc::new(GlobalConfig::default());
@@ -240,10 +866,10 @@ mod tests {
         let config = HermitConfig::create_new(path_buf.as_path(), Arc::downgrade(&global_cfg));
         let install_config = InstallConfig {
             name: "Hello World".to_string(),
-            check: Some("true".to_string()),
+            check: Some("true".into()),
             ..Default::default()
         };
-        let action = InstallAction::new(&install_config, &config).unwrap();
+        let action = InstallAction::new(&install_config, &config, None, None).unwrap();
         let id = id_from_hash(&action);
         assert_eq!(
             "hermitgrab::action::install::InstallAction:7370f721c8e5df3a",
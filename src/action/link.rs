@@ -2,17 +2,22 @@
 //
 // SPDX-License-Identifier: GPL-3.0-only
 
-use std::collections::BTreeMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use itertools::Itertools;
 use serde::Serialize;
 
-use crate::action::{Action, ActionObserver, PreprocessingType, SourceSpec, Status};
-use crate::config::{ArcHermitConfig, ConfigItem, FallbackOperation, FileStatus};
-use crate::file_ops::dirs::BASE_DIRS;
-use crate::file_ops::{check_copied, link_files};
+use crate::action::{Action, ActionObserver, ActionOutcome, SourceSpec, Status, TextEncoding};
+use crate::config::{
+    ArcHermitConfig, CliOptions, ConfigItem, DirMode, FallbackOperation, FileStatus,
+    HardlinkFallback, Ownership,
+};
+use crate::file_ops::dirs::{IS_WSL, on_windows_drive};
+use crate::file_ops::{
+    apply_ownership, check_copied, check_dir_mode, check_ownership, create_parents, link_files,
+    same_device,
+};
 use crate::hermitgrab_error::{ActionError, LinkActionError};
 use crate::{HermitConfig, LinkConfig, LinkType, RequireTag};
 
@@ -25,36 +30,134 @@ pub struct LinkAction {
     link_type: LinkType,
     requires: Vec<RequireTag>,
     fallback: FallbackOperation,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fallback_dir: Option<FallbackOperation>,
     order: u64,
+    #[serde(flatten)]
+    encoding: TextEncoding,
+    #[serde(flatten)]
+    ownership: Ownership,
+    #[serde(skip_serializing_if = "HardlinkFallback::is_default")]
+    hardlink_fallback: HardlinkFallback,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dir_mode: Option<DirMode>,
 }
 
 impl LinkAction {
     pub fn new(
         link_config: &LinkConfig,
+        target: &Path,
         cfg: &HermitConfig,
-        fallback: &Option<FallbackOperation>,
+        options: &CliOptions,
     ) -> Result<Self, LinkActionError> {
-        let dst = cfg.expand_directory(&link_config.target)?;
-        let rel_dst = dst
-            .strip_prefix(BASE_DIRS.home_dir())
-            .unwrap_or(&dst)
-            .to_string_lossy()
-            .to_string();
+        let (dst, rel_dst) = cfg.expand_target(target, options.target_prefix.as_deref())?;
+        let fallback = &options.fallback;
         let requires = link_config.get_all_requires(cfg);
+        // A fallback given on the command line applies to files and directories alike
+        let fallback_dir = match fallback {
+            Some(_) => None,
+            None => link_config.fallback_dir,
+        };
         let fallback = (*fallback).unwrap_or(link_config.fallback);
+        // Links from WSL into the Windows file system are not reliable, so files are copied
+        let link_type = if *IS_WSL && on_windows_drive(&dst) {
+            LinkType::Copy
+        } else {
+            link_config.link
+        };
         Ok(Self {
             src: link_config.source.normalize::<LinkActionError>(cfg, &dst)?,
             dst,
             rel_dst,
-            link_type: link_config.link,
+            link_type,
             order: link_config.total_order(cfg),
             requires: requires.into_iter().collect(),
             fallback,
+            fallback_dir,
+            encoding: link_config.encoding,
+            ownership: link_config.ownership.clone(),
+            hardlink_fallback: link_config.hardlink_fallback,
+            dir_mode: link_config.dir_mode,
         })
     }
 
+    /// The expanded destination of the link
+    pub fn dst(&self) -> &Path {
+        &self.dst
+    }
+
+    /// The destination as written in the configuration
+    pub fn rel_dst(&self) -> &str {
+        &self.rel_dst
+    }
+
+    /// The source file in the hermit directory
+    pub fn source_file(&self) -> &Path {
+        self.src.file()
+    }
+
+    /// Source and destination if the destination is a copy of the source
+    pub fn copied_files(&self) -> Option<(&Path, &Path)> {
+        let copied = match self.link_type {
+            LinkType::Copy => true,
+            LinkType::Hard => {
+                self.hardlink_fallback == HardlinkFallback::Copy
+                    && !same_device(self.src.file(), &self.dst)
+            }
+            LinkType::Soft => false,
+        };
+        copied.then_some((self.src.file(), self.dst.as_path()))
+    }
+
+    /// The fallback that applies to the existing destination, None if the destination
+    /// does not exist or is already linked
+    pub fn pending_fallback(&self) -> Option<FallbackOperation> {
+        let exists = self.dst.exists() || self.dst.is_symlink();
+        (exists && !self.check(true).is_ok()).then(|| self.effective_fallback())
+    }
+
+    /// The fallback for the current destination, which depends on it being a directory
+    fn effective_fallback(&self) -> FallbackOperation {
+        match self.fallback_dir {
+            Some(fallback_dir) if self.dst.is_dir() && !self.dst.is_symlink() => fallback_dir,
+            _ => self.fallback,
+        }
+    }
+
+    /// Source and target of the first copied file whose content differs from its source
+    pub fn differing_files(&self, quick: bool) -> Option<(PathBuf, PathBuf)> {
+        let (FileStatus::SizeDiffers(dst, ..) | FileStatus::HashDiffers(dst, ..)) =
+            self.check(quick)
+        else {
+            return None;
+        };
+        let src = match dst.strip_prefix(&self.dst) {
+            Ok(rel) if !rel.as_os_str().is_empty() => self.src.file().join(rel),
+            _ => self.src.file().to_path_buf(),
+        };
+        Some((src, dst))
+    }
+
     pub fn check(&self, quick: bool) -> FileStatus {
-        let actual_dst = self.dst.clone();
+        self.check_at(&self.dst, quick)
+    }
+
+    /// Checks the link as if the destination was `dst`, e.g. the destination below another
+    /// XDG base directory
+    pub fn check_at(&self, dst: &Path, quick: bool) -> FileStatus {
+        let status = self.check_link(dst, quick);
+        if !status.is_ok() {
+            return status;
+        }
+        let status = check_ownership(dst, &self.ownership);
+        if !status.is_ok() {
+            return status;
+        }
+        check_dir_mode(dst, self.dir_mode)
+    }
+
+    fn check_link(&self, dst: &Path, quick: bool) -> FileStatus {
+        let actual_dst = dst.to_path_buf();
         match actual_dst.try_exists() {
             Ok(exists) => {
                 if !exists {
@@ -78,6 +181,11 @@ impl LinkAction {
                 FileStatus::Ok
             }
             LinkType::Hard => {
+                if self.hardlink_fallback == HardlinkFallback::Copy
+                    && !same_device(self.src.file(), &actual_dst)
+                {
+                    return check_copied(quick, self.src.file(), &actual_dst);
+                }
                 #[cfg(target_family = "unix")]
                 {
                     let dst_meta = match actual_dst.metadata() {
@@ -106,7 +214,7 @@ impl LinkAction {
                     return check_copied(quick, &self.src.file(), &actual_dst);
                 }
             }
-            LinkType::Copy => check_copied(quick, &self.src.file(), &actual_dst),
+            LinkType::Copy => check_copied(quick, self.src.file(), &actual_dst),
         }
     }
 }
@@ -138,24 +246,39 @@ impl Action for LinkAction {
         &self,
         observer: &Arc<impl ActionObserver>,
         cfg: &ArcHermitConfig,
-    ) -> Result<(), ActionError> {
+    ) -> Result<ActionOutcome, ActionError> {
         observer.action_progress(&self.id(), 0, 2, "Linking files");
-        if matches!(self.src.pre_processing, PreprocessingType::Handlebars) {
-            observer.action_progress(&self.id(), 1, 2, "Rendering source with Handlebars");
-            let content =
-                std::fs::read_to_string(self.src.file()).map_err(|e| LinkActionError::Io(e))?;
-            let rendered_content = cfg
-                .render_handlebars(&content, &BTreeMap::new())
-                .map_err(|e| LinkActionError::Render(e))?;
-            std::fs::write(self.src.file(), rendered_content)
-                .map_err(|e| LinkActionError::Io(e))?;
+        if self.effective_fallback() == FallbackOperation::Ignore
+            && self.pending_fallback().is_some()
+        {
+            observer.action_progress(&self.id(), 2, 2, "Target exists, ignored");
+            return Ok(ActionOutcome::Skipped(format!(
+                "{} exists and the fallback is ignore",
+                self.dst.display()
+            )));
+        }
+        if self.src.render::<LinkActionError>(cfg, &self.encoding)? {
+            observer.action_progress(&self.id(), 1, 2, "Rendered source");
         } else {
             observer.action_progress(&self.id(), 1, 2, "No preprocessing required");
         }
-        link_files(&self.src.file(), &self.dst, &self.link_type, &self.fallback)
-            .map_err(LinkActionError::FileOps)?;
+        create_parents(&self.dst, self.dir_mode).map_err(LinkActionError::FileOps)?;
+        link_files(
+            self.src.file(),
+            &self.dst,
+            &self.link_type,
+            &self.effective_fallback(),
+            &self.hardlink_fallback,
+        )
+        .map_err(LinkActionError::FileOps)?;
+        apply_ownership(
+            &self.dst,
+            &self.ownership,
+            matches!(self.link_type, LinkType::Copy),
+        )
+        .map_err(LinkActionError::FileOps)?;
         observer.action_progress(&self.id(), 2, 2, "Linking completed");
-        Ok(())
+        Ok(ActionOutcome::Done)
     }
 
     fn id(&self) -> String {
@@ -201,7 +324,14 @@ mod tests {
         let src = tmp_dir.join("hermitgrab_test_src");
         let dst = tmp_dir.join("hermitgrab_test_dst");
         fs::write(&src, b"test").unwrap();
-        link_files(&src, &dst, &LinkType::Soft, &FallbackOperation::Abort).unwrap();
+        link_files(
+            &src,
+            &dst,
+            &LinkType::Soft,
+            &FallbackOperation::Abort,
+            &HardlinkFallback::Copy,
+        )
+        .unwrap();
         assert!(dst.exists());
         assert_eq!(fs::read_to_string(&dst).unwrap(), "test");
         fs::remove_file(&src).unwrap();
@@ -216,7 +346,13 @@ mod tests {
         if dst.exists() {
             fs::remove_file(&dst).unwrap();
         }
-        let result = link_files(&src, &dst, &LinkType::Soft, &FallbackOperation::Abort);
+        let result = link_files(
+            &src,
+            &dst,
+            &LinkType::Soft,
+            &FallbackOperation::Abort,
+            &HardlinkFallback::Copy,
+        );
         assert!(matches!(
             result,
             Err(crate::FileOpsError::SourceNotFound(_))
@@ -230,7 +366,13 @@ mod tests {
         let dst = tmp_dir.join("hermitgrab_test_dst3");
         fs::write(&src, b"test").unwrap();
         fs::write(&dst, b"existing").unwrap();
-        let result = link_files(&src, &dst, &LinkType::Soft, &FallbackOperation::Abort);
+        let result = link_files(
+            &src,
+            &dst,
+            &LinkType::Soft,
+            &FallbackOperation::Abort,
+            &HardlinkFallback::Copy,
+        );
         assert!(matches!(
             result,
             Err(crate::FileOpsError::DestinationExists(_))
@@ -255,7 +397,13 @@ mod tests {
             use std::os::windows::fs::symlink_file;
             symlink_file(&src, &dst).unwrap();
         }
-        let result = link_files(&src, &dst, &LinkType::Soft, &FallbackOperation::Abort);
+        let result = link_files(
+            &src,
+            &dst,
+            &LinkType::Soft,
+            &FallbackOperation::Abort,
+            &HardlinkFallback::Copy,
+        );
         assert!(result.is_ok());
         fs::remove_file(&src).unwrap();
         fs::remove_file(&dst).unwrap();
@@ -276,11 +424,211 @@ mod tests {
         }
         fs::create_dir(&src).unwrap();
         fs::write(src.join("file1.txt"), b"file 1").unwrap();
-        link_files(&src, &dst, &LinkType::Soft, &FallbackOperation::Abort).unwrap();
+        link_files(
+            &src,
+            &dst,
+            &LinkType::Soft,
+            &FallbackOperation::Abort,
+            &HardlinkFallback::Copy,
+        )
+        .unwrap();
         assert!(dst.exists());
         assert!(dst.is_symlink());
         assert_eq!(dst.read_link().unwrap(), src.canonicalize().unwrap());
         fs::remove_dir_all(&src).unwrap();
         fs::remove_dir_all(&dst).unwrap();
     }
+
+    #[test]
+    fn test_manage_children() {
+        use crate::config::{
+            CONF_FILE_NAME, CliOptions, ConfigItem, GlobalConfig, find_hermit_files,
+        };
+
+        let temp = tempfile::TempDir::new().unwrap();
+        let hermit_dir = temp.path().join("hermit");
+        fs::create_dir_all(hermit_dir.join("nvim/lua")).unwrap();
+        fs::write(hermit_dir.join("nvim/init.lua"), "").unwrap();
+        let dst = temp.path().join("nvim");
+        fs::write(
+            hermit_dir.join(CONF_FILE_NAME),
+            format!(
+                "[[link]]\nsource = \"nvim\"\ntarget = {dst:?}\nmanage_children = [\"init.lua\", \"lua/\"]\n"
+            ),
+        )
+        .unwrap();
+        let paths = find_hermit_files(&hermit_dir, &[CONF_FILE_NAME]);
+        let global = GlobalConfig::from_paths(&hermit_dir, &paths).unwrap();
+        let (_, cfg) = global.subconfigs().into_iter().next().unwrap();
+        let actions = cfg.link[0].as_actions(cfg, &CliOptions::default()).unwrap();
+        let dsts = actions
+            .iter()
+            .map(|action| match action.as_ref() {
+                crate::action::Actions::Link(link) => link.dst().to_path_buf(),
+                _ => unreachable!("Link config creates link actions"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(dsts, vec![dst.join("init.lua"), dst.join("lua")]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_dst_is_symlink_to_other_target() {
+        use crate::config::{
+            CONF_FILE_NAME, CliOptions, ConfigItem, GlobalConfig, find_hermit_files,
+        };
+
+        let temp = tempfile::TempDir::new().unwrap();
+        let hermit_dir = temp.path().join("hermit");
+        fs::create_dir_all(&hermit_dir).unwrap();
+        fs::write(hermit_dir.join("src"), "src").unwrap();
+        let other = temp.path().join("other");
+        fs::create_dir_all(&other).unwrap();
+        fs::write(other.join("keep"), "keep").unwrap();
+        let dst = temp.path().join("dst");
+        let backup = temp.path().join("dst.bak");
+        fs::write(
+            hermit_dir.join(CONF_FILE_NAME),
+            format!("[[link]]\nsource = \"src\"\ntarget = {dst:?}\n"),
+        )
+        .unwrap();
+        let paths = find_hermit_files(&hermit_dir, &[CONF_FILE_NAME]);
+        let global = GlobalConfig::from_paths(&hermit_dir, &paths).unwrap();
+        let (_, cfg) = global.subconfigs().into_iter().next().unwrap();
+        let action = cfg.link[0].as_action(cfg, &CliOptions::default()).unwrap();
+        let crate::action::Actions::Link(action) = action.as_ref() else {
+            unreachable!("Link config creates a link action");
+        };
+        let link_with = |fallback: FallbackOperation| {
+            let _ = fs::remove_file(&dst);
+            let _ = fs::remove_file(&backup);
+            std::os::unix::fs::symlink(&other, &dst).unwrap();
+            assert!(matches!(
+                action.check(true),
+                FileStatus::SymlinkDestinationMismatch(_, _)
+            ));
+            link_files(
+                action.source_file(),
+                &dst,
+                &LinkType::Soft,
+                &fallback,
+                &HardlinkFallback::Copy,
+            )
+        };
+
+        assert!(matches!(
+            link_with(FallbackOperation::Abort),
+            Err(crate::FileOpsError::DestinationExists(_))
+        ));
+        link_with(FallbackOperation::Ignore).unwrap();
+        assert!(matches!(
+            action.check(true),
+            FileStatus::SymlinkDestinationMismatch(_, _)
+        ));
+        for fallback in [
+            FallbackOperation::Backup,
+            FallbackOperation::BackupOverwrite,
+            FallbackOperation::Delete,
+            FallbackOperation::DeleteDir,
+        ] {
+            link_with(fallback).unwrap();
+            assert!(
+                action.check(true).is_ok(),
+                "{fallback}: {}",
+                action.check(true)
+            );
+            assert!(
+                other.join("keep").exists(),
+                "{fallback} removed the old target"
+            );
+            let backed_up = matches!(
+                fallback,
+                FallbackOperation::Backup | FallbackOperation::BackupOverwrite
+            );
+            assert_eq!(backup.is_symlink(), backed_up, "{fallback}");
+        }
+    }
+
+    struct NoObserver;
+
+    impl ActionObserver for NoObserver {
+        fn action_started(&self, _: &crate::action::ArcAction) {}
+        fn action_output(&self, _: &str, _: &crate::action::ActionOutput) {}
+        fn action_progress(&self, _: &str, _: u64, _: u64, _: &str) {}
+        fn action_finished(
+            &self,
+            _: &crate::action::ArcAction,
+            _: &Result<ActionOutcome, ActionError>,
+        ) {
+        }
+    }
+
+    /// A hermit dir with a handlebars link source that is rendered into a temporary data dir
+    fn handlebars_link(temp: &Path) -> (PathBuf, PathBuf, Arc<crate::config::GlobalConfig>) {
+        use crate::config::{CONF_FILE_NAME, GlobalConfig, find_hermit_files};
+
+        let hermit_dir = temp.join("hermit");
+        fs::create_dir_all(&hermit_dir).unwrap();
+        fs::write(hermit_dir.join("config.tpl"), "token = {{ var.token }}\n").unwrap();
+        let rendered = temp.join("data").join("config");
+        let dst = temp.join("config");
+        fs::write(
+            hermit_dir.join(CONF_FILE_NAME),
+            format!(
+                "[variables]\ntoken = \"s3cret\"\n\n[[link]]\nsource = {{ file = \"config.tpl\", pre_processing = \"Handlebars\", rendered_file = {rendered:?} }}\ntarget = {dst:?}\n"
+            ),
+        )
+        .unwrap();
+        let paths = find_hermit_files(&hermit_dir, &[CONF_FILE_NAME]);
+        let global = GlobalConfig::from_paths(&hermit_dir, &paths).unwrap();
+        (hermit_dir, dst, global)
+    }
+
+    #[test]
+    fn test_handlebars_source_is_not_modified() {
+        use crate::config::{CliOptions, ConfigItem};
+
+        let temp = tempfile::TempDir::new().unwrap();
+        let (hermit_dir, dst, global) = handlebars_link(temp.path());
+        let (_, cfg) = global.subconfigs().into_iter().next().unwrap();
+        let action = cfg.link[0].as_action(cfg, &CliOptions::default()).unwrap();
+        let crate::action::Actions::Link(action) = action.as_ref() else {
+            unreachable!("Link config creates a link action");
+        };
+        assert!(action.short_description().contains("config.tpl"));
+        assert!(!temp.path().join("data").exists());
+        action.execute(&Arc::new(NoObserver), cfg).unwrap();
+        assert_eq!(
+            fs::read_to_string(hermit_dir.join("config.tpl")).unwrap(),
+            "token = {{ var.token }}\n"
+        );
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "token = s3cret\n");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let rendered = temp.path().join("data").join("config");
+            let mode = fs::metadata(rendered).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn test_planning_again_keeps_rendered_target() {
+        use crate::config::CliOptions;
+        use crate::execution_plan::create_execution_plan;
+
+        let temp = tempfile::TempDir::new().unwrap();
+        let (_, dst, global) = handlebars_link(temp.path());
+        let plan = create_execution_plan(&global, &CliOptions::default()).unwrap();
+        for (cfg, action) in &plan {
+            action.execute(&Arc::new(NoObserver), cfg).unwrap();
+        }
+        for _ in 0..2 {
+            let plan = create_execution_plan(&global, &CliOptions::default()).unwrap();
+            assert_eq!(fs::read_to_string(&dst).unwrap(), "token = s3cret\n");
+            for (cfg, action) in &plan {
+                assert!(matches!(action.get_status(cfg, false), Status::Ok(_)));
+            }
+        }
+    }
 }
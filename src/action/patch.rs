@@ -11,11 +11,13 @@ use jsonc_parser::ParseOptions;
 use serde::Serialize;
 
 use crate::action::{
-    Action, ActionObserver, ActionOutput, ContentType, FileOrText, PreprocessingType, SourceSpec,
-    Status,
+    Action, ActionObserver, ActionOutcome, ActionOutput, BOM, ContentType, FileOrText,
+    PreprocessingType, SourceSpec, Status, TextEncoding,
+};
+use crate::config::{ArcHermitConfig, ConfigItem, DirMode, Ownership, PatchConfig, PatchType};
+use crate::file_ops::{
+    apply_ownership, check_dir_mode, check_ownership, create_parents, line_and_column, write_atomic,
 };
-use crate::config::{ArcHermitConfig, ConfigItem, PatchConfig, PatchType};
-use crate::file_ops::dirs::BASE_DIRS;
 use crate::hermitgrab_error::{ActionError, PatchActionError};
 use crate::{HermitConfig, RequireTag};
 
@@ -28,16 +30,21 @@ pub struct PatchAction {
     patch_type: PatchType,
     order: u64,
     requires: Vec<RequireTag>,
+    #[serde(flatten)]
+    encoding: TextEncoding,
+    #[serde(flatten)]
+    ownership: Ownership,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dir_mode: Option<DirMode>,
 }
 
 impl PatchAction {
-    pub fn new(patch: &PatchConfig, cfg: &HermitConfig) -> Result<Self, PatchActionError> {
-        let dst = cfg.expand_directory(&patch.target)?;
-        let rel_dst = dst
-            .strip_prefix(BASE_DIRS.home_dir())
-            .unwrap_or(&dst)
-            .to_string_lossy()
-            .to_string();
+    pub fn new(
+        patch: &PatchConfig,
+        cfg: &HermitConfig,
+        target_prefix: Option<&Path>,
+    ) -> Result<Self, PatchActionError> {
+        let (dst, rel_dst) = cfg.expand_target(&patch.target, target_prefix)?;
         let requires = patch.get_all_requires(cfg);
         Ok(Self {
             src: patch.source.normalize::<PatchActionError>(cfg, &dst)?,
@@ -46,8 +53,30 @@ impl PatchAction {
             order: patch.total_order(cfg),
             patch_type: patch.patch_type.clone(),
             requires: requires.into_iter().collect(),
+            encoding: patch.encoding,
+            ownership: patch.ownership.clone(),
+            dir_mode: patch.dir_mode,
         })
     }
+
+    /// Checks if applying the patch would leave the destination unchanged
+    fn is_applied(&self, cfg: &HermitConfig) -> Result<bool, PatchActionError> {
+        let mut content = std::fs::read_to_string(self.src.template_file())?;
+        if matches!(self.src.pre_processing, PreprocessingType::Handlebars) {
+            content = cfg.render_handlebars(&content, &BTreeMap::new())?;
+        }
+        let src_json = parse_file(content, &self.src.content_type)?;
+        let current = content_and_extension(&self.dst, &self.src.content_type)?;
+        let mut expected = current.clone();
+        match self.patch_type {
+            PatchType::JsonMerge => json_patch::merge(&mut expected, &src_json),
+            PatchType::JsonPatch => {
+                let patch: json_patch::Patch = serde_json::from_value(src_json)?;
+                json_patch::patch(&mut expected, &patch)?;
+            }
+        }
+        Ok(current == expected)
+    }
 }
 
 impl Action for PatchAction {
@@ -67,16 +96,23 @@ impl Action for PatchAction {
                 file.display(),
                 self.src.content_type
             ),
+            FileOrText::Snippet { snippet } => format!(
+                "{} {} with snippet {} [{}]",
+                self.patch_type,
+                self.dst.display(),
+                snippet,
+                self.src.content_type
+            ),
             FileOrText::Text { text } => {
                 if text.len() > 30 {
                     let snippet = &text[..30];
-                    return format!(
+                    format!(
                         "{} {} with '{}…' [{}]",
                         self.patch_type,
                         self.dst.display(),
                         snippet.replace('\n', "\\n"),
                         self.src.content_type
-                    );
+                    )
                 } else {
                     format!(
                         "{} {} with '{}' [{}]",
@@ -98,30 +134,38 @@ impl Action for PatchAction {
         &self,
         observer: &Arc<impl ActionObserver>,
         cfg: &ArcHermitConfig,
-    ) -> Result<(), ActionError> {
+    ) -> Result<ActionOutcome, ActionError> {
         observer.action_progress(&self.id(), 0, 2, "Applying patch");
-        if matches!(self.src.pre_processing, PreprocessingType::Handlebars) {
-            observer.action_progress(&self.id(), 1, 2, "Rendering source with Handlebars");
-            let content =
-                std::fs::read_to_string(self.src.file()).map_err(|e| PatchActionError::Io(e))?;
-            let rendered_content = cfg
-                .render_handlebars(&content, &BTreeMap::new())
-                .map_err(|e| PatchActionError::Render(e))?;
-            std::fs::write(self.src.file(), rendered_content)
-                .map_err(|e| PatchActionError::Io(e))?;
+        if self.src.render::<PatchActionError>(cfg, &self.encoding)? {
+            observer.action_progress(&self.id(), 1, 2, "Rendered source");
         } else {
             observer.action_progress(&self.id(), 1, 2, "No preprocessing required");
         }
+        create_parents(&self.dst, self.dir_mode).map_err(PatchActionError::FileOps)?;
         match self.patch_type {
             PatchType::JsonMerge => {
-                merge_json(&self.src.file(), &self.dst, &self.src.content_type)?;
+                merge_json(
+                    self.src.file(),
+                    &self.dst,
+                    &self.src.content_type,
+                    &self.encoding,
+                )?;
+                apply_ownership(&self.dst, &self.ownership, false)
+                    .map_err(PatchActionError::FileOps)?;
                 observer.action_progress(&self.id(), 2, 2, "Merge completed");
-                Ok(())
+                Ok(ActionOutcome::Done)
             }
             PatchType::JsonPatch => {
-                patch_json(&self.src.file(), &self.dst, &self.src.content_type)?;
+                patch_json(
+                    self.src.file(),
+                    &self.dst,
+                    &self.src.content_type,
+                    &self.encoding,
+                )?;
+                apply_ownership(&self.dst, &self.ownership, false)
+                    .map_err(PatchActionError::FileOps)?;
                 observer.action_progress(&self.id(), 2, 2, "Patch completed");
-                Ok(())
+                Ok(ActionOutcome::Done)
             }
         }
     }
@@ -135,8 +179,40 @@ impl Action for PatchAction {
         )
     }
 
-    fn get_status(&self, _cfg: &HermitConfig, _quick: bool) -> Status {
-        Status::NotSupported
+    fn get_status(&self, cfg: &HermitConfig, _quick: bool) -> Status {
+        if !matches!(
+            self.src.content_type,
+            ContentType::Json | ContentType::Yaml | ContentType::Toml
+        ) {
+            return Status::NotSupported;
+        }
+        if !self.dst.exists() {
+            return Status::NotOk(format!("{} does not exist", self.rel_dst));
+        }
+        // A hand edited target that no longer parses breaks the application that reads it
+        match std::fs::read_to_string(&self.dst) {
+            Ok(content) => {
+                if let Err(e) = validate_target(&content, &self.src.content_type) {
+                    return Status::Error(format!(
+                        "{} is not valid {}: {e}",
+                        self.rel_dst, self.src.content_type
+                    ));
+                }
+            }
+            Err(e) => return Status::Error(format!("Failed to read {}: {e}", self.rel_dst)),
+        }
+        let ownership = check_ownership(&self.dst, &self.ownership);
+        let dir_mode = check_dir_mode(&self.dst, self.dir_mode);
+        match self.is_applied(cfg) {
+            Ok(true) if !ownership.is_ok() => Status::NotOk(ownership.to_string()),
+            Ok(true) if !dir_mode.is_ok() => Status::NotOk(dir_mode.to_string()),
+            Ok(true) => Status::Ok(format!("{} contains {}", self.rel_dst, self.src.rel_path)),
+            Ok(false) => Status::NotOk(format!(
+                "{} is missing changes from {}",
+                self.rel_dst, self.src.rel_path
+            )),
+            Err(e) => Status::Error(format!("Failed to check {}: {e}", self.rel_dst)),
+        }
     }
 
     fn get_order(&self) -> u64 {
@@ -148,23 +224,35 @@ pub fn merge_json(
     src: &Path,
     dst: &Path,
     content_type: &ContentType,
+    encoding: &TextEncoding,
 ) -> Result<ActionOutput, PatchActionError> {
     let merge_content = content_and_extension(src, content_type)?;
     let mut dst_content = content_and_extension(dst, content_type)?;
     json_patch::merge(&mut dst_content, &merge_content);
     let updated_dst = to_content(dst_content, content_type)?;
-    write_contents(dst, updated_dst)?;
+    write_encoded(dst, &updated_dst, encoding)?;
     Ok(ActionOutput::new_stdout(format!(
         "Merged the contents of {src:?} into {dst:?}"
     )))
 }
 
-fn write_contents(dst: &Path, updated_dst: String) -> Result<(), PatchActionError> {
-    let dst_dir = dst.parent().expect("Failed to get parent directory");
-    if !dst_dir.exists() {
-        std::fs::create_dir_all(dst_dir)?;
-    }
-    std::fs::write(dst, updated_dst)?;
+/// Writes the contents with the given encoding, unset values of the encoding
+/// are taken from the existing file
+pub fn write_encoded(
+    dst: &Path,
+    content: &str,
+    encoding: &TextEncoding,
+) -> Result<(), PatchActionError> {
+    let existing = if dst.exists() {
+        std::fs::read_to_string(dst)?
+    } else {
+        String::new()
+    };
+    write_contents(dst, encoding.or_detected(&existing).apply(content))
+}
+
+pub fn write_contents(dst: &Path, updated_dst: String) -> Result<(), PatchActionError> {
+    write_atomic(dst, updated_dst)?;
     Ok(())
 }
 
@@ -172,13 +260,14 @@ pub fn patch_json(
     src: &Path,
     dst: &Path,
     content_type: &ContentType,
+    encoding: &TextEncoding,
 ) -> Result<ActionOutput, PatchActionError> {
     let merge_content = content_and_extension(src, content_type)?;
     let patch: json_patch::Patch = serde_json::from_value(merge_content)?;
     let mut dst_json = content_and_extension(dst, content_type)?;
     json_patch::patch(&mut dst_json, &patch)?;
-    let updated_dst = to_content(dst_json, &content_type)?;
-    write_contents(dst, updated_dst)?;
+    let updated_dst = to_content(dst_json, content_type)?;
+    write_encoded(dst, &updated_dst, encoding)?;
     Ok(ActionOutput::new_stdout(format!(
         "Merged the contents of {src:?} into {dst:?}"
     )))
@@ -193,10 +282,36 @@ fn content_and_extension(
     } else {
         "".to_string()
     };
-    Ok(parse_file(dst_content, &content_type)?)
+    parse_file(dst_content, content_type)
 }
 
-fn to_content(
+/// Computes a JSON merge patch (RFC 7396) that turns `base` into `current`
+pub fn merge_diff(base: &serde_json::Value, current: &serde_json::Value) -> serde_json::Value {
+    let (serde_json::Value::Object(base), serde_json::Value::Object(current)) = (base, current)
+    else {
+        return current.clone();
+    };
+    let mut patch = serde_json::Map::new();
+    for (key, base_value) in base {
+        match current.get(key) {
+            None => {
+                patch.insert(key.clone(), serde_json::Value::Null);
+            }
+            Some(value) if value != base_value => {
+                patch.insert(key.clone(), merge_diff(base_value, value));
+            }
+            Some(_) => {}
+        }
+    }
+    for (key, value) in current {
+        if !base.contains_key(key) {
+            patch.insert(key.clone(), value.clone());
+        }
+    }
+    serde_json::Value::Object(patch)
+}
+
+pub fn to_content(
     dst_json: serde_json::Value,
     content_type: &ContentType,
 ) -> Result<String, PatchActionError> {
@@ -213,10 +328,39 @@ fn to_content(
     }
 }
 
-fn parse_file(
+/// Parses the content of a patch target, the error names the location of the problem
+pub fn validate_target(content: &str, content_type: &ContentType) -> Result<(), String> {
+    let content = content.strip_prefix(BOM).unwrap_or(content);
+    match content_type {
+        ContentType::Toml => {
+            toml::from_str::<toml::Value>(content)
+                .map(|_| ())
+                .map_err(|e| match e.span() {
+                    Some(span) => {
+                        let (line, column) = line_and_column(content, span.start);
+                        format!("{} at line {line} column {column}", e.message().trim())
+                    }
+                    None => e.message().trim().to_string(),
+                })
+        }
+        ContentType::Yaml => serde_yaml_ng::from_str::<serde_yaml_ng::Value>(content)
+            .map(|_| ())
+            .map_err(|e| e.to_string()),
+        ContentType::Json => jsonc_parser::parse_to_serde_value(content, &ParseOptions::default())
+            .map(|_| ())
+            .map_err(|e| e.to_string()),
+        ContentType::Auto | ContentType::Unknown => Ok(()),
+    }
+}
+
+pub fn parse_file(
     dst_content: String,
     content_type: &ContentType,
 ) -> Result<serde_json::Value, PatchActionError> {
+    let dst_content = match dst_content.strip_prefix(BOM) {
+        Some(content) => content.to_string(),
+        None => dst_content,
+    };
     match content_type {
         ContentType::Yaml => {
             let yaml: serde_yaml_ng::Value = serde_yaml_ng::from_str(&dst_content)?;
@@ -238,3 +382,58 @@ fn parse_file(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action::Newline;
+
+    #[test]
+    fn test_validate_target() {
+        assert!(validate_target("", &ContentType::Toml).is_ok());
+        let toml = validate_target("[a]\nb = 1\nc = \n", &ContentType::Toml).unwrap_err();
+        assert!(toml.ends_with("at line 3 column 5"), "{toml}");
+        let json = validate_target("{\n  \"a\": 1,\n  \"b\": }", &ContentType::Json).unwrap_err();
+        assert!(json.contains("line 3"), "{json}");
+        let yaml = validate_target("a: 1\n b: 2\n", &ContentType::Yaml).unwrap_err();
+        assert!(yaml.contains("line 2"), "{yaml}");
+    }
+
+    #[test]
+    fn test_merge_diff_roundtrip() {
+        let base = serde_json::json!({"a": 1, "b": {"c": true, "d": "x"}, "e": [1]});
+        let current = serde_json::json!({"a": 2, "b": {"c": true}, "e": [1, 2], "f": "new"});
+        let patch = merge_diff(&base, &current);
+        assert_eq!(
+            patch,
+            serde_json::json!({"a": 2, "b": {"d": null}, "e": [1, 2], "f": "new"})
+        );
+        let mut merged = base.clone();
+        json_patch::merge(&mut merged, &patch);
+        assert_eq!(merged, current);
+    }
+
+    #[test]
+    fn test_merge_keeps_crlf_and_bom() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let src = temp.path().join("src.json");
+        let dst = temp.path().join("dst.json");
+        std::fs::write(&src, r#"{"b": 2}"#).unwrap();
+        std::fs::write(&dst, "\u{feff}{\r\n  \"a\": 1\r\n}").unwrap();
+        let encoding = TextEncoding::default();
+        merge_json(&src, &dst, &ContentType::Json, &encoding).unwrap();
+        let written = std::fs::read_to_string(&dst).unwrap();
+        assert!(written.starts_with(BOM));
+        assert!(written.contains("\r\n"));
+        assert!(!written.replace("\r\n", "").contains('\n'));
+
+        let encoding = TextEncoding {
+            newline: Some(Newline::Lf),
+            bom: Some(false),
+        };
+        merge_json(&src, &dst, &ContentType::Json, &encoding).unwrap();
+        let written = std::fs::read_to_string(&dst).unwrap();
+        assert!(!written.starts_with(BOM));
+        assert!(!written.contains('\r'));
+    }
+}
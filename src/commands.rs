@@ -3,19 +3,43 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
 use std::collections::BTreeMap;
+use std::ffi::OsString;
 use std::path::PathBuf;
 use std::sync::{Arc, OnceLock};
+use std::time::Duration;
 
-use clap::{Parser, Subcommand};
+use clap::{Args, CommandFactory, Parser, Subcommand};
 use git2::Repository;
+use itertools::Itertools;
 
-use crate::config::{CliOptions, FallbackOperation, GlobalConfig, PatchType, Tag};
+use crate::action::{ActionType, FileOrText};
+use crate::commands::cmd_hook::{HookOptions, HookShell};
+use crate::commands::cmd_init::{GitHubDiscoverOptions, GitRetry};
+use crate::commands::exit_report::ExitReport;
+use crate::common_cli::{Verbosity, message_counts, write_json};
+use crate::config::schedule::Schedule;
+use crate::config::{CliOptions, FallbackOperation, GlobalConfig, PatchType, Source, Tag};
 use crate::{LinkType, RequireTag, detector, hermitgrab_info, info};
 
 pub mod cmd_add;
 pub mod cmd_apply;
+pub mod cmd_bootstrap;
+pub mod cmd_completions;
+pub mod cmd_config;
+pub mod cmd_disable;
+pub mod cmd_docs;
+pub mod cmd_doctor;
+pub mod cmd_history;
+pub mod cmd_hook;
 pub mod cmd_init;
+pub mod cmd_plan;
+pub mod cmd_refactor;
+pub mod cmd_render;
+pub mod cmd_self_install;
+pub mod cmd_snippets;
 pub mod cmd_status;
+pub mod exit_report;
+pub mod report_template;
 
 fn long_version() -> &'static str {
     static VERSION: OnceLock<String> = OnceLock::new();
@@ -36,6 +60,13 @@ fn long_version() -> &'static str {
 #[command(name = "hermitgrab")]
 #[command(version, long_version = long_version())]
 #[command(about = "A modern dotfile manager", long_about = None)]
+#[command(after_long_help = "Exit codes:
+  0  Success, warnings may have been reported
+  1  The command failed
+  2  Invalid command line arguments
+  3  The configuration is invalid
+  4  Some actions failed
+  5  Aborted by the user")]
 pub struct Cli {
     #[command(subcommand)]
     pub command: Commands,
@@ -43,9 +74,13 @@ pub struct Cli {
     #[arg(short = 'i', env = "HERMIT_INTERACTIVE", global = true)]
     #[cfg(feature = "interactive")]
     pub interactive: bool,
-    /// Increase output verbosity
-    #[arg(short = 'v', long, env = "HERMIT_VERBOSE", global = true)]
-    pub verbose: bool,
+    /// Increase output verbosity, -vv also shows the command lines and environment of
+    /// each command. HERMIT_VERBOSE takes the level as number
+    #[arg(short = 'v', long, action = clap::ArgAction::Count, global = true)]
+    pub verbose: u8,
+    /// Only report warnings and errors
+    #[arg(short = 'q', long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
     /// Don't ask for confirmation, assume yes is the answer
     #[arg(short = 'y', long, env = "HERMIT_CONFIRM", global = true)]
     pub confirm: bool,
@@ -60,15 +95,31 @@ pub struct Cli {
         value_hint = clap::ValueHint::DirPath,
     )]
     pub hermit_dir: Option<PathBuf>,
+    /// File name of the configuration files (can be specified multiple times).
+    /// Defaults to hermit.toml and the config_names setting of the root hermit.toml
+    #[arg(
+        long,
+        env = "HERMIT_CONF_NAME",
+        global = true,
+        value_name = "NAME",
+        value_delimiter = ','
+    )]
+    pub conf_name: Vec<String>,
+    /// Fail if any configuration file could not be loaded, instead of skipping it
+    #[arg(long, env = "HERMIT_STRICT_CONFIG", global = true)]
+    pub strict_config: bool,
+    /// Write machine readable output of the command as JSON to this path, use - for stdout
     #[arg(
         long,
         env = "HERMIT_JSON",
         global = true,
         value_name = "PATH",
         value_hint = clap::ValueHint::FilePath,
-        hide = true,
     )]
     pub json: Option<PathBuf>,
+    /// Do not verify TLS certificates, e.g. behind a proxy with a self-signed certificate
+    #[arg(long, env = "HERMIT_INSECURE", global = true)]
+    pub insecure: bool,
 }
 
 #[derive(Subcommand)]
@@ -107,12 +158,26 @@ pub enum AddCommand {
         /// Order of the link in the config, if not specified, uses 0
         #[arg(short = 'o', long, value_name = "ORDER")]
         order: Option<u64>,
+        /// Store a target below an XDG base directory as `{{ dir.xdg_config }}/...`, so it
+        /// follows machines with other XDG directories
+        #[arg(long, default_value_t = false)]
+        relative: bool,
     },
     /// Add a new Link to the config
     Patch {
         /// Source file to patch
-        #[arg(value_hint = clap::ValueHint::FilePath)]
-        source: PathBuf,
+        #[arg(value_hint = clap::ValueHint::FilePath, required_unless_present_any = ["snippet", "content", "from_diff"], conflicts_with_all = ["snippet", "content", "from_diff"])]
+        source: Option<PathBuf>,
+        /// Use the named snippet as patch source instead of a file
+        #[arg(long, conflicts_with_all = ["content", "from_diff"], requires = "target")]
+        snippet: Option<String>,
+        /// Use inline content as patch source instead of a file
+        #[arg(long, conflicts_with = "from_diff", requires = "target")]
+        content: Option<String>,
+        /// Generate the patch from the local changes of the target compared to this file.
+        /// Without a file, the source of the link managing the target is used.
+        #[arg(long, value_name = "BASE", num_args = 0..=1, requires = "target", value_hint = clap::ValueHint::FilePath)]
+        from_diff: Option<Option<PathBuf>>,
         /// Subdirectory of the hermit.toml file to add the Patch to
         #[arg(long)]
         config_dir: Option<PathBuf>,
@@ -137,6 +202,9 @@ pub enum AddCommand {
         /// Tags to include in the profile (can be specified multiple times)
         #[arg(short = 't', long = "tag", value_name = "TAG", num_args = 0..)]
         tags: Vec<Tag>,
+        /// Include all currently active tags (detected and from the default profile)
+        #[arg(long)]
+        from_active: bool,
     },
 }
 #[derive(Subcommand)]
@@ -145,8 +213,31 @@ pub enum GetCommand {
     Tags,
     /// Show all profiles (from all configs)
     Profiles,
-    /// Config
-    Config,
+    /// Show all snippets, check that they render and report unknown references or cycles
+    Snippets {
+        /// Print the rendered snippet with this name
+        #[arg(long, value_name = "NAME")]
+        show: Option<String>,
+    },
+    /// Show the plan with the rendered check and install commands of all installs
+    Plan {
+        /// Include actions matching these tags (can be specified multiple times)
+        #[arg(short='t', long = "tag", env="HERMIT_TAGS", value_name = "TAG", num_args = 0..)]
+        tags: Vec<String>,
+        /// Use a named profile which is a set of tags
+        #[arg(short = 'p', long, env = "HERMIT_PROFILE", value_name = "PROFILE")]
+        profile: Option<String>,
+    },
+    /// Show the merged configuration of all hermit.toml files
+    Config {
+        /// Annotate each item with the file and line it was declared in
+        #[arg(long)]
+        origin: bool,
+    },
+    /// Check the configuration for problems.
+    /// With --json <PATH> (or - for stdout) the findings are written as JSON.
+    /// Exits with an error if any finding has error severity.
+    Doctor,
 }
 
 #[derive(Subcommand)]
@@ -156,6 +247,15 @@ pub enum Provider {
         /// A Github Token to use instead of the device authentication
         #[arg(long, env = "HERMIT_GITHUB_TOKEN")]
         token: Option<String>,
+        /// Query the search API by topic instead of listing all repositories
+        #[arg(long)]
+        search: bool,
+        /// Search the repositories of this organization instead of your own
+        #[arg(long, value_name = "ORG")]
+        org: Option<String>,
+        /// Name (or owner/name) of the repository to use, overrides default_repo of the user settings
+        #[arg(long, value_name = "REPO")]
+        name: Option<String>,
     },
 }
 
@@ -165,6 +265,12 @@ pub enum InitCommand {
     Clone {
         /// Git repository URL
         repo: String,
+        /// Mirror URL tried when the repository URL fails (can be specified multiple times)
+        #[arg(long = "mirror", value_name = "URL")]
+        mirrors: Vec<String>,
+        /// Additional attempts per URL after a network error
+        #[arg(long, value_name = "COUNT")]
+        retries: Option<u32>,
     },
     /// Discover dotfiles repo on GitHub
     Discover {
@@ -179,6 +285,153 @@ pub enum InitCommand {
     Create,
 }
 
+#[derive(Subcommand)]
+pub enum PlanCommand {
+    /// Show actions added, removed or changed between two profiles or a git ref and the working tree
+    Diff {
+        /// Profile to compare, pass it twice to compare two profiles
+        #[arg(short = 'p', long = "profile", value_name = "PROFILE")]
+        profiles: Vec<String>,
+        /// Compare the plan of this git ref of the hermit directory with the working tree
+        #[arg(long = "ref", value_name = "REF")]
+        git_ref: Option<String>,
+        /// Include actions matching these tags (can be specified multiple times)
+        #[arg(short='t', long = "tag", env="HERMIT_TAGS", value_name = "TAG", num_args = 0..)]
+        tags: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum HookCommand {
+    /// Install a shell hook that checks the dotfiles on login
+    Install {
+        /// Shell to install the hook for, if not specified it is detected from $SHELL
+        #[arg(short = 's', long, value_enum)]
+        shell: Option<HookShell>,
+        /// Run apply instead of status in the hook
+        #[arg(long)]
+        apply: bool,
+        /// Profile to use when running the hook
+        #[arg(short = 'p', long, value_name = "PROFILE")]
+        profile: Option<String>,
+        /// Minimum number of minutes between two runs of the hook
+        #[arg(long, default_value_t = 60, value_name = "MINUTES")]
+        interval: u64,
+    },
+    /// Remove a previously installed shell hook
+    Remove {
+        /// Shell to remove the hook from, if not specified it is detected from $SHELL
+        #[arg(short = 's', long, value_enum)]
+        shell: Option<HookShell>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum HistoryCommand {
+    /// Show the results of a recorded run
+    Show {
+        /// Id of the run as listed by `hermitgrab history`
+        id: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum DocsCommand {
+    /// Generate a Markdown overview of all configuration files, e.g. as README of the repo
+    Repo {
+        /// Write the overview to this file instead of printing it
+        #[arg(short = 'o', long, value_hint = clap::ValueHint::FilePath)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum RefactorCommand {
+    /// Rename a tag in all configuration files
+    Tag {
+        /// Current name of the tag
+        old: String,
+        /// New name of the tag
+        new: String,
+        /// Only show the changes as a diff instead of writing them
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum CompletionsCommand {
+    /// Print the completion script for a shell
+    Generate {
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+    /// Install the completions for a shell, they are updated when hermitgrab is updated
+    Install {
+        /// Shell to install the completions for, if not specified it is detected from $SHELL
+        #[arg(short = 's', long, value_enum)]
+        shell: Option<HookShell>,
+    },
+}
+
+/// Options of the `apply` command
+#[derive(Args, Default)]
+pub struct ApplyArgs {
+    /// Include actions matching these tags (can be specified multiple times)
+    #[arg(short='t', long = "tag", env="HERMIT_TAGS", value_name = "TAG", num_args = 0..)]
+    pub tags: Vec<String>,
+    /// Use a named profile which is a set of tags
+    #[arg(
+        short = 'p',
+        long,
+        env = "HERMIT_PROFILE",
+        value_name = "PROFILE",
+        global = true
+    )]
+    pub profile: Option<String>,
+    /// Override the fallback behavior for existing files
+    #[arg(short = 'f', long, value_enum)]
+    pub fallback: Option<FallbackOperation>,
+    /// Same as -f backupoverwrite
+    #[arg(short = 'F', long)]
+    pub force: bool,
+    /// Run actions in parallel
+    #[arg(long, default_value_t = false)]
+    pub parallel: bool,
+    /// Skip actions that already succeeded in an interrupted run of the same plan
+    #[arg(long, default_value_t = false)]
+    pub resume: bool,
+    /// Fail instead of warning when a link source resolves outside the hermit directory
+    #[arg(long, default_value_t = false)]
+    pub strict: bool,
+    /// Only run scheduled actions that are due, like weekly cache updates
+    #[arg(long, default_value_t = false)]
+    pub due_only: bool,
+    /// Place all targets below this directory to rehearse an apply without touching
+    /// $HOME. Install actions are skipped, they would change the real system.
+    #[arg(long, value_name = "DIR", value_hint = clap::ValueHint::DirPath)]
+    pub target_prefix: Option<PathBuf>,
+    /// Only run actions of these types, e.g. `--types link,patch`
+    #[arg(long, value_enum, value_delimiter = ',', value_name = "TYPE")]
+    pub types: Vec<ActionType>,
+    /// Skip actions of these types, e.g. `--skip-types install`
+    #[arg(long, value_enum, value_delimiter = ',', value_name = "TYPE")]
+    pub skip_types: Vec<ActionType>,
+    /// Render the JSON report of the run with this handlebars template and print the result,
+    /// e.g. for a chat message or an HTML page
+    #[arg(long, value_name = "PATH", value_hint = clap::ValueHint::FilePath)]
+    pub report_template: Option<PathBuf>,
+    /// Keep the report free of run specific values for snapshots in CI: results carry
+    /// their index in the plan and output files are relative to the output directory. A run
+    /// does not use randomness, so there is no seed to set.
+    #[arg(long, env = "HERMIT_DETERMINISTIC", default_value_t = false)]
+    pub deterministic: bool,
+    /// Write the metrics of the run in the Prometheus textfile format to this path, e.g.
+    /// for the textfile collector of node_exporter
+    #[arg(long, env = "HERMIT_METRICS_FILE", value_name = "PATH", value_hint = clap::ValueHint::FilePath)]
+    pub metrics_file: Option<PathBuf>,
+}
+
 #[derive(Subcommand)]
 pub enum Commands {
     /// Manage dotfiles repo initialization
@@ -187,30 +440,29 @@ pub enum Commands {
         init_command: InitCommand,
     },
     /// Install applications and link/copy dotfiles
+    #[command(visible_alias = "a")]
     Apply {
+        #[command(flatten)]
+        args: ApplyArgs,
+    },
+    /// Apply the configuration non-interactively in an ephemeral container
+    Bootstrap {
         /// Include actions matching these tags (can be specified multiple times)
         #[arg(short='t', long = "tag", env="HERMIT_TAGS", value_name = "TAG", num_args = 0..)]
         tags: Vec<String>,
         /// Use a named profile which is a set of tags
-        #[arg(
-            short = 'p',
-            long,
-            env = "HERMIT_PROFILE",
-            value_name = "PROFILE",
-            global = true
-        )]
+        #[arg(short = 'p', long, env = "HERMIT_PROFILE", value_name = "PROFILE")]
         profile: Option<String>,
-        /// Override the fallback behavior for existing files
-        #[arg(short = 'f', long, value_enum)]
-        fallback: Option<FallbackOperation>,
-        /// Same as -f backupoverwrite
-        #[arg(short = 'F', long)]
-        force: bool,
-        /// Run actions in parallel
+        /// Skip gui and desktop tagged items and copy files if the hermit directory is read-only,
+        /// always used in a detected container
         #[arg(long, default_value_t = false)]
-        parallel: bool,
+        minimal: bool,
+        /// Time budget in seconds for all installs, commands still running afterwards are killed
+        #[arg(long, value_name = "SECONDS", default_value_t = 600)]
+        time_budget: u64,
     },
     /// Show status of managed files
+    #[command(visible_alias = "st")]
     Status {
         /// Include actions matching these tags (can be specified multiple times)
         #[arg(short='t', long = "tag", env="HERMIT_TAGS", value_name = "TAG", num_args = 0..)]
@@ -227,6 +479,21 @@ pub enum Commands {
         /// Show status of all files, not just those with issues
         #[arg(short = 'e', long, global = true, default_value_t = false)]
         extensive: bool,
+        /// Scan $HOME for symlinks into the hermit directory that are not managed by any link
+        #[arg(long, default_value_t = false)]
+        scan_home: bool,
+        /// Print a unified diff for copied text files that differ from their source
+        #[arg(long, default_value_t = false)]
+        diff: bool,
+        /// Check the targets below this directory, as applied with `apply --target-prefix`
+        #[arg(long, value_name = "DIR", value_hint = clap::ValueHint::DirPath)]
+        target_prefix: Option<PathBuf>,
+        /// Only check actions of these types, e.g. `--types link,patch`
+        #[arg(long, value_enum, value_delimiter = ',', value_name = "TYPE")]
+        types: Vec<ActionType>,
+        /// Skip actions of these types, e.g. `--skip-types install`
+        #[arg(long, value_enum, value_delimiter = ',', value_name = "TYPE")]
+        skip_types: Vec<ActionType>,
     },
     /// Show tags or profiles
     Get {
@@ -240,27 +507,215 @@ pub enum Commands {
         #[arg(last = true)]
         ubi_args: Vec<String>,
     },
+    /// Inspect the execution plan
+    Plan {
+        #[command(subcommand)]
+        plan_command: PlanCommand,
+    },
+    /// Render a template file or snippet with the current configuration
+    Render {
+        /// Path of a template file or name of a snippet
+        input: String,
+        /// Variable to pass to the template as var.<key> (can be specified multiple times)
+        #[arg(long = "var", value_name = "KEY=VALUE", value_parser = cmd_render::parse_var)]
+        vars: Vec<(String, String)>,
+        /// Show a diff against this file instead of printing the result
+        #[arg(short = 't', long, value_hint = clap::ValueHint::FilePath)]
+        target: Option<PathBuf>,
+    },
     /// Add actions to an existing configuration
     Add {
         #[command(subcommand)]
         add_command: AddCommand,
     },
+    /// Manage the shell hook for automatic checks on login
+    Hook {
+        #[command(subcommand)]
+        hook_command: HookCommand,
+    },
+    /// Generate or install shell completions
+    Completions {
+        #[command(subcommand)]
+        completions_command: CompletionsCommand,
+    },
+    /// Install this executable into a directory of $PATH, e.g. after downloading it
+    SelfInstall {
+        /// Directory to install to, defaults to ~/.local/bin
+        #[arg(long, value_name = "DIR", value_hint = clap::ValueHint::DirPath)]
+        to: Option<PathBuf>,
+        /// Symlink the executable instead of copying it
+        #[arg(long)]
+        link: bool,
+        /// Expected SHA-256 of the executable, the installation fails if it differs
+        #[arg(long, value_name = "HEX", conflicts_with = "checksum_file")]
+        sha256: Option<String>,
+        /// File with the expected SHA-256, as written by sha256sum
+        #[arg(long, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+        checksum_file: Option<PathBuf>,
+        /// Also install the shell completions
+        #[arg(long)]
+        completions: bool,
+        /// Also install the login hook that checks the dotfiles
+        #[arg(long)]
+        hook: bool,
+    },
+    /// List the recorded apply and status runs
+    History {
+        #[command(subcommand)]
+        history_command: Option<HistoryCommand>,
+        /// Maximum number of runs to list
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: usize,
+        /// Only list runs within this time span, e.g. 12h, 3d or 2w
+        #[arg(long, value_name = "SPAN")]
+        since: Option<Schedule>,
+    },
+    /// Leave actions out of every plan on this machine, lists the disabled actions without ids
+    Disable {
+        /// Ids of the actions as shown by `plan` in verbose mode or `get plan --json -`
+        ids: Vec<String>,
+    },
+    /// Enable actions that were disabled on this machine
+    Enable {
+        /// Ids of the disabled actions
+        ids: Vec<String>,
+    },
+    /// Generate documentation of the dotfiles repo
+    Docs {
+        #[command(subcommand)]
+        docs_command: DocsCommand,
+    },
+    /// Change the configuration files in bulk, e.g. rename a tag
+    Refactor {
+        #[command(subcommand)]
+        refactor_command: RefactorCommand,
+    },
 }
 
+impl Commands {
+    /// Name of the subcommand, used for telemetry
+    pub fn name(&self) -> &'static str {
+        match self {
+            Commands::Init { .. } => "init",
+            Commands::Apply { .. } => "apply",
+            Commands::Bootstrap { .. } => "bootstrap",
+            Commands::Status { .. } => "status",
+            Commands::Get { .. } => "get",
+            #[cfg(feature = "ubi")]
+            Commands::Ubi { .. } => "ubi",
+            Commands::Plan { .. } => "plan",
+            Commands::Render { .. } => "render",
+            Commands::Add { .. } => "add",
+            Commands::Hook { .. } => "hook",
+            Commands::Completions { .. } => "completions",
+            Commands::SelfInstall { .. } => "self-install",
+            Commands::History { .. } => "history",
+            Commands::Disable { .. } => "disable",
+            Commands::Enable { .. } => "enable",
+            Commands::Docs { .. } => "docs",
+            Commands::Refactor { .. } => "refactor",
+        }
+    }
+}
+
+/// Replaces a user defined alias at the position of the command with its expansion. Aliases
+/// can not override built-in commands and are not expanded recursively.
+pub fn expand_alias(mut args: Vec<OsString>, aliases: &BTreeMap<String, String>) -> Vec<OsString> {
+    if aliases.is_empty() {
+        return args;
+    }
+    let cli = Cli::command();
+    let takes_value = |arg: &str| {
+        cli.get_arguments()
+            .filter(|a| a.get_action().takes_values())
+            .any(|a| match arg.strip_prefix("--") {
+                Some(long) => a.get_long() == Some(long),
+                None => arg.len() == 2 && a.get_short() == arg.chars().nth(1),
+            })
+    };
+    let mut i = 1;
+    while let Some(arg) = args.get(i).and_then(|a| a.to_str()) {
+        if arg == "--" {
+            break;
+        }
+        if arg.starts_with('-') {
+            i += if takes_value(arg) { 2 } else { 1 };
+            continue;
+        }
+        if cli.find_subcommand(arg).is_some() {
+            break;
+        }
+        if let Some(expansion) = aliases.get(arg) {
+            match shlex::split(expansion) {
+                Some(words) => {
+                    args.splice(i..=i, words.into_iter().map(OsString::from));
+                }
+                None => crate::warn!(
+                    "Ignoring the alias {arg}, the quoting of its expansion is invalid"
+                ),
+            }
+        }
+        break;
+    }
+    args
+}
+
+/// Runs the command and summarizes the outcome for the exit code of the process
 pub async fn execute(
     command: Commands,
     global_config: Arc<GlobalConfig>,
     confirm: bool,
-    verbose: bool,
+    verbosity: Verbosity,
+    interactive: bool,
+    json: Option<PathBuf>,
+) -> ExitReport {
+    let name = command.name();
+    let counts_before = message_counts();
+    let config_invalid = !global_config.load_errors().is_empty();
+    let result = run(
+        command,
+        global_config,
+        confirm,
+        verbosity,
+        interactive,
+        json,
+    )
+    .await;
+    ExitReport::new(name, result, counts_before, config_invalid)
+}
+
+async fn run(
+    command: Commands,
+    global_config: Arc<GlobalConfig>,
+    confirm: bool,
+    verbosity: Verbosity,
     interactive: bool,
     json: Option<PathBuf>,
 ) -> Result<(), anyhow::Error> {
+    if !matches!(command, Commands::Ubi { .. }) {
+        crate::file_ops::dirs::home_dir()?;
+    }
     let search_root = global_config.hermit_dir();
     match command {
         Commands::Init { init_command } => match init_command {
-            InitCommand::Clone { repo } => {
+            InitCommand::Clone {
+                repo,
+                mirrors,
+                retries,
+            } => {
                 let pat = std::env::var("HERMITGRAB_GITHUB_TOKEN");
-                cmd_init::clone_or_update_repo(&repo, pat.ok().as_deref(), &global_config)?;
+                let mut retry = GitRetry::load();
+                retry.mirrors.splice(0..0, mirrors);
+                retry.retries = retries.unwrap_or(retry.retries);
+                let outcome = cmd_init::clone_or_update_repo(
+                    &repo,
+                    pat.ok().as_deref(),
+                    &global_config,
+                    &retry,
+                )?;
+                if let Some(json_path) = &json {
+                    write_json(json_path, &outcome)?;
+                }
             }
             InitCommand::Discover { create, provider } => {
                 if search_root.exists() {
@@ -273,8 +728,20 @@ pub async fn execute(
                     return Ok(());
                 }
                 match provider {
-                    Provider::GitHub { token } => {
-                        cmd_init::discover_repo_with_github(create, token, &global_config).await?;
+                    Provider::GitHub {
+                        token,
+                        search,
+                        org,
+                        name,
+                    } => {
+                        let options = GitHubDiscoverOptions {
+                            token,
+                            search,
+                            org,
+                            name,
+                        };
+                        cmd_init::discover_repo_with_github(create, options, &global_config)
+                            .await?;
                     }
                 }
             }
@@ -306,6 +773,7 @@ pub async fn execute(
                 ref required_tags,
                 ref fallback,
                 order,
+                relative,
             } => {
                 cmd_add::add_link(
                     config_dir,
@@ -316,19 +784,42 @@ pub async fn execute(
                     fallback,
                     &global_config,
                     order,
+                    relative,
                 )?;
             }
             AddCommand::Patch {
                 ref config_dir,
-                ref source,
+                source,
+                snippet,
+                content,
+                from_diff,
                 ref patch_type,
                 ref target,
                 ref required_tags,
                 order,
             } => {
+                if let Some(base) = from_diff {
+                    let target = target.as_deref().expect("clap requires a target");
+                    cmd_add::add_patch_from_diff(
+                        config_dir,
+                        base.as_deref(),
+                        patch_type,
+                        target,
+                        required_tags,
+                        &global_config,
+                        order,
+                    )?;
+                    return Ok(());
+                }
+                let source = match (source, snippet, content) {
+                    (Some(file), _, _) => FileOrText::File { file },
+                    (_, Some(snippet), _) => FileOrText::Snippet { snippet },
+                    (_, _, Some(text)) => FileOrText::Text { text },
+                    _ => unreachable!("clap requires one of source, snippet or content"),
+                };
                 cmd_add::add_patch(
                     config_dir,
-                    source,
+                    &source,
                     patch_type,
                     target,
                     required_tags,
@@ -336,16 +827,36 @@ pub async fn execute(
                     order,
                 )?;
             }
-            AddCommand::Profile { ref name, ref tags } => {
-                cmd_add::add_profile(name, tags, &global_config)?;
+            AddCommand::Profile {
+                ref name,
+                ref tags,
+                from_active,
+            } => {
+                if from_active {
+                    cmd_add::add_profile_from_active(name, tags, &global_config)?;
+                } else {
+                    cmd_add::add_profile(name, tags, &global_config)?;
+                }
             }
         },
         Commands::Apply {
-            ref tags,
-            ref profile,
-            ref fallback,
-            force,
-            parallel,
+            args:
+                ApplyArgs {
+                    ref tags,
+                    ref profile,
+                    ref fallback,
+                    force,
+                    parallel,
+                    resume,
+                    strict,
+                    due_only,
+                    target_prefix,
+                    types,
+                    skip_types,
+                    report_template,
+                    deterministic,
+                    metrics_file,
+                },
         } => {
             let fallback = if force {
                 Some(FallbackOperation::BackupOverwrite)
@@ -353,48 +864,115 @@ pub async fn execute(
                 *fallback
             };
             let cli = CliOptions {
-                fallback,
-                confirm,
-                verbose,
-                tags: tags.clone(),
-                profile: profile.clone(),
-                json: json.clone(),
+                due_only,
+                target_prefix: target_prefix.map(std::path::absolute).transpose()?,
+                types,
+                skip_types,
+                report_template,
+                deterministic,
+                metrics_file,
+                ..CliOptions::builder()
+                    .fallback(fallback)
+                    .confirm(confirm)
+                    .verbosity(verbosity)
+                    .tags(tags)
+                    .profile(profile.clone())
+                    .json(json.clone())
+                    .strict(strict)
+                    .build()
             };
             if interactive {
                 todo!("Interactive apply is not yet implemented");
             } else {
-                cmd_apply::apply_with_tags(&global_config, &cli, parallel).await?;
+                cmd_apply::apply_with_tags(&global_config, &cli, parallel, resume).await?;
             }
         }
+        Commands::Bootstrap {
+            ref tags,
+            ref profile,
+            minimal,
+            time_budget,
+        } => {
+            cmd_bootstrap::bootstrap(
+                &global_config,
+                tags,
+                profile.clone(),
+                minimal,
+                Duration::from_secs(time_budget),
+                verbosity,
+                json.clone(),
+            )
+            .await?;
+        }
         Commands::Status {
             extensive,
             ref tags,
             ref profile,
+            scan_home,
+            diff,
+            target_prefix,
+            types,
+            skip_types,
         } => {
             let cli = CliOptions {
-                tags: tags.clone(),
-                profile: profile.clone(),
-                json: json.clone(),
-                ..Default::default()
+                target_prefix: target_prefix.map(std::path::absolute).transpose()?,
+                types,
+                skip_types,
+                ..CliOptions::builder()
+                    .tags(tags)
+                    .profile(profile.clone())
+                    .json(json.clone())
+                    .verbosity(verbosity)
+                    .build()
             };
-            cmd_status::get_status(&global_config, !extensive, &cli)?;
+            cmd_status::get_status(&global_config, !extensive, scan_home, diff, &cli)?;
         }
         Commands::Get { get_command } => match get_command {
             GetCommand::Tags => {
+                let builtin = global_config
+                    .all_detected_tags()
+                    .iter()
+                    .filter(|t| !matches!(t.source(), Source::Detector(_)))
+                    .collect::<Vec<_>>();
+                let detected = detector::get_detected_tags(&global_config)?;
+                if let Some(json_path) = &json {
+                    let aliases = global_config
+                        .all_tag_aliases()
+                        .into_iter()
+                        .collect::<BTreeMap<_, _>>();
+                    let tags = serde_json::json!({
+                        "required": global_config.all_required_tags(),
+                        "builtin": builtin,
+                        "detected": detected,
+                        "aliases": aliases,
+                    });
+                    write_json(json_path, &tags)?;
+                }
                 hermitgrab_info!("All tags as required in the configuration:");
                 for t in global_config.all_required_tags() {
                     info!("- {t}");
                 }
                 hermitgrab_info!("All built-in detected:");
-                for t in detector::detect_builtin_tags() {
+                for t in &builtin {
                     info!("- {t}");
                 }
                 hermitgrab_info!("All tags by detectors in the configuration:");
-                for t in detector::get_detected_tags(&global_config)? {
+                for t in &detected {
                     info!("- {t}");
                 }
+                hermitgrab_info!("All tag aliases:");
+                for (alias, tags) in global_config.all_tag_aliases() {
+                    info!("- {alias}: {}", tags.iter().join(", "));
+                }
             }
             GetCommand::Profiles => {
+                if let Some(json_path) = &json {
+                    let profiles = global_config
+                        .all_profiles()
+                        .into_iter()
+                        .collect::<BTreeMap<_, _>>();
+                    write_json(json_path, &profiles)?;
+                }
                 hermitgrab_info!("All profiles:");
                 for (profile, tags) in global_config.all_profiles() {
                     info!(
@@ -407,20 +985,141 @@ pub async fn execute(
                     );
                 }
             }
-            GetCommand::Config => {
+            GetCommand::Snippets { ref show } => {
+                cmd_snippets::get_snippets(&global_config, show.as_deref(), json.as_deref())?;
+            }
+            GetCommand::Plan { tags, profile } => {
+                let cli = CliOptions::builder()
+                    .tags(&tags)
+                    .profile(profile)
+                    .verbosity(verbosity)
+                    .build();
+                cmd_plan::show_plan(&global_config, &cli, json.as_deref())?;
+            }
+            GetCommand::Config { origin: true } => {
+                let config_map = cmd_config::config_with_origins(&global_config)?;
+                if let Some(json_path) = &json {
+                    write_json(json_path, &config_map)?;
+                    info!("Configuration written to {}", json_path.display());
+                } else {
+                    let formatted = serde_yaml_ng::to_string(&config_map)?;
+                    info!("Printing the complete configuration with the origin of each item:");
+                    println!("{formatted}");
+                }
+            }
+            GetCommand::Config { origin: false } => {
                 let mut config_map = BTreeMap::new();
                 for (config_name, config) in global_config.subconfigs().into_iter() {
                     config_map.insert(config_name, config.clone());
                 }
 
-                let formatted = serde_yaml_ng::to_string(&config_map)?;
-                info!("Printing the complete configuration:");
-                println!("{formatted}");
                 if let Some(json_path) = &json {
-                    std::fs::write(json_path, serde_json::to_string_pretty(&config_map)?)?;
+                    write_json(json_path, &config_map)?;
                     info!("Configuration written to {}", json_path.display());
+                } else {
+                    let formatted = serde_yaml_ng::to_string(&config_map)?;
+                    info!("Printing the complete configuration:");
+                    println!("{formatted}");
                 }
             }
+            GetCommand::Doctor => {
+                cmd_doctor::doctor(&global_config, json.as_deref())?;
+            }
+        },
+        Commands::Plan { plan_command } => match plan_command {
+            PlanCommand::Diff {
+                profiles,
+                git_ref,
+                tags,
+            } => {
+                cmd_plan::plan_diff(
+                    &global_config,
+                    &profiles,
+                    git_ref.as_deref(),
+                    &tags,
+                    json.as_deref(),
+                )?;
+            }
+        },
+        Commands::Render {
+            input,
+            vars,
+            target,
+        } => {
+            cmd_render::render(&global_config, &input, &vars, target.as_deref())?;
+        }
+        Commands::Hook { hook_command } => match hook_command {
+            HookCommand::Install {
+                shell,
+                apply,
+                profile,
+                interval,
+            } => {
+                let options = HookOptions {
+                    apply,
+                    profile,
+                    interval,
+                    exe: None,
+                };
+                cmd_hook::install_hook(shell, &options)?;
+            }
+            HookCommand::Remove { shell } => {
+                cmd_hook::remove_hook(shell)?;
+            }
+        },
+        Commands::Completions {
+            completions_command,
+        } => match completions_command {
+            CompletionsCommand::Generate { shell } => {
+                cmd_completions::generate_completions(shell, &mut std::io::stdout());
+            }
+            CompletionsCommand::Install { shell } => {
+                cmd_completions::install_completions(shell)?;
+            }
+        },
+        Commands::SelfInstall {
+            to,
+            link,
+            sha256,
+            checksum_file,
+            completions,
+            hook,
+        } => {
+            let to = match to {
+                Some(to) => to,
+                None => crate::file_ops::dirs::home_dir()?
+                    .join(".local")
+                    .join("bin"),
+            };
+            let options = cmd_self_install::SelfInstallOptions {
+                to: std::path::absolute(to)?,
+                link,
+                sha256,
+                checksum_file,
+                completions,
+                hook,
+            };
+            cmd_self_install::self_install(&options)?;
+        }
+        Commands::Disable { ids } => cmd_disable::disable(&global_config, &ids)?,
+        Commands::Enable { ids } => cmd_disable::enable(&ids)?,
+        Commands::History {
+            history_command,
+            limit,
+            since,
+        } => match history_command {
+            None => cmd_history::list_history(limit, since, json.as_deref())?,
+            Some(HistoryCommand::Show { id }) => cmd_history::show_history(&id, json.as_deref())?,
+        },
+        Commands::Docs { docs_command } => match docs_command {
+            DocsCommand::Repo { output } => {
+                cmd_docs::docs_repo(&global_config, output.as_deref())?;
+            }
+        },
+        Commands::Refactor { refactor_command } => match refactor_command {
+            RefactorCommand::Tag { old, new, dry_run } => {
+                cmd_refactor::refactor_tag(&global_config, &old, &new, dry_run)?;
+            }
         },
         #[cfg(feature = "ubi")]
         Commands::Ubi { mut ubi_args } => {
@@ -433,3 +1132,47 @@ pub async fn execute(
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn test_expand_alias() {
+        let aliases = BTreeMap::from([
+            (
+                "up".to_string(),
+                "apply --profile 'my default' -y".to_string(),
+            ),
+            ("status".to_string(), "apply".to_string()),
+        ]);
+        assert_eq!(
+            expand_alias(args(&["hermitgrab", "-c", "up", "up", "-v"]), &aliases),
+            args(&[
+                "hermitgrab",
+                "-c",
+                "up",
+                "apply",
+                "--profile",
+                "my default",
+                "-y",
+                "-v"
+            ])
+        );
+        assert_eq!(
+            expand_alias(args(&["hermitgrab", "status"]), &aliases),
+            args(&["hermitgrab", "status"])
+        );
+        assert_eq!(
+            expand_alias(args(&["hermitgrab", "st"]), &aliases),
+            args(&["hermitgrab", "st"])
+        );
+        let cli = Cli::try_parse_from(expand_alias(args(&["hermitgrab", "up"]), &aliases)).unwrap();
+        assert!(matches!(cli.command, Commands::Apply { .. }));
+        assert!(cli.confirm);
+    }
+}
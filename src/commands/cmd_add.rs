@@ -11,14 +11,18 @@ use itertools::Itertools;
 use serde::Serialize;
 use toml_edit::{Array, ArrayOfTables, Formatted, Item, Table, Value};
 
-use crate::action::SourceSpec;
+use crate::action::patch::{merge_diff, parse_file, to_content};
+use crate::action::{Action, Actions, ContentType, FileOrText, SourceSpec};
 use crate::common_cli::{hint, prompt};
 use crate::config::{
-    CONF_FILE_NAME, FallbackOperation, GlobalConfig, PatchConfig, PatchType, SourceSpecOrPath, Tag,
-    load_hermit_config_editable,
+    CONF_FILE_NAME, CliOptions, FallbackOperation, GlobalConfig, PatchConfig, PatchType,
+    SourceSpecOrPath, Tag, load_hermit_config_editable,
 };
+use crate::execution_plan::create_execution_plan;
 use crate::file_ops::copy;
-use crate::file_ops::dirs::BASE_DIRS;
+use crate::file_ops::dirs::{
+    BASE_DIRS, XDG_BIN_HOME, XDG_CACHE_HOME, XDG_CONFIG_HOME, XDG_DATA_HOME, XDG_STATE_HOME,
+};
 use crate::hermitgrab_error::AddError;
 use crate::{
     HermitConfig, InstallConfig, LinkConfig, LinkType, RequireTag, choice, error, info, success,
@@ -46,7 +50,7 @@ pub fn add_config(
     } else {
         global_config.hermit_dir().join(config_dir)
     };
-    let config_file = config_dir.join(CONF_FILE_NAME);
+    let config_file = global_config.config_file_in(&config_dir);
     if config_file.exists() {
         error!(
             "The configuration file {config_file:?} already exists. Please use a different directory or remove the existing file."
@@ -65,15 +69,21 @@ pub fn add_config(
     Ok(())
 }
 
+/// Adds a patch to the config. The source is either a file that is copied into the
+/// config directory, inline content or the name of a snippet.
 pub fn add_patch(
     config_dir: &Option<PathBuf>,
-    source: &Path,
+    source: &FileOrText,
     patch_type: &PatchType,
     target: &Option<PathBuf>,
     required_tags: &[RequireTag],
     global_config: &Arc<GlobalConfig>,
     order: Option<u64>,
 ) -> Result<(), AddError> {
+    let source_file = match source {
+        FileOrText::File { file } => Some(file.as_path()),
+        _ => None,
+    };
     let config_dir = if let Some(target_dir) = config_dir {
         let new_target = PathBuf::from(target_dir);
         if new_target.is_absolute() {
@@ -81,23 +91,38 @@ pub fn add_patch(
         } else {
             global_config.hermit_dir().join(new_target)
         }
+    } else if let Some(source_file) = source_file {
+        get_config_dir_interactive(source_file, global_config)?
     } else {
-        get_config_dir_interactive(source, global_config)?
+        hint("Adding the patch to the root config, use --config-dir to select a different one");
+        global_config.hermit_dir().to_path_buf()
+    };
+    let config_file = global_config.config_file_in(&config_dir);
+    let target = normalize_target(source_file, target)?;
+    let (spec, copy_source) = match source_file {
+        Some(source_file) => {
+            let source_filename: PathBuf = source_file
+                .file_name()
+                .ok_or(AddError::FileName)?
+                .to_string_lossy()
+                .to_string()
+                .into();
+            (
+                SourceSpec::raw_path(source_filename.clone()),
+                Some((source_file, source_filename)),
+            )
+        }
+        None => (SourceSpec::from_source(source.clone()), None),
     };
-    let config_file = config_dir.join(CONF_FILE_NAME);
-    let target = normalize_target(source, target)?;
-    let source_filename: PathBuf = source
-        .file_name()
-        .ok_or(AddError::FileName)?
-        .to_string_lossy()
-        .to_string()
-        .into();
     let file_entry = PatchConfig {
-        source: SourceSpecOrPath::SourceSpec(SourceSpec::raw_path(source_filename.clone())),
+        source: SourceSpecOrPath::SourceSpec(spec),
         target,
         patch_type: patch_type.clone(),
+        encoding: Default::default(),
+        ownership: Default::default(),
         requires: BTreeSet::from_iter(required_tags.iter().cloned()),
         order,
+        dir_mode: None,
     };
     if config_file.exists() {
         insert_into_existing(&config_file, &file_entry)?;
@@ -112,11 +137,80 @@ pub fn add_patch(
             &None,
         )?;
     }
-    copy(source, config_dir.join(source_filename).as_path())?;
+    if let Some((source_file, source_filename)) = copy_source {
+        copy(source_file, config_dir.join(source_filename).as_path())?;
+    }
     crate::success!("Added new patch to {config_file:?}");
     Ok(())
 }
 
+/// Adds a patch with the changes of `target` compared to `base`, which defaults to the
+/// source of the link managing the target. Running apply then reproduces the local changes.
+pub fn add_patch_from_diff(
+    config_dir: &Option<PathBuf>,
+    base: Option<&Path>,
+    patch_type: &PatchType,
+    target: &Path,
+    required_tags: &[RequireTag],
+    global_config: &Arc<GlobalConfig>,
+    order: Option<u64>,
+) -> Result<(), AddError> {
+    let target = target
+        .canonicalize()
+        .map_err(|_| AddError::SourceNotFound(target.to_path_buf()))?;
+    let content_type = ContentType::from_extension(&target);
+    if content_type == ContentType::Unknown {
+        return Err(AddError::UnsupportedDiffFormat(target));
+    }
+    let plan = create_execution_plan(global_config, &CliOptions::default())?;
+    let link = plan.iter().find_map(|(cfg, action)| match &**action {
+        Actions::Link(link) if link.dst() == target => Some((cfg.clone(), link)),
+        _ => None,
+    });
+    let base = match (base, &link) {
+        (Some(base), _) => base.to_path_buf(),
+        (None, Some((_, link))) => link.source_file().to_path_buf(),
+        (None, None) => return Err(AddError::NoDiffBase(target)),
+    };
+    let base_json = parse_file(std::fs::read_to_string(&base)?, &content_type)?;
+    let target_json = parse_file(std::fs::read_to_string(&target)?, &content_type)?;
+    if base_json == target_json {
+        info!(
+            "{} does not differ from {}",
+            target.display(),
+            base.display()
+        );
+        return Ok(());
+    }
+    let patch = match patch_type {
+        PatchType::JsonMerge => merge_diff(&base_json, &target_json),
+        PatchType::JsonPatch => serde_json::to_value(json_patch::diff(&base_json, &target_json))?,
+    };
+    let file_name = target.file_name().ok_or(AddError::FileName)?;
+    let patch_file_name = Path::new(file_name).with_extension(format!(
+        "patch.{}",
+        target.extension().unwrap_or_default().to_string_lossy()
+    ));
+    let temp = tempfile::TempDir::new()?;
+    let patch_file = temp.path().join(patch_file_name);
+    std::fs::write(&patch_file, to_content(patch, &content_type)?)?;
+    // The patch has to run after the link that copies the base into place
+    let order = order.or_else(|| link.as_ref().map(|(_, link)| link.get_order() + 1));
+    let config_dir = config_dir
+        .clone()
+        .or_else(|| link.as_ref().map(|(cfg, _)| cfg.directory().to_path_buf()))
+        .unwrap_or_else(|| global_config.hermit_dir().to_path_buf());
+    add_patch(
+        &Some(config_dir),
+        &FileOrText::File { file: patch_file },
+        patch_type,
+        &Some(target),
+        required_tags,
+        global_config,
+        order,
+    )
+}
+
 #[allow(clippy::too_many_arguments)]
 pub fn add_link(
     config_dir: &Option<PathBuf>,
@@ -127,6 +221,7 @@ pub fn add_link(
     fallback: &FallbackOperation,
     global_config: &Arc<GlobalConfig>,
     order: Option<u64>,
+    relative: bool,
 ) -> Result<(), AddError> {
     let config_dir = if let Some(target_dir) = config_dir {
         let new_target = PathBuf::from(target_dir);
@@ -138,8 +233,30 @@ pub fn add_link(
     } else {
         get_config_dir_interactive(source, global_config)?
     };
-    let config_file = config_dir.join(CONF_FILE_NAME);
-    let target = normalize_target(source, target)?;
+    let config_file = global_config.config_file_in(&config_dir);
+    let target = normalize_target(Some(source), target)?;
+    let absolute_target = match target.strip_prefix("~") {
+        Ok(rest) => BASE_DIRS.home_dir().join(rest),
+        Err(_) => target.clone(),
+    };
+    let target = match relative_target(&absolute_target, &xdg_dirs()) {
+        Some(portable) if relative => portable,
+        Some(portable) => {
+            hint(&format!(
+                "Use --relative to store the target as {}, it follows other XDG directories",
+                portable.display()
+            ));
+            target
+        }
+        None if relative => {
+            info!(
+                "{} is not below an XDG base directory, the target is stored as it is",
+                target.display()
+            );
+            target
+        }
+        None => target,
+    };
     let source_filename: PathBuf = source
         .file_name()
         .ok_or(AddError::FileName)?
@@ -148,11 +265,18 @@ pub fn add_link(
         .into();
     let file_entry = LinkConfig {
         source: SourceSpecOrPath::Path(source_filename.clone()),
-        target,
+        target: target.into(),
         link: *link_type,
         requires: BTreeSet::from_iter(required_tags.iter().cloned()),
         fallback: *fallback,
+        fallback_dir: None,
         order,
+        encoding: Default::default(),
+        ownership: Default::default(),
+        hardlink_fallback: Default::default(),
+        manage_children: Vec::new(),
+        allow_in_repo: false,
+        dir_mode: None,
     };
     if config_file.exists() {
         insert_into_existing(&config_file, &file_entry)?;
@@ -172,14 +296,36 @@ pub fn add_link(
     Ok(())
 }
 
-fn normalize_target(source: &Path, target: &Option<PathBuf>) -> Result<PathBuf, AddError> {
+/// The XDG base directories by their name in the `dir` variables of templates
+fn xdg_dirs() -> Vec<(&'static str, PathBuf)> {
+    vec![
+        ("xdg_config", PathBuf::from(XDG_CONFIG_HOME.as_str())),
+        ("xdg_data", PathBuf::from(XDG_DATA_HOME.as_str())),
+        ("xdg_cache", PathBuf::from(XDG_CACHE_HOME.as_str())),
+        ("xdg_state", PathBuf::from(XDG_STATE_HOME.as_str())),
+        ("xdg_bin", PathBuf::from(XDG_BIN_HOME.as_str())),
+    ]
+}
+
+/// The target relative to the most specific base directory containing it, like
+/// `{{ dir.xdg_config }}/fish/config.fish`
+fn relative_target(target: &Path, dirs: &[(&str, PathBuf)]) -> Option<PathBuf> {
+    dirs.iter()
+        .filter_map(|(name, dir)| Some((name, target.strip_prefix(dir).ok()?)))
+        .min_by_key(|(_, rest)| rest.components().count())
+        .map(|(name, rest)| PathBuf::from(format!("{{{{ dir.{name} }}}}")).join(rest))
+}
+
+fn normalize_target(source: Option<&Path>, target: &Option<PathBuf>) -> Result<PathBuf, AddError> {
     let target = if let Some(target) = target {
         let path = PathBuf::from(target);
         path.strip_prefix(BASE_DIRS.home_dir())
             .map(|x| x.to_path_buf())
             .unwrap_or(path)
-    } else {
+    } else if let Some(source) = source {
         source.strip_prefix(BASE_DIRS.home_dir())?.to_path_buf()
+    } else {
+        return Err(AddError::MissingTarget);
     };
     let target = if target.is_absolute() {
         target
@@ -224,18 +370,17 @@ fn get_config_dir_interactive(
             .and_then(|p| p.file_name().and_then(|f| f.to_str()))
             .unwrap_or("")
     };
-    let deep_config_file = global_config
-        .hermit_dir()
-        .join(relative_source.parent().unwrap_or(relative_source))
-        .join(CONF_FILE_NAME);
+    let deep_config_file = global_config.config_file_in(
+        &global_config
+            .hermit_dir()
+            .join(relative_source.parent().unwrap_or(relative_source)),
+    );
     let deep_config_display_path = deep_config_file
         .strip_prefix(global_config.hermit_dir())
         .unwrap_or(&deep_config_file)
         .display();
-    let simple_config_file = global_config
-        .hermit_dir()
-        .join(last_segment_from_absolute)
-        .join(CONF_FILE_NAME);
+    let simple_config_file =
+        global_config.config_file_in(&global_config.hermit_dir().join(last_segment_from_absolute));
     let simple_config_display_path = simple_config_file
         .strip_prefix(global_config.hermit_dir())
         .unwrap_or(&simple_config_file)
@@ -275,26 +420,26 @@ fn get_config_dir_interactive(
 }
 
 trait GetSourceAndTarget<'a> {
-    fn source(&'a self) -> &'a Path;
+    fn source(&'a self) -> Option<&'a Path>;
     fn target(&'a self) -> &'a Path;
     fn entry_name(&self) -> &'static str;
 }
 
 impl<'a> GetSourceAndTarget<'a> for LinkConfig {
-    fn source(&'a self) -> &'a Path {
-        &self.source.path()
+    fn source(&'a self) -> Option<&'a Path> {
+        self.source.path()
     }
 
     fn target(&'a self) -> &'a Path {
-        &self.target
+        self.target.default_path().unwrap_or(Path::new(""))
     }
     fn entry_name(&self) -> &'static str {
         "link"
     }
 }
 impl<'a> GetSourceAndTarget<'a> for PatchConfig {
-    fn source(&'a self) -> &'a Path {
-        &self.source.path()
+    fn source(&'a self) -> Option<&'a Path> {
+        self.source.path()
     }
 
     fn target(&'a self) -> &'a Path {
@@ -325,15 +470,15 @@ fn insert_into_existing<'a, T: Serialize + GetSourceAndTarget<'a>>(
                 };
                 let source_str = PathBuf::from(source.value());
                 let target_str = PathBuf::from(target.value());
-                if source_str == file_entry.source() && target_str == file_entry.target() {
+                if Some(source_str.as_path()) == file_entry.source()
+                    && target_str == file_entry.target()
+                {
                     error!(
                         "The {entry_name} table already contains an entry with the same source {} and target {}",
                         source_str.display(),
                         target_str.display()
                     );
-                    return Err(AddError::SourceAlreadyExists(
-                        file_entry.source().to_path_buf(),
-                    ));
+                    return Err(AddError::SourceAlreadyExists(source_str));
                 }
             }
             arr.push(table);
@@ -367,12 +512,25 @@ fn to_table<T: Serialize>(file_entry: &T) -> Result<toml_edit::Table, AddError>
     Ok(table)
 }
 
+/// Adds a profile with the currently active tags, which are the detected tags, the tags of
+/// the default profile and the given tags
+pub fn add_profile_from_active(
+    name: &str,
+    tags: &[Tag],
+    global_config: &Arc<GlobalConfig>,
+) -> Result<(), AddError> {
+    let mut active_tags = global_config.get_active_tags(&[], &None)?;
+    active_tags.extend(tags.iter().cloned());
+    let active_tags = active_tags.into_iter().collect::<Vec<_>>();
+    add_profile(name, &active_tags, global_config)
+}
+
 pub fn add_profile(
     name: &str,
     tags: &[Tag],
     global_config: &Arc<GlobalConfig>,
 ) -> Result<(), AddError> {
-    let config_file = global_config.hermit_dir().join(CONF_FILE_NAME);
+    let config_file = global_config.config_file_in(global_config.hermit_dir());
     info!("Updating profiles in {config_file:?}");
     if !config_file.exists() {
         config_file.parent().map_or_else(
@@ -395,7 +553,7 @@ pub fn add_profile(
             let entry = t.get_mut(name);
             match entry {
                 None | Some(Item::None) => {
-                    let new_tags = BTreeSet::from_iter(tags.iter().map(|t| t.name()));
+                    let new_tags = BTreeSet::from_iter(tags.iter().map(|t| t.to_string()));
                     let mut arr = Array::new();
                     for tag in &new_tags {
                         arr.push(Value::String(Formatted::new(tag.to_string())));
@@ -407,7 +565,7 @@ pub fn add_profile(
                     );
                 }
                 Some(Item::Value(Value::Array(arr))) => {
-                    let mut new_tags = BTreeSet::from_iter(tags.iter().map(|t| t.name()));
+                    let mut new_tags = BTreeSet::from_iter(tags.iter().map(|t| t.to_string()));
                     for (idx, item) in arr.iter().enumerate() {
                         match item {
                             Value::String(val) => {
@@ -448,3 +606,52 @@ pub fn add_profile(
     std::fs::write(config_file, new_config)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::find_hermit_files;
+
+    #[test]
+    fn test_relative_target() {
+        let dirs = [
+            ("xdg_config", PathBuf::from("/home/me/.config")),
+            ("xdg_data", PathBuf::from("/home/me/.local/share")),
+            ("xdg_state", PathBuf::from("/home/me/.local/state")),
+            ("xdg_nested", PathBuf::from("/home/me/.config/nested")),
+        ];
+        assert_eq!(
+            relative_target(Path::new("/home/me/.config/fish/config.fish"), &dirs),
+            Some(PathBuf::from("{{ dir.xdg_config }}/fish/config.fish"))
+        );
+        assert_eq!(
+            relative_target(Path::new("/home/me/.config/nested/app.toml"), &dirs),
+            Some(PathBuf::from("{{ dir.xdg_nested }}/app.toml"))
+        );
+        assert_eq!(relative_target(Path::new("/home/me/.bashrc"), &dirs), None);
+        assert_eq!(
+            relative_target(Path::new("/home/me/.configs/x"), &dirs),
+            None
+        );
+    }
+
+    #[test]
+    fn test_add_profile_from_active() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let config_file = temp.path().join(CONF_FILE_NAME);
+        std::fs::write(&config_file, "[profiles]\ndefault = [\"work\"]\n").unwrap();
+        let paths = find_hermit_files(temp.path(), &[CONF_FILE_NAME]);
+        let global_config = GlobalConfig::from_paths(temp.path(), &paths).unwrap();
+        add_profile_from_active("snapshot", &["extra".parse().unwrap()], &global_config).unwrap();
+        let config: HermitConfig =
+            toml::from_str(&std::fs::read_to_string(&config_file).unwrap()).unwrap();
+        let names = config.profiles["snapshot"]
+            .iter()
+            .map(|t| t.to_string())
+            .collect::<Vec<_>>();
+        assert!(names.contains(&"work".to_string()), "{names:?}");
+        assert!(names.contains(&"extra".to_string()), "{names:?}");
+        let family = format!("os_family={}", std::env::consts::FAMILY);
+        assert!(names.contains(&family), "{names:?}");
+    }
+}
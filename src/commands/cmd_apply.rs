@@ -4,46 +4,86 @@
 
 use std::collections::{BTreeMap, BTreeSet};
 use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
+use clap::ValueEnum;
 use crossterm::style::{Attribute, Color, Stylize};
 
-use crate::action::{Action, ActionObserver, ActionOutput, ArcAction};
+use crate::action::install::OutputLog;
+use crate::action::{
+    Action, ActionObserver, ActionOutcome, ActionOutput, ActionType, Actions, ArcAction,
+};
+use crate::commands::report_template::{REPORT_VERSION, render_report};
 #[allow(unused_imports)]
 use crate::common_cli::step;
-use crate::common_cli::{stderr, stdout, success};
-use crate::config::{CliOptions, GlobalConfig};
+use crate::common_cli::{Verbosity, stderr, stdout, success};
+use crate::config::secrets::redact;
+use crate::config::user_settings::UserSettings;
+use crate::config::{CliOptions, FallbackOperation, GlobalConfig, Tag};
 use crate::execution_plan::{ExecutionPlan, create_execution_plan};
+use crate::file_ops::dirs::xdg_bases;
 use crate::hermitgrab_error::{ActionError, ApplyError};
-use crate::{error, hermitgrab_info};
+use crate::integrations::metrics::RunMetrics;
+use crate::integrations::notify::notify_finished;
+use crate::integrations::report_commit::commit_report_if_enabled;
+use crate::state::{ApplyProgress, HistoryEntry, ScheduleState, XdgBases, now};
+use crate::{debug, error, hermitgrab_info, info, skipped, warn};
 
 pub struct CliReporter {
-    verbose: bool,
+    verbosity: Verbosity,
     reported_output: Mutex<BTreeMap<String, BTreeSet<String>>>,
     short_descriptions: Mutex<BTreeMap<String, String>>,
+    progress: Mutex<ApplyProgress>,
+    schedule: Mutex<ScheduleState>,
 }
 impl CliReporter {
-    fn new(verbose: bool) -> Self {
+    fn new(verbosity: Verbosity, progress: ApplyProgress, schedule: ScheduleState) -> Self {
         Self {
-            verbose,
+            verbosity,
             reported_output: Mutex::new(BTreeMap::new()),
             short_descriptions: Mutex::new(BTreeMap::new()),
+            progress: Mutex::new(progress),
+            schedule: Mutex::new(schedule),
+        }
+    }
+
+    /// Remember the last run of a scheduled action, so it is skipped until it is due again
+    fn record_scheduled(&self, action: &ArcAction) {
+        if action.schedule().is_none() {
+            return;
+        }
+        let mut schedule = self.schedule.lock().expect("should lock");
+        schedule.last_run.insert(action.id(), now());
+        if let Err(e) = schedule.save() {
+            warn!("Failed to save schedule state: {e}");
+        }
+    }
+
+    /// Persist a successful action so an interrupted run can be resumed
+    fn record_completed(&self, action: &ArcAction) {
+        let mut progress = self.progress.lock().expect("should lock");
+        progress.completed.insert(action.id());
+        if let Err(e) = progress.save() {
+            warn!("Failed to save apply progress: {e}");
         }
     }
 }
 
 impl ActionObserver for CliReporter {
     fn action_started(&self, action: &ArcAction) {
-        if !self.verbose {
+        if !self.verbosity.is_verbose() {
             return;
         }
         hermitgrab_info!("Starting action: {}", action.short_description());
+        debug!("{}", action.long_description());
         let mut descriptions = self.short_descriptions.lock().expect("should lock");
         descriptions.insert(action.id().to_string(), action.short_description());
     }
 
     fn action_output(&self, action_id: &str, output: &ActionOutput) {
-        if !self.verbose {
+        if !self.verbosity.is_verbose() {
             return;
         }
         let mut map = self.reported_output.lock().expect("should lock");
@@ -69,7 +109,7 @@ impl ActionObserver for CliReporter {
     }
 
     fn action_progress(&self, action_id: &str, current: u64, total: u64, msg: &str) {
-        if !self.verbose {
+        if !self.verbosity.is_verbose() {
             return;
         }
         let descriptions = self.short_descriptions.lock().expect("should lock");
@@ -85,17 +125,26 @@ impl ActionObserver for CliReporter {
         );
     }
 
-    fn action_finished(&self, action: &ArcAction, result: &Result<(), ActionError>) {
+    fn action_finished(&self, action: &ArcAction, result: &Result<ActionOutcome, ActionError>) {
         let short_description = action.short_description();
         match result {
-            Ok(_) => {
+            Ok(ActionOutcome::Skipped(reason)) => {
+                self.record_completed(action);
+                self.record_scheduled(action);
+                skipped!("{short_description}: {reason}");
+            }
+            Ok(ActionOutcome::Done) => {
+                self.record_completed(action);
+                self.record_scheduled(action);
                 success(&short_description);
-                if self.verbose {
+                print_changes(action);
+                if self.verbosity.is_verbose() {
                     print_action_output(action);
                 }
             }
             Err(e) => {
                 error!("{}: {}", short_description, e);
+                print_changes(action);
                 print_action_output(action);
             }
         }
@@ -106,58 +155,334 @@ pub async fn apply_with_tags(
     global_config: &Arc<GlobalConfig>,
     cli: &CliOptions,
     parallel: bool,
+    resume: bool,
 ) -> Result<(), ApplyError> {
-    let active_tags = global_config.get_active_tags(&cli.tags, &cli.profile)?;
+    let start = Instant::now();
+    global_config.report_load_errors();
+    let mut active_tags = global_config.get_active_tags(&cli.tags, &cli.profile)?;
+    active_tags.retain(|t| !cli.exclude_tags.iter().any(|e| e == t.name()));
     let active_tags_str = active_tags
         .iter()
         .map(|t| t.to_string())
         .collect::<Vec<_>>()
         .join(", ");
     hermitgrab_info!("Active tags: {}", active_tags_str);
-    let actions = create_execution_plan(global_config, cli)?;
-    let filtered_actions = actions.filter_actions_by_tags(&active_tags);
-    present_execution_plan(&filtered_actions, parallel, cli.verbose);
+    let mut history = HistoryEntry::new(
+        "apply",
+        cli.profile.clone(),
+        active_tags.iter().map(|t| t.to_string()).collect(),
+    );
+    let settings = UserSettings::load().unwrap_or_default();
+    let mut cli = CliOptions {
+        output_log: Some(OutputLog {
+            dir: history.output_dir(),
+            limit: settings.report.output_limit(),
+        }),
+        ..cli.clone()
+    };
+    let schedule = ScheduleState::load().unwrap_or_else(|e| {
+        warn!("Ignoring unreadable schedule state: {e}");
+        ScheduleState::default()
+    });
+    let (mut filtered_actions, skipped) =
+        due_actions(global_config, &cli, &active_tags, &schedule)?;
+    if skipped > 0 {
+        info!("Skipping {skipped} action(s) that are not scheduled to run now");
+    }
+    // Conflicting targets are resolved before anything runs, not one by one during the run
+    let conflicts = TargetConflict::from_plan(&filtered_actions);
+    if !conflicts.is_empty() {
+        present_conflicts(&conflicts);
+        if !cli.confirm
+            && let Some(fallback) = ask_for_fallback()
+        {
+            cli.fallback = Some(fallback);
+            (filtered_actions, _) = due_actions(global_config, &cli, &active_tags, &schedule)?;
+            present_conflicts(&TargetConflict::from_plan(&filtered_actions));
+        }
+    }
+    let cli = &cli;
+    let plan_hash = filtered_actions.plan_hash();
+    history.plan_hash = plan_hash.clone();
+    let mut progress = ApplyProgress::load().unwrap_or_else(|e| {
+        warn!("Ignoring unreadable apply progress: {e}");
+        ApplyProgress::default()
+    });
+    let filtered_actions = match progress.completed_for(&plan_hash) {
+        Some(completed) if resume => {
+            hermitgrab_info!(
+                "Resuming previous run, skipping {} completed actions",
+                completed.len()
+            );
+            filtered_actions.without_actions(completed)
+        }
+        Some(completed) => {
+            info!(
+                "A previous run of this plan was interrupted after {} actions, use --resume to skip them",
+                completed.len()
+            );
+            progress.completed.clear();
+            filtered_actions
+        }
+        None => {
+            if resume {
+                info!("No interrupted run of this plan found, applying all actions");
+            }
+            progress = ApplyProgress {
+                plan_hash,
+                completed: BTreeSet::new(),
+            };
+            filtered_actions
+        }
+    };
+    if let Some(prefix) = &cli.target_prefix {
+        hermitgrab_info!(
+            "Placing all targets below {}, install actions are skipped",
+            prefix.display()
+        );
+    }
+    present_execution_plan(&filtered_actions, parallel, cli.verbosity.is_verbose());
+    let destructive = DestructiveSummary::from_plan(&filtered_actions);
+    destructive.present();
     if !cli.confirm {
-        confirm_with_user()?;
+        confirm_with_user(!destructive.removed_recursively.is_empty())?;
     }
-    let observer = Arc::new(CliReporter::new(cli.verbose));
+    let observer = Arc::new(CliReporter::new(cli.verbosity, progress, schedule));
     let results = if !parallel {
         filtered_actions.execute_actions(&observer)
     } else {
         filtered_actions.execute_actions_parallel(&observer).await
     };
+    if results.iter().all(|r| r.result.is_ok()) {
+        ApplyProgress::clear()?;
+    }
+    for result in &results {
+        match &result.result {
+            Ok(ActionOutcome::Skipped(reason)) => history.add_skipped(
+                result.action.id(),
+                result.action.short_description(),
+                reason.clone(),
+            ),
+            other => history.add_result(
+                result.action.id(),
+                other.is_ok(),
+                result.action.short_description(),
+                other.as_ref().err().map(|e| redact(&e.to_string())),
+            ),
+        }
+    }
+    if !results.is_empty() {
+        info!(
+            "{} action(s) done, {} skipped, {} failed",
+            history.ok, history.skipped, history.failed
+        );
+    }
+    if let Err(e) = history.save() {
+        warn!("Failed to record the run in the history: {e}");
+    }
+    commit_report_if_enabled(global_config, &history);
+    notify_finished(&settings.notify, start.elapsed(), &history);
+    if let Some(metrics_file) = &cli.metrics_file {
+        let metrics = RunMetrics::from_results(&results, start.elapsed(), now());
+        match metrics.write(metrics_file) {
+            Ok(()) => debug!("Wrote the metrics to {}", metrics_file.display()),
+            Err(e) => warn!(
+                "Failed to write the metrics to {}: {e}",
+                metrics_file.display()
+            ),
+        }
+    }
+    // Targets below a prefix do not tell where the XDG base directories are
+    if cli.target_prefix.is_none() {
+        let xdg_bases = XdgBases { bases: xdg_bases() };
+        if let Err(e) = xdg_bases.save() {
+            warn!("Failed to record the XDG base directories: {e}");
+        }
+    }
+    if cli.json.is_none() && cli.report_template.is_none() {
+        return Ok(());
+    }
+    let actions = filtered_actions
+        .actions
+        .iter()
+        .map(|(_, action)| (action.id(), action))
+        .collect::<BTreeMap<_, _>>();
+    let plan_index = filtered_actions
+        .actions
+        .iter()
+        .enumerate()
+        .map(|(index, (_, action))| (action.id(), index))
+        .collect::<BTreeMap<_, _>>();
+    let output_dir = history.output_dir();
+    let mut results = results.iter().collect::<Vec<_>>();
+    results.sort_by_key(|a| plan_index.get(&a.action.id()).copied());
+    let results = results
+        .into_iter()
+        .map(|a| {
+            let id = a.action.id();
+            let output = a.action.get_output();
+            let mut log_files = output
+                .as_ref()
+                .map(|o| o.log_files().clone())
+                .unwrap_or_default();
+            if cli.deterministic {
+                for path in log_files.values_mut() {
+                    if let Ok(relative) = path.strip_prefix(&output_dir) {
+                        *path = relative.to_path_buf();
+                    }
+                }
+            }
+            // Streamed output is only referenced to keep the report small
+            let mut result = serde_json::json!({
+                "id": id,
+                "ok": a.result.is_ok(),
+                "error": a.result.as_ref().err().map(|e| e.to_string()),
+                "output": output.filter(|_| log_files.is_empty()),
+                "short_description": a.action.short_description(),
+            });
+            if !log_files.is_empty() {
+                result["output_files"] = serde_json::json!(log_files);
+            }
+            if let Actions::Install(install) = &*a.action
+                && let Some(changes) = install.changes()
+            {
+                result["changes"] = serde_json::json!(changes);
+            }
+            if let Some(reason) = a.result.as_ref().ok().and_then(ActionOutcome::skip_reason) {
+                result["skipped"] = serde_json::json!(reason);
+            }
+            if cli.deterministic {
+                result["index"] = serde_json::json!(plan_index.get(&id));
+            }
+            result
+        })
+        .collect::<Vec<_>>();
+    let report = serde_json::json!({
+        "version": REPORT_VERSION,
+        "summary": {
+            "ok": history.ok,
+            "skipped": history.skipped,
+            "failed": history.failed,
+        },
+        "actions": actions,
+        "results": results,
+    });
     if let Some(json_path) = &cli.json {
-        let actions = filtered_actions
-            .actions
-            .iter()
-            .map(|(_, action)| (action.id(), action))
-            .collect::<BTreeMap<_, _>>();
-        let results = results
-            .iter()
-            .map(|a| {
-                let id = a.action.id();
-                let output = a.action.get_output();
-                (
-                    id,
-                    serde_json::json!({
-                        "ok": a.result.is_ok(),
-                        "error": a.result.as_ref().err().map(|e| e.to_string()),
-                        "output": output,
-                        "short_description": a.action.short_description(),
-                    }),
-                )
-            })
-            .collect::<BTreeMap<_, _>>();
-        let json = serde_json::json!({
-            "actions": actions,
-            "results": results,
-        });
-        std::fs::write(json_path, serde_json::to_string_pretty(&json)?)?;
+        std::fs::write(json_path, redact(&serde_json::to_string_pretty(&report)?))?;
+    }
+    if let Some(template) = &cli.report_template {
+        print!("{}", redact(&render_report(template, &report)?));
     }
     Ok(())
 }
 
-fn present_execution_plan(sorted: &ExecutionPlan, parallel: bool, verbose: bool) {
+/// The plan of the active actions that are due, with the number of skipped scheduled actions
+fn due_actions(
+    global_config: &Arc<GlobalConfig>,
+    cli: &CliOptions,
+    active_tags: &BTreeSet<Tag>,
+    schedule: &ScheduleState,
+) -> Result<(ExecutionPlan, usize), ApplyError> {
+    let mut skip_types = cli.skip_types.clone();
+    // Install commands change the real system, they can not be placed below the prefix
+    if cli.target_prefix.is_some() && !skip_types.contains(&ActionType::Install) {
+        skip_types.push(ActionType::Install);
+    }
+    let filtered_actions = create_execution_plan(global_config, cli)?
+        .filter_actions_by_tags(active_tags)
+        .filter_actions_by_type(&cli.types, &skip_types);
+    let scheduled = filtered_actions.filter_scheduled(schedule, cli.due_only);
+    let skipped = filtered_actions.actions.len() - scheduled.actions.len();
+    Ok((scheduled, skipped))
+}
+
+/// An existing target that the plan replaces or fails on
+#[derive(Debug, PartialEq)]
+struct TargetConflict {
+    target: PathBuf,
+    current: &'static str,
+    fallback: FallbackOperation,
+}
+
+impl TargetConflict {
+    fn from_plan(plan: &ExecutionPlan) -> Vec<Self> {
+        plan.iter()
+            .filter_map(|(_, action)| match &**action {
+                Actions::Link(link) => link.pending_fallback().map(|fallback| Self {
+                    target: link.dst().to_path_buf(),
+                    current: file_kind(link.dst()),
+                    fallback,
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+fn file_kind(path: &Path) -> &'static str {
+    match path.symlink_metadata() {
+        Ok(meta) if meta.is_symlink() => "symlink",
+        Ok(meta) if meta.is_dir() => "directory",
+        Ok(meta) if meta.is_file() => "file",
+        Ok(_) => "other",
+        Err(_) => "missing",
+    }
+}
+
+fn present_conflicts(conflicts: &[TargetConflict]) {
+    warn!("{} target(s) exist already:", conflicts.len());
+    let width = conflicts
+        .iter()
+        .map(|c| c.target.display().to_string().len())
+        .max()
+        .unwrap_or_default()
+        .max("Target".len());
+    info!("  {:<width$}  {:<9}  Fallback", "Target", "Type");
+    for conflict in conflicts {
+        info!(
+            "  {:<width$}  {:<9}  {}",
+            conflict.target.display().to_string(),
+            conflict.current,
+            conflict.fallback
+        );
+    }
+}
+
+/// Asks for a fallback that replaces the planned ones, None keeps the plan
+fn ask_for_fallback() -> Option<FallbackOperation> {
+    let names = FallbackOperation::value_variants()
+        .iter()
+        .map(|f| f.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    loop {
+        print!(
+            "{} {}",
+            "[hermitgrab]"
+                .stylize()
+                .with(Color::Cyan)
+                .attribute(Attribute::Bold),
+            format!("Fallback for all existing targets ({names}), empty keeps the above: ")
+                .stylize()
+                .with(Color::Yellow)
+        );
+        std::io::stdout().flush().unwrap();
+        let mut input = String::new();
+        if std::io::stdin().read_line(&mut input).unwrap_or_default() == 0 {
+            return None;
+        }
+        let input = input.trim();
+        if input.is_empty() {
+            return None;
+        }
+        match FallbackOperation::from_str(input, true) {
+            Ok(fallback) => return Some(fallback),
+            Err(_) => warn!("Unknown fallback {input}"),
+        }
+    }
+}
+
+pub fn present_execution_plan(sorted: &ExecutionPlan, parallel: bool, verbose: bool) {
     if parallel {
         hermitgrab_info!("Execution plan with parallel execution:");
     } else {
@@ -171,38 +496,142 @@ fn present_execution_plan(sorted: &ExecutionPlan, parallel: bool, verbose: bool)
         }
         if verbose {
             crate::step!(
-                "[{:>2}] {} (order {})",
+                "[{:>2}] {} (order {}, id {})",
                 i + 1,
                 a.long_description(),
-                a.get_order()
+                a.get_order(),
+                a.id()
             );
+            if let Actions::Install(install) = &**a {
+                for check in install.check_commands() {
+                    present_command("check", check);
+                }
+                present_command("install", install.install_command());
+            }
         } else {
             crate::step!("[{:>2}] {}", i + 1, a.short_description());
         }
     }
 }
 
-fn confirm_with_user() -> Result<(), ApplyError> {
+/// Shows a rendered command below its action, secrets are redacted by the output
+fn present_command(kind: &str, command: &str) {
+    let mut lines = command.trim().lines();
+    if let Some(first) = lines.next() {
+        crate::step!("     {kind}: {first}");
+    }
+    let indent = " ".repeat(kind.len() + 2);
+    for line in lines {
+        crate::step!("     {indent}{line}");
+    }
+}
+
+/// Existing files and directories the plan replaces, grouped by the fallback operation
+#[derive(Debug, Default)]
+struct DestructiveSummary {
+    backed_up: Vec<PathBuf>,
+    deleted: Vec<PathBuf>,
+    removed_recursively: Vec<PathBuf>,
+    aborting: Vec<PathBuf>,
+}
+
+impl DestructiveSummary {
+    fn from_plan(plan: &ExecutionPlan) -> Self {
+        let mut summary = Self::default();
+        for (_, action) in plan.iter() {
+            let Actions::Link(link) = &**action else {
+                continue;
+            };
+            let dst = link.dst().to_path_buf();
+            match link.pending_fallback() {
+                Some(FallbackOperation::Backup | FallbackOperation::BackupOverwrite) => {
+                    summary.backed_up.push(dst)
+                }
+                Some(FallbackOperation::Delete) => summary.deleted.push(dst),
+                Some(FallbackOperation::DeleteDir) if dst.is_dir() && !dst.is_symlink() => {
+                    summary.removed_recursively.push(dst)
+                }
+                Some(FallbackOperation::DeleteDir) => summary.deleted.push(dst),
+                Some(FallbackOperation::Abort) => summary.aborting.push(dst),
+                Some(FallbackOperation::Ignore) | None => {}
+            }
+        }
+        summary
+    }
+
+    fn present(&self) {
+        if !self.backed_up.is_empty() {
+            warn!(
+                "{} existing file(s) will be overwritten with a backup",
+                self.backed_up.len()
+            );
+        }
+        if !self.deleted.is_empty() {
+            warn!("{} existing file(s) will be deleted", self.deleted.len());
+        }
+        if !self.removed_recursively.is_empty() {
+            error!(
+                "{} existing directories will be removed recursively:",
+                self.removed_recursively.len()
+            );
+            self.removed_recursively
+                .iter()
+                .for_each(|p| info!("  {}", p.display()));
+        }
+        if !self.aborting.is_empty() {
+            warn!(
+                "{} action(s) will fail because the target exists, use --fallback to replace them",
+                self.aborting.len()
+            );
+        }
+    }
+}
+
+/// Asks the user to confirm the plan. When `require_yes` is set, only typing "yes" is accepted.
+fn confirm_with_user(require_yes: bool) -> Result<(), ApplyError> {
+    let question = if require_yes {
+        "Directories will be removed recursively, type \"yes\" to apply the above actions: "
+    } else {
+        "Do you want to apply the above actions? (y/n) "
+    };
     print!(
         "{} {}",
         "[hermitgrab]"
             .stylize()
             .with(Color::Cyan)
             .attribute(Attribute::Bold),
-        "Do you want to apply the above actions? (y/n) "
-            .stylize()
-            .with(Color::Yellow)
+        question.stylize().with(Color::Yellow)
     );
     std::io::stdout().flush().unwrap();
     let mut input = String::new();
     std::io::stdin().read_line(&mut input).unwrap();
-    if !matches!(input.to_lowercase().trim(), "y" | "yes") {
+    let accepted = if require_yes {
+        input.trim() == "yes"
+    } else {
+        matches!(input.to_lowercase().trim(), "y" | "yes")
+    };
+    if !accepted {
         crate::common_cli::error("Aborted.");
         return Err(ApplyError::UserAborted);
     }
     Ok(())
 }
 
+/// Lists the files an install changed, see [crate::config::InstallConfig::report_changes]
+fn print_changes(action: &ArcAction) {
+    let Actions::Install(install) = &**action else {
+        return;
+    };
+    match install.changes() {
+        Some(changes) if changes.is_empty() => info!("  No changes detected"),
+        Some(changes) => {
+            info!("  Changes detected:");
+            changes.iter().for_each(|change| info!("    {change}"));
+        }
+        None => {}
+    }
+}
+
 fn print_action_output(action: &ArcAction) {
     if let Some(output) = action.get_output() {
         if output.is_empty() {
@@ -218,3 +647,118 @@ fn print_action_output(action: &ArcAction) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{CONF_FILE_NAME, find_hermit_files};
+
+    #[test]
+    fn test_destructive_summary() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let hermit_dir = temp.path().join("hermit");
+        std::fs::create_dir_all(&hermit_dir).unwrap();
+        std::fs::write(hermit_dir.join("a"), "a").unwrap();
+        std::fs::create_dir_all(temp.path().join("dir")).unwrap();
+        std::fs::write(temp.path().join("file"), "").unwrap();
+        let link = |target: &str, fallback: &str| {
+            format!(
+                "[[link]]\nsource = \"a\"\ntarget = {:?}\nfallback = \"{fallback}\"\n",
+                temp.path().join(target)
+            )
+        };
+        std::fs::write(
+            hermit_dir.join(CONF_FILE_NAME),
+            [
+                link("dir", "DeleteDir"),
+                link("file", "Backup"),
+                link("new", "Delete"),
+            ]
+            .concat(),
+        )
+        .unwrap();
+        let paths = find_hermit_files(&hermit_dir, &[CONF_FILE_NAME]);
+        let global = GlobalConfig::from_paths(&hermit_dir, &paths).unwrap();
+        let plan = create_execution_plan(&global, &CliOptions::default()).unwrap();
+        let summary = DestructiveSummary::from_plan(&plan);
+        assert_eq!(summary.removed_recursively, vec![temp.path().join("dir")]);
+        assert_eq!(summary.backed_up, vec![temp.path().join("file")]);
+        assert!(summary.deleted.is_empty());
+        assert!(summary.aborting.is_empty());
+        let conflicts = TargetConflict::from_plan(&plan);
+        assert_eq!(
+            conflicts,
+            vec![
+                TargetConflict {
+                    target: temp.path().join("dir"),
+                    current: "directory",
+                    fallback: FallbackOperation::DeleteDir,
+                },
+                TargetConflict {
+                    target: temp.path().join("file"),
+                    current: "file",
+                    fallback: FallbackOperation::Backup,
+                },
+            ]
+        );
+        let cli = CliOptions {
+            fallback: Some(FallbackOperation::Abort),
+            ..Default::default()
+        };
+        let plan = create_execution_plan(&global, &cli).unwrap();
+        assert!(
+            TargetConflict::from_plan(&plan)
+                .iter()
+                .all(|c| c.fallback == FallbackOperation::Abort)
+        );
+    }
+
+    #[test]
+    fn test_target_prefix_skips_installs() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let hermit_dir = temp.path().join("hermit");
+        std::fs::create_dir_all(&hermit_dir).unwrap();
+        std::fs::write(hermit_dir.join("a"), "a").unwrap();
+        std::fs::write(
+            hermit_dir.join(CONF_FILE_NAME),
+            "[[link]]\nsource = \"a\"\ntarget = \"~/.a\"\n\n[[install]]\nname = \"tool\"\ninstall = \"true\"\n",
+        )
+        .unwrap();
+        let paths = find_hermit_files(&hermit_dir, &[CONF_FILE_NAME]);
+        let global = GlobalConfig::from_paths(&hermit_dir, &paths).unwrap();
+        let active_tags = global.get_active_tags(&[], &None).unwrap();
+        let prefix = temp.path().join("prefix");
+        let plan = |target_prefix: Option<PathBuf>| {
+            let cli = CliOptions {
+                target_prefix,
+                ..Default::default()
+            };
+            due_actions(&global, &cli, &active_tags, &ScheduleState::default())
+                .unwrap()
+                .0
+        };
+        let link_dst = |plan: &ExecutionPlan| {
+            plan.iter()
+                .find_map(|(_, action)| match action.as_ref() {
+                    Actions::Link(link) => Some(link.dst().to_path_buf()),
+                    _ => None,
+                })
+                .unwrap()
+        };
+        let prefixed = plan(Some(prefix.clone()));
+        assert!(link_dst(&prefixed).starts_with(&prefix));
+        assert!(
+            prefixed
+                .iter()
+                .all(|(_, action)| action.action_type() != ActionType::Install)
+        );
+        // The prefix of an earlier plan does not leak into the next one
+        let unprefixed = plan(None);
+        assert!(!link_dst(&unprefixed).starts_with(&prefix));
+        assert!(
+            unprefixed
+                .iter()
+                .any(|(_, action)| action.action_type() == ActionType::Install)
+        );
+    }
+}
@@ -0,0 +1,103 @@
+// SPDX-FileCopyrightText: 2025 Karsten Becker
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::commands::cmd_apply::apply_with_tags;
+use crate::common_cli::Verbosity;
+use crate::config::{CliOptions, GlobalConfig, Source, Tag};
+use crate::hermitgrab_error::ApplyError;
+use crate::{LinkType, RequireTag, hermitgrab_info, info};
+
+/// Tags of items that are skipped in minimal mode
+pub const GUI_TAGS: &[&str] = &["gui", "desktop"];
+
+/// Checks whether a file can be created in the directory, which fails for read-only mounts
+fn is_read_only(dir: &Path) -> bool {
+    tempfile::tempfile_in(dir).is_err()
+}
+
+/// Minimal mode is used if it was requested or a container was detected
+fn use_minimal(minimal: bool, detected_tags: &BTreeSet<Tag>) -> bool {
+    let container = RequireTag::Positive(Tag::new("container", Source::BuiltInDetector));
+    minimal || container.matches(detected_tags)
+}
+
+/// Applies the configuration non-interactively, as needed for ephemeral containers like
+/// GitHub Codespaces or devcontainers. In minimal mode, which is used automatically in a
+/// container, items tagged gui or desktop are skipped and links are copied when the hermit
+/// directory is mounted read-only.
+pub async fn bootstrap(
+    global_config: &Arc<GlobalConfig>,
+    tags: &[String],
+    profile: Option<String>,
+    minimal: bool,
+    time_budget: Duration,
+    verbosity: Verbosity,
+    json: Option<PathBuf>,
+) -> Result<(), ApplyError> {
+    let container = global_config
+        .all_detected_tags()
+        .iter()
+        .find(|t| t.name() == "container");
+    match &container {
+        Some(container) => {
+            hermitgrab_info!("Bootstrapping in container {container} with the minimal setup")
+        }
+        None if minimal => info!("No container detected, applying the minimal setup anyway"),
+        None => {}
+    }
+    let minimal = use_minimal(minimal, global_config.all_detected_tags());
+    // Bootstrapping never asks and is time bounded, other options are resolved as for apply
+    let mut cli = CliOptions {
+        confirm: true,
+        install_deadline: Some(Instant::now() + time_budget),
+        ..CliOptions::builder()
+            .verbosity(verbosity)
+            .tags(tags)
+            .profile(profile)
+            .json(json)
+            .build()
+    };
+    if minimal {
+        info!("Skipping items tagged with {}", GUI_TAGS.join(", "));
+        cli.exclude_tags
+            .extend(GUI_TAGS.iter().map(|t| t.to_string()));
+        if is_read_only(global_config.hermit_dir()) {
+            info!(
+                "{} is read-only, copying files instead of linking them",
+                global_config.hermit_dir().display()
+            );
+            cli.link_override = Some(LinkType::Copy);
+        }
+    }
+    apply_with_tags(global_config, &cli, false, false).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_read_only() {
+        let temp = tempfile::TempDir::new().unwrap();
+        assert!(!is_read_only(temp.path()));
+        assert!(is_read_only(&temp.path().join("missing")));
+    }
+
+    #[test]
+    fn test_container_uses_minimal() {
+        let docker = BTreeSet::from([Tag::new_with_value(
+            "container",
+            "docker",
+            Source::BuiltInDetector,
+        )]);
+        assert!(use_minimal(false, &docker));
+        assert!(use_minimal(true, &BTreeSet::new()));
+        assert!(!use_minimal(false, &BTreeSet::new()));
+    }
+}
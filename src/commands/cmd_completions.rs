@@ -0,0 +1,188 @@
+// SPDX-FileCopyrightText: 2025 Karsten Becker
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::path::{Path, PathBuf};
+
+use clap::{Command, CommandFactory};
+use clap_complete::Shell;
+
+use crate::commands::Cli;
+use crate::commands::cmd_hook::HookShell;
+use crate::file_ops::dirs::{HERMIT_STATE_DIR, XDG_CONFIG_HOME, XDG_DATA_HOME};
+use crate::hermitgrab_error::CompletionError;
+use crate::{info, success, warn};
+
+const BIN_NAME: &str = "hermitgrab";
+
+/// The command line used for completions. The arguments of the `ubi` passthrough are added
+/// to the `ubi` subcommand, as far as they don't clash with the global arguments.
+pub fn completion_command() -> Command {
+    let cmd = Cli::command();
+    #[cfg(feature = "ubi")]
+    let cmd = {
+        let globals = cmd
+            .get_arguments()
+            .filter(|a| a.is_global_set())
+            .cloned()
+            .collect::<Vec<_>>();
+        let clashes = |arg: &clap::Arg| {
+            globals.iter().any(|g| {
+                g.get_id() == arg.get_id()
+                    || (g.get_short().is_some() && g.get_short() == arg.get_short())
+                    || (g.get_long().is_some() && g.get_long() == arg.get_long())
+            })
+        };
+        let ubi_args = crate::integrations::ubi_int::cmd()
+            .get_arguments()
+            .filter(|a| !a.is_positional() && !clashes(a))
+            .cloned()
+            .collect::<Vec<_>>();
+        cmd.mut_subcommand("ubi", |ubi| ubi.args(ubi_args))
+    };
+    cmd
+}
+
+/// Writes the completion script for the shell
+pub fn generate_completions(shell: Shell, out: &mut dyn std::io::Write) {
+    clap_complete::generate(shell, &mut completion_command(), BIN_NAME, out);
+}
+
+impl HookShell {
+    fn completion_shell(&self) -> Shell {
+        match self {
+            Self::Bash => Shell::Bash,
+            Self::Zsh => Shell::Zsh,
+            Self::Fish => Shell::Fish,
+        }
+    }
+
+    /// The file the shell loads the completions from
+    pub fn completion_file(&self) -> PathBuf {
+        match self {
+            Self::Bash => PathBuf::from(XDG_DATA_HOME.as_str())
+                .join("bash-completion")
+                .join("completions")
+                .join(BIN_NAME),
+            Self::Zsh => PathBuf::from(XDG_DATA_HOME.as_str())
+                .join("zsh")
+                .join("site-functions")
+                .join(format!("_{BIN_NAME}")),
+            Self::Fish => PathBuf::from(XDG_CONFIG_HOME.as_str())
+                .join("fish")
+                .join("completions")
+                .join(format!("{BIN_NAME}.fish")),
+        }
+    }
+}
+
+/// Directory with the generated scripts, one directory per version of hermitgrab
+fn completions_dir() -> PathBuf {
+    HERMIT_STATE_DIR.join("completions")
+}
+
+fn versioned_script(shell: HookShell) -> PathBuf {
+    let file_name = shell
+        .completion_file()
+        .file_name()
+        .map(|f| f.to_os_string())
+        .unwrap_or_default();
+    completions_dir()
+        .join(env!("CARGO_PKG_VERSION"))
+        .join(file_name)
+}
+
+/// The script a completion file links to, if it is managed by hermitgrab
+fn managed_target(completion_file: &Path) -> Option<PathBuf> {
+    let target = std::fs::read_link(completion_file).ok()?;
+    target.starts_with(completions_dir()).then_some(target)
+}
+
+/// Generates the script for this version and links the completion file of the shell to it
+fn link_completions(shell: HookShell) -> Result<PathBuf, CompletionError> {
+    let completion_file = shell.completion_file();
+    if completion_file.symlink_metadata().is_ok() {
+        if managed_target(&completion_file).is_none() {
+            return Err(CompletionError::Occupied(completion_file));
+        }
+        std::fs::remove_file(&completion_file)?;
+    }
+    let script = versioned_script(shell);
+    if let Some(parent) = script.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut content = Vec::new();
+    generate_completions(shell.completion_shell(), &mut content);
+    std::fs::write(&script, content)?;
+    if let Some(parent) = completion_file.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(&script, &completion_file)?;
+    #[cfg(windows)]
+    std::os::windows::fs::symlink_file(&script, &completion_file)?;
+    Ok(completion_file)
+}
+
+pub fn install_completions(shell: Option<HookShell>) -> Result<(), CompletionError> {
+    let shell = match shell {
+        Some(shell) => shell,
+        None => HookShell::detect()?,
+    };
+    let completion_file = link_completions(shell)?;
+    success!("Installed completions in {}", completion_file.display());
+    if shell == HookShell::Zsh {
+        info!(
+            "Make sure {} is part of $fpath before compinit runs",
+            completion_file
+                .parent()
+                .map(|p| p.display().to_string())
+                .unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+/// Regenerates installed completions that were generated by another version of hermitgrab
+pub fn refresh_completions() {
+    for shell in [HookShell::Bash, HookShell::Zsh, HookShell::Fish] {
+        let Some(target) = managed_target(&shell.completion_file()) else {
+            continue;
+        };
+        if target == versioned_script(shell) && target.exists() {
+            continue;
+        }
+        match link_completions(shell) {
+            Ok(file) => info!("Updated completions in {}", file.display()),
+            Err(e) => {
+                warn!("Failed to update completions for {shell:?}: {e}");
+                continue;
+            }
+        }
+        if target == versioned_script(shell) {
+            continue;
+        }
+        let _ = std::fs::remove_file(&target);
+        if let Some(old_dir) = target.parent()
+            && old_dir != completions_dir()
+            && std::fs::read_dir(old_dir).is_ok_and(|mut d| d.next().is_none())
+        {
+            let _ = std::fs::remove_dir(old_dir);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_completion_script() {
+        let mut script = Vec::new();
+        generate_completions(Shell::Bash, &mut script);
+        let script = String::from_utf8(script).unwrap();
+        assert!(script.contains("hermitgrab__subcmd__completions"));
+        #[cfg(feature = "ubi")]
+        assert!(script.contains("--project"));
+    }
+}
@@ -0,0 +1,84 @@
+// SPDX-FileCopyrightText: 2025 Karsten Becker
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::config::{GlobalConfig, HermitConfig};
+
+/// Sections with a list of items that are annotated with their origin
+const ITEM_SECTIONS: [&str; 4] = ["link", "patch", "install", "fetch"];
+
+/// The configuration with an `origin` of `file:line` added to every item, the file is
+/// relative to the hermit directory
+pub fn config_with_origins(
+    global_config: &GlobalConfig,
+) -> Result<BTreeMap<String, Value>, serde_json::Error> {
+    let hermit_dir = global_config.hermit_dir();
+    let canonical = hermit_dir
+        .canonicalize()
+        .unwrap_or_else(|_| hermit_dir.to_path_buf());
+    let mut config_map = BTreeMap::new();
+    for (config_name, cfg) in global_config.subconfigs() {
+        let file = cfg
+            .hermit_file()
+            .strip_prefix(&canonical)
+            .or_else(|_| cfg.hermit_file().strip_prefix(hermit_dir))
+            .unwrap_or(cfg.hermit_file());
+        config_map.insert(config_name.clone(), annotate(cfg, file)?);
+    }
+    Ok(config_map)
+}
+
+fn annotate(cfg: &HermitConfig, file: &Path) -> Result<Value, serde_json::Error> {
+    let mut value = serde_json::to_value(cfg)?;
+    for section in ITEM_SECTIONS {
+        let Some(Value::Array(items)) = value.get_mut(section) else {
+            continue;
+        };
+        for (index, item) in items.iter_mut().enumerate() {
+            let origin = match cfg.item_line(section, index) {
+                Some(line) => format!("{}:{line}", file.display()),
+                None => file.display().to_string(),
+            };
+            if let Value::Object(map) = item {
+                map.insert("origin".to_string(), Value::String(origin));
+            }
+        }
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{CONF_FILE_NAME, find_hermit_files};
+
+    #[test]
+    fn test_config_with_origins() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let sub = temp.path().join("git");
+        std::fs::create_dir_all(&sub).unwrap();
+        std::fs::write(temp.path().join(CONF_FILE_NAME), "").unwrap();
+        std::fs::write(
+            sub.join(CONF_FILE_NAME),
+            "requires = [\"+work\"]\n\ninstall = [\n  { name = \"git\", install = \"apt install git\" },\n]\n\n[[link]]\nsource = \"gitconfig\"\ntarget = \"~/.gitconfig\"\n\n[[link]]\nsource = \"gitignore\"\ntarget = \"~/.gitignore\"\n",
+        )
+        .unwrap();
+        let paths = find_hermit_files(temp.path(), &[CONF_FILE_NAME]);
+        let global = GlobalConfig::from_paths(temp.path(), &paths).unwrap();
+        let configs = config_with_origins(&global).unwrap();
+        let git = configs
+            .iter()
+            .find(|(name, _)| name.ends_with("git/hermit.toml"))
+            .map(|(_, cfg)| cfg)
+            .unwrap();
+        assert_eq!(git["link"][0]["origin"], "git/hermit.toml:7");
+        assert_eq!(git["link"][1]["origin"], "git/hermit.toml:11");
+        assert_eq!(git["install"][0]["origin"], "git/hermit.toml:4");
+        assert!(git.get("origin").is_none());
+    }
+}
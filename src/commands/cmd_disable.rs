@@ -0,0 +1,68 @@
+// SPDX-FileCopyrightText: 2025 Karsten Becker
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use crate::action::Action;
+use crate::config::{CliOptions, GlobalConfig};
+use crate::execution_plan::create_execution_plan;
+use crate::hermitgrab_error::{ApplyError, StateError};
+use crate::state::DisabledActions;
+use crate::{info, success, warn};
+
+fn list_disabled(disabled: &DisabledActions) {
+    if disabled.ids.is_empty() {
+        info!("No actions are disabled on this machine");
+    }
+    for id in &disabled.ids {
+        info!("{id}");
+    }
+}
+
+/// Disables actions on this machine, they are left out of every plan until they are enabled
+/// again. Without ids the disabled actions are listed.
+pub fn disable(global_config: &Arc<GlobalConfig>, ids: &[String]) -> Result<(), ApplyError> {
+    let mut disabled = DisabledActions::load()?;
+    if ids.is_empty() {
+        list_disabled(&disabled);
+        return Ok(());
+    }
+    let known = create_execution_plan(global_config, &CliOptions::default())?
+        .iter()
+        .map(|(_, action)| action.id())
+        .collect::<BTreeSet<_>>();
+    if let Some(unknown) = ids
+        .iter()
+        .find(|id| !known.contains(*id) && !disabled.ids.contains(*id))
+    {
+        return Err(ApplyError::UnknownAction(unknown.clone()));
+    }
+    for id in ids {
+        if disabled.ids.insert(id.clone()) {
+            success!("Disabled {id} on this machine");
+        } else {
+            info!("{id} is already disabled");
+        }
+    }
+    disabled.save()?;
+    Ok(())
+}
+
+/// Enables previously disabled actions again
+pub fn enable(ids: &[String]) -> Result<(), StateError> {
+    let mut disabled = DisabledActions::load()?;
+    if ids.is_empty() {
+        list_disabled(&disabled);
+        return Ok(());
+    }
+    for id in ids {
+        if disabled.ids.remove(id) {
+            success!("Enabled {id} on this machine");
+        } else {
+            warn!("{id} is not disabled");
+        }
+    }
+    disabled.save()
+}
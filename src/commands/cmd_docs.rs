@@ -0,0 +1,254 @@
+// SPDX-FileCopyrightText: 2025 Karsten Becker
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use itertools::Itertools;
+
+use crate::action::FileOrText;
+use crate::config::{
+    DetectorConfig, GlobalConfig, HermitConfig, RequireTag, SourceSpecOrPath, TargetSpec,
+};
+use crate::success;
+
+/// Escapes a value for a Markdown table cell
+fn cell(value: &str) -> String {
+    if value.is_empty() {
+        return String::new();
+    }
+    format!("`{}`", value.replace('|', "\\|").replace('\n', " "))
+}
+
+fn requires(requires: &BTreeSet<RequireTag>) -> String {
+    cell(&requires.iter().join(" "))
+}
+
+fn target(target: &TargetSpec) -> String {
+    match target {
+        TargetSpec::Path(path) => cell(&path.display().to_string()),
+        TargetSpec::PerTag(map) => map
+            .iter()
+            .map(|(tag, path)| format!("{tag}: {}", cell(&path.display().to_string())))
+            .join("<br>"),
+    }
+}
+
+/// Links a source file relative to the root of the repository
+fn source(source: &SourceSpecOrPath, dir: &Path) -> String {
+    let file = match source {
+        SourceSpecOrPath::Path(path) => path,
+        SourceSpecOrPath::SourceSpec(spec) => match &spec.source {
+            FileOrText::File { file } => file,
+            FileOrText::Text { .. } => return "inline text".to_string(),
+            FileOrText::Snippet { snippet } => return format!("snippet {}", cell(snippet)),
+        },
+    };
+    if file.is_absolute() || file.starts_with("~") {
+        return cell(&file.display().to_string());
+    }
+    let link = dir.join(file).to_string_lossy().replace('\\', "/");
+    format!("[{}]({link})", file.display())
+}
+
+fn detector(detector: &DetectorConfig) -> String {
+    match detector {
+        DetectorConfig::EnableIf { enable_if } => format!("enabled if {}", cell(enable_if)),
+        DetectorConfig::EnableIfNot { enable_if_not } => {
+            format!("enabled if not {}", cell(enable_if_not))
+        }
+        DetectorConfig::ValueOf { value_of } => format!("value of {}", cell(value_of)),
+    }
+}
+
+/// The tags used by the configuration, without the required state
+fn used_tags(cfg: &HermitConfig) -> BTreeSet<String> {
+    cfg.requires
+        .iter()
+        .chain(cfg.config_items().flat_map(|item| item.requires()))
+        .map(|r| r.name().to_string())
+        .chain(cfg.detectors.keys().cloned())
+        .collect()
+}
+
+fn config_section(out: &mut String, cfg: &HermitConfig, dir: &Path) -> std::fmt::Result {
+    let file = dir.join(cfg.hermit_file().file_name().unwrap_or_default());
+    writeln!(out, "## {}\n", file.display())?;
+    if !cfg.requires.is_empty() {
+        writeln!(out, "Requires {}\n", requires(&cfg.requires))?;
+    }
+    let tags = used_tags(cfg);
+    if !tags.is_empty() {
+        writeln!(out, "Tags: {}\n", tags.iter().map(|t| cell(t)).join(", "))?;
+    }
+    if !cfg.link.is_empty() {
+        writeln!(out, "### Links\n")?;
+        writeln!(out, "| Source | Target | Type | Requires |")?;
+        writeln!(out, "|---|---|---|---|")?;
+        for link in &cfg.link {
+            writeln!(
+                out,
+                "| {} | {} | {} | {} |",
+                source(&link.source, dir),
+                target(&link.target),
+                link.link,
+                requires(&link.requires)
+            )?;
+        }
+        writeln!(out)?;
+    }
+    if !cfg.patch.is_empty() {
+        writeln!(out, "### Patches\n")?;
+        writeln!(out, "| Source | Target | Type | Requires |")?;
+        writeln!(out, "|---|---|---|---|")?;
+        for patch in &cfg.patch {
+            writeln!(
+                out,
+                "| {} | {} | {} | {} |",
+                source(&patch.source, dir),
+                cell(&patch.target.display().to_string()),
+                patch.patch_type,
+                requires(&patch.requires)
+            )?;
+        }
+        writeln!(out)?;
+    }
+    if !cfg.install.is_empty() {
+        writeln!(out, "### Installs\n")?;
+        writeln!(out, "| Name | Check | Requires |")?;
+        writeln!(out, "|---|---|---|")?;
+        for install in &cfg.install {
+            let check = install
+                .check
+                .as_ref()
+                .map(|c| c.commands().iter().map(|c| cell(c)).join("<br>"))
+                .unwrap_or_default();
+            writeln!(
+                out,
+                "| {} | {check} | {} |",
+                install.name,
+                requires(&install.requires)
+            )?;
+        }
+        writeln!(out)?;
+    }
+    if !cfg.fetch.is_empty() {
+        writeln!(out, "### Fetches\n")?;
+        writeln!(out, "| Url | Target | Requires |")?;
+        writeln!(out, "|---|---|---|")?;
+        for fetch in &cfg.fetch {
+            writeln!(
+                out,
+                "| {} | {} | {} |",
+                fetch.url,
+                cell(&fetch.target.display().to_string()),
+                requires(&fetch.requires)
+            )?;
+        }
+        writeln!(out)?;
+    }
+    if !cfg.profiles.is_empty() {
+        writeln!(out, "### Profiles\n")?;
+        for (name, tags) in &cfg.profiles {
+            writeln!(
+                out,
+                "- **{name}**: {}",
+                tags.iter().map(|t| cell(&t.to_string())).join(", ")
+            )?;
+        }
+        writeln!(out)?;
+    }
+    if !cfg.detectors.is_empty() {
+        writeln!(out, "### Detectors\n")?;
+        for (name, config) in &cfg.detectors {
+            writeln!(out, "- **{name}**: {}", detector(config))?;
+        }
+        writeln!(out)?;
+    }
+    if !cfg.snippets.is_empty() {
+        writeln!(out, "### Snippets\n")?;
+        writeln!(out, "{}\n", cfg.snippets.keys().map(|s| cell(s)).join(", "))?;
+    }
+    Ok(())
+}
+
+/// Renders a Markdown overview of all configuration files, links are relative to the
+/// hermit directory
+pub fn repo_overview(global_config: &GlobalConfig) -> String {
+    let hermit_dir = global_config.hermit_dir();
+    let canonical = hermit_dir
+        .canonicalize()
+        .unwrap_or_else(|_| hermit_dir.to_path_buf());
+    let configs = global_config
+        .subconfigs()
+        .into_iter()
+        .map(|(_, cfg)| {
+            let dir = cfg
+                .directory()
+                .strip_prefix(&canonical)
+                .or_else(|_| cfg.directory().strip_prefix(hermit_dir))
+                .map(Path::to_path_buf)
+                .unwrap_or_else(|_| cfg.directory().to_path_buf());
+            (dir, cfg)
+        })
+        .sorted_by(|(a, _), (b, _)| a.cmp(b))
+        .collect::<Vec<(PathBuf, _)>>();
+    let mut out = String::from("# Dotfiles\n\n");
+    out.push_str("Generated by `hermitgrab docs repo`, do not edit by hand.\n\n");
+    for (dir, cfg) in &configs {
+        config_section(&mut out, cfg, dir).expect("Writing to a String can not fail");
+    }
+    out
+}
+
+/// Writes the overview of the repository to `output` or prints it
+pub fn docs_repo(
+    global_config: &Arc<GlobalConfig>,
+    output: Option<&Path>,
+) -> Result<(), std::io::Error> {
+    let overview = repo_overview(global_config);
+    match output {
+        Some(output) => {
+            std::fs::write(output, overview)?;
+            success!("Wrote the overview to {}", output.display());
+        }
+        None => print!("{overview}"),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{CONF_FILE_NAME, find_hermit_files};
+
+    #[test]
+    fn test_repo_overview() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let sub = temp.path().join("git");
+        std::fs::create_dir_all(&sub).unwrap();
+        std::fs::write(
+            temp.path().join(CONF_FILE_NAME),
+            "[profiles]\nwork = [\"work\"]\n\n[detectors]\nwork = { enable_if = \"test -d ~/work\" }\n",
+        )
+        .unwrap();
+        std::fs::write(
+            sub.join(CONF_FILE_NAME),
+            "requires = [\"+work\"]\n\n[[link]]\nsource = \"gitconfig\"\ntarget = \"~/.gitconfig\"\n\n[[install]]\nname = \"git\"\ncheck = \"which git\"\ninstall = \"apt install git\"\n",
+        )
+        .unwrap();
+        let paths = find_hermit_files(temp.path(), &[CONF_FILE_NAME]);
+        let global = GlobalConfig::from_paths(temp.path(), &paths).unwrap();
+        let overview = repo_overview(&global);
+        assert!(overview.contains("## hermit.toml\n"), "{overview}");
+        assert!(overview.contains("- **work**: `work`\n"), "{overview}");
+        assert!(overview.contains("- **work**: enabled if `test -d ~/work`\n"));
+        assert!(overview.contains("## git/hermit.toml\n\nRequires `+work`\n"));
+        assert!(overview.contains("| [gitconfig](git/gitconfig) | `~/.gitconfig` | soft |  |\n"));
+        assert!(overview.contains("| git | `which git` |  |\n"));
+        assert!(overview.find("## hermit.toml").unwrap() < overview.find("## git/").unwrap());
+    }
+}
@@ -0,0 +1,192 @@
+// SPDX-FileCopyrightText: 2025 Karsten Becker
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use git2::Repository;
+use serde::Serialize;
+
+use crate::common_cli::write_json;
+use crate::config::{CliOptions, GlobalConfig};
+use crate::execution_plan::create_execution_plan;
+use crate::hermitgrab_error::DoctorError;
+use crate::{error, info, success, warn};
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+/// A single problem found by the doctor
+#[derive(Debug, Clone, Serialize)]
+pub struct Finding {
+    /// Stable identifier of the check that produced the finding
+    pub id: &'static str,
+    pub severity: Severity,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suggested_fix: Option<String>,
+}
+
+impl Finding {
+    fn new(id: &'static str, severity: Severity, message: impl Into<String>) -> Self {
+        Self {
+            id,
+            severity,
+            message: message.into(),
+            file: None,
+            suggested_fix: None,
+        }
+    }
+
+    fn file(mut self, file: Option<&Path>) -> Self {
+        self.file = file.map(Path::to_path_buf);
+        self
+    }
+
+    fn fix(mut self, fix: impl Into<String>) -> Self {
+        self.suggested_fix = Some(fix.into());
+        self
+    }
+}
+
+/// Runs all checks and returns the findings, most severe first
+pub fn diagnose(global_config: &Arc<GlobalConfig>) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let hermit_dir = global_config.hermit_dir();
+    if !hermit_dir.exists() {
+        findings.push(
+            Finding::new(
+                "hermit-dir-missing",
+                Severity::Error,
+                format!(
+                    "The hermit directory {} does not exist",
+                    hermit_dir.display()
+                ),
+            )
+            .file(Some(hermit_dir))
+            .fix("Run `hermitgrab init clone <repo>` or `hermitgrab init create`"),
+        );
+    } else if let Ok(repo) = Repository::open(hermit_dir) {
+        let dirty = repo
+            .statuses(None)
+            .map(|s| s.iter().filter(|e| !e.status().is_ignored()).count())
+            .unwrap_or_default();
+        if dirty > 0 {
+            findings.push(
+                Finding::new(
+                    "uncommitted-changes",
+                    Severity::Info,
+                    format!("The hermit directory has {dirty} uncommitted change(s)"),
+                )
+                .file(Some(hermit_dir))
+                .fix("Commit and push the changes to share them with your other machines"),
+            );
+        }
+    } else {
+        findings.push(
+            Finding::new(
+                "not-a-git-repo",
+                Severity::Warning,
+                format!(
+                    "The hermit directory {} is not a git repository",
+                    hermit_dir.display()
+                ),
+            )
+            .file(Some(hermit_dir))
+            .fix("Run `git init` in the hermit directory to track your dotfiles"),
+        );
+    }
+    for e in global_config.load_errors() {
+        findings.push(
+            Finding::new("config-load", Severity::Error, e.to_string())
+                .file(e.path())
+                .fix("Fix the file, items of a file that fails to load are skipped"),
+        );
+    }
+    for problem in global_config.snippet_problems() {
+        findings.push(
+            Finding::new("snippets", Severity::Error, problem)
+                .fix("Check the snippet names used in {{ snippet <name> }}"),
+        );
+    }
+    if let Err(e) = global_config.get_active_tags(&[], &None) {
+        findings.push(
+            Finding::new("active-tags", Severity::Error, e.to_string())
+                .fix("Check the detectors and tag aliases of the configuration"),
+        );
+    }
+    if let Err(e) = create_execution_plan(global_config, &CliOptions::default()) {
+        findings.push(
+            Finding::new("execution-plan", Severity::Error, e.to_string())
+                .fix("Check the sources and targets of the affected action"),
+        );
+    }
+    findings.sort_by(|a, b| b.severity.cmp(&a.severity).then(a.id.cmp(b.id)));
+    findings
+}
+
+/// Reports all findings, as JSON if a path is given (`-` for stdout).
+/// Fails if any finding has error severity.
+pub fn doctor(global_config: &Arc<GlobalConfig>, json: Option<&Path>) -> Result<(), DoctorError> {
+    let findings = diagnose(global_config);
+    match json {
+        Some(path) => {
+            write_json(path, &findings)?;
+            info!("Findings written to {}", path.display());
+        }
+        None => {
+            if findings.is_empty() {
+                success!("No problems found in the configuration");
+            }
+            for finding in &findings {
+                let file = finding
+                    .file
+                    .as_ref()
+                    .map(|f| format!(" ({})", f.display()))
+                    .unwrap_or_default();
+                match finding.severity {
+                    Severity::Error => error!("[{}] {}{file}", finding.id, finding.message),
+                    Severity::Warning => warn!("[{}] {}{file}", finding.id, finding.message),
+                    Severity::Info => info!("[{}] {}{file}", finding.id, finding.message),
+                }
+                if let Some(fix) = &finding.suggested_fix {
+                    info!("  Suggested fix: {fix}");
+                }
+            }
+        }
+    }
+    let errors = findings
+        .iter()
+        .filter(|f| f.severity == Severity::Error)
+        .count();
+    if errors > 0 {
+        return Err(DoctorError::Findings(errors));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_hermit_dir() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let hermit_dir = temp.path().join("missing");
+        let global_config = GlobalConfig::from_paths(&hermit_dir, &[]).unwrap();
+        let findings = diagnose(&global_config);
+        assert_eq!(findings[0].id, "hermit-dir-missing");
+        assert_eq!(findings[0].severity, Severity::Error);
+        let json = serde_json::to_value(&findings[0]).unwrap();
+        assert_eq!(json["severity"], "error");
+        assert!(json["suggested_fix"].is_string());
+    }
+}
@@ -0,0 +1,85 @@
+// SPDX-FileCopyrightText: 2025 Karsten Becker
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::path::Path;
+
+use crate::common_cli::write_json;
+use crate::config::schedule::Schedule;
+use crate::file_ops::human_duration;
+use crate::hermitgrab_error::StateError;
+use crate::state::{HistoryEntry, now};
+use crate::{error, info, skipped, success};
+
+/// Prints the most recent apply and status runs, optionally only those within `since`
+pub fn list_history(
+    limit: usize,
+    since: Option<Schedule>,
+    json: Option<&Path>,
+) -> Result<(), StateError> {
+    let now = now();
+    let entries = HistoryEntry::list()?
+        .into_iter()
+        .filter(|e| since.is_none_or(|s| now.saturating_sub(e.timestamp) <= s.interval().as_secs()))
+        .take(limit)
+        .collect::<Vec<_>>();
+    if let Some(json_path) = json {
+        let file = json_path.to_path_buf();
+        write_json(json_path, &entries).map_err(|e| StateError::Io(e, file))?;
+        return Ok(());
+    }
+    if entries.is_empty() {
+        info!("No runs recorded in the history");
+    }
+    for entry in &entries {
+        let line = format!(
+            "{}  {:>9}  {:<6}  {:<12}  {} ok, {} skipped, {} failed",
+            entry.id,
+            ago(now.saturating_sub(entry.timestamp)),
+            entry.command,
+            entry.profile.as_deref().unwrap_or("-"),
+            entry.ok,
+            entry.skipped,
+            entry.failed
+        );
+        if entry.failed == 0 {
+            success!("{line}");
+        } else {
+            error!("{line}");
+        }
+    }
+    Ok(())
+}
+
+/// Prints the results of a single recorded run
+pub fn show_history(id: &str, json: Option<&Path>) -> Result<(), StateError> {
+    let entry = HistoryEntry::load(id)?;
+    if let Some(json_path) = json {
+        let file = json_path.to_path_buf();
+        return write_json(json_path, &entry).map_err(|e| StateError::Io(e, file));
+    }
+    info!(
+        "{} {} with profile {} and tags {}",
+        entry.command,
+        ago(now().saturating_sub(entry.timestamp)),
+        entry.profile.as_deref().unwrap_or("-"),
+        entry.tags.join(", ")
+    );
+    info!("Plan hash: {}", entry.plan_hash);
+    for result in &entry.results {
+        match (&result.message, result.ok) {
+            (_, true) if let Some(reason) = &result.skipped => {
+                skipped!("{}: {reason}", result.description)
+            }
+            (_, true) => success!("{}", result.description),
+            (Some(message), false) => error!("{}: {message}", result.description),
+            (None, false) => error!("{}", result.description),
+        }
+    }
+    Ok(())
+}
+
+/// Formats an age in seconds with its largest unit, like `3d ago` or `5m ago`
+fn ago(secs: u64) -> String {
+    format!("{} ago", human_duration(secs))
+}
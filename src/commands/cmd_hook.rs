@@ -0,0 +1,229 @@
+// SPDX-FileCopyrightText: 2025 Karsten Becker
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::path::PathBuf;
+
+use clap::ValueEnum;
+
+use crate::action::patch::write_contents;
+use crate::file_ops::dirs::{BASE_DIRS, HERMIT_EXE, HERMIT_STATE_DIR, XDG_CONFIG_HOME};
+use crate::hermitgrab_error::HookError;
+use crate::{info, success, warn};
+
+const HOOK_START: &str = "# >>> hermitgrab hook >>>";
+const HOOK_END: &str = "# <<< hermitgrab hook <<<";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum HookShell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl HookShell {
+    /// Determine the shell from the $SHELL environment variable
+    pub fn detect() -> Result<Self, HookError> {
+        let shell = std::env::var("SHELL").unwrap_or_default();
+        let name = shell.rsplit('/').next().unwrap_or_default();
+        match name {
+            "bash" => Ok(Self::Bash),
+            "zsh" => Ok(Self::Zsh),
+            "fish" => Ok(Self::Fish),
+            _ => Err(HookError::UnknownShell(shell)),
+        }
+    }
+
+    /// The file the hook is written to. For fish a dedicated file in conf.d is used,
+    /// the other shells get a marked block in their rc file.
+    pub fn hook_file(&self) -> PathBuf {
+        match self {
+            Self::Bash => BASE_DIRS.home_dir().join(".bashrc"),
+            Self::Zsh => std::env::var("ZDOTDIR")
+                .map(PathBuf::from)
+                .unwrap_or_else(|_| BASE_DIRS.home_dir().to_path_buf())
+                .join(".zshrc"),
+            Self::Fish => PathBuf::from(XDG_CONFIG_HOME.as_str())
+                .join("fish")
+                .join("conf.d")
+                .join("hermitgrab.fish"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct HookOptions {
+    /// Run apply instead of status
+    pub apply: bool,
+    /// Profile to pass to the command
+    pub profile: Option<String>,
+    /// Minimum number of minutes between two runs
+    pub interval: u64,
+    /// Executable the hook runs, defaults to the running one
+    pub exe: Option<PathBuf>,
+}
+
+impl HookOptions {
+    fn command(&self) -> Result<String, HookError> {
+        let exe = self.exe.as_deref().unwrap_or(&HERMIT_EXE);
+        let mut cmd = format!("\"{}\"", exe.display());
+        if self.apply {
+            cmd.push_str(" apply --confirm");
+        } else {
+            cmd.push_str(" status --quiet");
+        }
+        if let Some(profile) = &self.profile {
+            let quoted = shlex::try_quote(profile)
+                .map_err(|_| HookError::InvalidProfile(profile.clone()))?;
+            cmd.push_str(&format!(" --profile {quoted}"));
+        }
+        Ok(cmd)
+    }
+}
+
+pub fn render_hook(shell: HookShell, options: &HookOptions) -> Result<String, HookError> {
+    let stamp = HERMIT_STATE_DIR.join("hook.stamp");
+    let stamp = stamp.display();
+    let interval = options.interval;
+    let cmd = options.command()?;
+    let hook = match shell {
+        HookShell::Bash | HookShell::Zsh => format!(
+            r#"{HOOK_START}
+__hermit_stamp="{stamp}"
+if [ ! -f "$__hermit_stamp" ] || [ -n "$(find "$__hermit_stamp" -mmin +{interval} 2>/dev/null)" ]; then
+  mkdir -p "$(dirname "$__hermit_stamp")" && touch "$__hermit_stamp"
+  {cmd}
+fi
+unset __hermit_stamp
+{HOOK_END}
+"#
+        ),
+        HookShell::Fish => format!(
+            r#"{HOOK_START}
+if status is-login; or status is-interactive
+  set -l __hermit_stamp "{stamp}"
+  if not test -f $__hermit_stamp; or test -n "$(find $__hermit_stamp -mmin +{interval} 2>/dev/null)"
+    mkdir -p (dirname $__hermit_stamp); and touch $__hermit_stamp
+    {cmd}
+  end
+end
+{HOOK_END}
+"#
+        ),
+    };
+    Ok(hook)
+}
+
+/// Removes an existing hook block from the given content
+fn strip_hook(content: &str) -> (String, bool) {
+    let Some(start) = content.find(HOOK_START) else {
+        return (content.to_string(), false);
+    };
+    let Some(end) = content[start..].find(HOOK_END) else {
+        return (content.to_string(), false);
+    };
+    let end = start + end + HOOK_END.len();
+    let end = if content[end..].starts_with('\n') {
+        end + 1
+    } else {
+        end
+    };
+    (format!("{}{}", &content[..start], &content[end..]), true)
+}
+
+pub fn install_hook(shell: Option<HookShell>, options: &HookOptions) -> Result<(), HookError> {
+    let shell = match shell {
+        Some(shell) => shell,
+        None => HookShell::detect()?,
+    };
+    let hook_file = shell.hook_file();
+    let existing = if hook_file.exists() {
+        std::fs::read_to_string(&hook_file)?
+    } else {
+        String::new()
+    };
+    let (mut content, replaced) = strip_hook(&existing);
+    if !content.is_empty() && !content.ends_with('\n') {
+        content.push('\n');
+    }
+    content.push_str(&render_hook(shell, options)?);
+    write_contents(&hook_file, content)?;
+    if replaced {
+        success!("Updated hermitgrab hook in {}", hook_file.display());
+    } else {
+        success!("Installed hermitgrab hook in {}", hook_file.display());
+    }
+    info!("The hook runs at most every {} minutes", options.interval);
+    Ok(())
+}
+
+pub fn remove_hook(shell: Option<HookShell>) -> Result<(), HookError> {
+    let shell = match shell {
+        Some(shell) => shell,
+        None => HookShell::detect()?,
+    };
+    let hook_file = shell.hook_file();
+    if !hook_file.exists() {
+        warn!("No hook file found at {}", hook_file.display());
+        return Ok(());
+    }
+    let existing = std::fs::read_to_string(&hook_file)?;
+    let (content, removed) = strip_hook(&existing);
+    if !removed {
+        warn!("No hermitgrab hook found in {}", hook_file.display());
+        return Ok(());
+    }
+    if shell == HookShell::Fish && content.trim().is_empty() {
+        std::fs::remove_file(&hook_file)?;
+    } else {
+        write_contents(&hook_file, content)?;
+    }
+    success!("Removed hermitgrab hook from {}", hook_file.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_hook_roundtrip() {
+        let options = HookOptions {
+            interval: 60,
+            ..Default::default()
+        };
+        let original = "export FOO=bar\n";
+        let with_hook = format!(
+            "{original}{}",
+            render_hook(HookShell::Bash, &options).unwrap()
+        );
+        assert!(with_hook.contains("status --quiet"));
+        let (stripped, removed) = strip_hook(&with_hook);
+        assert!(removed);
+        assert_eq!(stripped, original);
+        let (unchanged, removed) = strip_hook(original);
+        assert!(!removed);
+        assert_eq!(unchanged, original);
+    }
+
+    #[test]
+    fn test_hook_quotes_profile() {
+        let options = HookOptions {
+            profile: Some("work\"; rm -rf ~; echo \"".to_string()),
+            exe: Some("/usr/bin/hermit".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            options.command().unwrap(),
+            "\"/usr/bin/hermit\" status --quiet --profile 'work\"; rm -rf ~; echo \"'"
+        );
+        let options = HookOptions {
+            profile: Some("work\0".to_string()),
+            ..Default::default()
+        };
+        assert!(matches!(
+            options.command(),
+            Err(HookError::InvalidProfile(_))
+        ));
+    }
+}
@@ -3,24 +3,128 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use anyhow::Result;
 use git2::{Cred, RemoteCallbacks, Repository};
 use oauth2::http::header::ACCEPT;
-use octocrab::Octocrab;
+use octocrab::models::Repository as GitHubRepository;
+use octocrab::{Octocrab, Page};
 use secrecy::{ExposeSecret, SecretBox};
+use serde::Serialize;
 
 use crate::common_cli::success;
 use crate::config::GlobalConfig;
+use crate::config::user_settings::{GitSettings, UserSettings};
 use crate::hermitgrab_error::DiscoverError;
-use crate::{hermitgrab_info, info, prompt, success, warn};
+use crate::integrations::http::{self, GitHubAuth};
+use crate::{hermitgrab_info, info, prompt, step, success, warn};
 
-pub fn clone_or_update_repo(
-    repo: &str,
-    token: Option<&str>,
-    global_config: &Arc<GlobalConfig>,
-) -> Result<(), DiscoverError> {
-    let hermit_dir = global_config.hermit_dir();
+/// Retries and mirrors of network git operations, configured in the `[git]` user settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct GitRetry {
+    /// Additional attempts per URL after a transient failure
+    pub retries: u32,
+    /// Delay before the first retry, doubled for every further retry
+    pub backoff: Duration,
+    /// URLs tried in order after the primary URL failed
+    pub mirrors: Vec<String>,
+}
+
+impl Default for GitRetry {
+    fn default() -> Self {
+        Self {
+            retries: 2,
+            backoff: Duration::from_millis(500),
+            mirrors: Vec::new(),
+        }
+    }
+}
+
+/// The remote a git operation finally succeeded with
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct GitOutcome {
+    pub operation: &'static str,
+    pub remote: String,
+    /// Attempts over all remotes, including the successful one
+    pub attempts: u32,
+}
+
+impl GitRetry {
+    pub fn from_settings(settings: &GitSettings) -> Self {
+        let default = Self::default();
+        Self {
+            retries: settings.retries.unwrap_or(default.retries),
+            backoff: settings
+                .backoff_ms
+                .map(Duration::from_millis)
+                .unwrap_or(default.backoff),
+            mirrors: settings.mirrors.clone(),
+        }
+    }
+
+    /// The retry behavior of the user settings
+    pub fn load() -> Self {
+        let settings = UserSettings::load().unwrap_or_else(|e| {
+            warn!("Ignoring unreadable user settings: {e}");
+            UserSettings::default()
+        });
+        Self::from_settings(&settings.git)
+    }
+
+    /// Runs `op` with the URL and then with the mirrors until it succeeds. Transient network
+    /// errors are retried with exponential backoff, other errors continue with the next URL.
+    pub fn run<T>(
+        &self,
+        operation: &'static str,
+        url: &str,
+        mut op: impl FnMut(&str) -> Result<T, git2::Error>,
+    ) -> Result<(GitOutcome, T), git2::Error> {
+        let mut attempts = 0;
+        let mut last_error = None;
+        for url in std::iter::once(url).chain(self.mirrors.iter().map(String::as_str)) {
+            for retry in 0..=self.retries {
+                if retry > 0 {
+                    let delay = self.backoff * 2u32.saturating_pow(retry - 1);
+                    info!("Retrying {operation} of {url} in {delay:?}");
+                    std::thread::sleep(delay);
+                }
+                attempts += 1;
+                match op(url) {
+                    Ok(value) => {
+                        let outcome = GitOutcome {
+                            operation,
+                            remote: url.to_string(),
+                            attempts,
+                        };
+                        return Ok((outcome, value));
+                    }
+                    Err(e) => {
+                        warn!("Failed to {operation} {url}: {}", e.message());
+                        let transient = is_transient(&e);
+                        last_error = Some(e);
+                        if !transient {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        Err(last_error.expect("The primary URL is always tried"))
+    }
+}
+
+/// Network failures that may succeed when tried again, authentication errors are not retried
+fn is_transient(error: &git2::Error) -> bool {
+    use git2::ErrorClass;
+    error.code() != git2::ErrorCode::Auth
+        && matches!(
+            error.class(),
+            ErrorClass::Net | ErrorClass::Http | ErrorClass::Ssh | ErrorClass::Ssl | ErrorClass::Os
+        )
+}
+
+fn fetch_options(token: Option<&str>) -> git2::FetchOptions<'_> {
     let mut callbacks = RemoteCallbacks::new();
     if let Some(token) = token {
         callbacks.credentials(|_url, username_from_url, _allowed_types| {
@@ -30,37 +134,101 @@ pub fn clone_or_update_repo(
         todo!("Implement SSH key authentication or other methods if token is not provided");
     }
     let mut fetch_opts = git2::FetchOptions::new();
+    http::git_options(&mut fetch_opts, &mut callbacks);
     fetch_opts.remote_callbacks(callbacks);
-    if hermit_dir.exists() {
+    fetch_opts
+}
+
+pub fn clone_or_update_repo(
+    repo: &str,
+    token: Option<&str>,
+    global_config: &Arc<GlobalConfig>,
+    retry: &GitRetry,
+) -> Result<GitOutcome, DiscoverError> {
+    let hermit_dir = global_config.hermit_dir();
+    let outcome = if hermit_dir.exists() {
         info!("Updating existing repo at {}", hermit_dir.display());
-        let repo = Repository::open(hermit_dir)?;
-        let mut remote = repo.find_remote("origin")?;
-        remote.fetch(&["main"], Some(&mut fetch_opts), None)?;
+        let local = Repository::open(hermit_dir)?;
+        let origin = local.find_remote("origin")?;
+        let origin_url = origin.url().unwrap_or(repo).to_string();
+        let (outcome, _) = retry.run("fetch", &origin_url, |url| {
+            let mut remote = if url == origin_url {
+                local.find_remote("origin")?
+            } else {
+                local.remote_anonymous(url)?
+            };
+            remote.fetch(
+                &["+refs/heads/main:refs/remotes/origin/main"],
+                Some(&mut fetch_options(token)),
+                None,
+            )
+        })?;
+        outcome
     } else {
         info!("Cloning {} into {}", &repo, hermit_dir.display());
-        let mut builder = git2::build::RepoBuilder::new();
-        builder
-            .fetch_options(fetch_opts)
-            .branch("main")
-            .clone(repo, hermit_dir)?;
+        let (outcome, _) = retry.run("clone", repo, |url| {
+            let result = git2::build::RepoBuilder::new()
+                .fetch_options(fetch_options(token))
+                .branch("main")
+                .clone(url, hermit_dir);
+            if result.is_err() && hermit_dir.exists() {
+                // A failed clone leaves a partial checkout behind that blocks the next attempt
+                let _ = std::fs::remove_dir_all(hermit_dir);
+            }
+            result
+        })?;
         success!("Cloned repository to {}", hermit_dir.display());
+        outcome
+    };
+    if outcome.attempts > 1 || outcome.remote != repo {
+        info!(
+            "The {} succeeded with {} after {} attempt(s)",
+            outcome.operation, outcome.remote, outcome.attempts
+        );
     }
-    Ok(())
+    Ok(outcome)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct GitHubDiscoverOptions {
+    /// A token to use instead of the device authentication
+    pub token: Option<String>,
+    /// Query the search API by topic instead of listing all repositories
+    pub search: bool,
+    /// Only consider repositories of this organization
+    pub org: Option<String>,
+    /// Select the repository with this name or full name
+    pub name: Option<String>,
+}
+
+fn matches_repo_name(repo: &GitHubRepository, name: &str) -> bool {
+    repo.name == name || repo.full_name.as_deref() == Some(name)
 }
 
 pub async fn discover_repo_with_github(
     create: bool,
-    token: Option<String>,
+    options: GitHubDiscoverOptions,
     global_config: &Arc<GlobalConfig>,
 ) -> Result<(), DiscoverError> {
     hermitgrab_info!("Discovering dotfiles repository...");
-    let (octocrab, token) = if let Some(token) = token {
-        let octocrab = Octocrab::builder().personal_token(token.clone()).build()?;
+    let name = match options.name {
+        Some(name) => Some(name),
+        None => UserSettings::load()?.default_repo,
+    };
+    let (octocrab, token) = if let Some(token) = options.token {
+        let octocrab = http::github(GitHubAuth::Token(token.clone()), None, &[])?;
         (octocrab, token)
     } else {
         github_device_flow_auth().await?
     };
-    let found_repos = github_find_hermitgrab_topic_repos(&octocrab).await?;
+    let mut found_repos =
+        github_find_hermitgrab_topic_repos(&octocrab, options.search, options.org.as_deref())
+            .await?;
+    found_repos.sort_by(|a, b| a.full_name.cmp(&b.full_name));
+    if let Some(name) = &name {
+        info!("Looking for repository {name}");
+        found_repos.retain(|repo| matches_repo_name(repo, name));
+    }
 
     if found_repos.is_empty() {
         if create {
@@ -74,7 +242,11 @@ pub async fn discover_repo_with_github(
 
     hermitgrab_info!("Found the following repositories:");
     for (i, repo) in found_repos.iter().enumerate() {
-        info!("{}: {:?}", i + 1, repo.name);
+        info!(
+            "{}: {}",
+            i + 1,
+            repo.full_name.as_deref().unwrap_or(&repo.name)
+        );
     }
 
     let selected_repo = if found_repos.len() == 1 {
@@ -91,34 +263,150 @@ pub async fn discover_repo_with_github(
     };
 
     if let Some(clone_url) = &selected_repo.clone_url {
-        clone_or_update_repo(clone_url.as_ref(), Some(&token), global_config)?;
+        clone_or_update_repo(
+            clone_url.as_ref(),
+            Some(&token),
+            global_config,
+            &GitRetry::load(),
+        )?;
     } else {
         return Err(DiscoverError::NoGitCloneUrl(selected_repo.name.to_string()));
     }
     Ok(())
 }
 
+/// Longest time to wait for a GitHub rate limit to reset before giving up
+const MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(300);
+
+fn is_hermitgrab_repo(repo: &GitHubRepository) -> bool {
+    if let Some(ref topics) = repo.topics
+        && topics.iter().any(|t| t.to_lowercase() == "hermitgrab")
+    {
+        return true;
+    }
+    repo.name == "dotfiles"
+}
+
+/// Waits for the rate limit to reset when the error was caused by it.
+/// Returns true if the request should be retried.
+async fn wait_for_rate_limit(octocrab: &Octocrab, err: &octocrab::Error) -> bool {
+    let octocrab::Error::GitHub { source, .. } = err else {
+        return false;
+    };
+    if !matches!(source.status_code.as_u16(), 403 | 429) {
+        return false;
+    }
+    let Ok(limits) = octocrab.ratelimit().get().await else {
+        return false;
+    };
+    let Some(reset) = [limits.resources.core, limits.resources.search]
+        .iter()
+        .filter(|rate| rate.remaining == 0)
+        .map(|rate| rate.reset)
+        .max()
+    else {
+        return false;
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let wait = Duration::from_secs(reset.saturating_sub(now) + 1);
+    if wait > MAX_RATE_LIMIT_WAIT {
+        warn!(
+            "GitHub rate limit exceeded, it resets in {} minutes",
+            wait.as_secs() / 60
+        );
+        return false;
+    }
+    warn!(
+        "GitHub rate limit exceeded, waiting {} seconds",
+        wait.as_secs()
+    );
+    tokio::time::sleep(wait).await;
+    true
+}
+
+async fn github_first_page(
+    octocrab: &Octocrab,
+    search: bool,
+    org: Option<&str>,
+) -> Result<Page<GitHubRepository>, DiscoverError> {
+    let query = match (search, org) {
+        (false, _) => None,
+        (true, Some(org)) => Some(format!("topic:hermitgrab org:{org}")),
+        (true, None) => {
+            let login = octocrab.current().user().await?.login;
+            Some(format!("topic:hermitgrab user:{login}"))
+        }
+    };
+    loop {
+        let result = match (&query, org) {
+            (Some(query), _) => {
+                octocrab
+                    .search()
+                    .repositories(query)
+                    .per_page(100)
+                    .send()
+                    .await
+            }
+            (None, Some(org)) => {
+                octocrab
+                    .orgs(org)
+                    .list_repos()
+                    .sort(octocrab::params::repos::Sort::FullName)
+                    .per_page(100)
+                    .send()
+                    .await
+            }
+            (None, None) => {
+                octocrab
+                    .current()
+                    .list_repos_for_authenticated_user()
+                    .type_("all")
+                    .sort("full_name")
+                    .per_page(100)
+                    .send()
+                    .await
+            }
+        };
+        match result {
+            Ok(page) => return Ok(page),
+            Err(e) if wait_for_rate_limit(octocrab, &e).await => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
 async fn github_find_hermitgrab_topic_repos(
     octocrab: &Octocrab,
-) -> Result<Vec<octocrab::models::Repository>, DiscoverError> {
-    let my_repos = octocrab
-        .current()
-        .list_repos_for_authenticated_user()
-        .type_("all")
-        .sort("full_name")
-        .per_page(100)
-        .send()
-        .await?;
+    search: bool,
+    org: Option<&str>,
+) -> Result<Vec<GitHubRepository>, DiscoverError> {
+    let mut page = github_first_page(octocrab, search, org).await?;
     let mut found_repos = vec![];
-    for repo in my_repos {
-        if let Some(ref topics) = repo.topics
-            && topics.iter().any(|t| t.to_lowercase() == "hermitgrab")
-        {
-            found_repos.push(repo.clone());
-            continue;
+    let mut scanned = 0;
+    loop {
+        scanned += page.items.len();
+        found_repos.extend(
+            page.items
+                .drain(..)
+                .filter(|repo| search || is_hermitgrab_repo(repo)),
+        );
+        match page.total_count {
+            Some(total) => step!("Scanned {scanned} of {total} repositories"),
+            None => step!("Scanned {scanned} repositories"),
         }
-        if repo.name == "dotfiles" {
-            found_repos.push(repo.clone());
+        let next = loop {
+            match octocrab.get_page::<GitHubRepository>(&page.next).await {
+                Ok(next) => break next,
+                Err(e) if wait_for_rate_limit(octocrab, &e).await => continue,
+                Err(e) => return Err(e.into()),
+            }
+        };
+        match next {
+            Some(next) => page = next,
+            None => break,
         }
     }
     Ok(found_repos)
@@ -141,7 +429,12 @@ async fn github_create_repo(
     success!("Created repo: {:?}", repo.full_name);
     if let Some(clone_url) = &repo.clone_url {
         hermitgrab_info!("Cloning {}...", clone_url);
-        clone_or_update_repo(clone_url.as_ref(), Some(token), global_config)?;
+        clone_or_update_repo(
+            clone_url.as_ref(),
+            Some(token),
+            global_config,
+            &GitRetry::load(),
+        )?;
     } else {
         return Err(DiscoverError::NoGitCloneUrl(repo_name.to_string()));
     };
@@ -150,10 +443,11 @@ async fn github_create_repo(
 
 async fn github_device_flow_auth() -> Result<(Octocrab, String), DiscoverError> {
     let client_id = SecretBox::new("Ov23liA8rPwqTP9hUCtL".to_string().into_boxed_str());
-    let octocrab = Octocrab::builder()
-        .base_uri("https://github.com")?
-        .add_header(ACCEPT, "application/json".to_string())
-        .build()?;
+    let octocrab = http::github(
+        GitHubAuth::None,
+        Some("https://github.com"),
+        &[(ACCEPT, "application/json".to_string())],
+    )?;
     let codes = octocrab
         .authenticate_as_device(&client_id, ["repo"])
         .await?;
@@ -164,7 +458,7 @@ async fn github_device_flow_auth() -> Result<(Octocrab, String), DiscoverError>
     let auth = codes.poll_until_available(&octocrab, &client_id).await?;
     success("Authentication successful");
     let token = auth.clone().access_token.expose_secret().to_string();
-    Ok((Octocrab::builder().oauth(auth).build()?, token))
+    Ok((http::github(GitHubAuth::OAuth(auth), None, &[])?, token))
 }
 
 pub fn create_local_repo(global_config: &Arc<GlobalConfig>) -> Result<(), DiscoverError> {
@@ -192,3 +486,40 @@ pub fn create_local_repo(global_config: &Arc<GlobalConfig>) -> Result<(), Discov
     info!("You can now add your dotfiles to this directory and commit them.");
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_git_retry_with_mirrors() {
+        let retry = GitRetry {
+            retries: 1,
+            backoff: Duration::ZERO,
+            mirrors: vec!["mirror".to_string()],
+        };
+        let net = || git2::Error::new(git2::ErrorCode::GenericError, git2::ErrorClass::Net, "x");
+        let mut tried = Vec::new();
+        let (outcome, _) = retry
+            .run("clone", "primary", |url| {
+                tried.push(url.to_string());
+                if url == "mirror" { Ok(()) } else { Err(net()) }
+            })
+            .unwrap();
+        assert_eq!(tried, ["primary", "primary", "mirror"]);
+        assert_eq!(outcome.remote, "mirror");
+        assert_eq!(outcome.attempts, 3);
+        let auth = || git2::Error::new(git2::ErrorCode::Auth, git2::ErrorClass::Http, "denied");
+        let mut attempts = 0;
+        let result = GitRetry {
+            mirrors: Vec::new(),
+            ..retry
+        }
+        .run("fetch", "primary", |_| -> Result<(), git2::Error> {
+            attempts += 1;
+            Err(auth())
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 1, "authentication errors are not retried");
+    }
+}
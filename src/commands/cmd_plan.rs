@@ -0,0 +1,226 @@
+// SPDX-FileCopyrightText: 2025 Karsten Becker
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use git2::{FileMode, ObjectType, Repository, TreeWalkMode, TreeWalkResult};
+
+use crate::action::Action;
+use crate::commands::cmd_apply::present_execution_plan;
+use crate::common_cli::write_json;
+use crate::config::{CliOptions, GlobalConfig, config_file_names, find_hermit_files};
+use crate::execution_plan::{PlanDiff, create_execution_plan};
+use crate::hermitgrab_error::PlanError;
+use crate::{error, hermitgrab_info, info, success, warn};
+
+/// Fingerprints of the plan for the given profile, filtered by its active tags
+fn plan_fingerprints(
+    global_config: &Arc<GlobalConfig>,
+    tags: &[String],
+    profile: Option<&str>,
+) -> Result<BTreeMap<String, String>, PlanError> {
+    let cli = CliOptions {
+        tags: tags.to_vec(),
+        profile: profile.map(str::to_string),
+        ..Default::default()
+    };
+    let active_tags = global_config.get_active_tags(&cli.tags, &cli.profile)?;
+    let plan = create_execution_plan(global_config, &cli)?.filter_actions_by_tags(&active_tags);
+    Ok(plan.fingerprints()?)
+}
+
+/// Shows the plan for the active tags with the rendered commands of all installs
+pub fn show_plan(
+    global_config: &Arc<GlobalConfig>,
+    cli: &CliOptions,
+    json: Option<&Path>,
+) -> Result<(), PlanError> {
+    let active_tags = global_config.get_active_tags(&cli.tags, &cli.profile)?;
+    let plan = create_execution_plan(global_config, cli)?.filter_actions_by_tags(&active_tags);
+    if let Some(json_path) = json {
+        let actions = plan
+            .iter()
+            .map(|(_, action)| (action.id(), action))
+            .collect::<BTreeMap<_, _>>();
+        write_json(json_path, &actions)?;
+        info!("Plan written to {}", json_path.display());
+        return Ok(());
+    }
+    present_execution_plan(&plan, false, true);
+    Ok(())
+}
+
+/// Writes the files of `git_ref` of the hermit repository to `dir` and loads its
+/// configuration. The blobs are read from the tree, the index and the working tree of the
+/// repository are not touched.
+fn load_config_at_ref(
+    hermit_dir: &Path,
+    git_ref: &str,
+    dir: &Path,
+) -> Result<Arc<GlobalConfig>, PlanError> {
+    let repo = Repository::open(hermit_dir)?;
+    let tree = repo.revparse_single(git_ref)?.peel_to_tree()?;
+    let mut blobs = Vec::new();
+    tree.walk(TreeWalkMode::PreOrder, |root, entry| {
+        if let (Some(ObjectType::Blob), Some(name)) = (entry.kind(), entry.name()) {
+            blobs.push((Path::new(root).join(name), entry.id(), entry.filemode()));
+        }
+        TreeWalkResult::Ok
+    })?;
+    for (path, id, filemode) in blobs {
+        let blob = repo.find_blob(id)?;
+        let file = dir.join(path);
+        if let Some(parent) = file.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        #[cfg(unix)]
+        if filemode == i32::from(FileMode::Link) {
+            let target = String::from_utf8_lossy(blob.content()).to_string();
+            std::os::unix::fs::symlink(target, &file)?;
+            continue;
+        }
+        std::fs::write(&file, blob.content())?;
+    }
+    let names = config_file_names(dir, &[]);
+    Ok(GlobalConfig::from_paths(
+        dir,
+        &find_hermit_files(dir, &names),
+    )?)
+}
+
+/// Shows the actions that differ between two profiles, or between `git_ref` and the
+/// working tree of the hermit directory.
+pub fn plan_diff(
+    global_config: &Arc<GlobalConfig>,
+    profiles: &[String],
+    git_ref: Option<&str>,
+    tags: &[String],
+    json: Option<&Path>,
+) -> Result<(), PlanError> {
+    let (old, new) = match (git_ref, profiles) {
+        (Some(git_ref), [] | [_]) => {
+            let profile = profiles.first().map(String::as_str);
+            let temp = tempfile::TempDir::new()?;
+            let old_config = load_config_at_ref(global_config.hermit_dir(), git_ref, temp.path())?;
+            let old = plan_fingerprints(&old_config, tags, profile)?;
+            // Paths of the checkout are replaced so that they compare equal to the working tree
+            let hermit_dir = global_config.hermit_dir().to_string_lossy().to_string();
+            let mut checkout_dirs = vec![temp.path().to_string_lossy().to_string()];
+            if let Ok(canonical) = temp.path().canonicalize() {
+                checkout_dirs.push(canonical.to_string_lossy().to_string());
+            }
+            let old = old
+                .into_iter()
+                .map(|(key, fingerprint)| {
+                    let fingerprint = checkout_dirs
+                        .iter()
+                        .fold(fingerprint, |f, dir| f.replace(dir, &hermit_dir));
+                    (key, fingerprint)
+                })
+                .collect();
+            hermitgrab_info!("Comparing {git_ref} with the working tree");
+            (old, plan_fingerprints(global_config, tags, profile)?)
+        }
+        (None, [old, new]) => {
+            hermitgrab_info!("Comparing profile {old} with profile {new}");
+            (
+                plan_fingerprints(global_config, tags, Some(old))?,
+                plan_fingerprints(global_config, tags, Some(new))?,
+            )
+        }
+        _ => {
+            return Err(PlanError::InvalidArguments(
+                "Either pass --profile twice or --ref with at most one --profile".to_string(),
+            ));
+        }
+    };
+    let diff = PlanDiff::between(&old, &new);
+    if diff.is_empty() {
+        success!("Both plans contain the same actions");
+    }
+    for action in &diff.added {
+        success!("+ {action}");
+    }
+    for action in &diff.removed {
+        error!("- {action}");
+    }
+    for action in &diff.changed {
+        warn!("~ {action}");
+    }
+    if let Some(json_path) = json {
+        write_json(json_path, &diff)?;
+        info!("Plan diff written to {}", json_path.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CONF_FILE_NAME;
+
+    #[test]
+    fn test_plan_diff_between() {
+        let old = BTreeMap::from([
+            ("Symlink a -> .a".to_string(), "1".to_string()),
+            ("Symlink b -> .b".to_string(), "2".to_string()),
+        ]);
+        let new = BTreeMap::from([
+            ("Symlink b -> .b".to_string(), "3".to_string()),
+            ("Symlink c -> .c".to_string(), "4".to_string()),
+        ]);
+        let diff = PlanDiff::between(&old, &new);
+        assert_eq!(diff.added, vec!["Symlink c -> .c"]);
+        assert_eq!(diff.removed, vec!["Symlink a -> .a"]);
+        assert_eq!(diff.changed, vec!["Symlink b -> .b"]);
+        assert!(PlanDiff::between(&new, &new).is_empty());
+    }
+
+    #[test]
+    fn test_load_config_at_ref_keeps_index() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let repo = Repository::init(temp.path()).unwrap();
+        let conf = temp.path().join(CONF_FILE_NAME);
+        std::fs::write(&conf, "[[link]]\nsource = \"a\"\ntarget = \"~/.a\"\n").unwrap();
+        std::fs::write(temp.path().join("a"), "old").unwrap();
+        let mut index = repo.index().unwrap();
+        index
+            .add_all(["*"], git2::IndexAddOption::DEFAULT, None)
+            .unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = git2::Signature::now("test", "test@localhost").unwrap();
+        repo.commit(Some("HEAD"), &sig, &sig, "init", &tree, &[])
+            .unwrap();
+        // A staged and an unstaged change that must survive loading the old config
+        std::fs::write(temp.path().join("a"), "staged").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("a")).unwrap();
+        index.write().unwrap();
+        std::fs::write(&conf, "").unwrap();
+        let status = |repo: &Repository| {
+            let statuses = repo.statuses(None).unwrap();
+            statuses
+                .iter()
+                .map(|s| (s.path().unwrap().to_string(), s.status()))
+                .collect::<Vec<_>>()
+        };
+        let index_tree = |repo: &Repository| repo.index().unwrap().write_tree().unwrap();
+        let status_before = status(&repo);
+        let index_before = index_tree(&repo);
+
+        let checkout = tempfile::TempDir::new().unwrap();
+        let config = load_config_at_ref(temp.path(), "HEAD", checkout.path()).unwrap();
+        assert_eq!(config.subconfigs().into_iter().count(), 1);
+        assert_eq!(
+            std::fs::read_to_string(checkout.path().join("a")).unwrap(),
+            "old"
+        );
+        assert_eq!(status(&repo), status_before);
+        assert_eq!(index_tree(&repo), index_before);
+        assert_eq!(std::fs::read_to_string(&conf).unwrap(), "");
+    }
+}
@@ -0,0 +1,262 @@
+// SPDX-FileCopyrightText: 2025 Karsten Becker
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::sync::Arc;
+
+use similar::TextDiff;
+use toml_edit::{Array, DocumentMut, Item, TableLike, Value};
+
+use crate::config::{GlobalConfig, TargetSpec};
+use crate::hermitgrab_error::RefactorError;
+use crate::{info, success};
+
+/// Renames the tag in a tag like `name` or `name=value`
+fn rename_tag(tag: &str, old: &str, new: &str) -> Option<String> {
+    match tag.split_once('=') {
+        Some((name, value)) if name == old => Some(format!("{new}={value}")),
+        None if tag == old => Some(new.to_string()),
+        _ => None,
+    }
+}
+
+/// Renames the tag in a requirement like `+name`, `-name=value` or `name`
+fn rename_require(require: &str, old: &str, new: &str) -> Option<String> {
+    let trimmed = require.trim();
+    let (prefix, tag) = match trimmed.strip_prefix(['+', '-', '~']) {
+        Some(tag) => trimmed.split_at(trimmed.len() - tag.len()),
+        None => ("", trimmed),
+    };
+    rename_tag(tag, old, new).map(|tag| format!("{prefix}{tag}"))
+}
+
+/// Replaces the strings of the array, keeping the formatting of the elements
+fn rename_in_array(array: &mut Array, rename: impl Fn(&str) -> Option<String>) -> usize {
+    let mut count = 0;
+    for value in array.iter_mut() {
+        if let Some(renamed) = value.as_str().and_then(&rename) {
+            let decor = value.decor().clone();
+            *value = Value::from(renamed);
+            *value.decor_mut() = decor;
+            count += 1;
+        }
+    }
+    count
+}
+
+/// Renames a key of the table, the order of the entries is kept
+fn rename_key(table: &mut dyn TableLike, old: &str, new: &str) -> Result<usize, RefactorError> {
+    if !table.contains_key(old) {
+        return Ok(0);
+    }
+    if table.contains_key(new) {
+        return Err(RefactorError::KeyExists(old.to_string(), new.to_string()));
+    }
+    let keys: Vec<String> = table.iter().map(|(k, _)| k.to_string()).collect();
+    for key in keys {
+        if let Some(item) = table.remove(&key) {
+            table.insert(if key == old { new } else { &key }, item);
+        }
+    }
+    Ok(1)
+}
+
+/// Renames the tag in all `requires` arrays and per tag targets below the item
+fn rename_in_item(item: &mut Item, old: &str, new: &str) -> Result<usize, RefactorError> {
+    let mut count = 0;
+    if let Some(array) = item.as_array_of_tables_mut() {
+        for table in array.iter_mut() {
+            count += rename_in_table(table, old, new)?;
+        }
+    } else if let Some(table) = item.as_table_like_mut() {
+        count += rename_in_table(table, old, new)?;
+    } else if let Some(array) = item.as_array_mut() {
+        for value in array.iter_mut() {
+            if let Some(table) = value.as_inline_table_mut() {
+                count += rename_in_table(table, old, new)?;
+            }
+        }
+    }
+    Ok(count)
+}
+
+fn rename_in_table(
+    table: &mut dyn TableLike,
+    old: &str,
+    new: &str,
+) -> Result<usize, RefactorError> {
+    let mut count = 0;
+    for (key, item) in table.iter_mut() {
+        match key.get() {
+            "requires" => {
+                if let Some(array) = item.as_array_mut() {
+                    count += rename_in_array(array, |r| rename_require(r, old, new));
+                }
+            }
+            "target" if old != TargetSpec::DEFAULT_KEY => {
+                if let Some(targets) = item.as_table_like_mut() {
+                    count += rename_key(targets, old, new)?;
+                }
+            }
+            _ => count += rename_in_item(item, old, new)?,
+        }
+    }
+    Ok(count)
+}
+
+/// Renames the tag in a hermit.toml document, returns the number of replacements
+pub fn rename_tag_in_document(
+    doc: &mut DocumentMut,
+    old: &str,
+    new: &str,
+) -> Result<usize, RefactorError> {
+    let mut count = 0;
+    let root = doc.as_table_mut();
+    for (key, item) in root.iter_mut() {
+        match key.get() {
+            "profiles" => {
+                if let Some(profiles) = item.as_table_like_mut() {
+                    for (_, tags) in profiles.iter_mut() {
+                        if let Some(tags) = tags.as_array_mut() {
+                            count += rename_in_array(tags, |t| rename_tag(t, old, new));
+                        }
+                    }
+                }
+            }
+            "detectors" => {
+                if let Some(detectors) = item.as_table_like_mut() {
+                    count += rename_key(detectors, old, new)?;
+                }
+            }
+            "tag_aliases" => {
+                if let Some(aliases) = item.as_table_like_mut() {
+                    for (_, tags) in aliases.iter_mut() {
+                        if let Some(tags) = tags.as_array_mut() {
+                            count += rename_in_array(tags, |t| rename_tag(t, old, new));
+                        }
+                    }
+                    count += rename_key(aliases, old, new)?;
+                }
+            }
+            "requires" => {
+                if let Some(array) = item.as_array_mut() {
+                    count += rename_in_array(array, |r| rename_require(r, old, new));
+                }
+            }
+            _ => count += rename_in_item(item, old, new)?,
+        }
+    }
+    Ok(count)
+}
+
+/// Renames a tag in all configuration files, with `dry_run` only a diff is printed
+pub fn refactor_tag(
+    global_config: &Arc<GlobalConfig>,
+    old: &str,
+    new: &str,
+    dry_run: bool,
+) -> Result<(), RefactorError> {
+    if new.is_empty() || new.contains('=') || new.starts_with(['+', '-', '~']) {
+        return Err(RefactorError::InvalidTagName(new.to_string()));
+    }
+    let mut files = 0;
+    let mut total = 0;
+    for (_, cfg) in global_config.subconfigs() {
+        let path = cfg.hermit_file();
+        let content = std::fs::read_to_string(path)?;
+        let mut doc: DocumentMut = content
+            .parse()
+            .map_err(|e| RefactorError::Parse(e, path.to_path_buf()))?;
+        let count = rename_tag_in_document(&mut doc, old, new)
+            .map_err(|e| RefactorError::InFile(Box::new(e), path.to_path_buf()))?;
+        if count == 0 {
+            continue;
+        }
+        files += 1;
+        total += count;
+        let renamed = doc.to_string();
+        if dry_run {
+            let display = path.display().to_string();
+            print!(
+                "{}",
+                TextDiff::from_lines(&content, &renamed)
+                    .unified_diff()
+                    .header(&display, &display)
+            );
+        } else {
+            std::fs::write(path, renamed)?;
+            info!("Renamed {count} occurrence(s) in {}", path.display());
+        }
+    }
+    if total == 0 {
+        info!("The tag {old} is not used in any configuration file");
+    } else if dry_run {
+        info!("Would rename {total} occurrence(s) of {old} in {files} file(s)");
+    } else {
+        success!("Renamed {total} occurrence(s) of {old} to {new} in {files} file(s)");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rename_tag_in_document() {
+        let content = r#"requires = ["+work", "-work=laptop", "other"] # keep
+
+[profiles]
+default = ["work", "home"]
+
+[detectors.work]
+enable_if = "true"
+
+[tag_aliases]
+work = ["office"]
+laptop = ["work"]
+
+[[link]]
+source = "foo"
+target = { work = "~/work/foo", default = "~/foo" }
+requires = [ "~work" ]
+
+[snippets]
+greeting = [{ value = "hi", requires = ["work"] }]
+"#;
+        let mut doc: DocumentMut = content.parse().unwrap();
+        assert_eq!(rename_tag_in_document(&mut doc, "work", "job").unwrap(), 9);
+        assert_eq!(
+            doc.to_string(),
+            r#"requires = ["+job", "-job=laptop", "other"] # keep
+
+[profiles]
+default = ["job", "home"]
+
+[detectors.job]
+enable_if = "true"
+
+[tag_aliases]
+job = ["office"]
+laptop = ["job"]
+
+[[link]]
+source = "foo"
+target = { job = "~/work/foo", default = "~/foo" }
+requires = [ "~job" ]
+
+[snippets]
+greeting = [{ value = "hi", requires = ["job"] }]
+"#
+        );
+    }
+
+    #[test]
+    fn test_rename_existing_key() {
+        let mut doc: DocumentMut = "[detectors]\nwork = {}\njob = {}\n".parse().unwrap();
+        assert!(matches!(
+            rename_tag_in_document(&mut doc, "work", "job"),
+            Err(RefactorError::KeyExists(..))
+        ));
+    }
+}
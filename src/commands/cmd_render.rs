@@ -0,0 +1,105 @@
+// SPDX-FileCopyrightText: 2025 Karsten Becker
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use similar::TextDiff;
+
+use crate::config::{ArcHermitConfig, GlobalConfig};
+use crate::hermitgrab_error::RenderCommandError;
+use crate::{info, success};
+
+/// Parses a `--var key=value` argument
+pub fn parse_var(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(k, v)| (k.trim().to_string(), v.to_string()))
+        .filter(|(k, _)| !k.is_empty())
+        .ok_or_else(|| format!("Invalid variable '{s}', expected key=value"))
+}
+
+/// Finds the config a file belongs to, which is the one with the deepest directory
+/// containing the file. Falls back to the root config.
+fn config_for(global_config: &GlobalConfig, file: Option<&Path>) -> Option<ArcHermitConfig> {
+    let nearest = file.and_then(|file| {
+        global_config
+            .subconfigs()
+            .into_iter()
+            .map(|(_, cfg)| cfg)
+            .filter(|cfg| file.starts_with(cfg.directory()))
+            .max_by_key(|cfg| cfg.directory().components().count())
+    });
+    nearest
+        .or_else(|| global_config.root_config())
+        .or_else(|| {
+            global_config
+                .subconfigs()
+                .into_iter()
+                .map(|(_, cfg)| cfg)
+                .next()
+        })
+        .cloned()
+}
+
+/// Renders a template file or a snippet and prints the result, or a diff against `target`
+pub fn render(
+    global_config: &Arc<GlobalConfig>,
+    input: &str,
+    vars: &[(String, String)],
+    target: Option<&Path>,
+) -> Result<(), RenderCommandError> {
+    let variables: BTreeMap<String, String> = vars.iter().cloned().collect();
+    let file = Path::new(input);
+    let rendered = if file.is_file() {
+        let file = file.canonicalize()?;
+        let cfg = config_for(global_config, Some(&file)).ok_or(RenderCommandError::NoConfig)?;
+        let content = std::fs::read_to_string(&file)?;
+        cfg.render_handlebars(&content, &variables)?
+    } else {
+        let cfg = config_for(global_config, None).ok_or(RenderCommandError::NoConfig)?;
+        cfg.get_snippet(&input.to_lowercase(), &variables)
+            .map_err(|_| RenderCommandError::NotFound(input.to_string()))?
+    };
+    let Some(target) = target else {
+        print!("{rendered}");
+        return Ok(());
+    };
+    let target = match config_for(global_config, None) {
+        Some(cfg) => cfg.expand_directory(target)?,
+        None => target.to_path_buf(),
+    };
+    let existing = if target.exists() {
+        std::fs::read_to_string(&target)?
+    } else {
+        info!("{} does not exist yet", target.display());
+        String::new()
+    };
+    if existing == rendered {
+        success!("{} is up to date", target.display());
+        return Ok(());
+    }
+    let diff = TextDiff::from_lines(&existing, &rendered);
+    print!(
+        "{}",
+        diff.unified_diff()
+            .header(&target.display().to_string(), input)
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_var() {
+        assert_eq!(
+            parse_var("name=a=b").unwrap(),
+            ("name".to_string(), "a=b".to_string())
+        );
+        assert!(parse_var("name").is_err());
+        assert!(parse_var("=value").is_err());
+    }
+}
@@ -0,0 +1,165 @@
+// SPDX-FileCopyrightText: 2025 Karsten Becker
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::path::{Path, PathBuf};
+
+use crate::action::fetch::sha256_file;
+use crate::commands::cmd_completions::install_completions;
+use crate::commands::cmd_hook::{HookOptions, install_hook};
+use crate::file_ops::dirs::HERMIT_EXE;
+use crate::hermitgrab_error::SelfInstallError;
+use crate::{info, success, warn};
+
+#[derive(Debug, Clone, Default)]
+pub struct SelfInstallOptions {
+    /// Directory the executable is installed to
+    pub to: PathBuf,
+    /// Symlink the executable instead of copying it
+    pub link: bool,
+    /// Expected SHA-256 of the executable
+    pub sha256: Option<String>,
+    /// File with the expected SHA-256, as written by `sha256sum`
+    pub checksum_file: Option<PathBuf>,
+    pub completions: bool,
+    pub hook: bool,
+}
+
+/// Reads the checksum from a file like `<hex>  hermitgrab`, as written by `sha256sum`
+fn read_checksum_file(path: &Path) -> Result<String, SelfInstallError> {
+    let content = std::fs::read_to_string(path)?;
+    content
+        .split_whitespace()
+        .next()
+        .filter(|hex| hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit()))
+        .map(str::to_lowercase)
+        .ok_or_else(|| SelfInstallError::InvalidChecksumFile(path.to_path_buf()))
+}
+
+/// Verifies the checksum of `exe` against the expected one, returns the actual checksum
+fn verify_checksum(exe: &Path, expected: Option<&str>) -> Result<String, SelfInstallError> {
+    let actual = sha256_file(exe)?;
+    match expected {
+        Some(expected) if !expected.eq_ignore_ascii_case(&actual) => Err(
+            SelfInstallError::ChecksumMismatch(expected.to_string(), actual),
+        ),
+        _ => Ok(actual),
+    }
+}
+
+/// Copies `exe` into `dir` through a temporary file, so that a running executable at the
+/// destination is replaced instead of overwritten
+fn copy_exe(exe: &Path, dst: &Path) -> Result<(), SelfInstallError> {
+    let tmp = dst.with_extension("hermitgrab-new");
+    std::fs::copy(exe, &tmp)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&tmp, std::fs::Permissions::from_mode(0o755))?;
+    }
+    std::fs::rename(&tmp, dst)?;
+    Ok(())
+}
+
+fn link_exe(exe: &Path, dst: &Path) -> Result<(), SelfInstallError> {
+    if dst.exists() || dst.is_symlink() {
+        std::fs::remove_file(dst)?;
+    }
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(exe, dst)?;
+    #[cfg(windows)]
+    std::os::windows::fs::symlink_file(exe, dst)?;
+    Ok(())
+}
+
+fn in_path(dir: &Path) -> bool {
+    let canonical = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+    std::env::var_os("PATH").is_some_and(|path| {
+        std::env::split_paths(&path)
+            .any(|p| p == dir || p.canonicalize().is_ok_and(|p| p == canonical))
+    })
+}
+
+/// Installs `exe` into the directory of the options, returns the installed path
+pub fn install_exe(exe: &Path, options: &SelfInstallOptions) -> Result<PathBuf, SelfInstallError> {
+    let expected = match (&options.sha256, &options.checksum_file) {
+        (Some(sha256), _) => Some(sha256.to_lowercase()),
+        (None, Some(file)) => Some(read_checksum_file(file)?),
+        (None, None) => None,
+    };
+    let checksum = verify_checksum(exe, expected.as_deref())?;
+    if expected.is_some() {
+        success!("Verified the SHA-256 of {}", exe.display());
+    } else {
+        info!("SHA-256 of {}: {checksum}", exe.display());
+    }
+    let file_name = exe
+        .file_name()
+        .ok_or_else(|| SelfInstallError::NoFileName(exe.to_path_buf()))?;
+    std::fs::create_dir_all(&options.to)?;
+    let dst = options.to.join(file_name);
+    if dst.canonicalize().is_ok_and(|dst| dst == exe) {
+        info!("{} is already installed", dst.display());
+        return Ok(dst);
+    }
+    if options.link {
+        link_exe(exe, &dst)?;
+    } else {
+        copy_exe(exe, &dst)?;
+        verify_checksum(&dst, Some(&checksum))?;
+    }
+    success!("Installed {}", dst.display());
+    if !in_path(&options.to) {
+        warn!("{} is not part of $PATH", options.to.display());
+    }
+    Ok(dst)
+}
+
+/// Installs the running executable and optionally the completions and the login hook
+pub fn self_install(options: &SelfInstallOptions) -> Result<(), SelfInstallError> {
+    let dst = install_exe(&HERMIT_EXE, options)?;
+    if options.completions {
+        install_completions(None)?;
+    }
+    if options.hook {
+        let hook = HookOptions {
+            interval: 60,
+            exe: Some(dst),
+            ..Default::default()
+        };
+        install_hook(None, &hook)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_install_exe() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let exe = temp.path().join("hermitgrab");
+        std::fs::write(&exe, "binary").unwrap();
+        let checksum = sha256_file(&exe).unwrap();
+        let options = SelfInstallOptions {
+            to: temp.path().join("bin"),
+            sha256: Some(checksum.to_uppercase()),
+            ..Default::default()
+        };
+        let dst = install_exe(&exe, &options).unwrap();
+        assert_eq!(std::fs::read_to_string(&dst).unwrap(), "binary");
+
+        let checksum_file = temp.path().join("hermitgrab.sha256");
+        std::fs::write(&checksum_file, format!("{}  hermitgrab\n", "0".repeat(64))).unwrap();
+        let options = SelfInstallOptions {
+            sha256: None,
+            checksum_file: Some(checksum_file),
+            ..options
+        };
+        assert!(matches!(
+            install_exe(&exe, &options),
+            Err(SelfInstallError::ChecksumMismatch(..))
+        ));
+    }
+}
@@ -0,0 +1,71 @@
+// SPDX-FileCopyrightText: 2025 Karsten Becker
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::Serialize;
+
+use crate::common_cli::write_json;
+use crate::config::GlobalConfig;
+use crate::hermitgrab_error::SnippetError;
+use crate::{error, hermitgrab_info, info, success, warn};
+
+#[derive(Debug, Serialize)]
+struct SnippetInfo {
+    file: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Lists all snippets with the file declaring them and whether they render with the
+/// current tags and variables. With `show`, the rendered snippet is printed instead.
+pub fn get_snippets(
+    global_config: &Arc<GlobalConfig>,
+    show: Option<&str>,
+    json: Option<&Path>,
+) -> Result<(), SnippetError> {
+    let origins = global_config.snippet_origins();
+    if let Some(name) = show {
+        let name = name.to_lowercase();
+        let cfg = origins
+            .get(&name)
+            .ok_or_else(|| SnippetError::NotFound(name.clone()))?;
+        print!("{}", cfg.get_snippet(&name, &BTreeMap::new())?);
+        return Ok(());
+    }
+    let snippets = origins
+        .iter()
+        .map(|(name, cfg)| {
+            let error = cfg
+                .get_snippet(name, &BTreeMap::new())
+                .err()
+                .map(|e| e.to_string());
+            let file = cfg.hermit_file().to_path_buf();
+            (name.clone(), SnippetInfo { file, error })
+        })
+        .collect::<BTreeMap<_, _>>();
+    let problems = global_config.snippet_problems();
+    if let Some(json_path) = json {
+        write_json(
+            json_path,
+            &serde_json::json!({"snippets": snippets, "problems": problems}),
+        )?;
+    }
+    hermitgrab_info!("All snippets:");
+    for (name, snippet) in &snippets {
+        match &snippet.error {
+            None => success!("{name} ({})", snippet.file.display()),
+            Some(e) => error!("{name} ({}): {e}", snippet.file.display()),
+        }
+    }
+    for problem in &problems {
+        warn!("{problem}");
+    }
+    if snippets.is_empty() {
+        info!("No snippets found in the configuration");
+    }
+    Ok(())
+}
@@ -2,40 +2,146 @@
 //
 // SPDX-License-Identifier: GPL-3.0-only
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-use crate::action::{Action, Status};
+use crate::action::{Action, Actions, Status};
+use crate::config::secrets::redact;
 use crate::config::{CliOptions, GlobalConfig};
-use crate::execution_plan::create_execution_plan;
+use crate::execution_plan::{ExecutionPlan, create_execution_plan};
+use crate::file_ops::dirs::{BASE_DIRS, relocate, xdg_bases};
+use crate::file_ops::{
+    binary_diff_hint, find_links_into, is_binary, prehash_copied, save_hash_cache, unified_diff,
+};
 use crate::hermitgrab_error::StatusError;
-use crate::{error, hermitgrab_info, success, warn};
+use crate::state::{HistoryEntry, XdgBases};
+use crate::{debug, error, hermitgrab_info, hint, info, success, warn};
+
+/// Maximum directory depth below $HOME that is searched with --scan-home
+const SCAN_HOME_MAX_DEPTH: usize = 6;
 
 pub fn get_status(
     global_config: &Arc<GlobalConfig>,
     quick: bool,
+    scan_home: bool,
+    diff: bool,
     cli: &CliOptions,
 ) -> Result<(), StatusError> {
+    global_config.report_load_errors();
     let active_tags = global_config.get_active_tags(&cli.tags, &cli.profile)?;
     let active_tags_str = active_tags
         .iter()
         .map(|t| t.to_string())
         .collect::<Vec<_>>()
         .join(", ");
-    hermitgrab_info!("Active tags: {}", active_tags_str);
+    if !cli.verbosity.is_quiet() {
+        hermitgrab_info!("Active tags: {}", active_tags_str);
+    }
     let actions = create_execution_plan(global_config, cli)?;
-    let filtered_actions = actions.filter_actions_by_tags(&active_tags);
+    let filtered_actions = actions
+        .filter_actions_by_tags(&active_tags)
+        .filter_actions_by_type(&cli.types, &cli.skip_types);
+    if !quick {
+        let copied = filtered_actions
+            .iter()
+            .filter_map(|(_, action)| match action.as_ref() {
+                Actions::Link(link) => link.copied_files(),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        prehash_copied(&copied);
+    }
+    let current_bases = xdg_bases();
+    let recorded_bases = XdgBases::load().unwrap_or_else(|e| {
+        warn!("Ignoring unreadable XDG base directories of the last apply: {e}");
+        XdgBases::default()
+    });
+    let changed_bases = recorded_bases
+        .bases
+        .iter()
+        .filter(|(name, base)| current_bases.get(*name).is_some_and(|c| c != *base))
+        .collect::<BTreeMap<_, _>>();
+    for (name, base) in &changed_bases {
+        info!(
+            "{name} was {base} when applied, it is {} now",
+            current_bases[*name]
+        );
+    }
+    let mut history = HistoryEntry::new(
+        "status",
+        cli.profile.clone(),
+        active_tags.iter().map(|t| t.to_string()).collect(),
+    );
+    history.plan_hash = filtered_actions.plan_hash();
     let mut results = Vec::new();
     for (cfg, action) in filtered_actions.iter() {
-        let fs = action.get_status(cfg, quick);
+        let mut fs = action.get_status(cfg, quick);
+        if !matches!(fs, Status::Ok(_))
+            && let Actions::Link(link) = &**action
+            && let Some((name, relocated)) =
+                relocate(link.dst(), &current_bases, &recorded_bases.bases)
+            && link.check_at(&relocated, quick).is_ok()
+        {
+            fs = Status::Ok(format!(
+                "{} is linked at {} ({name} was {} when applied)",
+                link.rel_dst(),
+                relocated.display(),
+                recorded_bases.bases[&name]
+            ));
+        }
         match &fs {
-            Status::Ok(msg) => success!("{}", msg),
+            Status::Ok(msg) => {
+                if !cli.verbosity.is_quiet() {
+                    success!("{}", msg)
+                }
+            }
             Status::NotOk(msg) => warn!("{}", msg),
             Status::Error(msg) => error!("{}", msg),
             Status::NotSupported => {}
         }
+        if matches!(fs, Status::NotOk(_))
+            && let Actions::Link(link) = &**action
+            && let Some((src, dst)) = link.differing_files(quick)
+        {
+            print_diff_hint(&src, &dst, diff);
+        }
+        if cli.verbosity.is_verbose()
+            && !matches!(fs, Status::Ok(_))
+            && let Actions::Install(install) = &**action
+        {
+            let checks = install.check_results();
+            if checks.len() > 1 {
+                for (cmd, passed) in checks {
+                    let result = if passed { "passed" } else { "failed" };
+                    info!("  Check {result}: {cmd}");
+                }
+            }
+        }
+        match &fs {
+            Status::Ok(_) | Status::NotSupported => {
+                history.add_result(action.id(), true, action.short_description(), None)
+            }
+            Status::NotOk(msg) | Status::Error(msg) => history.add_result(
+                action.id(),
+                false,
+                action.short_description(),
+                Some(redact(msg)),
+            ),
+        }
         results.push((action.id(), fs));
     }
+    if let Err(e) = history.save() {
+        warn!("Failed to record the run in the history: {e}");
+    }
+    if !quick && let Err(e) = save_hash_cache() {
+        warn!("Failed to save the hash cache: {e}");
+    }
+    let unmanaged_links = if scan_home {
+        find_unmanaged_links(global_config, &actions, cli.verbosity.is_quiet())
+    } else {
+        Vec::new()
+    };
     if let Some(json_path) = &cli.json {
         let actions = filtered_actions
             .actions
@@ -43,11 +149,85 @@ pub fn get_status(
             .map(|(_, action)| (action.id(), action))
             .collect::<BTreeMap<_, _>>();
         let results = results.into_iter().collect::<BTreeMap<_, _>>();
-        let json = serde_json::json!({
+        let mut json = serde_json::json!({
             "actions": actions,
             "results": results,
         });
-        std::fs::write(json_path, serde_json::to_string_pretty(&json)?)?;
+        if !changed_bases.is_empty() {
+            json["xdg_bases_applied"] = serde_json::json!(changed_bases);
+        }
+        if scan_home {
+            json["unmanaged_links"] = unmanaged_links
+                .iter()
+                .map(|(link, dst)| {
+                    serde_json::json!({"link": link, "target": dst, "broken": !dst.exists()})
+                })
+                .collect();
+        }
+        std::fs::write(json_path, redact(&serde_json::to_string_pretty(&json)?))?;
     }
     Ok(())
 }
+
+/// Helps to decide between absorbing a changed copy and applying the source again
+fn print_diff_hint(src: &Path, dst: &Path, diff: bool) {
+    let binary = is_binary(src).and_then(|binary| Ok(binary || is_binary(dst)?));
+    match binary {
+        Ok(true) => match binary_diff_hint(src, dst) {
+            Ok(hint) => hint!("{}: {hint}", dst.display()),
+            Err(e) => debug!("Failed to compare {}: {e}", dst.display()),
+        },
+        Ok(false) if diff => match unified_diff(src, dst) {
+            Ok(diff) => print!("{diff}"),
+            Err(e) => warn!("Failed to diff {}: {e}", dst.display()),
+        },
+        Ok(false) => hint!("Use --diff to show the changes of {}", dst.display()),
+        Err(e) => debug!("Failed to read {}: {e}", dst.display()),
+    }
+}
+
+/// Finds symlinks below $HOME that point into the hermit directory but do not
+/// belong to any configured link, e.g. leftovers of renamed or removed entries.
+fn find_unmanaged_links(
+    global_config: &Arc<GlobalConfig>,
+    plan: &ExecutionPlan,
+    quiet: bool,
+) -> Vec<(PathBuf, PathBuf)> {
+    let managed = plan
+        .iter()
+        .filter_map(|(_, action)| match action.as_ref() {
+            Actions::Link(link) => Some(link.dst().to_path_buf()),
+            _ => None,
+        })
+        .collect::<BTreeSet<_>>();
+    let hermit_dir = global_config
+        .hermit_dir()
+        .canonicalize()
+        .unwrap_or_else(|_| global_config.hermit_dir().to_path_buf());
+    let mut unmanaged = Vec::new();
+    for hermit_dir in BTreeSet::from([global_config.hermit_dir().to_path_buf(), hermit_dir]) {
+        for (link, dst) in find_links_into(BASE_DIRS.home_dir(), &hermit_dir, SCAN_HOME_MAX_DEPTH) {
+            if managed.contains(&link) {
+                continue;
+            }
+            if dst.exists() {
+                warn!(
+                    "{} points to {} but is not managed by any link",
+                    link.display(),
+                    dst.display()
+                );
+            } else {
+                error!(
+                    "{} is a broken link to {} which is not managed by any link",
+                    link.display(),
+                    dst.display()
+                );
+            }
+            unmanaged.push((link, dst));
+        }
+    }
+    if unmanaged.is_empty() && !quiet {
+        success!("No unmanaged links into the hermit directory found");
+    }
+    unmanaged
+}
@@ -0,0 +1,198 @@
+// SPDX-FileCopyrightText: 2025 Karsten Becker
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::fmt::Display;
+
+use serde::Serialize;
+
+use crate::common_cli::message_counts;
+use crate::hermitgrab_error::{
+    AddError, ApplyError, ConfigError, DiscoverError, PlanError, StatusError,
+};
+
+/// Exit codes of the hermitgrab process
+///
+/// | Code | Meaning                                                        |
+/// |------|----------------------------------------------------------------|
+/// | 0    | The command succeeded, warnings may have been reported         |
+/// | 1    | The command failed for another reason                          |
+/// | 2    | Invalid command line arguments (reported by clap)              |
+/// | 3    | The configuration is invalid, e.g. unknown tag or profile      |
+/// | 4    | The command completed, but some actions failed                 |
+/// | 5    | The user aborted the command                                   |
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExitCode {
+    Ok = 0,
+    Error = 1,
+    ConfigInvalid = 3,
+    ActionFailed = 4,
+    Aborted = 5,
+}
+
+impl ExitCode {
+    pub fn code(self) -> i32 {
+        self as i32
+    }
+
+    fn of_apply(error: &ApplyError) -> Self {
+        match error {
+            ApplyError::UserAborted => Self::Aborted,
+            ApplyError::ProfileNotFound(_)
+            | ApplyError::TagNotFound(_)
+            | ApplyError::ConfigLoad(_) => Self::ConfigInvalid,
+            _ => Self::Error,
+        }
+    }
+
+    /// Classifies the error returned by a command
+    fn of_error(error: &anyhow::Error) -> Self {
+        if let Some(e) = error.downcast_ref::<ApplyError>() {
+            return Self::of_apply(e);
+        }
+        if error.downcast_ref::<ConfigError>().is_some() {
+            return Self::ConfigInvalid;
+        }
+        if let Some(e) = error.downcast_ref::<StatusError>() {
+            return match e {
+                StatusError::Config(_) => Self::ConfigInvalid,
+                StatusError::Apply(e) => Self::of_apply(e),
+                _ => Self::Error,
+            };
+        }
+        if let Some(e) = error.downcast_ref::<AddError>() {
+            return match e {
+                AddError::ConfigLoad(_) => Self::ConfigInvalid,
+                AddError::Plan(e) => Self::of_apply(e),
+                _ => Self::Error,
+            };
+        }
+        if let Some(e) = error.downcast_ref::<PlanError>() {
+            return match e {
+                PlanError::Config(_) => Self::ConfigInvalid,
+                PlanError::Apply(e) => Self::of_apply(e),
+                _ => Self::Error,
+            };
+        }
+        if let Some(DiscoverError::Config(_)) = error.downcast_ref::<DiscoverError>() {
+            return Self::ConfigInvalid;
+        }
+        Self::Error
+    }
+}
+
+/// Outcome of a command, mapped to the process exit code with [`ExitReport::exit_code`]
+#[derive(Debug, Serialize)]
+pub struct ExitReport {
+    pub command: &'static str,
+    /// Warnings reported while running the command
+    pub warnings: usize,
+    /// Errors reported while running the command, e.g. failed actions
+    pub errors: usize,
+    pub aborted: bool,
+    /// Some configuration files could not be loaded
+    pub config_invalid: bool,
+    /// The error that ended the command
+    #[serde(serialize_with = "serialize_error")]
+    pub error: Option<anyhow::Error>,
+}
+
+fn serialize_error<S: serde::Serializer>(
+    error: &Option<anyhow::Error>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match error {
+        Some(e) => serializer.serialize_some(&format!("{e:#}")),
+        None => serializer.serialize_none(),
+    }
+}
+
+impl ExitReport {
+    /// Creates the report of a command, `counts_before` are the warnings and errors reported
+    /// before the command started
+    pub fn new(
+        command: &'static str,
+        result: Result<(), anyhow::Error>,
+        counts_before: (usize, usize),
+        config_invalid: bool,
+    ) -> Self {
+        let (warnings, errors) = message_counts();
+        let error = result.err();
+        Self {
+            command,
+            warnings: warnings.saturating_sub(counts_before.0),
+            errors: errors.saturating_sub(counts_before.1),
+            aborted: error
+                .as_ref()
+                .is_some_and(|e| ExitCode::of_error(e) == ExitCode::Aborted),
+            config_invalid,
+            error,
+        }
+    }
+
+    pub fn exit_code(&self) -> ExitCode {
+        match &self.error {
+            Some(e) => ExitCode::of_error(e),
+            None if self.config_invalid => ExitCode::ConfigInvalid,
+            None if self.errors > 0 => ExitCode::ActionFailed,
+            None => ExitCode::Ok,
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.exit_code() == ExitCode::Ok
+    }
+
+    /// The error that ended the command, reported errors of actions are not included
+    pub fn into_result(self) -> Result<Self, anyhow::Error> {
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(self),
+        }
+    }
+}
+
+impl Display for ExitReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} finished with {} warning(s) and {} error(s), exit code {}",
+            self.command,
+            self.warnings,
+            self.errors,
+            self.exit_code().code()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_codes() {
+        // Messages of tests running in parallel are not counted
+        let none_reported = (usize::MAX, usize::MAX);
+        let report = |result: Result<(), anyhow::Error>| {
+            ExitReport::new("test", result, none_reported, false).exit_code()
+        };
+        assert_eq!(report(Ok(())), ExitCode::Ok);
+        assert_eq!(
+            report(Err(ApplyError::UserAborted.into())),
+            ExitCode::Aborted
+        );
+        assert_eq!(
+            report(Err(
+                StatusError::Apply(ApplyError::TagNotFound("x".into())).into()
+            )),
+            ExitCode::ConfigInvalid
+        );
+        assert_eq!(
+            report(Err(anyhow::anyhow!("something else"))),
+            ExitCode::Error
+        );
+        let invalid = ExitReport::new("test", Ok(()), none_reported, true);
+        assert_eq!(invalid.exit_code(), ExitCode::ConfigInvalid);
+    }
+}
@@ -0,0 +1,72 @@
+// SPDX-FileCopyrightText: 2025 Karsten Becker
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::path::Path;
+
+use handlebars::Handlebars;
+use serde_json::Value;
+
+use crate::config::handlebar_math::math_helper;
+use crate::hermitgrab_error::ReportTemplateError;
+
+/// Version of the JSON report of `apply`, increased when fields are renamed or removed so
+/// that report templates can check what they get
+pub const REPORT_VERSION: u32 = 2;
+
+/// Renders the report of a run with a handlebars template. The report is the same JSON that
+/// `--json` writes. Templates ending in `.html` or `.htm` escape HTML, others are rendered
+/// as they are, e.g. for a chat message.
+pub fn render_report(template: &Path, report: &Value) -> Result<String, ReportTemplateError> {
+    let content = std::fs::read_to_string(template)
+        .map_err(|e| ReportTemplateError::Io(e, template.to_path_buf()))?;
+    let mut reg = Handlebars::new();
+    reg.register_helper("math", Box::new(math_helper));
+    let html = template
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("html") || ext.eq_ignore_ascii_case("htm"));
+    if !html {
+        reg.register_escape_fn(handlebars::no_escape);
+    }
+    reg.render_template(&content, report)
+        .map_err(|e| ReportTemplateError::Render(e, template.to_path_buf()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_report() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let report = serde_json::json!({
+            "version": REPORT_VERSION,
+            "summary": {"ok": 1, "skipped": 0, "failed": 1},
+            "results": [
+                {"id": "link:a", "ok": true, "short_description": "Symlink a -> <a>"},
+                {"id": "link:b", "ok": false, "short_description": "Symlink b", "error": "exists"},
+            ],
+        });
+        let template = "v{{version}}: {{summary.ok}}/{{math summary.ok \"+\" summary.failed}} ok\n\
+            {{#each results}}{{#unless ok}}{{id}}: {{error}}\n{{/unless}}{{/each}}\
+            {{#each results}}{{#if ok}}{{short_description}}{{/if}}{{/each}}";
+        let text = temp.path().join("slack.hbs");
+        std::fs::write(&text, template).unwrap();
+        assert_eq!(
+            render_report(&text, &report).unwrap(),
+            "v2: 1/2 ok\nlink:b: exists\nSymlink a -> <a>"
+        );
+        let html = temp.path().join("report.html");
+        std::fs::write(&html, template).unwrap();
+        assert!(
+            render_report(&html, &report)
+                .unwrap()
+                .ends_with("Symlink a -&gt; &lt;a&gt;")
+        );
+        std::fs::write(&text, "{{#each}}").unwrap();
+        assert!(matches!(
+            render_report(&text, &report),
+            Err(ReportTemplateError::Render(..))
+        ));
+    }
+}
@@ -3,24 +3,139 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
 use std::io::Write;
+use std::path::Path;
 use std::sync::LazyLock;
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
 
 use crossterm::style::Stylize;
+use serde::{Deserialize, Serialize};
+
+use crate::config::secrets::redact;
+
+/// Set when stdout is reserved for machine readable output, messages then go to stderr
+static STDOUT_RESERVED: AtomicBool = AtomicBool::new(false);
+
+/// How much is reported, warnings and errors are always shown
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verbosity {
+    /// Only warnings and errors, `-q`
+    Quiet,
+    #[default]
+    Normal,
+    /// Output of the actions and the full execution plan, `-v`
+    Verbose,
+    /// Additionally the command lines and environment of each command, `-vv`
+    Debug,
+}
+
+impl Verbosity {
+    /// The level of `-v` given `count` times
+    pub fn from_count(count: u8) -> Self {
+        match count {
+            0 => Self::Normal,
+            1 => Self::Verbose,
+            _ => Self::Debug,
+        }
+    }
+
+    pub fn is_verbose(self) -> bool {
+        self >= Self::Verbose
+    }
+
+    pub fn is_quiet(self) -> bool {
+        self == Self::Quiet
+    }
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
+/// Sets the verbosity of all messages of the process
+pub fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+}
+
+pub fn verbosity() -> Verbosity {
+    match VERBOSITY.load(Ordering::Relaxed) {
+        0 => Verbosity::Quiet,
+        1 => Verbosity::Normal,
+        2 => Verbosity::Verbose,
+        _ => Verbosity::Debug,
+    }
+}
+
+fn quiet() -> bool {
+    verbosity().is_quiet()
+}
+
+/// Number of warnings and errors reported so far
+static WARNINGS: AtomicUsize = AtomicUsize::new(0);
+static ERRORS: AtomicUsize = AtomicUsize::new(0);
+
+/// Warnings and errors reported since the start of the process
+pub fn message_counts() -> (usize, usize) {
+    (
+        WARNINGS.load(Ordering::Relaxed),
+        ERRORS.load(Ordering::Relaxed),
+    )
+}
+
+/// Redirects all messages to stderr, so that stdout only contains JSON output
+pub fn reserve_stdout() {
+    STDOUT_RESERVED.store(true, Ordering::Relaxed);
+}
+
+macro_rules! emit {
+    ($($arg:tt)*) => {
+        if STDOUT_RESERVED.load(Ordering::Relaxed) {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+
+/// Writes `value` as pretty printed JSON to `path`, or to stdout if the path is `-`
+pub fn write_json<T: Serialize + ?Sized>(path: &Path, value: &T) -> std::io::Result<()> {
+    let json = redact(&serde_json::to_string_pretty(value)?);
+    if path == Path::new("-") {
+        println!("{json}");
+    } else {
+        std::fs::write(path, json)?;
+    }
+    Ok(())
+}
 
 pub fn hermitgrab_info(msg: &str) {
-    println!("{} {}", "[hermitgrab]".bold().cyan(), msg.cyan());
+    if quiet() {
+        return;
+    }
+    let msg = redact(msg);
+    emit!("{} {}", "[hermitgrab]".bold().cyan(), msg.cyan());
 }
 
 pub fn order(msg: &str) {
-    println!("{} {}", "     [order]".bold().cyan(), msg.cyan());
+    if quiet() {
+        return;
+    }
+    let msg = redact(msg);
+    emit!("{} {}", "     [order]".bold().cyan(), msg.cyan());
 }
 
 pub fn step(msg: &str) {
-    println!("{} {}", "      [step]".bold().cyan(), msg.cyan());
+    if quiet() {
+        return;
+    }
+    let msg = redact(msg);
+    emit!("{} {}", "      [step]".bold().cyan(), msg.cyan());
 }
 
 pub fn choice(msg: &str) {
-    println!("{} {}", "    [choice]".bold().blue(), msg.blue());
+    if quiet() {
+        return;
+    }
+    let msg = redact(msg);
+    emit!("{} {}", "    [choice]".bold().blue(), msg.blue());
 }
 
 static DEBUG_ENABLED: LazyLock<bool> = LazyLock::new(|| {
@@ -29,29 +144,56 @@ static DEBUG_ENABLED: LazyLock<bool> = LazyLock::new(|| {
         .unwrap_or(false)
 });
 
-#[allow(dead_code)]
 pub fn debug(msg: &str) {
-    if *DEBUG_ENABLED {
-        println!("{} {}", "     [debug]".bold().dark_grey(), msg.dark_grey());
+    if *DEBUG_ENABLED || verbosity() >= Verbosity::Debug {
+        let msg = redact(msg);
+        emit!("{} {}", "     [debug]".bold().dark_grey(), msg.dark_grey());
     }
 }
 pub fn info(msg: &str) {
-    println!("{} {}", "      [info]".bold().cyan(), msg.cyan());
+    if quiet() {
+        return;
+    }
+    let msg = redact(msg);
+    emit!("{} {}", "      [info]".bold().cyan(), msg.cyan());
 }
 pub fn warn(msg: &str) {
-    println!("{} {}", "      [warn]".bold().yellow(), msg.yellow());
+    WARNINGS.fetch_add(1, Ordering::Relaxed);
+    let msg = redact(msg);
+    emit!("{} {}", "      [warn]".bold().yellow(), msg.yellow());
 }
 pub fn error(msg: &str) {
-    println!("{} {}", "     [error]".bold().red(), msg.red());
+    ERRORS.fetch_add(1, Ordering::Relaxed);
+    let msg = redact(msg);
+    emit!("{} {}", "     [error]".bold().red(), msg.red());
 }
 pub fn success(msg: &str) {
-    println!("{} {}", "   [success]".bold().green(), msg.green());
+    if quiet() {
+        return;
+    }
+    let msg = redact(msg);
+    emit!("{} {}", "   [success]".bold().green(), msg.green());
+}
+pub fn skipped(msg: &str) {
+    if quiet() {
+        return;
+    }
+    let msg = redact(msg);
+    emit!("{} {}", "   [skipped]".bold().blue(), msg.blue());
 }
 pub fn hint(msg: &str) {
-    println!("{} {}", "      [hint]".bold().dark_grey(), msg.dark_grey());
+    if quiet() {
+        return;
+    }
+    let msg = redact(msg);
+    emit!("{} {}", "      [hint]".bold().dark_grey(), msg.dark_grey());
 }
 
 pub fn stdout(tag: &str, msg: &str) {
+    if quiet() {
+        return;
+    }
+    let msg = redact(msg);
     let lines = msg.lines().collect::<Vec<_>>();
     if lines.is_empty() {
         return;
@@ -62,18 +204,22 @@ pub fn stdout(tag: &str, msg: &str) {
             continue;
         }
         if !tag.is_empty() {
-            println!(
+            emit!(
                 "{}[{}] {}",
                 "    [stdout]".bold().dark_grey(),
                 tag.dark_grey(),
                 line.dark_grey()
             );
         } else {
-            println!("{} {}", "    [stdout]".bold().dark_grey(), line.dark_grey());
+            emit!("{} {}", "    [stdout]".bold().dark_grey(), line.dark_grey());
         }
     }
 }
 pub fn stderr(tag: &str, msg: &str) {
+    if quiet() {
+        return;
+    }
+    let msg = redact(msg);
     let lines = msg.lines().collect::<Vec<_>>();
     if lines.is_empty() {
         return;
@@ -84,14 +230,14 @@ pub fn stderr(tag: &str, msg: &str) {
             continue;
         }
         if !tag.is_empty() {
-            println!(
+            emit!(
                 "{}[{}] {}",
                 "    [stderr]".bold().dark_red(),
                 tag.dark_red(),
                 line.dark_red()
             );
         } else {
-            println!("{} {}", "    [stderr]".bold().dark_red(), line.dark_red());
+            emit!("{} {}", "    [stderr]".bold().dark_red(), line.dark_red());
         }
     }
 }
@@ -154,6 +300,18 @@ macro_rules! success {
     };
 }
 #[macro_export]
+macro_rules! skipped {
+    ($($arg:tt)*) => {
+        $crate::common_cli::skipped(&format!($($arg)*))
+    };
+}
+#[macro_export]
+macro_rules! hint {
+    ($($arg:tt)*) => {
+        $crate::common_cli::hint(&format!($($arg)*))
+    };
+}
+#[macro_export]
 macro_rules! hermitgrab_info {
     ($($arg:tt)*) => {
         $crate::common_cli::hermitgrab_info(&format!($($arg)*))
@@ -6,32 +6,44 @@ use std::collections::{BTreeMap, BTreeSet};
 use std::convert::Infallible;
 use std::fmt::Display;
 use std::hash::Hash;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use std::str::FromStr;
-use std::sync::{Arc, Weak};
+use std::sync::{Arc, RwLock, Weak};
 
 use clap::ValueEnum;
 use clap::builder::PossibleValue;
 use handlebars::{
     Context, Handlebars, Helper, Output, RenderContext, RenderError, RenderErrorReason,
 };
+use itertools::Itertools;
 use serde::{Deserialize, Deserializer, Serialize};
 use toml_edit::DocumentMut;
 
-use crate::action::install::InstallAction;
+use crate::action::fetch::FetchAction;
+use crate::action::install::{InstallAction, OutputLog};
 use crate::action::link::LinkAction;
 use crate::action::patch::PatchAction;
-use crate::action::{Actions, ArcAction, SourceSpec};
+use crate::action::{ActionType, Actions, ArcAction, PreprocessingType, SourceSpec, TextEncoding};
+use crate::common_cli::Verbosity;
 use crate::config::handlebar_math::math_helper;
+use crate::config::sandbox::Sandbox;
+use crate::config::schedule::Schedule;
+use crate::config::secrets::secret_helper;
 use crate::debug;
-use crate::detector::{detect_builtin_tags, get_detected_tags};
+use crate::detector::{detect_builtin_tags, get_referenced_detected_tags, override_builtin_tags};
 use crate::file_ops::dirs::*;
-use crate::hermitgrab_error::{ApplyError, ConfigError};
+use crate::file_ops::line_and_column;
+use crate::hermitgrab_error::{ApplyError, ConfigError, LinkActionError};
 
 pub const CONF_FILE_NAME: &str = "hermit.toml";
 pub const DEFAULT_PROFILE: &str = "default";
 
-mod handlebar_math;
+pub mod handlebar_math;
+pub mod options;
+pub mod sandbox;
+pub mod schedule;
+pub mod secrets;
+pub mod user_settings;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum Source {
@@ -40,6 +52,7 @@ pub enum Source {
     Detector(String),
     BuiltInDetector,
     Config,
+    Alias(String),
 }
 impl Display for Source {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -49,6 +62,7 @@ impl Display for Source {
             Source::Detector(name) => write!(f, "detector: {name}"),
             Source::BuiltInDetector => write!(f, "built-in detector"),
             Source::Config => write!(f, "config"),
+            Source::Alias(name) => write!(f, "alias: {name}"),
         }
     }
 }
@@ -155,10 +169,16 @@ pub enum RequireTag {
 }
 
 impl RequireTag {
+    /// A tag without a value matches every value of the tag, e.g. `has_apt` matches the
+    /// detected `has_apt=/usr/bin/apt`
     pub fn matches(&self, tags: &BTreeSet<Tag>) -> bool {
+        let contains = |tag: &Tag| match tag.value() {
+            Some(_) => tags.contains(tag),
+            None => tags.iter().any(|t| t.name() == tag.name()),
+        };
         match self {
-            RequireTag::Positive(tag) => tags.contains(tag),
-            RequireTag::Negative(tag) => !tags.contains(tag),
+            RequireTag::Positive(tag) => contains(tag),
+            RequireTag::Negative(tag) => !contains(tag),
         }
     }
     pub fn name(&self) -> &str {
@@ -229,6 +249,43 @@ pub enum DetectorConfig {
 pub struct HermitSettings {
     /// If true, handlebars will error on missing variables instead of leaving them blank
     pub strict_mode: bool,
+    /// Commands for additional secret managers, keyed by the scheme of the secret reference.
+    /// `{ref}` is replaced with the reference without the scheme.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub secret_commands: BTreeMap<String, String>,
+    /// File names of configuration files in subdirectories, only read from the root hermit.toml.
+    /// hermit.toml is always accepted in addition to these names.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub config_names: Vec<String>,
+    /// How the `order` of an item is combined with the `order` of its configuration
+    #[serde(default)]
+    #[serde(skip_serializing_if = "OrderMode::is_default")]
+    pub order_mode: OrderMode,
+    /// Commit the redacted report of an apply to `reports/<hostname>/` in the dotfiles
+    /// repository and push it to the upstream of the branch, when the result changed. Only
+    /// read from the root hermit.toml
+    #[serde(default)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub report_commit: bool,
+}
+
+/// Combination of the order of an item with the default order of its configuration
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderMode {
+    /// An explicitly set order of the item wins, the order of the configuration is only a default
+    #[default]
+    Override,
+    /// The larger of both orders is used, an item can not run earlier than its configuration
+    Max,
+}
+
+impl OrderMode {
+    pub fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
 }
 
 impl HermitSettings {
@@ -245,6 +302,12 @@ pub struct HermitConfig {
     canonicalize_dir: PathBuf,
     #[serde(skip)]
     global_cfg: Weak<GlobalConfig>,
+    /// Line of each item of the link, patch, install and fetch sections, captured at load
+    #[serde(skip)]
+    item_lines: BTreeMap<String, Vec<usize>>,
+    /// Names of the tags used as `tag.<name>` in templates of the hermit.toml file
+    #[serde(skip)]
+    template_tags: BTreeSet<String>,
     #[serde(default)]
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub link: Vec<LinkConfig>,
@@ -255,14 +318,24 @@ pub struct HermitConfig {
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub install: Vec<InstallConfig>,
     #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fetch: Vec<FetchConfig>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub snippets: BTreeMap<String, TaggedValue>,
+    /// Variables available as `{{ var.name }}` in all templates, the variables of an
+    /// action take precedence
+    #[serde(default)]
     #[serde(skip_serializing_if = "BTreeMap::is_empty")]
-    pub snippets: BTreeMap<String, String>,
+    pub variables: BTreeMap<String, TaggedValue>,
     #[serde(default)]
     #[serde(skip_serializing_if = "HermitSettings::is_default")]
     pub settings: HermitSettings,
     #[serde(default)]
     #[serde(skip_serializing_if = "BTreeSet::is_empty")]
     pub requires: BTreeSet<RequireTag>,
+    /// Default order of the items, an explicit order of an item takes precedence unless
+    /// `order_mode = "max"` is set in the settings
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub order: Option<u64>,
@@ -272,6 +345,25 @@ pub struct HermitConfig {
     #[serde(default)]
     #[serde(skip_serializing_if = "BTreeMap::is_empty")]
     pub detectors: BTreeMap<String, DetectorConfig>,
+    /// Tags that imply other tags, when the key is active all tags of the value are activated as well
+    #[serde(default)]
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub tag_aliases: BTreeMap<Tag, BTreeSet<Tag>>,
+    /// Disable or fix the value of built-in tags, e.g. `builtin_tags.os_version = false`,
+    /// only read from the root hermit.toml
+    #[serde(default)]
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub builtin_tags: BTreeMap<String, BuiltinTagOverride>,
+}
+
+/// Override of a built-in tag for environments that misreport it, e.g. containers
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum BuiltinTagOverride {
+    /// `false` removes the tag, `true` keeps the detected value
+    Enabled(bool),
+    /// A fixed value instead of the detected one
+    Value(String),
 }
 
 pub type ArcHermitConfig = Arc<HermitConfig>;
@@ -326,6 +418,11 @@ impl HermitConfig {
         Ok(())
     }
 
+    /// Line in the hermit.toml file of the item at `index` of a section like `link`
+    pub fn item_line(&self, section: &str, index: usize) -> Option<usize> {
+        self.item_lines.get(section)?.get(index).copied()
+    }
+
     /// Directory containing the hermit.toml file
     pub fn directory(&self) -> &Path {
         &self.canonicalize_dir
@@ -337,6 +434,7 @@ impl HermitConfig {
             .map(|c| c as &dyn ConfigItem)
             .chain(self.patch.iter().map(|c| c as &dyn ConfigItem))
             .chain(self.install.iter().map(|c| c as &dyn ConfigItem))
+            .chain(self.fetch.iter().map(|c| c as &dyn ConfigItem))
             .chain(std::iter::once(self as &dyn ConfigItem))
     }
 
@@ -345,20 +443,53 @@ impl HermitConfig {
         lc_src: &str,
         variables: &BTreeMap<String, String>,
     ) -> Result<String, RenderError> {
-        self.snippets
+        thread_local! {
+            /// Snippets that are currently rendered, used to detect cycles
+            static RENDERING: std::cell::RefCell<Vec<String>> = const { std::cell::RefCell::new(Vec::new()) };
+        }
+        let global_config = self.global_config();
+        let snippet = self
+            .snippets
             .get(lc_src)
-            .cloned()
-            .or_else(|| self.global_config().get_snippet(lc_src).cloned())
-            .map(|src| self.render_handlebars(&src, variables))
-            .unwrap_or_else(|| {
-                Err(RenderErrorReason::Other(format!("Snippet not found: {lc_src}")).into())
-            })
+            .or_else(|| global_config.get_snippet(lc_src))
+            .ok_or_else(|| RenderErrorReason::Other(format!("Snippet not found: {lc_src}")))?;
+        let src = snippet
+            .resolve(&global_config.render_tags())
+            .ok_or_else(|| {
+                RenderErrorReason::Other(format!(
+                    "Snippet {lc_src} has no variant for the active tags"
+                ))
+            })?
+            .to_string();
+        let cycle = RENDERING.with_borrow_mut(|stack| {
+            if stack.iter().any(|s| s == lc_src) {
+                return Some(format!("{} -> {lc_src}", stack.join(" -> ")));
+            }
+            stack.push(lc_src.to_string());
+            None
+        });
+        if let Some(cycle) = cycle {
+            return Err(RenderErrorReason::Other(format!("Snippet cycle: {cycle}")).into());
+        }
+        let rendered = self.render_handlebars(&src, variables);
+        RENDERING.with_borrow_mut(|stack| stack.pop());
+        rendered
     }
 
     pub fn render_handlebars(
         &self,
         content: &str,
         variables: &BTreeMap<String, String>,
+    ) -> Result<String, RenderError> {
+        self.render_template(content, variables, true)
+    }
+
+    /// Renders a template, the values of variables are rendered without variables
+    fn render_template(
+        &self,
+        content: &str,
+        variables: &BTreeMap<String, String>,
+        with_variables: bool,
     ) -> Result<String, RenderError> {
         let global_config = self.global_config();
         let dir_map = self.collect_dir_map(&global_config);
@@ -370,8 +501,16 @@ impl HermitConfig {
         let mut rendered_variables = BTreeMap::new();
         let empty_map = BTreeMap::new();
         let cfg = self;
-        for (key, value) in variables.iter() {
-            rendered_variables.insert(key, cfg.render_handlebars(value, &empty_map)?);
+        if with_variables {
+            let render_tags = global_config.render_tags();
+            for (key, value) in global_config.all_variables.iter() {
+                if let Some(value) = value.resolve(&render_tags) {
+                    rendered_variables.insert(key, cfg.render_template(value, &empty_map, false)?);
+                }
+            }
+            for (key, value) in variables.iter() {
+                rendered_variables.insert(key, cfg.render_template(value, &empty_map, false)?);
+            }
         }
         let env_vars: BTreeMap<String, String> = std::env::vars().collect();
         let sys_info: BTreeMap<String, String> = BTreeMap::from([
@@ -466,6 +605,10 @@ impl HermitConfig {
             debug!("Using xdg runtime dir: {}", xdg_runtime_dir);
             paths.push(("xdg_runtime", xdg_runtime_dir));
         }
+        if let Some(win_home) = WIN_HOME.clone() {
+            debug!("Using Windows home dir: {}", win_home);
+            paths.push(("win_home", win_home));
+        }
         let dir_map: BTreeMap<&'static str, String> = BTreeMap::from_iter(paths);
         dir_map
     }
@@ -494,6 +637,25 @@ impl HermitConfig {
         }
     }
 
+    /// Expands a target like [`HermitConfig::expand_directory`] and places it below the
+    /// target prefix. Returns the path and the unprefixed path relative to $HOME for display.
+    pub fn expand_target<P: Into<PathBuf>>(
+        &self,
+        target: P,
+        target_prefix: Option<&Path>,
+    ) -> Result<(PathBuf, String), RenderError> {
+        let dst = self.expand_directory(target)?;
+        let rel_dst = dst
+            .strip_prefix(BASE_DIRS.home_dir())
+            .unwrap_or(&dst)
+            .to_string_lossy()
+            .to_string();
+        match target_prefix {
+            Some(prefix) => Ok((with_prefix(prefix, &dst), rel_dst)),
+            None => Ok((dst, rel_dst)),
+        }
+    }
+
     pub fn canonicalize_source_path<E>(
         &self,
         file: &PathBuf,
@@ -503,7 +665,7 @@ impl HermitConfig {
         E: From<std::io::Error>,
         E: From<RenderError>,
     {
-        let src = self.expand_directory(&file)?;
+        let src = self.expand_directory(file)?;
         let src = if src.is_absolute() {
             src.clone()
         } else {
@@ -523,6 +685,26 @@ impl HermitConfig {
     }
 }
 
+/// Names of the snippets referenced with `{{ snippet name }}` in a template
+pub fn snippet_references(template: &str) -> Vec<String> {
+    template
+        .split("{{")
+        .skip(1)
+        .filter_map(|part| {
+            let expression = part.split("}}").next()?.trim_start_matches(['{', '~']);
+            let mut words = expression.split_whitespace();
+            if words.next()? != "snippet" {
+                return None;
+            }
+            let name = words
+                .next()?
+                .trim_end_matches('~')
+                .trim_matches(['"', '\'']);
+            Some(name.to_lowercase())
+        })
+        .collect()
+}
+
 fn create_handlebars<'cfg, 'v>(
     variables: &'v BTreeMap<String, String>,
     cfg: &'cfg HermitConfig,
@@ -551,6 +733,10 @@ where
         ),
     );
     reg.register_helper("math", Box::new(math_helper));
+    reg.register_helper(
+        "secret",
+        Box::new(secret_helper(&cfg.settings.secret_commands)),
+    );
     debug!(
         "Setting handlebars strict mode to {}",
         cfg.settings.strict_mode
@@ -664,10 +850,11 @@ pub enum SourceSpecOrPath {
 }
 
 impl SourceSpecOrPath {
-    pub fn path(&self) -> &Path {
+    /// The source file, None if the source is inline text or a snippet
+    pub fn path(&self) -> Option<&Path> {
         match self {
-            Self::Path(p) => p,
-            Self::SourceSpec(s) => s.file(),
+            Self::Path(p) => Some(p),
+            Self::SourceSpec(s) => s.file_path(),
         }
     }
 
@@ -698,12 +885,20 @@ pub struct PatchConfig {
     pub target: PathBuf,
     #[serde(rename = "type", default)]
     pub patch_type: PatchType,
+    #[serde(flatten)]
+    pub encoding: TextEncoding,
+    #[serde(flatten)]
+    pub ownership: Ownership,
     #[serde(default)]
     #[serde(skip_serializing_if = "BTreeSet::is_empty")]
     pub requires: BTreeSet<RequireTag>,
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub order: Option<u64>,
+    /// Mode of the directories created for the target and of its parent directory
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dir_mode: Option<DirMode>,
 }
 
 impl ConfigItem for PatchConfig {
@@ -714,9 +909,13 @@ impl ConfigItem for PatchConfig {
     fn as_action(
         &self,
         cfg: &HermitConfig,
-        _options: &CliOptions,
+        options: &CliOptions,
     ) -> Result<ArcAction, ConfigError> {
-        Ok(Arc::new(Actions::Patch(PatchAction::new(self, cfg)?)))
+        Ok(Arc::new(Actions::Patch(PatchAction::new(
+            self,
+            cfg,
+            options.target_prefix.as_deref(),
+        )?)))
     }
 
     fn id(&self) -> String {
@@ -728,10 +927,103 @@ impl ConfigItem for PatchConfig {
     }
 }
 
+/// A target path, optionally selected by the active tags.
+///
+/// The per tag form uses tags (`name` or `name=value`) as keys and the special key `default`
+/// as fallback when none of the tags is active.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum TargetSpec {
+    Path(PathBuf),
+    PerTag(BTreeMap<String, PathBuf>),
+}
+
+impl TargetSpec {
+    pub const DEFAULT_KEY: &str = "default";
+
+    /// The target used when no tag is matching
+    pub fn default_path(&self) -> Option<&Path> {
+        match self {
+            Self::Path(p) => Some(p),
+            Self::PerTag(map) => map.get(Self::DEFAULT_KEY).map(|p| p.as_path()),
+        }
+    }
+
+    pub fn resolve(&self, active_tags: &BTreeSet<Tag>) -> Result<PathBuf, ConfigError> {
+        match self {
+            Self::Path(p) => Ok(p.clone()),
+            Self::PerTag(map) => {
+                let matching = map
+                    .iter()
+                    .filter(|(key, _)| key.as_str() != Self::DEFAULT_KEY)
+                    .find(|(key, _)| {
+                        active_tags.contains(&Tag::from_str_with_src(key, Source::Config))
+                    });
+                if let Some((key, path)) = matching {
+                    debug!("Target {path:?} selected by tag {key}");
+                    return Ok(path.clone());
+                }
+                self.default_path()
+                    .map(|p| p.to_path_buf())
+                    .ok_or_else(|| ConfigError::NoMatchingTarget(map.keys().join(", ")))
+            }
+        }
+    }
+}
+
+impl From<PathBuf> for TargetSpec {
+    fn from(value: PathBuf) -> Self {
+        Self::Path(value)
+    }
+}
+
+/// A snippet or variable value, optionally with variants that depend on the active tags.
+///
+/// The variants are checked in order and the first one whose `requires` match is used.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum TaggedValue {
+    Value(String),
+    Variants(Vec<TaggedVariant>),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TaggedVariant {
+    pub value: String,
+    #[serde(skip_serializing_if = "BTreeSet::is_empty", default)]
+    pub requires: BTreeSet<RequireTag>,
+}
+
+impl TaggedValue {
+    pub fn resolve(&self, active_tags: &BTreeSet<Tag>) -> Option<&str> {
+        match self {
+            Self::Value(value) => Some(value),
+            Self::Variants(variants) => variants
+                .iter()
+                .find(|v| v.requires.iter().all(|r| r.matches(active_tags)))
+                .map(|v| v.value.as_str()),
+        }
+    }
+
+    /// All values regardless of the active tags
+    pub fn values(&self) -> Vec<&str> {
+        match self {
+            Self::Value(value) => vec![value],
+            Self::Variants(variants) => variants.iter().map(|v| v.value.as_str()).collect(),
+        }
+    }
+}
+
+impl From<&str> for TaggedValue {
+    fn from(value: &str) -> Self {
+        Self::Value(value.to_string())
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct LinkConfig {
     pub source: SourceSpecOrPath,
-    pub target: PathBuf,
+    pub target: TargetSpec,
     #[serde(default)]
     #[serde(skip_serializing_if = "is_default_link")]
     pub link: LinkType,
@@ -741,10 +1033,124 @@ pub struct LinkConfig {
     #[serde(default)]
     #[serde(skip_serializing_if = "is_default_fallback")]
     pub fallback: FallbackOperation,
+    /// Fallback used instead of `fallback` when the existing target is a directory
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fallback_dir: Option<FallbackOperation>,
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub order: Option<u64>,
+    /// Encoding of the rendered source when handlebars pre-processing is used
+    #[serde(flatten)]
+    pub encoding: TextEncoding,
+    #[serde(flatten)]
+    pub ownership: Ownership,
+    /// What to do when a hard link crosses file systems
+    #[serde(default)]
+    #[serde(skip_serializing_if = "HardlinkFallback::is_default")]
+    pub hardlink_fallback: HardlinkFallback,
+    /// Only link these children of the source directory individually, e.g.
+    /// `["init.lua", "lua/"]`. Everything else in the target directory is left alone.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub manage_children: Vec<String>,
+    /// Allow the target to be inside of the hermit directory, otherwise the plan fails
+    #[serde(default)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub allow_in_repo: bool,
+    /// Mode of the directories created for the target and of its parent directory
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dir_mode: Option<DirMode>,
+}
+
+/// Permissions of a directory, an octal string like `"700"` or a TOML integer like `0o700`.
+/// The umask does not apply. Ignored on platforms without unix permissions.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct DirMode(pub u32);
+
+impl Display for DirMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:04o}", self.0)
+    }
+}
+
+impl Serialize for DirMode {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for DirMode {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Octal(String),
+            Number(u32),
+        }
+        let mode = match Raw::deserialize(deserializer)? {
+            Raw::Octal(s) => u32::from_str_radix(s.trim().trim_start_matches("0o"), 8)
+                .map_err(|e| serde::de::Error::custom(format!("invalid mode '{s}': {e}")))?,
+            Raw::Number(n) => n,
+        };
+        if mode > 0o7777 {
+            return Err(serde::de::Error::custom(format!("invalid mode {mode:o}")));
+        }
+        Ok(Self(mode))
+    }
+}
+
+/// Owner and group of created files, either names or numeric ids.
+/// Changing the ownership requires elevated privileges.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, Hash, PartialEq, Eq)]
+pub struct Ownership {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+}
+
+impl Ownership {
+    pub fn is_default(&self) -> bool {
+        self.owner.is_none() && self.group.is_none()
+    }
+}
+
+impl Display for Ownership {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}",
+            self.owner.as_deref().unwrap_or("*"),
+            self.group.as_deref().unwrap_or("*")
+        )
+    }
 }
+
+/// Behavior when a hard link can not be created because source and target are on different file systems
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, Hash, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HardlinkFallback {
+    /// Copy the file instead, status then checks it like a copy
+    #[default]
+    Copy,
+    /// Fail with an error naming both mount points
+    Fail,
+}
+
+impl HardlinkFallback {
+    pub fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
 fn is_default_fallback(fallback: &FallbackOperation) -> bool {
     matches!(fallback, FallbackOperation::Abort)
 }
@@ -817,6 +1223,9 @@ pub enum FileStatus {
     FailedToAccessFile(PathBuf, std::io::Error),
     FailedToTraverseDir(PathBuf, std::io::Error),
     FailedToHashFile(PathBuf, std::io::Error),
+    OwnershipMismatch(PathBuf, String),
+    SymlinkLoop(PathBuf),
+    DirModeMismatch(PathBuf, DirMode, DirMode),
 }
 impl FileStatus {
     pub fn is_ok(&self) -> bool {
@@ -829,6 +1238,7 @@ impl FileStatus {
                 | Self::FailedToGetMetadata(_, _)
                 | Self::FailedToHashFile(_, _)
                 | Self::FailedToTraverseDir(_, _)
+                | Self::SymlinkLoop(_)
         )
     }
 }
@@ -849,6 +1259,9 @@ impl Display for FileStatus {
             FileStatus::DestinationDoesNotExist(path_buf) => {
                 write!(f, "The destination {path_buf:?} does not exist")
             }
+            FileStatus::OwnershipMismatch(path_buf, msg) => {
+                write!(f, "The ownership of {path_buf:?} differs: {msg}")
+            }
             FileStatus::FailedToGetMetadata(path_buf, error) => {
                 write!(
                     f,
@@ -885,11 +1298,68 @@ impl Display for FileStatus {
                 f,
                 "The hash of the file {path_buf:?} differs: {src_hash} (src) vs {dst_hash} (dst)"
             ),
+            FileStatus::SymlinkLoop(path_buf) => {
+                write!(f, "Symlink loop detected at {path_buf:?}")
+            }
+            FileStatus::DirModeMismatch(path_buf, actual, expected) => write!(
+                f,
+                "The directory {path_buf:?} has mode {actual} instead of {expected}"
+            ),
+        }
+    }
+}
+
+impl LinkConfig {
+    /// Rejects fallback combinations that can only fail or are ambiguous when applied
+    pub fn validate_fallback(&self) -> Result<(), String> {
+        match (self.fallback, self.fallback_dir) {
+            (_, Some(FallbackOperation::Delete)) => Err(
+                "fallback_dir = \"Delete\" can only remove empty directories, use \"DeleteDir\""
+                    .to_string(),
+            ),
+            (FallbackOperation::DeleteDir, Some(dir)) if dir != FallbackOperation::DeleteDir => {
+                Err(format!(
+                    "fallback = \"DeleteDir\" removes directories, which contradicts fallback_dir = \"{dir:?}\", use fallback = \"Delete\""
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The target path, selected by the active tags for per tag targets
+    fn resolve_target(
+        &self,
+        cfg: &HermitConfig,
+        options: &CliOptions,
+    ) -> Result<PathBuf, ConfigError> {
+        match &self.target {
+            TargetSpec::Path(p) => Ok(p.clone()),
+            per_tag => {
+                let active_tags = cfg
+                    .global_config()
+                    .get_active_tags(&options.tags, &options.profile)?;
+                per_tag.resolve(&active_tags)
+            }
         }
     }
 }
 
-impl LinkConfig {}
+/// Canonicalizes the longest existing ancestor of the path, so that symlinked parents
+/// of a path that does not exist yet are resolved as well. The last component is kept
+/// as is, as it is usually an existing link into the hermit directory.
+fn resolve_existing(path: &Path) -> PathBuf {
+    for ancestor in path.ancestors().skip(1) {
+        if let Ok(resolved) = ancestor.canonicalize() {
+            let rest = path.strip_prefix(ancestor).unwrap_or(Path::new(""));
+            return if rest.as_os_str().is_empty() {
+                resolved
+            } else {
+                resolved.join(rest)
+            };
+        }
+    }
+    path.to_path_buf()
+}
 
 impl ConfigItem for LinkConfig {
     fn requires(&self) -> &BTreeSet<RequireTag> {
@@ -900,11 +1370,76 @@ impl ConfigItem for LinkConfig {
         cfg: &HermitConfig,
         options: &CliOptions,
     ) -> Result<ArcAction, ConfigError> {
-        Ok(Arc::new(Actions::Link(LinkAction::new(
-            self,
-            cfg,
-            &options.fallback,
-        )?)))
+        let target = self.resolve_target(cfg, options)?;
+        if let Some(file) = self.source.path() {
+            let src = cfg.canonicalize_source_path::<LinkActionError>(&file.to_path_buf(), true)?;
+            let hermit_dir = cfg.global_config().hermit_dir().to_path_buf();
+            let hermit_dir = hermit_dir.canonicalize().unwrap_or(hermit_dir);
+            if !src.starts_with(&hermit_dir) {
+                if options.strict {
+                    return Err(ConfigError::SourceOutsideHermitDir(
+                        src,
+                        cfg.hermit_file().to_path_buf(),
+                    ));
+                }
+                crate::warn!(
+                    "The link source {} in {} is outside of the hermit directory and will not exist on other machines",
+                    src.display(),
+                    cfg.hermit_file().display()
+                );
+            }
+        }
+        let link = match options.link_override {
+            Some(link) if link != self.link => &LinkConfig {
+                link,
+                ..self.clone()
+            },
+            _ => self,
+        };
+        let action = LinkAction::new(link, &target, cfg, options)?;
+        if !self.allow_in_repo {
+            let hermit_dir = cfg.global_config().hermit_dir().to_path_buf();
+            let canonical = hermit_dir.canonicalize().unwrap_or(hermit_dir.clone());
+            let dst = resolve_existing(action.dst());
+            if dst.starts_with(&hermit_dir) || dst.starts_with(&canonical) {
+                return Err(ConfigError::TargetInsideHermitDir(
+                    self.id(),
+                    dst,
+                    cfg.hermit_file().to_path_buf(),
+                ));
+            }
+        }
+        Ok(Arc::new(Actions::Link(action)))
+    }
+
+    fn as_actions(
+        &self,
+        cfg: &HermitConfig,
+        options: &CliOptions,
+    ) -> Result<Vec<ArcAction>, ConfigError> {
+        if self.manage_children.is_empty() {
+            return Ok(vec![self.as_action(cfg, options)?]);
+        }
+        let Some(source_dir) = self.source.path() else {
+            return Err(ConfigError::ManageChildrenWithoutDirectory(
+                self.id(),
+                cfg.hermit_file().to_path_buf(),
+            ));
+        };
+        let target = self.resolve_target(cfg, options)?;
+        self.manage_children
+            .iter()
+            .map(|child| {
+                let child = child.trim_end_matches('/');
+                LinkConfig {
+                    source: SourceSpecOrPath::Path(source_dir.join(child)),
+                    target: TargetSpec::Path(target.join(child)),
+                    manage_children: Vec::new(),
+                    ..self.clone()
+                }
+                .as_action(cfg, options)
+            })
+            .collect()
     }
 
     fn id(&self) -> String {
@@ -920,7 +1455,11 @@ impl ConfigItem for LinkConfig {
 pub struct InstallConfig {
     pub name: String,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub check: Option<String>,
+    pub check: Option<Check>,
+    /// Whether all or any of the check commands have to succeed
+    #[serde(default)]
+    #[serde(skip_serializing_if = "CheckMode::is_default")]
+    pub check_mode: CheckMode,
     pub install: String,
     #[serde(skip_serializing_if = "BTreeSet::is_empty", default)]
     pub requires: BTreeSet<RequireTag>,
@@ -929,54 +1468,364 @@ pub struct InstallConfig {
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub order: Option<u64>,
+    /// Shell used for the check and install commands, defaults to the platform shell
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Shell::is_default")]
+    pub shell: Shell,
+    /// Run at most this often, for maintenance tasks like updating caches
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<Schedule>,
+    /// Run the check and install commands with the home directory mounted read-only
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sandbox: Option<Sandbox>,
+    /// Repeat the check after the installation, e.g. in a login shell for installers that
+    /// extend the PATH in rc files
+    #[serde(default)]
+    #[serde(skip_serializing_if = "PostInstallCheck::is_default")]
+    pub post_install_check: PostInstallCheck,
+    /// Compare the directories of the PATH and $XDG_CONFIG_HOME before and after the
+    /// installation and report the files the installer added, changed or removed
+    #[serde(default)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub report_changes: bool,
 }
 
-impl ConfigItem for InstallConfig {
-    fn requires(&self) -> &BTreeSet<RequireTag> {
-        &self.requires
-    }
+/// How the check is repeated for tools that only show up in a new shell, like rustup or
+/// nvm which extend the PATH in rc files. The check fails if it does not pass after the
+/// installation. Only supported for POSIX shells.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PostInstallCheck {
+    /// The check is not repeated
+    #[default]
+    None,
+    /// The check is repeated in the same environment
+    Check,
+    /// A failing check is repeated in a login shell of the user, `$SHELL -l -c`
+    LoginShell,
+    /// A failing check is repeated after sourcing these files, e.g. `~/.cargo/env`
+    Source(Vec<PathBuf>),
+}
 
-    fn as_action(
-        &self,
-        cfg: &HermitConfig,
-        _options: &CliOptions,
-    ) -> Result<ArcAction, ConfigError> {
-        Ok(Arc::new(Actions::Install(InstallAction::new(self, cfg)?)))
+impl PostInstallCheck {
+    pub fn is_default(&self) -> bool {
+        self == &Self::default()
     }
+}
 
-    fn id(&self) -> String {
-        format!("Install {}", self.name)
+/// A single check command or a list of commands that are combined by the check mode
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Check {
+    Single(String),
+    List(Vec<String>),
+}
+
+impl Check {
+    pub fn commands(&self) -> Vec<&str> {
+        match self {
+            Check::Single(cmd) => vec![cmd.as_str()],
+            Check::List(cmds) => cmds.iter().map(String::as_str).collect(),
+        }
     }
 
-    fn order(&self) -> Option<u64> {
-        self.order
+    /// Applies `f` to every command, keeping the single or list form
+    pub fn try_map<E>(&self, f: impl Fn(&str) -> Result<String, E>) -> Result<Self, E> {
+        Ok(match self {
+            Check::Single(cmd) => Check::Single(f(cmd)?),
+            Check::List(cmds) => Check::List(
+                cmds.iter()
+                    .map(|cmd| f(cmd))
+                    .collect::<Result<Vec<_>, E>>()?,
+            ),
+        })
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Default)]
-pub struct CliOptions {
-    pub fallback: Option<FallbackOperation>,
-    pub confirm: bool,
-    pub verbose: bool,
-    pub tags: Vec<String>,
-    pub profile: Option<String>,
-    pub json: Option<PathBuf>,
+impl Hash for Check {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        // A single check hashes like a plain string to keep the ids of existing installs stable
+        match self {
+            Check::Single(cmd) => cmd.hash(state),
+            Check::List(cmds) => cmds.hash(state),
+        }
+    }
 }
 
-pub trait ConfigItem {
-    fn id(&self) -> String;
-    fn requires(&self) -> &BTreeSet<RequireTag>;
-    fn order(&self) -> Option<u64>;
-    fn total_order(&self, cfg: &HermitConfig) -> u64 {
-        self.order().or(cfg.order).unwrap_or(0)
+impl From<&str> for Check {
+    fn from(value: &str) -> Self {
+        Check::Single(value.to_string())
     }
-    fn get_all_requires(&self, cfg: &HermitConfig) -> BTreeSet<RequireTag> {
-        let mut requires = self.requires().clone();
-        requires.extend(cfg.requires.iter().cloned());
-        requires
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, Hash, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckMode {
+    /// All checks have to succeed
+    #[default]
+    All,
+    /// One successful check is sufficient
+    Any,
+}
+
+impl CheckMode {
+    pub fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+/// The shell used to run commands and scripts
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, Hash, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Shell {
+    /// sh on unix, pwsh or powershell on Windows
+    #[default]
+    Auto,
+    Sh,
+    Bash,
+    Zsh,
+    Pwsh,
+    Powershell,
+    Cmd,
+}
+
+impl Shell {
+    pub fn is_default(&self) -> bool {
+        *self == Shell::default()
+    }
+
+    /// Resolves [`Shell::Auto`] to the default shell of the platform
+    pub fn resolve(self) -> Shell {
+        match self {
+            Shell::Auto if cfg!(windows) => {
+                if which::which("pwsh").is_ok() {
+                    Shell::Pwsh
+                } else {
+                    Shell::Powershell
+                }
+            }
+            Shell::Auto => Shell::Sh,
+            shell => shell,
+        }
+    }
+
+    pub fn program(&self) -> &'static str {
+        match self.resolve() {
+            Shell::Auto | Shell::Sh => "sh",
+            Shell::Bash => "bash",
+            Shell::Zsh => "zsh",
+            Shell::Pwsh => "pwsh",
+            Shell::Powershell => "powershell",
+            Shell::Cmd => "cmd",
+        }
+    }
+
+    /// Arguments to run an inline command, the command is appended as last argument
+    pub fn command_args(&self) -> &'static [&'static str] {
+        match self.resolve() {
+            Shell::Pwsh | Shell::Powershell => &["-NoProfile", "-NonInteractive", "-Command"],
+            Shell::Cmd => &["/C"],
+            _ => &["-c"],
+        }
+    }
+
+    /// Arguments to run a script file, the file is appended as last argument
+    pub fn script_args(&self) -> &'static [&'static str] {
+        match self.resolve() {
+            Shell::Pwsh | Shell::Powershell => &[
+                "-NoProfile",
+                "-NonInteractive",
+                "-ExecutionPolicy",
+                "Bypass",
+                "-File",
+            ],
+            Shell::Cmd => &["/C"],
+            _ => &[],
+        }
+    }
+
+    /// Extension of script files, Windows shells refuse to run scripts without it
+    pub fn script_extension(&self) -> &'static str {
+        match self.resolve() {
+            Shell::Pwsh | Shell::Powershell => ".ps1",
+            Shell::Cmd => ".cmd",
+            _ => ".sh",
+        }
+    }
+}
+
+impl ConfigItem for InstallConfig {
+    fn requires(&self) -> &BTreeSet<RequireTag> {
+        &self.requires
+    }
+
+    fn as_action(
+        &self,
+        cfg: &HermitConfig,
+        options: &CliOptions,
+    ) -> Result<ArcAction, ConfigError> {
+        Ok(Arc::new(Actions::Install(InstallAction::new(
+            self,
+            cfg,
+            options.install_deadline,
+            options.output_log.clone(),
+        )?)))
+    }
+
+    fn id(&self) -> String {
+        format!("Install {}", self.name)
+    }
+
+    fn order(&self) -> Option<u64> {
+        self.order
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum UnpackType {
+    #[serde(rename = "tar.gz", alias = "tgz")]
+    TarGz,
+    #[serde(rename = "zip")]
+    Zip,
+}
+
+impl Display for UnpackType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TarGz => write!(f, "tar.gz"),
+            Self::Zip => write!(f, "zip"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct FetchConfig {
+    /// URL to download, may contain handlebars expressions
+    pub url: String,
+    /// Target file, or target directory when unpacking
+    pub target: PathBuf,
+    /// Expected SHA-256 checksum of the downloaded file
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// Archive format to unpack into the target directory
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unpack: Option<UnpackType>,
+    /// Number of leading path components to remove when unpacking
+    #[serde(default)]
+    #[serde(skip_serializing_if = "is_zero")]
+    pub strip_components: usize,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    pub requires: BTreeSet<RequireTag>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order: Option<u64>,
+}
+
+fn is_zero(value: &usize) -> bool {
+    *value == 0
+}
+
+impl ConfigItem for FetchConfig {
+    fn requires(&self) -> &BTreeSet<RequireTag> {
+        &self.requires
+    }
+
+    fn as_action(
+        &self,
+        cfg: &HermitConfig,
+        options: &CliOptions,
+    ) -> Result<ArcAction, ConfigError> {
+        Ok(Arc::new(Actions::Fetch(FetchAction::new(
+            self,
+            cfg,
+            options.target_prefix.as_deref(),
+        )?)))
+    }
+
+    fn id(&self) -> String {
+        format!("Fetch {} -> {:?}", self.url, self.target)
+    }
+
+    fn order(&self) -> Option<u64> {
+        self.order
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct CliOptions {
+    pub fallback: Option<FallbackOperation>,
+    pub confirm: bool,
+    pub verbosity: Verbosity,
+    pub tags: Vec<String>,
+    pub profile: Option<String>,
+    pub json: Option<PathBuf>,
+    /// Fail on link sources outside of the hermit directory instead of warning
+    pub strict: bool,
+    /// Tags that are never active, even if detected or requested
+    pub exclude_tags: Vec<String>,
+    /// Use this link type for all links instead of the configured one
+    pub link_override: Option<LinkType>,
+    /// Install commands still running at this point in time are killed
+    #[serde(skip)]
+    pub install_deadline: Option<std::time::Instant>,
+    /// Only run scheduled actions that are due
+    pub due_only: bool,
+    /// Place all targets below this directory instead of the root directory
+    pub target_prefix: Option<PathBuf>,
+    /// Only include actions of these types, all types if empty
+    pub types: Vec<ActionType>,
+    /// Exclude actions of these types
+    pub skip_types: Vec<ActionType>,
+    /// Stream the output of install commands to files below this directory
+    #[serde(skip)]
+    pub output_log: Option<OutputLog>,
+    /// Handlebars template to render the report of the run with
+    pub report_template: Option<PathBuf>,
+    /// Leave run specific values like the output directory out of the report
+    pub deterministic: bool,
+    /// Prometheus textfile the metrics of the run are written to
+    pub metrics_file: Option<PathBuf>,
+}
+
+/// Places an absolute `path` below `prefix`, relative paths are joined as they are
+pub fn with_prefix(prefix: &Path, path: &Path) -> PathBuf {
+    prefix.join(
+        path.components()
+            .filter(|c| !matches!(c, Component::Prefix(_) | Component::RootDir))
+            .collect::<PathBuf>(),
+    )
+}
+
+pub trait ConfigItem {
+    fn id(&self) -> String;
+    fn requires(&self) -> &BTreeSet<RequireTag>;
+    fn order(&self) -> Option<u64>;
+    fn total_order(&self, cfg: &HermitConfig) -> u64 {
+        match (cfg.settings.order_mode, self.order(), cfg.order) {
+            (OrderMode::Max, Some(item), Some(cfg)) => item.max(cfg),
+            (_, item, cfg) => item.or(cfg).unwrap_or(0),
+        }
+    }
+    fn get_all_requires(&self, cfg: &HermitConfig) -> BTreeSet<RequireTag> {
+        let mut requires = self.requires().clone();
+        requires.extend(cfg.requires.iter().cloned());
+        requires
+    }
+    fn as_action(&self, cfg: &HermitConfig, options: &CliOptions)
+    -> Result<ArcAction, ConfigError>;
+    /// All actions of the item, items that expand to several actions override this
+    fn as_actions(
+        &self,
+        cfg: &HermitConfig,
+        options: &CliOptions,
+    ) -> Result<Vec<ArcAction>, ConfigError> {
+        Ok(vec![self.as_action(cfg, options)?])
     }
-    fn as_action(&self, cfg: &HermitConfig, options: &CliOptions)
-    -> Result<ArcAction, ConfigError>;
 }
 
 #[derive(Debug, Default)]
@@ -986,11 +1835,18 @@ pub struct GlobalConfig {
     all_profiles: BTreeMap<String, BTreeSet<Tag>>,
     all_required_tags: BTreeSet<RequireTag>,
     all_detected_tags: BTreeSet<Tag>,
-    all_snippets: BTreeMap<String, String>,
+    all_snippets: BTreeMap<String, TaggedValue>,
+    all_variables: BTreeMap<String, TaggedValue>,
+    /// The tags used to resolve tagged snippets and variables, see [`GlobalConfig::set_render_tags`]
+    render_tags: RwLock<Option<BTreeSet<Tag>>>,
     all_detectors: BTreeMap<String, DetectorConfig>,
+    all_tag_aliases: BTreeMap<Tag, BTreeSet<Tag>>,
+    load_errors: Vec<ConfigError>,
 }
 
 impl GlobalConfig {
+    /// Loads all given hermit.toml files. Files that fail to load are skipped, the
+    /// errors are retained and can be inspected with [`GlobalConfig::load_errors`].
     pub fn from_paths(hermit_dir: &Path, paths: &[PathBuf]) -> Result<Arc<Self>, ConfigError> {
         let mut errors = Vec::new();
         Ok(Arc::new_cyclic(|global_config: &Weak<GlobalConfig>| {
@@ -1027,9 +1883,25 @@ impl GlobalConfig {
                         ));
                         continue;
                     }
-                    log::debug!("Adding source {k}: {v}");
+                    log::debug!("Adding source {k}: {v:?}");
                     result.all_snippets.insert(k.to_lowercase(), v.clone());
                 }
+                for (k, v) in &config.variables {
+                    if result.all_variables.contains_key(k) {
+                        crate::error!(
+                            "Duplicate variable '{}' in config file: {}",
+                            k,
+                            config.path.display()
+                        );
+                        errors.push(ConfigError::DuplicateVariable(
+                            k.to_string(),
+                            config.path.clone(),
+                        ));
+                        continue;
+                    }
+                    log::debug!("Adding variable {k}: {v:?}");
+                    result.all_variables.insert(k.to_string(), v.clone());
+                }
                 for (k, v) in &config.detectors {
                     if result.all_detectors.contains_key(&k.to_lowercase()) {
                         crate::error!(
@@ -1063,21 +1935,83 @@ impl GlobalConfig {
                     }
                     result.all_profiles.insert(profile_lc, tags.clone());
                 }
+                for (alias, tags) in &config.tag_aliases {
+                    log::debug!("Adding tag alias {alias}: {tags:?}");
+                    if result.all_tag_aliases.contains_key(alias) {
+                        crate::error!(
+                            "Duplicate tag alias '{}' in config file: {}",
+                            alias,
+                            config.path.display()
+                        );
+                        errors.push(ConfigError::DuplicateTagAlias(
+                            alias.to_string(),
+                            config.path.clone(),
+                        ));
+                        continue;
+                    }
+                    result.all_tag_aliases.insert(alias.clone(), tags.clone());
+                }
                 let relative_path = path.strip_prefix(hermit_dir).unwrap_or(path);
                 let relative_path_str = relative_path.to_string_lossy().to_string();
                 result.subconfigs.insert(relative_path_str, config);
             }
-            match get_detected_tags(&result) {
+            // Built-in tags are overridden before custom detectors run, they may reference them
+            if let Some(overrides) = result.root_config().map(|cfg| cfg.builtin_tags.clone()) {
+                override_builtin_tags(&mut result.all_detected_tags, &overrides);
+            }
+            match get_referenced_detected_tags(&result) {
                 Ok(custom_detected) => result.all_detected_tags.extend(custom_detected),
                 Err(e) => {
                     crate::error!("Custom detector caused error: {e}");
+                    errors.push(ConfigError::Detector(e));
                 }
             }
             log::debug!("Detected tags: {:?}", result.all_detected_tags);
+            result.load_errors = errors;
             result
         }))
     }
 
+    /// Same as [`GlobalConfig::from_paths`], but fails if any error occurred while loading
+    pub fn from_paths_strict(
+        hermit_dir: &Path,
+        paths: &[PathBuf],
+    ) -> Result<Arc<Self>, ConfigError> {
+        let global_config = Self::from_paths(hermit_dir, paths)?;
+        let errors = global_config.load_errors();
+        if !errors.is_empty() {
+            let files = errors
+                .iter()
+                .filter_map(ConfigError::path)
+                .collect::<BTreeSet<_>>()
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            return Err(ConfigError::StrictConfig(errors.len(), files));
+        }
+        Ok(global_config)
+    }
+
+    /// Errors that occurred while loading the configuration files
+    pub fn load_errors(&self) -> &[ConfigError] {
+        &self.load_errors
+    }
+
+    /// Prints all errors that occurred while loading the configuration
+    pub fn report_load_errors(&self) {
+        if self.load_errors.is_empty() {
+            return;
+        }
+        crate::warn!(
+            "The configuration was loaded with {} error(s), affected items are skipped:",
+            self.load_errors.len()
+        );
+        for e in &self.load_errors {
+            crate::error!("{e}");
+        }
+    }
+
     pub fn hermit_dir(&self) -> &Path {
         &self.hermit_dir
     }
@@ -1098,14 +2032,95 @@ impl GlobalConfig {
         self.all_detectors.iter()
     }
 
+    pub fn all_tag_aliases(&self) -> impl IntoIterator<Item = (&Tag, &BTreeSet<Tag>)> {
+        self.all_tag_aliases.iter()
+    }
+
     pub fn subconfigs(&self) -> impl IntoIterator<Item = (&String, &ArcHermitConfig)> {
         self.subconfigs.iter()
     }
 
-    pub fn get_snippet(&self, key: &str) -> Option<&String> {
+    pub fn get_snippet(&self, key: &str) -> Option<&TaggedValue> {
         self.all_snippets.get(key)
     }
 
+    /// Sets the active tags used to select the variants of snippets and variables
+    pub fn set_render_tags(&self, tags: BTreeSet<Tag>) {
+        *self.render_tags.write().expect("Render tags lock poisoned") = Some(tags);
+    }
+
+    /// The tags set with [`GlobalConfig::set_render_tags`] or the detected tags and the default
+    /// profile if none were set
+    pub fn render_tags(&self) -> BTreeSet<Tag> {
+        if let Some(tags) = self
+            .render_tags
+            .read()
+            .expect("Render tags lock poisoned")
+            .as_ref()
+        {
+            return tags.clone();
+        }
+        self.get_active_tags(&[], &None)
+            .unwrap_or_else(|_| self.all_detected_tags.clone())
+    }
+
+    /// All snippets by name with the config that declares them
+    pub fn snippet_origins(&self) -> BTreeMap<String, &ArcHermitConfig> {
+        let mut origins = BTreeMap::new();
+        for (_, cfg) in self.subconfigs() {
+            for name in cfg.snippets.keys() {
+                origins.entry(name.to_lowercase()).or_insert(cfg);
+            }
+        }
+        origins
+    }
+
+    /// References to unknown snippets and reference cycles between snippets
+    pub fn snippet_problems(&self) -> Vec<String> {
+        fn visit(
+            name: &str,
+            snippets: &BTreeMap<String, TaggedValue>,
+            stack: &mut Vec<String>,
+            done: &mut BTreeSet<String>,
+            problems: &mut Vec<String>,
+        ) {
+            if let Some(pos) = stack.iter().position(|s| s == name) {
+                let cycle = stack[pos..].join(" -> ");
+                problems.push(format!("Snippet cycle: {cycle} -> {name}"));
+                return;
+            }
+            let Some(src) = snippets.get(name) else {
+                return;
+            };
+            if !done.insert(name.to_string()) {
+                return;
+            }
+            stack.push(name.to_string());
+            for reference in src.values().into_iter().flat_map(snippet_references) {
+                if snippets.contains_key(&reference) {
+                    visit(&reference, snippets, stack, done, problems);
+                } else {
+                    problems.push(format!(
+                        "Snippet '{name}' references unknown snippet '{reference}'"
+                    ));
+                }
+            }
+            stack.pop();
+        }
+        let mut problems = Vec::new();
+        let mut done = BTreeSet::new();
+        for name in self.all_snippets.keys() {
+            visit(
+                name,
+                &self.all_snippets,
+                &mut Vec::new(),
+                &mut done,
+                &mut problems,
+            );
+        }
+        problems
+    }
+
     pub fn get_tags_for_profile(&self, profile: &str) -> Result<BTreeSet<Tag>, ApplyError> {
         let profile = profile.to_lowercase();
         if let Some(tags) = self.all_profiles.get(&profile) {
@@ -1131,6 +2146,7 @@ impl GlobalConfig {
                         .all_required_tags
                         .iter()
                         .any(|r| r.name() == cli_tag.name())
+                        || self.all_tag_aliases.contains_key(&cli_tag)
                     {
                         active_tags.insert(cli_tag);
                     } else {
@@ -1148,9 +2164,99 @@ impl GlobalConfig {
         if let Some(profile_tags) = profile_to_use {
             active_tags.extend(profile_tags.iter().cloned());
         }
+        self.expand_tag_aliases(&mut active_tags);
         Ok(active_tags)
     }
 
+    /// Names of all tags that influence which actions are selected, where they point to or
+    /// how they are rendered
+    pub fn referenced_tag_names(&self) -> BTreeSet<String> {
+        let mut names: BTreeSet<String> = self
+            .all_required_tags
+            .iter()
+            .map(|t| t.name().to_string())
+            .collect();
+        for (alias, implied) in &self.all_tag_aliases {
+            names.insert(alias.name().to_string());
+            names.extend(implied.iter().map(|t| t.name().to_string()));
+        }
+        for tags in self.all_profiles.values() {
+            names.extend(tags.iter().map(|t| t.name().to_string()));
+        }
+        for value in self
+            .all_snippets
+            .values()
+            .chain(self.all_variables.values())
+        {
+            if let TaggedValue::Variants(variants) = value {
+                names.extend(
+                    variants
+                        .iter()
+                        .flat_map(|v| &v.requires)
+                        .map(|t| t.name().to_string()),
+                );
+            }
+        }
+        for cfg in self.subconfigs.values() {
+            names.extend(cfg.requires.iter().map(|t| t.name().to_string()));
+            names.extend(cfg.template_tags.iter().cloned());
+            // Templates in source files are only known after reading them
+            let templates = cfg
+                .link
+                .iter()
+                .map(|link| &link.source)
+                .chain(cfg.patch.iter().map(|patch| &patch.source))
+                .filter_map(|source| match source {
+                    SourceSpecOrPath::SourceSpec(spec)
+                        if spec.pre_processing == PreprocessingType::Handlebars =>
+                    {
+                        spec.file_path()
+                    }
+                    _ => None,
+                });
+            for template in templates {
+                if let Ok(content) = std::fs::read_to_string(cfg.directory().join(template)) {
+                    names.extend(template_tag_names(&content));
+                }
+            }
+            for link in &cfg.link {
+                if let TargetSpec::PerTag(map) = &link.target {
+                    names.extend(
+                        map.keys()
+                            .filter(|key| key.as_str() != TargetSpec::DEFAULT_KEY)
+                            .map(|key| {
+                                Tag::from_str_with_src(key, Source::Config)
+                                    .name()
+                                    .to_string()
+                            }),
+                    );
+                }
+            }
+        }
+        names
+    }
+
+    /// Adds all tags implied by active aliases, aliases can imply other aliases
+    pub fn expand_tag_aliases(&self, active_tags: &mut BTreeSet<Tag>) {
+        let mut pending: Vec<Tag> = active_tags.iter().cloned().collect();
+        while let Some(tag) = pending.pop() {
+            let Some(implied) = self.all_tag_aliases.get(&tag) else {
+                continue;
+            };
+            for implied_tag in implied {
+                let implied_tag = Tag::from_str_with_src(
+                    &implied_tag.to_string(),
+                    Source::Alias(tag.to_string()),
+                );
+                if !active_tags.contains(&implied_tag) {
+                    debug!("Tag {tag} implies {implied_tag}");
+                    pending.push(implied_tag.clone());
+                    active_tags.insert(implied_tag);
+                }
+            }
+        }
+    }
+
     pub fn get_profile(
         &self,
         cli_profile: &Option<String>,
@@ -1179,9 +2285,28 @@ impl GlobalConfig {
     }
 
     pub fn root_config(&self) -> Option<&ArcHermitConfig> {
-        let root_path = self.hermit_dir.join(CONF_FILE_NAME);
+        // The configs are keyed by their path relative to the hermit directory, the path of a
+        // loaded config is canonical
+        let hermit_dir = self
+            .hermit_dir
+            .canonicalize()
+            .unwrap_or_else(|_| self.hermit_dir.clone());
+        let root_path = self.config_file_in(&hermit_dir);
+        self.subconfigs
+            .values()
+            .find(|cfg| cfg.hermit_file() == root_path)
+    }
+
+    /// The config file of a directory, which is the loaded one if it exists
+    /// (it may use an alternative name) or a new hermit.toml otherwise
+    pub fn config_file_in(&self, dir: &Path) -> PathBuf {
         self.subconfigs
-            .get(&root_path.to_string_lossy().to_string())
+            .values()
+            .map(|cfg| cfg.hermit_file())
+            .filter(|path| path.parent() == Some(dir))
+            .min_by_key(|path| path.file_name() != Some(CONF_FILE_NAME.as_ref()))
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| dir.join(CONF_FILE_NAME))
     }
 }
 
@@ -1193,11 +2318,60 @@ pub fn load_hermit_config<P: AsRef<Path>>(
         .map_err(|e| ConfigError::Io(e, path.as_ref().to_path_buf()))?;
     let config: HermitConfig = toml::from_str(&content)
         .map_err(|e| ConfigError::DeserializeToml(e, path.as_ref().to_path_buf()))?;
+    for link in &config.link {
+        link.validate_fallback().map_err(|e| {
+            ConfigError::InvalidFallback(format!("{}: {e}", link.id()), path.as_ref().to_path_buf())
+        })?;
+    }
     let mut config = config.update_path(path);
     config.global_cfg = global_config;
+    config.item_lines = item_lines(&content);
+    config.template_tags = template_tag_names(&content);
     Ok(Arc::new(config))
 }
 
+/// Names used as `tag.<name>` in handlebars templates of the content
+fn template_tag_names(content: &str) -> BTreeSet<String> {
+    let is_name_char = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+    content
+        .match_indices("tag.")
+        .filter(|(start, _)| !content[..*start].ends_with(is_name_char))
+        .map(|(start, matched)| {
+            content[start + matched.len()..]
+                .chars()
+                .take_while(|c| is_name_char(*c))
+                .collect::<String>()
+                .to_lowercase()
+        })
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+/// Lines of the items of the sections that hold a list of items, from the spans of the document
+fn item_lines(content: &str) -> BTreeMap<String, Vec<usize>> {
+    let Ok(document) = toml_edit::Document::parse(content) else {
+        return BTreeMap::new();
+    };
+    let line = |span: Option<std::ops::Range<usize>>| {
+        span.map(|span| line_and_column(content, span.start).0)
+            .unwrap_or_default()
+    };
+    let mut lines = BTreeMap::new();
+    for section in ["link", "patch", "install", "fetch"] {
+        let items = match document.get(section) {
+            Some(toml_edit::Item::ArrayOfTables(tables)) => {
+                tables.iter().map(|t| line(t.span())).collect()
+            }
+            Some(toml_edit::Item::Value(toml_edit::Value::Array(array))) => {
+                array.iter().map(|v| line(v.span())).collect()
+            }
+            _ => continue,
+        };
+        lines.insert(section.to_string(), items);
+    }
+    lines
+}
+
 pub fn load_hermit_config_editable<P: AsRef<Path>>(path: P) -> Result<DocumentMut, ConfigError> {
     let content = std::fs::read_to_string(path.as_ref())
         .map_err(|e| ConfigError::Io(e, path.as_ref().to_path_buf()))?;
@@ -1206,9 +2380,33 @@ pub fn load_hermit_config_editable<P: AsRef<Path>>(path: P) -> Result<DocumentMu
         .map_err(|e| ConfigError::DeserializeDocumentToml(e, path.as_ref().to_path_buf()))
 }
 
-pub fn find_hermit_files(root: &Path) -> Vec<PathBuf> {
+/// Names of the configuration files to discover. Names given on the command line take
+/// precedence over the `config_names` setting of the root hermit.toml.
+pub fn config_file_names(hermit_dir: &Path, cli_names: &[String]) -> Vec<String> {
+    if !cli_names.is_empty() {
+        return cli_names.to_vec();
+    }
+    let mut names = vec![CONF_FILE_NAME.to_string()];
+    let root_settings = std::fs::read_to_string(hermit_dir.join(CONF_FILE_NAME))
+        .ok()
+        .and_then(|content| toml::from_str::<HermitConfig>(&content).ok())
+        .map(|cfg| cfg.settings.config_names)
+        .unwrap_or_default();
+    for name in root_settings {
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    names
+}
+
+pub fn find_hermit_files<S: AsRef<str>>(root: &Path, names: &[S]) -> Vec<PathBuf> {
+    let matches = |path: &Path| {
+        path.file_name()
+            .is_some_and(|f| names.iter().any(|n| f == n.as_ref()))
+    };
     let mut result = Vec::new();
-    if root.is_file() && root.file_name().is_some_and(|f| f == CONF_FILE_NAME) {
+    if root.is_file() && matches(root) {
         result.push(root.to_path_buf());
     } else if root.is_dir()
         && let Ok(entries) = std::fs::read_dir(root)
@@ -1216,8 +2414,8 @@ pub fn find_hermit_files(root: &Path) -> Vec<PathBuf> {
         for entry in entries.flatten() {
             let path = entry.path();
             if path.is_dir() {
-                result.extend(find_hermit_files(&path));
-            } else if path.file_name().is_some_and(|f| f == CONF_FILE_NAME) {
+                result.extend(find_hermit_files(&path, names));
+            } else if matches(&path) {
                 result.push(path);
             }
         }
@@ -1228,22 +2426,46 @@ pub fn find_hermit_files(root: &Path) -> Vec<PathBuf> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::action::{Action, Status};
+
+    /// Writes `content` as the hermit.toml of `dir` and loads the configuration below it
+    fn load_config(dir: &Path, content: &str) -> Arc<GlobalConfig> {
+        std::fs::create_dir_all(dir).unwrap();
+        std::fs::write(dir.join(CONF_FILE_NAME), content).unwrap();
+        load_dir(dir)
+    }
+
+    /// Loads all hermit.toml files below `dir`
+    fn load_dir(dir: &Path) -> Arc<GlobalConfig> {
+        let paths = find_hermit_files(dir, &[CONF_FILE_NAME]);
+        GlobalConfig::from_paths(dir, &paths).unwrap()
+    }
+
+    #[test]
+    fn test_with_prefix() {
+        let prefix = Path::new("/tmp/rehearsal");
+        assert_eq!(
+            with_prefix(prefix, Path::new("/home/u/.zshrc")),
+            PathBuf::from("/tmp/rehearsal/home/u/.zshrc")
+        );
+        assert_eq!(
+            with_prefix(prefix, Path::new("etc/hosts")),
+            PathBuf::from("/tmp/rehearsal/etc/hosts")
+        );
+    }
+
     #[test]
     fn test_handlebar_snippets() {
         let mut hermit_cfg = None;
         let _global = Arc::new_cyclic(|weak| {
             let mut hermit = HermitConfig::create_new(Path::new("bla/hermit.toml"), weak.clone());
+            hermit.snippets.insert("echo1".to_string(), "echo 1".into());
             hermit
                 .snippets
-                .insert("echo1".to_string(), "echo 1".to_string());
-            hermit.snippets.insert(
-                "echo2".to_string(),
-                "echo 2;{{ snippet echo1 }}".to_string(),
-            );
-            hermit.snippets.insert(
-                "echo3".to_string(),
-                "echo 3;{{ snippet echo2 }}".to_string(),
-            );
+                .insert("echo2".to_string(), "echo 2;{{ snippet echo1 }}".into());
+            hermit
+                .snippets
+                .insert("echo3".to_string(), "echo 3;{{ snippet echo2 }}".into());
             let hermit = Arc::new(hermit);
             hermit_cfg = Some(hermit.clone());
             let mut global = GlobalConfig::default();
@@ -1262,4 +2484,434 @@ mod tests {
         let snippet = hermit_cfg.get_snippet("echo3", &BTreeMap::new()).unwrap();
         assert_eq!(snippet, "echo 3;echo 2;echo 1");
     }
+
+    #[test]
+    fn test_snippet_cycles_and_unknown_references() {
+        assert_eq!(
+            snippet_references("a {{ snippet one }} b {{~snippet \"Two\"~}} {{ var.x }}"),
+            vec!["one", "two"]
+        );
+        let temp = tempfile::TempDir::new().unwrap();
+        let global = load_config(
+            temp.path(),
+            "[snippets]\na = \"{{ snippet b }}\"\nb = \"{{ snippet a }}\"\nc = \"{{ snippet missing }}\"\nd = \"ok\"\n",
+        );
+        assert_eq!(
+            global.snippet_problems(),
+            vec![
+                "Snippet cycle: a -> b -> a",
+                "Snippet 'c' references unknown snippet 'missing'"
+            ]
+        );
+        let cfg = global.subconfigs.values().next().unwrap();
+        let err = cfg.get_snippet("a", &BTreeMap::new()).unwrap_err();
+        assert!(err.to_string().contains("Snippet cycle"), "{err}");
+        assert_eq!(cfg.get_snippet("d", &BTreeMap::new()).unwrap(), "ok");
+        assert_eq!(global.snippet_origins().len(), 4);
+    }
+
+    #[test]
+    fn test_tagged_snippets_and_variables() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let global = load_config(
+            temp.path(),
+            r#"
+[snippets]
+install_pkg = [
+    { value = "brew install {{ var.pkg }}", requires = ["mac"] },
+    { value = "apt install {{ var.pkg }}", requires = ["-mac"] },
+]
+only_mac = [{ value = "mac only", requires = ["mac"] }]
+
+[variables]
+pkg = "jq"
+greeting = [
+    { value = "hello mac", requires = ["mac"] },
+    { value = "hello" },
+]
+"#,
+        );
+        let cfg = global.subconfigs.values().next().unwrap();
+        let mac = Tag::new("mac", Source::CommandLine);
+        global.set_render_tags(BTreeSet::from([mac.clone()]));
+        assert_eq!(
+            cfg.get_snippet("install_pkg", &BTreeMap::new()).unwrap(),
+            "brew install jq"
+        );
+        assert_eq!(
+            cfg.render_handlebars("{{ var.greeting }}", &BTreeMap::new())
+                .unwrap(),
+            "hello mac"
+        );
+        let action_vars = BTreeMap::from([("pkg".to_string(), "git".to_string())]);
+        global.set_render_tags(BTreeSet::new());
+        assert_eq!(
+            cfg.get_snippet("install_pkg", &action_vars).unwrap(),
+            "apt install git"
+        );
+        assert_eq!(
+            cfg.render_handlebars("{{ var.greeting }}", &BTreeMap::new())
+                .unwrap(),
+            "hello"
+        );
+        let err = cfg.get_snippet("only_mac", &BTreeMap::new()).unwrap_err();
+        assert!(err.to_string().contains("no variant"), "{err}");
+
+        let sub = temp.path().join("sub");
+        std::fs::create_dir_all(&sub).unwrap();
+        std::fs::write(sub.join(CONF_FILE_NAME), "[variables]\npkg = \"fd\"\n").unwrap();
+        let global = load_dir(temp.path());
+        assert!(matches!(
+            global.load_errors(),
+            [ConfigError::DuplicateVariable(name, _)] if name == "pkg"
+        ));
+    }
+
+    #[test]
+    fn test_load_errors_are_retained() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let broken_dir = temp.path().join("broken");
+        std::fs::create_dir_all(&broken_dir).unwrap();
+        let broken = broken_dir.join(CONF_FILE_NAME);
+        std::fs::write(&broken, "[[link]]\nsource = ").unwrap();
+        let global = load_config(temp.path(), "[snippets]\nhello = \"echo hello\"\n");
+        assert_eq!(global.load_errors().len(), 1);
+        assert!(matches!(
+            global.load_errors()[0],
+            ConfigError::DeserializeToml(_, _)
+        ));
+        assert!(global.get_snippet("hello").is_some());
+        let paths = find_hermit_files(temp.path(), &[CONF_FILE_NAME]);
+        let strict = GlobalConfig::from_paths_strict(temp.path(), &paths);
+        match strict {
+            Err(ConfigError::StrictConfig(1, files)) => {
+                assert_eq!(files, broken.display().to_string())
+            }
+            _ => panic!("Expected a strict config error"),
+        }
+    }
+
+    #[test]
+    fn test_fallback_dir_validation() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let link = |fallback: &str, fallback_dir: &str| {
+            format!(
+                "[[link]]\nsource = \"a\"\ntarget = \"~/.a\"\nfallback = \"{fallback}\"\nfallback_dir = \"{fallback_dir}\"\n"
+            )
+        };
+        let file = temp.path().join(CONF_FILE_NAME);
+        for (fallback, fallback_dir, valid) in [
+            ("Backup", "Abort", true),
+            ("DeleteDir", "DeleteDir", true),
+            ("Backup", "Delete", false),
+            ("DeleteDir", "Backup", false),
+        ] {
+            std::fs::write(&file, link(fallback, fallback_dir)).unwrap();
+            let result = load_hermit_config(&file, Weak::new());
+            assert_eq!(result.is_ok(), valid, "{fallback} / {fallback_dir}");
+            if !valid {
+                assert!(matches!(result, Err(ConfigError::InvalidFallback(_, _))));
+            }
+        }
+    }
+
+    #[test]
+    fn test_alternative_config_names() {
+        let temp = tempfile::TempDir::new().unwrap();
+        std::fs::write(
+            temp.path().join(CONF_FILE_NAME),
+            "[settings]\nstrict_mode = false\nconfig_names = [\"dotfiles.toml\"]\n",
+        )
+        .unwrap();
+        let sub = temp.path().join("zsh");
+        std::fs::create_dir_all(&sub).unwrap();
+        std::fs::write(sub.join("dotfiles.toml"), "").unwrap();
+        let names = config_file_names(temp.path(), &[]);
+        assert_eq!(names, vec![CONF_FILE_NAME, "dotfiles.toml"]);
+        let paths = find_hermit_files(temp.path(), &names);
+        assert_eq!(paths.len(), 2);
+        let global = GlobalConfig::from_paths(temp.path(), &paths).unwrap();
+        assert_eq!(global.config_file_in(&sub), sub.join("dotfiles.toml"));
+        let root = global.root_config().unwrap();
+        assert_eq!(root.settings.config_names, vec!["dotfiles.toml"]);
+        assert_eq!(
+            config_file_names(temp.path(), &["other.toml".to_string()]),
+            vec!["other.toml"]
+        );
+    }
+
+    #[test]
+    fn test_link_source_outside_hermit_dir() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let outside = temp.path().join("outside");
+        std::fs::write(&outside, "").unwrap();
+        let global = load_config(
+            &temp.path().join("hermit"),
+            &format!(
+                "[[link]]\nsource = {:?}\ntarget = {:?}\n",
+                outside,
+                temp.path().join("target")
+            ),
+        );
+        let (_, cfg) = global.subconfigs().into_iter().next().unwrap();
+        let link = &cfg.link[0];
+        assert!(link.as_action(cfg, &CliOptions::default()).is_ok());
+        let strict = CliOptions {
+            strict: true,
+            ..Default::default()
+        };
+        assert!(matches!(
+            link.as_action(cfg, &strict),
+            Err(ConfigError::SourceOutsideHermitDir(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_link_target_inside_hermit_dir() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let hermit_dir = temp.path().join("hermit");
+        std::fs::create_dir_all(&hermit_dir).unwrap();
+        std::fs::write(hermit_dir.join("foo"), "").unwrap();
+        let global = load_config(
+            &hermit_dir,
+            &format!(
+                "[[link]]\nsource = \"foo\"\ntarget = {:?}\n",
+                hermit_dir.join("sub").join("foo")
+            ),
+        );
+        let (_, cfg) = global.subconfigs().into_iter().next().unwrap();
+        let link = &cfg.link[0];
+        assert!(matches!(
+            link.as_action(cfg, &CliOptions::default()),
+            Err(ConfigError::TargetInsideHermitDir(..))
+        ));
+        assert!(matches!(
+            crate::execution_plan::create_execution_plan(&global, &CliOptions::default()),
+            Err(ApplyError::ConfigLoad(ConfigError::TargetInsideHermitDir(
+                ..
+            )))
+        ));
+        let allowed = LinkConfig {
+            allow_in_repo: true,
+            ..link.clone()
+        };
+        assert!(allowed.as_action(cfg, &CliOptions::default()).is_ok());
+    }
+
+    #[test]
+    fn test_order_mode() {
+        let mut cfg = HermitConfig {
+            order: Some(10),
+            ..Default::default()
+        };
+        let item = |order| InstallConfig {
+            order,
+            ..Default::default()
+        };
+        assert_eq!(item(Some(1)).total_order(&cfg), 1);
+        assert_eq!(item(None).total_order(&cfg), 10);
+        cfg.settings.order_mode = OrderMode::Max;
+        assert_eq!(item(Some(1)).total_order(&cfg), 10);
+        assert_eq!(item(Some(20)).total_order(&cfg), 20);
+        assert_eq!(item(None).total_order(&cfg), 10);
+        cfg.order = None;
+        assert_eq!(item(Some(1)).total_order(&cfg), 1);
+    }
+
+    #[test]
+    fn test_only_referenced_detectors_run() {
+        let temp = tempfile::TempDir::new().unwrap();
+        std::fs::write(temp.path().join("b.tpl"), "{{ tag.In_Source }}").unwrap();
+        let global = load_config(
+            temp.path(),
+            r#"
+[detectors]
+used = { enable_if = "exit 0" }
+unused = { enable_if = "exit 0" }
+in_profile = { enable_if = "exit 0" }
+in_variant = { enable_if = "exit 0" }
+in_template = { enable_if = "exit 0" }
+in_source = { enable_if = "exit 0" }
+
+[profiles]
+work = ["in_profile"]
+
+[variables]
+editor = [{ value = "code", requires = ["in_variant"] }, { value = "vim" }]
+
+[[install]]
+name = "tool"
+install = "true"
+requires = ["+used"]
+
+[[link]]
+source = { text = "{{#if tag.in_template}}x{{/if}}", pre_processing = "Handlebars" }
+target = "~/.a"
+
+[[link]]
+source = { file = "b.tpl", pre_processing = "Handlebars" }
+target = "~/.b"
+"#,
+        );
+        assert!(global.load_errors().is_empty());
+        let detected = global.all_detected_tags();
+        for name in [
+            "used",
+            "in_profile",
+            "in_variant",
+            "in_template",
+            "in_source",
+        ] {
+            assert!(detected.contains(&Tag::new(name, Source::Config)), "{name}");
+        }
+        assert!(!detected.contains(&Tag::new("unused", Source::Config)));
+        assert_eq!(
+            template_tag_names("{{tag.a}} {{ mytag.b }} {{#if (eq tag.c-d 1)}}"),
+            BTreeSet::from(["a".to_string(), "c-d".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_tag_aliases() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let global = load_config(
+            temp.path(),
+            r#"
+tag_aliases = { laptop = ["battery", "mobile"], mobile = ["wifi"] }
+
+[[install]]
+name = "powertop"
+install = "true"
+requires = ["+battery"]
+"#,
+        );
+        let active = global
+            .get_active_tags(&["laptop".to_string()], &None)
+            .unwrap();
+        for expected in ["laptop", "battery", "mobile", "wifi"] {
+            assert!(
+                active.contains(&Tag::new(expected, Source::Unknown)),
+                "Missing tag {expected}"
+            );
+        }
+        let active = global.get_active_tags(&[], &None).unwrap();
+        assert!(!active.contains(&Tag::new("battery", Source::Unknown)));
+    }
+
+    #[test]
+    fn test_patch_source_snippet_and_content() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let dst = temp.path().join("settings.json");
+        let global = load_config(
+            temp.path(),
+            &format!(
+                r#"
+[snippets]
+vscode_settings = '{{"editor.fontSize": {{{{math 2 "*" 7}}}}}}'
+
+[[patch]]
+source = {{ snippet = "vscode_settings" }}
+target = "{dst}"
+
+[[patch]]
+source = {{ content = '{{"files.eol": "\n"}}' }}
+target = "{dst}"
+"#,
+                dst = dst.display()
+            ),
+        );
+        assert!(
+            global.load_errors().is_empty(),
+            "{:?}",
+            global.load_errors()
+        );
+        let (_, cfg) = global.subconfigs().into_iter().next().unwrap();
+        let snippet = PatchAction::new(&cfg.patch[0], cfg, None).unwrap();
+        assert!(
+            snippet
+                .short_description()
+                .contains("snippet vscode_settings")
+        );
+        assert!(matches!(snippet.get_status(cfg, false), Status::NotOk(_)));
+        std::fs::write(&dst, r#"{"editor.fontSize": 14}"#).unwrap();
+        assert!(matches!(snippet.get_status(cfg, false), Status::Ok(_)));
+        let content = PatchAction::new(&cfg.patch[1], cfg, None).unwrap();
+        assert!(content.short_description().contains("inline content"));
+        assert!(matches!(content.get_status(cfg, false), Status::NotOk(_)));
+    }
+
+    #[test]
+    fn test_requires_without_value() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let link = |name: &str, requires: &str| {
+            format!(
+                "[[link]]\nsource = {{ text = \"\" }}\ntarget = \"~/.{name}\"\nrequires = [{requires:?}]\n"
+            )
+        };
+        let global = load_config(
+            temp.path(),
+            &[
+                link("apt", "has_apt"),
+                link("no_apt", "-has_apt"),
+                link("brew", "has_brew"),
+                link("apt_path", "has_apt=/usr/bin/apt"),
+                link("apt_other", "has_apt=/bin/apt"),
+            ]
+            .concat(),
+        );
+        let detected = BTreeSet::from([Tag::new_with_value(
+            "has_apt",
+            "/usr/bin/apt",
+            Source::BuiltInDetector,
+        )]);
+        let plan = crate::execution_plan::create_execution_plan(&global, &CliOptions::default())
+            .unwrap()
+            .filter_actions_by_tags(&detected);
+        let targets = plan
+            .actions
+            .iter()
+            .map(|(_, action)| action.short_description())
+            .collect::<Vec<_>>();
+        assert_eq!(targets.len(), 2, "{targets:?}");
+        assert!(targets.iter().any(|t| t.ends_with(".apt")), "{targets:?}");
+        assert!(
+            targets.iter().any(|t| t.ends_with(".apt_path")),
+            "{targets:?}"
+        );
+        assert!(RequireTag::Negative(Tag::new("has_brew", Source::Config)).matches(&detected));
+        assert!(!RequireTag::Positive(Tag::new("has_brew", Source::Config)).matches(&detected));
+    }
+
+    #[test]
+    fn test_target_spec_per_tag() {
+        let link: LinkConfig = toml::from_str(
+            r#"
+source = "foo"
+target.default = "~/.config/foo"
+target."hostname=work-laptop" = "~/work/foo"
+"#,
+        )
+        .unwrap();
+        let work = BTreeSet::from([Tag::new_with_value(
+            "hostname",
+            "work-laptop",
+            Source::BuiltInDetector,
+        )]);
+        assert_eq!(
+            link.target.resolve(&work).unwrap(),
+            PathBuf::from("~/work/foo")
+        );
+        assert_eq!(
+            link.target.resolve(&BTreeSet::new()).unwrap(),
+            PathBuf::from("~/.config/foo")
+        );
+        let no_default = TargetSpec::PerTag(BTreeMap::from([(
+            "work".to_string(),
+            PathBuf::from("~/work/foo"),
+        )]));
+        assert!(matches!(
+            no_default.resolve(&BTreeSet::new()),
+            Err(ConfigError::NoMatchingTarget(_))
+        ));
+    }
 }
@@ -24,7 +24,7 @@ pub fn math_helper(
     // Check if it's an infix operation (val op val)
     let is_infix = p1
         .and_then(|v| v.value().as_str())
-        .map_or(false, |s| is_unary_operator(s));
+        .is_some_and(is_unary_operator);
 
     if is_infix {
         let Some(operator) = p1 else {
@@ -183,6 +183,7 @@ fn get_f64(param: Option<&handlebars::PathAndJson>, context: &str) -> Result<f64
 }
 
 #[cfg(test)]
+#[allow(clippy::approx_constant)]
 mod tests {
     use serde_json::json;
 
@@ -0,0 +1,255 @@
+// SPDX-FileCopyrightText: 2025 Karsten Becker
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::path::PathBuf;
+
+use clap::ValueEnum;
+
+use crate::common_cli::Verbosity;
+use crate::config::user_settings::UserSettings;
+use crate::config::{CliOptions, FallbackOperation};
+use crate::warn;
+
+/// Builds [`CliOptions`] with a single precedence: values given to the builder (the command
+/// line arguments) override the `HERMIT_*` environment variables, which override the
+/// `[options]` of the user settings file, which override the defaults.
+///
+/// Flags can only be enabled and empty tag lists count as not given, so the parsed
+/// arguments can be passed as they are.
+#[derive(Debug, Default, Clone)]
+pub struct CliOptionsBuilder {
+    fallback: Option<FallbackOperation>,
+    confirm: Option<bool>,
+    verbosity: Option<Verbosity>,
+    tags: Vec<String>,
+    profile: Option<String>,
+    json: Option<PathBuf>,
+    strict: Option<bool>,
+    exclude_tags: Vec<String>,
+}
+
+impl CliOptionsBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn fallback(mut self, fallback: Option<FallbackOperation>) -> Self {
+        self.fallback = fallback.or(self.fallback);
+        self
+    }
+
+    pub fn confirm(mut self, confirm: bool) -> Self {
+        if confirm {
+            self.confirm = Some(true);
+        }
+        self
+    }
+
+    /// Sets the verbosity from the number of `-v` flags, a level already set is kept
+    pub fn verbose(mut self, count: u8) -> Self {
+        if count > 0 {
+            self.verbosity = self.verbosity.or(Some(Verbosity::from_count(count)));
+        }
+        self
+    }
+
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        if quiet {
+            self.verbosity = self.verbosity.or(Some(Verbosity::Quiet));
+        }
+        self
+    }
+
+    pub fn verbosity(mut self, verbosity: Verbosity) -> Self {
+        if verbosity != Verbosity::Normal {
+            self.verbosity = self.verbosity.or(Some(verbosity));
+        }
+        self
+    }
+
+    pub fn strict(mut self, strict: bool) -> Self {
+        if strict {
+            self.strict = Some(true);
+        }
+        self
+    }
+
+    pub fn tags(mut self, tags: &[String]) -> Self {
+        self.tags.extend(tags.iter().cloned());
+        self
+    }
+
+    pub fn exclude_tags(mut self, tags: &[String]) -> Self {
+        self.exclude_tags.extend(tags.iter().cloned());
+        self
+    }
+
+    pub fn profile(mut self, profile: Option<String>) -> Self {
+        self.profile = profile.or(self.profile);
+        self
+    }
+
+    pub fn json(mut self, json: Option<PathBuf>) -> Self {
+        self.json = json.or(self.json);
+        self
+    }
+
+    /// Resolves the options with the process environment and the user settings file
+    pub fn build(self) -> CliOptions {
+        let settings = UserSettings::load().unwrap_or_else(|e| {
+            warn!("Ignoring unreadable user settings: {e}");
+            UserSettings::default()
+        });
+        self.build_with(|name| std::env::var(name).ok(), &settings)
+    }
+
+    /// Resolves the options with the given environment lookup and user settings
+    pub fn build_with<E>(self, env: E, settings: &UserSettings) -> CliOptions
+    where
+        E: Fn(&str) -> Option<String>,
+    {
+        let defaults = &settings.options;
+        let flag = |name: &str| env(name).map(|v| is_truthy(&v));
+        let list = |name: &str| {
+            env(name).map(|v| {
+                v.split(',')
+                    .map(str::trim)
+                    .filter(|t| !t.is_empty())
+                    .map(str::to_string)
+                    .collect::<Vec<_>>()
+            })
+        };
+        let or_list = |given: Vec<String>, name: &str, default: &Vec<String>| {
+            if given.is_empty() {
+                list(name).unwrap_or_else(|| default.clone())
+            } else {
+                given
+            }
+        };
+        let fallback = self.fallback.or_else(|| {
+            env("HERMIT_FALLBACK").and_then(|v| match FallbackOperation::from_str(&v, true) {
+                Ok(fallback) => Some(fallback),
+                Err(e) => {
+                    warn!("Ignoring HERMIT_FALLBACK: {e}");
+                    None
+                }
+            })
+        });
+        CliOptions {
+            fallback: fallback.or(defaults.fallback),
+            confirm: self
+                .confirm
+                .or_else(|| flag("HERMIT_CONFIRM"))
+                .or(defaults.confirm)
+                .unwrap_or_default(),
+            verbosity: self
+                .verbosity
+                .or_else(|| {
+                    // HERMIT_VERBOSE takes a level like -v given n times, or a boolean
+                    let verbose = env("HERMIT_VERBOSE").map(|v| match v.trim().parse::<u8>() {
+                        Ok(count) => Verbosity::from_count(count),
+                        Err(_) if is_truthy(&v) => Verbosity::Verbose,
+                        Err(_) => Verbosity::Normal,
+                    });
+                    verbosity(verbose, flag("HERMIT_QUIET"))
+                })
+                .or_else(|| {
+                    let verbose = defaults.verbose.map(|v| match v {
+                        true => Verbosity::Verbose,
+                        false => Verbosity::Normal,
+                    });
+                    verbosity(verbose, defaults.quiet)
+                })
+                .unwrap_or_default(),
+            tags: or_list(self.tags, "HERMIT_TAGS", &defaults.tags),
+            profile: self
+                .profile
+                .or_else(|| env("HERMIT_PROFILE"))
+                .or_else(|| defaults.profile.clone()),
+            json: self.json.or_else(|| env("HERMIT_JSON").map(PathBuf::from)),
+            strict: self
+                .strict
+                .or_else(|| flag("HERMIT_STRICT"))
+                .or(defaults.strict)
+                .unwrap_or_default(),
+            exclude_tags: or_list(
+                self.exclude_tags,
+                "HERMIT_EXCLUDE_TAGS",
+                &defaults.exclude_tags,
+            ),
+            ..Default::default()
+        }
+    }
+}
+
+/// Combines a verbose and a quiet setting, verbose wins if both are enabled. None if
+/// neither is set, so that the next source is used.
+fn verbosity(verbose: Option<Verbosity>, quiet: Option<bool>) -> Option<Verbosity> {
+    match (verbose, quiet) {
+        (Some(v), _) if v > Verbosity::Normal => Some(v),
+        (_, Some(true)) => Some(Verbosity::Quiet),
+        (None, None) => None,
+        _ => Some(Verbosity::Normal),
+    }
+}
+
+/// Same interpretation of boolean environment variables as clap
+fn is_truthy(value: &str) -> bool {
+    !matches!(
+        value.trim().to_lowercase().as_str(),
+        "" | "0" | "n" | "no" | "f" | "false" | "off"
+    )
+}
+
+impl CliOptions {
+    pub fn builder() -> CliOptionsBuilder {
+        CliOptionsBuilder::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    #[test]
+    fn test_option_precedence() {
+        let env = BTreeMap::from([
+            ("HERMIT_PROFILE", "env"),
+            ("HERMIT_TAGS", "a, b"),
+            ("HERMIT_VERBOSE", "false"),
+        ]);
+        let env = |name: &str| env.get(name).map(|v| v.to_string());
+        let settings: UserSettings = toml::from_str(
+            "[options]\nprofile = \"settings\"\nconfirm = true\nverbose = true\nexclude_tags = [\"gui\"]\n",
+        )
+        .unwrap();
+        let options = CliOptions::builder().build_with(env, &settings);
+        assert_eq!(options.profile.as_deref(), Some("env"));
+        assert_eq!(options.tags, vec!["a", "b"]);
+        assert_eq!(options.exclude_tags, vec!["gui"]);
+        assert!(options.confirm);
+        assert_eq!(options.verbosity, Verbosity::Normal);
+        let options = CliOptions::builder()
+            .profile(Some("cli".to_string()))
+            .tags(&["c".to_string()])
+            .verbose(2)
+            .build_with(env, &settings);
+        assert_eq!(options.profile.as_deref(), Some("cli"));
+        assert_eq!(options.tags, vec!["c"]);
+        assert_eq!(options.verbosity, Verbosity::Debug);
+        let options = CliOptions::builder().build_with(|_| None, &settings);
+        assert_eq!(options.verbosity, Verbosity::Verbose);
+        let quiet = |name: &str| (name == "HERMIT_QUIET").then(|| "1".to_string());
+        let options = CliOptions::builder().build_with(quiet, &settings);
+        assert_eq!(options.verbosity, Verbosity::Quiet);
+        let options = CliOptions::builder()
+            .quiet(true)
+            .build_with(|_| None, &settings);
+        assert_eq!(options.verbosity, Verbosity::Quiet);
+        let options = CliOptions::builder().build_with(|_| None, &UserSettings::default());
+        assert!(options.profile.is_none() && !options.confirm && options.tags.is_empty());
+    }
+}
@@ -0,0 +1,168 @@
+// SPDX-FileCopyrightText: 2025 Karsten Becker
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde::{Deserialize, Serialize};
+
+use crate::file_ops::dirs::HOME_DIR;
+
+/// Runs commands with the home directory mounted read-only, either `sandbox = true` or
+/// `sandbox = { writable = ["~/.cargo"] }` to allow writing below some paths.
+///
+/// Uses bubblewrap (`bwrap`) on Linux and `sandbox-exec` on macOS. Commands fail if the
+/// sandbox is not available instead of running unprotected.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Sandbox {
+    Enabled(bool),
+    Writable {
+        /// Paths that stay writable, `~` and `{{ dir.* }}` are expanded
+        #[serde(default)]
+        writable: Vec<String>,
+    },
+}
+
+impl Sandbox {
+    pub fn is_enabled(&self) -> bool {
+        !matches!(self, Sandbox::Enabled(false))
+    }
+
+    pub fn writable(&self) -> &[String] {
+        match self {
+            Sandbox::Enabled(_) => &[],
+            Sandbox::Writable { writable } => writable,
+        }
+    }
+}
+
+/// A sandbox with expanded paths, ready to wrap commands
+#[derive(Debug, Serialize, Clone, PartialEq, Eq, Default)]
+pub struct SandboxSpec {
+    /// Mounted read-only
+    pub read_only: PathBuf,
+    pub writable: Vec<PathBuf>,
+}
+
+impl SandboxSpec {
+    pub fn new(writable: Vec<PathBuf>) -> Self {
+        Self {
+            read_only: PathBuf::from(HOME_DIR.as_str()),
+            writable,
+        }
+    }
+
+    /// The tool used to sandbox commands on this platform
+    pub fn program() -> Option<&'static str> {
+        if cfg!(target_os = "linux") {
+            Some("bwrap")
+        } else if cfg!(target_os = "macos") {
+            Some("sandbox-exec")
+        } else {
+            None
+        }
+    }
+
+    /// Wraps the command so it runs inside the sandbox, the environment is kept
+    pub fn wrap(&self, command: &Command) -> Result<Command, std::io::Error> {
+        let Some(program) = Self::program() else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "sandboxing is not supported on this platform",
+            ));
+        };
+        let program = which::which(program).map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("{program} is required to run sandboxed commands"),
+            )
+        })?;
+        // Bind mounts need existing paths
+        for path in &self.writable {
+            std::fs::create_dir_all(path)?;
+        }
+        let mut wrapped = Command::new(program);
+        wrapped.args(self.sandbox_args());
+        wrapped.arg(command.get_program()).args(command.get_args());
+        for (key, value) in command.get_envs() {
+            match value {
+                Some(value) => wrapped.env(key, value),
+                None => wrapped.env_remove(key),
+            };
+        }
+        if let Some(dir) = command.get_current_dir() {
+            wrapped.current_dir(dir);
+        }
+        Ok(wrapped)
+    }
+
+    fn sandbox_args(&self) -> Vec<String> {
+        let path = |p: &Path| p.display().to_string();
+        if cfg!(target_os = "macos") {
+            return vec!["-p".to_string(), self.seatbelt_profile()];
+        }
+        let mut args = vec![
+            "--die-with-parent".to_string(),
+            "--dev-bind".to_string(),
+            "/".to_string(),
+            "/".to_string(),
+            "--ro-bind".to_string(),
+            path(&self.read_only),
+            path(&self.read_only),
+        ];
+        for writable in &self.writable {
+            args.extend(["--bind".to_string(), path(writable), path(writable)]);
+        }
+        args
+    }
+
+    /// Profile for sandbox-exec, later rules take precedence
+    fn seatbelt_profile(&self) -> String {
+        let quote = |p: &Path| format!("{:?}", p.display().to_string());
+        let mut profile = format!(
+            "(version 1)(allow default)(deny file-write* (subpath {}))",
+            quote(&self.read_only)
+        );
+        for writable in &self.writable {
+            profile.push_str(&format!(
+                "(allow file-write* (subpath {}))",
+                quote(writable)
+            ));
+        }
+        profile
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sandbox_config() {
+        #[derive(Deserialize)]
+        struct Item {
+            sandbox: Sandbox,
+        }
+        let item: Item = toml::from_str("sandbox = true").unwrap();
+        assert!(item.sandbox.is_enabled());
+        assert!(item.sandbox.writable().is_empty());
+        let item: Item = toml::from_str("sandbox = { writable = [\"~/.cargo\"] }").unwrap();
+        assert!(item.sandbox.is_enabled());
+        assert_eq!(item.sandbox.writable(), ["~/.cargo"]);
+        let spec = SandboxSpec {
+            read_only: PathBuf::from("/home/u"),
+            writable: vec![PathBuf::from("/home/u/.cargo")],
+        };
+        let args = spec.sandbox_args();
+        if cfg!(target_os = "linux") {
+            let ro = args.iter().position(|a| a == "--ro-bind").unwrap();
+            let rw = args.iter().position(|a| a == "--bind").unwrap();
+            assert!(
+                ro < rw,
+                "writable paths must be bound after the read-only home"
+            );
+        }
+    }
+}
@@ -0,0 +1,125 @@
+// SPDX-FileCopyrightText: 2025 Karsten Becker
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::fmt::Display;
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+const HOUR: u64 = 60 * 60;
+const DAY: u64 = 24 * HOUR;
+const WEEK: u64 = 7 * DAY;
+
+/// How often a scheduled action runs at most: `daily`, `weekly`, `monthly` or an
+/// interval like `12h`, `3d` or `2w`
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Schedule {
+    Daily,
+    Weekly,
+    Monthly,
+    /// Interval in seconds
+    Every(u64),
+}
+
+impl Schedule {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(match self {
+            Schedule::Daily => DAY,
+            Schedule::Weekly => WEEK,
+            Schedule::Monthly => 30 * DAY,
+            Schedule::Every(secs) => *secs,
+        })
+    }
+
+    /// An action is due if it never ran or its interval has passed since the last run
+    pub fn is_due(&self, last_run: Option<u64>, now: u64) -> bool {
+        match last_run {
+            Some(last_run) => now.saturating_sub(last_run) >= self.interval().as_secs(),
+            None => true,
+        }
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_lowercase();
+        match s.as_str() {
+            "daily" => return Ok(Schedule::Daily),
+            "weekly" => return Ok(Schedule::Weekly),
+            "monthly" => return Ok(Schedule::Monthly),
+            _ => {}
+        }
+        let invalid = || {
+            format!("Invalid schedule '{s}', expected daily, weekly, monthly or e.g. 12h, 3d, 2w")
+        };
+        let split = s.find(|c: char| !c.is_ascii_digit()).ok_or_else(invalid)?;
+        let (count, unit) = s.split_at(split);
+        let count: u64 = count.parse().map_err(|_| invalid())?;
+        let unit = match unit {
+            "m" => 60,
+            "h" => HOUR,
+            "d" => DAY,
+            "w" => WEEK,
+            _ => return Err(invalid()),
+        };
+        if count == 0 {
+            return Err(invalid());
+        }
+        Ok(Schedule::Every(count * unit))
+    }
+}
+
+impl TryFrom<String> for Schedule {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Schedule> for String {
+    fn from(value: Schedule) -> Self {
+        value.to_string()
+    }
+}
+
+impl Display for Schedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Schedule::Daily => f.write_str("daily"),
+            Schedule::Weekly => f.write_str("weekly"),
+            Schedule::Monthly => f.write_str("monthly"),
+            Schedule::Every(secs) => {
+                let (unit, name) = [(WEEK, "w"), (DAY, "d"), (HOUR, "h"), (60, "m")]
+                    .into_iter()
+                    .find(|(unit, _)| secs % unit == 0)
+                    .unwrap_or((60, "m"));
+                write!(f, "{}{name}", secs / unit)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_due() {
+        assert_eq!("weekly".parse(), Ok(Schedule::Weekly));
+        assert_eq!("3d".parse(), Ok(Schedule::Every(3 * DAY)));
+        assert_eq!(Schedule::Every(3 * DAY).to_string(), "3d");
+        assert_eq!(Schedule::Every(36 * HOUR).to_string(), "36h");
+        assert!("0d".parse::<Schedule>().is_err());
+        assert!("3x".parse::<Schedule>().is_err());
+        assert!("d".parse::<Schedule>().is_err());
+        assert!(Schedule::Daily.is_due(None, 0));
+        assert!(!Schedule::Daily.is_due(Some(1000), 1000 + DAY - 1));
+        assert!(Schedule::Daily.is_due(Some(1000), 1000 + DAY));
+    }
+}
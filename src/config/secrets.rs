@@ -0,0 +1,163 @@
+// SPDX-FileCopyrightText: 2025 Karsten Becker
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::collections::BTreeMap;
+use std::process::Command;
+use std::sync::{LazyLock, Mutex};
+
+use handlebars::{
+    Context, Handlebars, Helper, HelperResult, Output, RenderContext, RenderError,
+    RenderErrorReason,
+};
+
+/// Replacement for secret values in logs and reports
+pub const REDACTED: &str = "********";
+
+/// Secrets resolved during this run, keyed by their reference
+static SECRET_CACHE: LazyLock<Mutex<BTreeMap<String, String>>> =
+    LazyLock::new(|| Mutex::new(BTreeMap::new()));
+
+/// Builds the command line for a secret reference like `op://vault/item/field`.
+/// Custom commands use `{ref}` as placeholder for the reference without the scheme.
+fn secret_command(
+    reference: &str,
+    custom: &BTreeMap<String, String>,
+) -> Result<Vec<String>, RenderError> {
+    let Some((scheme, path)) = reference.split_once("://") else {
+        return Err(RenderErrorReason::Other(format!(
+            "Secret reference '{reference}' must have the form <scheme>://<path>"
+        ))
+        .into());
+    };
+    if let Some(template) = custom.get(scheme) {
+        return Ok(template
+            .split_whitespace()
+            .map(|part| part.replace("{ref}", path))
+            .collect());
+    }
+    let cmd: Vec<String> = match scheme {
+        "op" => vec!["op".into(), "read".into(), reference.into()],
+        "bw" => match path.split_once('/') {
+            Some((item, field)) => vec!["bw".into(), "get".into(), field.into(), item.into()],
+            None => vec!["bw".into(), "get".into(), "password".into(), path.into()],
+        },
+        "pass" => vec!["pass".into(), "show".into(), path.into()],
+        _ => {
+            return Err(RenderErrorReason::Other(format!(
+                "Unknown secret manager '{scheme}' in reference '{reference}'"
+            ))
+            .into());
+        }
+    };
+    Ok(cmd)
+}
+
+/// Resolves a secret by calling the secret manager CLI. Results are cached for the run.
+pub fn resolve_secret(
+    reference: &str,
+    custom: &BTreeMap<String, String>,
+) -> Result<String, RenderError> {
+    if let Some(secret) = SECRET_CACHE.lock().expect("should lock").get(reference) {
+        return Ok(secret.clone());
+    }
+    let cmd = secret_command(reference, custom)?;
+    let (program, args) = cmd.split_first().ok_or_else(|| {
+        RenderErrorReason::Other(format!("Empty secret command for '{reference}'"))
+    })?;
+    let output = Command::new(program).args(args).output().map_err(|e| {
+        RenderErrorReason::Other(format!(
+            "Failed to run {program} for secret '{reference}': {e}"
+        ))
+    })?;
+    if !output.status.success() {
+        return Err(RenderErrorReason::Other(format!(
+            "{program} failed to resolve secret '{reference}': {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+        .into());
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // pass stores metadata after the first line, the other managers print only the value
+    let secret = if program == "pass" {
+        stdout.lines().next().unwrap_or_default().to_string()
+    } else {
+        stdout.trim_end_matches(['\r', '\n']).to_string()
+    };
+    register_secret(reference, &secret);
+    Ok(secret)
+}
+
+/// Remembers a secret value so it gets redacted from all output
+pub fn register_secret(reference: &str, secret: &str) {
+    SECRET_CACHE
+        .lock()
+        .expect("should lock")
+        .insert(reference.to_string(), secret.to_string());
+}
+
+/// Replaces all secret values resolved during this run with [`REDACTED`]
+pub fn redact(msg: &str) -> String {
+    let cache = SECRET_CACHE.lock().expect("should lock");
+    let mut result = msg.to_string();
+    for secret in cache.values().filter(|s| !s.is_empty()) {
+        result = result.replace(secret, REDACTED);
+    }
+    result
+}
+
+pub fn secret_helper(
+    custom: &BTreeMap<String, String>,
+) -> impl Fn(&Helper, &Handlebars, &Context, &mut RenderContext, &mut dyn Output) -> HelperResult
++ Send
++ Sync
++ '_ {
+    move |h: &Helper,
+          _: &Handlebars,
+          _: &Context,
+          _: &mut RenderContext,
+          out: &mut dyn Output|
+          -> HelperResult {
+        let reference = h
+            .param(0)
+            .and_then(|x| x.value().as_str())
+            .ok_or(RenderErrorReason::ParamNotFoundForIndex("secret", 0))?;
+        let secret = resolve_secret(reference, custom)?;
+        out.write(&secret)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secret_command() {
+        let custom = BTreeMap::from([("vault".to_string(), "vault kv get {ref}".to_string())]);
+        assert_eq!(
+            secret_command("op://vault/item/field", &custom).unwrap(),
+            vec!["op", "read", "op://vault/item/field"]
+        );
+        assert_eq!(
+            secret_command("bw://github/username", &custom).unwrap(),
+            vec!["bw", "get", "username", "github"]
+        );
+        assert_eq!(
+            secret_command("vault://secret/token", &custom).unwrap(),
+            vec!["vault", "kv", "get", "secret/token"]
+        );
+        assert!(secret_command("unknown://x", &custom).is_err());
+        assert!(secret_command("no-scheme", &custom).is_err());
+    }
+
+    #[test]
+    fn test_redact() {
+        register_secret("test://redact", "hunter2");
+        assert_eq!(redact("password=hunter2"), format!("password={REDACTED}"));
+        assert_eq!(
+            resolve_secret("test://redact", &BTreeMap::new()).unwrap(),
+            "hunter2"
+        );
+    }
+}
@@ -0,0 +1,218 @@
+// SPDX-FileCopyrightText: 2025 Karsten Becker
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::FallbackOperation;
+use crate::file_ops::dirs::XDG_CONFIG_HOME;
+use crate::hermitgrab_error::ConfigError;
+
+pub const USER_SETTINGS_FILE_NAME: &str = "settings.toml";
+
+/// Settings of the user that are independent of the dotfiles repository,
+/// stored in $XDG_CONFIG_HOME/hermitgrab/settings.toml
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct UserSettings {
+    /// Repository to select during discovery, either `name` or `owner/name`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_repo: Option<String>,
+    #[serde(default, skip_serializing_if = "TelemetrySettings::is_default")]
+    pub telemetry: TelemetrySettings,
+    #[serde(default, skip_serializing_if = "OptionSettings::is_default")]
+    pub options: OptionSettings,
+    #[serde(default, skip_serializing_if = "GitSettings::is_default")]
+    pub git: GitSettings,
+    #[serde(default, skip_serializing_if = "ReportSettings::is_default")]
+    pub report: ReportSettings,
+    #[serde(default, skip_serializing_if = "HttpSettings::is_default")]
+    pub http: HttpSettings,
+    #[serde(default, skip_serializing_if = "NotifySettings::is_default")]
+    pub notify: NotifySettings,
+    /// Commands expanded before parsing, e.g. `alias.up = "apply --profile default --confirm"`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub alias: BTreeMap<String, String>,
+}
+
+/// Proxy and certificate settings of all HTTP and git connections
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct HttpSettings {
+    /// Proxy URL, e.g. `http://proxy:3128`, instead of HTTPS_PROXY and HTTP_PROXY
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    /// Comma separated hosts that are reached without the proxy, instead of NO_PROXY
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub no_proxy: Option<String>,
+    /// Do not verify certificates, same as --insecure
+    #[serde(default)]
+    pub insecure: bool,
+}
+
+impl HttpSettings {
+    pub fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+/// Notification when a long apply finishes, e.g. while it runs in another terminal
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct NotifySettings {
+    /// Seconds an apply has to run before it notifies, defaults to 60
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after_secs: Option<u64>,
+    /// Ring the terminal bell
+    #[serde(default)]
+    pub bell: bool,
+    /// Command run with HERMIT_RESULT, HERMIT_OK_COUNT, HERMIT_SKIPPED_COUNT,
+    /// HERMIT_FAILED_COUNT and HERMIT_DURATION_SECS in its environment
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_finish: Option<String>,
+}
+
+impl NotifySettings {
+    pub fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Runs shorter than this do not notify
+    pub fn threshold(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.after_secs.unwrap_or(60))
+    }
+}
+
+/// Output of install commands that is kept in the history of a run
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ReportSettings {
+    /// Kilobytes kept per output stream of a command, defaults to 1024
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_output_kb: Option<usize>,
+}
+
+impl ReportSettings {
+    pub fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// The number of bytes kept per output stream
+    pub fn output_limit(&self) -> usize {
+        self.max_output_kb.unwrap_or(1024) * 1024
+    }
+}
+
+/// Retries of clone and fetch for flaky networks
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct GitSettings {
+    /// Additional attempts per URL after a network error, defaults to 2
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+    /// Delay before the first retry in milliseconds, doubled for every further retry
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backoff_ms: Option<u64>,
+    /// Mirror URLs tried in order when the repository URL keeps failing
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mirrors: Vec<String>,
+}
+
+impl GitSettings {
+    pub fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+/// Defaults for command line options, overridden by `HERMIT_*` environment variables and
+/// the command line, see [`crate::config::options::CliOptionsBuilder`]
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct OptionSettings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confirm: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verbose: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quiet: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude_tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fallback: Option<FallbackOperation>,
+}
+
+impl OptionSettings {
+    pub fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+/// Anonymous usage statistics, strictly off by default. Events are stored locally
+/// and only uploaded if an endpoint is configured.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TelemetrySettings {
+    #[serde(default)]
+    pub enabled: bool,
+    /// URL the recorded events are posted to as JSON array
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+}
+
+impl TelemetrySettings {
+    pub fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+impl UserSettings {
+    pub fn file() -> PathBuf {
+        PathBuf::from(XDG_CONFIG_HOME.as_str())
+            .join("hermitgrab")
+            .join(USER_SETTINGS_FILE_NAME)
+    }
+
+    /// Loads the user settings, a missing file results in the default settings
+    pub fn load() -> Result<Self, ConfigError> {
+        Self::load_from(&Self::file())
+    }
+
+    pub fn load_from(path: &Path) -> Result<Self, ConfigError> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content =
+            std::fs::read_to_string(path).map_err(|e| ConfigError::Io(e, path.to_path_buf()))?;
+        toml::from_str(&content).map_err(|e| ConfigError::DeserializeToml(e, path.to_path_buf()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_user_settings() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let file = temp.path().join(USER_SETTINGS_FILE_NAME);
+        assert_eq!(
+            UserSettings::load_from(&file).unwrap(),
+            UserSettings::default()
+        );
+        std::fs::write(
+            &file,
+            "default_repo = \"me/dotfiles\"\n[telemetry]\nenabled = true\n",
+        )
+        .unwrap();
+        assert!(UserSettings::load_from(&file).unwrap().telemetry.enabled);
+        assert_eq!(
+            UserSettings::load_from(&file)
+                .unwrap()
+                .default_repo
+                .as_deref(),
+            Some("me/dotfiles")
+        );
+    }
+}
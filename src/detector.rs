@@ -2,11 +2,14 @@
 //
 // SPDX-License-Identifier: GPL-3.0-only
 
-use std::collections::{BTreeSet, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
 
 use crate::action::install::execute_script;
-use crate::config::{DetectorConfig, GlobalConfig, Tag};
-use crate::debug;
+use crate::config::{BuiltinTagOverride, DetectorConfig, GlobalConfig, Shell, Source, Tag};
+use crate::file_ops::dirs::IS_WSL;
+use crate::{debug, warn};
 
 pub fn detect_builtin_tags() -> BTreeSet<Tag> {
     let mut tags = BTreeSet::new();
@@ -80,9 +83,126 @@ pub fn detect_builtin_tags() -> BTreeSet<Tag> {
         )),
         _ => false,
     };
+    if let Ok(os_release) = std::fs::read_to_string("/etc/os-release") {
+        let (id, like) = parse_os_release(&os_release);
+        if let Some(id) = &id {
+            tags.insert(Tag::new_with_value(
+                "os_id",
+                id,
+                crate::config::Source::BuiltInDetector,
+            ));
+        }
+        // A distribution belongs to its own family, so os_like=debian also matches Debian
+        for family in id.iter().chain(like.iter()) {
+            tags.insert(Tag::new_with_value(
+                "os_like",
+                family,
+                crate::config::Source::BuiltInDetector,
+            ));
+        }
+    }
+    if *IS_WSL {
+        tags.insert(Tag::new("wsl", crate::config::Source::BuiltInDetector));
+        if let Ok(distro) = std::env::var("WSL_DISTRO_NAME")
+            && !distro.is_empty()
+        {
+            tags.insert(Tag::new_with_value(
+                "wsl_distro",
+                &distro,
+                crate::config::Source::BuiltInDetector,
+            ));
+        }
+    }
+    if let Some(container) = detect_container() {
+        tags.insert(Tag::new_with_value(
+            "container",
+            container,
+            crate::config::Source::BuiltInDetector,
+        ));
+    }
+    for (manager, path) in PACKAGE_MANAGERS.iter() {
+        tags.insert(Tag::new_with_value(
+            &format!("has_{manager}"),
+            &path.display().to_string(),
+            crate::config::Source::BuiltInDetector,
+        ));
+    }
     tags
 }
 
+/// Package managers that are reported as `has_<name>` tags with the path as value
+const PACKAGE_MANAGER_NAMES: &[&str] = &[
+    "apk", "apt", "brew", "cargo", "choco", "dnf", "flatpak", "nix", "pacman", "port", "scoop",
+    "snap", "winget", "yum", "zypper",
+];
+
+/// The package managers found on the PATH, probed once per process
+static PACKAGE_MANAGERS: LazyLock<BTreeMap<&'static str, PathBuf>> =
+    LazyLock::new(|| find_in_path(PACKAGE_MANAGER_NAMES, std::env::var_os("PATH")));
+
+/// Finds the first executable of each name in a single pass over the PATH entries
+fn find_in_path(
+    names: &[&'static str],
+    path: Option<std::ffi::OsString>,
+) -> BTreeMap<&'static str, PathBuf> {
+    let mut found = BTreeMap::new();
+    let Some(path) = path else {
+        return found;
+    };
+    for dir in std::env::split_paths(&path) {
+        for name in names {
+            if found.contains_key(name) {
+                continue;
+            }
+            let exe = dir.join(format!("{name}{}", std::env::consts::EXE_SUFFIX));
+            if exe.is_file() {
+                found.insert(*name, exe);
+            }
+        }
+        if found.len() == names.len() {
+            break;
+        }
+    }
+    found
+}
+
+/// Extracts ID and the space separated ID_LIKE entries from the contents of /etc/os-release.
+/// Values may be quoted and are compared in lower case.
+fn parse_os_release(contents: &str) -> (Option<String>, Vec<String>) {
+    let mut id = None;
+    let mut like = Vec::new();
+    for line in contents.lines() {
+        let Some((key, value)) = line.trim().split_once('=') else {
+            continue;
+        };
+        let value = value.trim().trim_matches(['"', '\'']).to_lowercase();
+        match key.trim() {
+            "ID" if !value.is_empty() => id = Some(value),
+            "ID_LIKE" => like = value.split_whitespace().map(str::to_string).collect(),
+            _ => {}
+        }
+    }
+    (id, like)
+}
+
+/// Detects whether we are running inside of a container and which kind it is
+fn detect_container() -> Option<&'static str> {
+    let env_set = |name: &str| std::env::var_os(name).is_some_and(|v| !v.is_empty());
+    if env_set("CODESPACES") {
+        Some("codespaces")
+    } else if env_set("REMOTE_CONTAINERS") || env_set("DEVCONTAINER") {
+        Some("devcontainer")
+    } else if Path::new("/.dockerenv").exists() {
+        Some("docker")
+    } else if Path::new("/run/.containerenv").exists() {
+        Some("podman")
+    } else if env_set("container") {
+        Some("other")
+    } else {
+        None
+    }
+}
+
 fn get_arch_alias() -> &'static str {
     let arch_map = HashMap::from([
         ("aarch64", "arm64"),
@@ -103,7 +223,7 @@ fn create_detected_tag(
 ) -> Result<Option<Tag>, std::io::Error> {
     match config {
         DetectorConfig::EnableIf { enable_if } => {
-            let output = execute_script(enable_if)?;
+            let output = execute_script(enable_if, Shell::Auto)?;
             debug!(
                 "Detector '{}' exited with code {}",
                 name,
@@ -119,7 +239,7 @@ fn create_detected_tag(
             }
         }
         DetectorConfig::EnableIfNot { enable_if_not } => {
-            let output = execute_script(enable_if_not)?;
+            let output = execute_script(enable_if_not, Shell::Auto)?;
             debug!(
                 "Detector '{}' exited with code {}",
                 name,
@@ -135,7 +255,7 @@ fn create_detected_tag(
             }
         }
         DetectorConfig::ValueOf { value_of } => {
-            let output = execute_script(value_of)?;
+            let output = execute_script(value_of, Shell::Auto)?;
             debug!(
                 "Detector '{}' exited with code {}",
                 name,
@@ -156,6 +276,52 @@ fn create_detected_tag(
     }
 }
 
+/// Runs only the detectors whose tag is referenced by the configuration. Value detectors
+/// always run, as their value can be used in any template.
+pub fn get_referenced_detected_tags(config: &GlobalConfig) -> Result<Vec<Tag>, std::io::Error> {
+    let referenced = config.referenced_tag_names();
+    let tags: Result<Vec<Option<Tag>>, std::io::Error> = config
+        .all_detectors()
+        .into_iter()
+        .filter(|(name, detector)| {
+            let needed = matches!(detector, DetectorConfig::ValueOf { .. })
+                || referenced.contains(name.as_str());
+            if !needed {
+                debug!("Skipping detector '{name}', its tag is not referenced");
+            }
+            needed
+        })
+        .map(create_detected_tag)
+        .collect();
+    Ok(tags?.into_iter().flatten().collect::<Vec<Tag>>())
+}
+
+/// Removes built-in tags that are disabled and replaces the values of the fixed ones
+pub fn override_builtin_tags(
+    tags: &mut BTreeSet<Tag>,
+    overrides: &BTreeMap<String, BuiltinTagOverride>,
+) {
+    for (name, tag_override) in overrides {
+        let name = name.to_lowercase();
+        let is_builtin = |t: &Tag| t.name() == name && t.source() == &Source::BuiltInDetector;
+        if !tags.iter().any(is_builtin) && tag_override != &BuiltinTagOverride::Enabled(true) {
+            warn!("The built-in tag '{name}' is not detected on this machine");
+        }
+        match tag_override {
+            BuiltinTagOverride::Enabled(true) => {}
+            BuiltinTagOverride::Enabled(false) => {
+                debug!("Disabling built-in tag '{name}'");
+                tags.retain(|t| !is_builtin(t));
+            }
+            BuiltinTagOverride::Value(value) => {
+                debug!("Fixing built-in tag '{name}' to '{value}'");
+                tags.retain(|t| !is_builtin(t));
+                tags.insert(Tag::new_with_value(&name, value, Source::Config));
+            }
+        }
+    }
+}
+
 pub fn get_detected_tags(config: &GlobalConfig) -> Result<Vec<Tag>, std::io::Error> {
     let tags: Result<Vec<Option<Tag>>, std::io::Error> = config
         .all_detectors()
@@ -164,3 +330,59 @@ pub fn get_detected_tags(config: &GlobalConfig) -> Result<Vec<Tag>, std::io::Err
         .collect();
     Ok(tags?.into_iter().flatten().collect::<Vec<Tag>>())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_override_builtin_tags() {
+        let mut tags = BTreeSet::from([
+            Tag::new_with_value("os_version", "6.1", Source::BuiltInDetector),
+            Tag::new_with_value("os", "linux", Source::BuiltInDetector),
+            Tag::new_with_value("arch", "x86_64", Source::BuiltInDetector),
+        ]);
+        let overrides = BTreeMap::from([
+            ("OS_Version".to_string(), BuiltinTagOverride::Enabled(false)),
+            (
+                "os".to_string(),
+                BuiltinTagOverride::Value("debian".to_string()),
+            ),
+            ("arch".to_string(), BuiltinTagOverride::Enabled(true)),
+        ]);
+        override_builtin_tags(&mut tags, &overrides);
+        let tags = tags.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+        assert_eq!(tags, vec!["arch=x86_64", "os=debian"]);
+        let config: crate::config::HermitConfig =
+            toml::from_str("[builtin_tags]\nos_version = false\nos = \"debian\"\n").unwrap();
+        assert_eq!(
+            config.builtin_tags["os"],
+            BuiltinTagOverride::Value("debian".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_os_release() {
+        let ubuntu = "NAME=\"Ubuntu\"\nID=ubuntu\nID_LIKE=debian\nVERSION_ID=\"24.04\"\n";
+        assert_eq!(
+            parse_os_release(ubuntu),
+            (Some("ubuntu".to_string()), vec!["debian".to_string()])
+        );
+        let rocky = "ID=\"rocky\"\nID_LIKE=\"rhel centos fedora\"\n";
+        assert_eq!(
+            parse_os_release(rocky),
+            (
+                Some("rocky".to_string()),
+                vec![
+                    "rhel".to_string(),
+                    "centos".to_string(),
+                    "fedora".to_string()
+                ]
+            )
+        );
+        assert_eq!(
+            parse_os_release("# comment\nID=debian"),
+            (Some("debian".to_string()), vec![])
+        );
+    }
+}
@@ -3,28 +3,126 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
 use std::collections::{BTreeMap, BTreeSet};
+use std::hash::{Hash, Hasher};
 use std::sync::Arc;
 
 use serde::Serialize;
 use tokio::task::JoinSet;
+use xxhash_rust::xxh3::Xxh3;
 
-use crate::action::{Action, ActionObserver, Actions, ArcAction};
+use crate::action::{Action, ActionObserver, ActionOutcome, ActionType, Actions, ArcAction};
 use crate::config::{ArcHermitConfig, CliOptions, GlobalConfig, Tag};
-use crate::hermitgrab_error::ConfigError::HermitConfigNotAction;
+use crate::hermitgrab_error::ConfigError::{
+    HermitConfigNotAction, SourceOutsideHermitDir, TargetInsideHermitDir,
+};
 use crate::hermitgrab_error::{ActionError, ApplyError};
+use crate::state::{DisabledActions, ScheduleState, now};
 pub type ArcConfigAction = (ArcHermitConfig, ArcAction);
 #[derive(Debug, Serialize)]
 pub struct ExecutionPlan {
     pub actions: Vec<ArcConfigAction>,
 }
+/// Actions that differ between two plans, identified by their short description
+#[derive(Debug, Default, Serialize, PartialEq)]
+pub struct PlanDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+impl PlanDiff {
+    /// Compares two sets of [`ExecutionPlan::fingerprints`]
+    pub fn between(old: &BTreeMap<String, String>, new: &BTreeMap<String, String>) -> Self {
+        let mut diff = PlanDiff::default();
+        for (key, fingerprint) in new {
+            match old.get(key) {
+                None => diff.added.push(key.clone()),
+                Some(old_fingerprint) if old_fingerprint != fingerprint => {
+                    diff.changed.push(key.clone())
+                }
+                Some(_) => {}
+            }
+        }
+        diff.removed = old
+            .keys()
+            .filter(|key| !new.contains_key(*key))
+            .cloned()
+            .collect();
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
 pub struct ActionResult {
     pub action: ArcAction,
-    pub result: Result<(), ActionError>,
+    pub result: Result<ActionOutcome, ActionError>,
 }
 impl ExecutionPlan {
+    /// A stable hash over all action ids of the plan in execution order
+    pub fn plan_hash(&self) -> String {
+        let mut hash = Xxh3::new();
+        for (_, action) in self.actions.iter() {
+            action.id().hash(&mut hash);
+        }
+        format!("{:016x}", hash.finish())
+    }
+
+    /// Returns a plan without the actions whose id is in `skip`
+    pub fn without_actions(&self, skip: &BTreeSet<String>) -> ExecutionPlan {
+        let actions = self
+            .actions
+            .iter()
+            .filter(|(_, action)| !skip.contains(&action.id()))
+            .cloned()
+            .collect();
+        ExecutionPlan { actions }
+    }
+
+    /// Returns a plan without the scheduled actions that are not due. With `due_only`,
+    /// only the scheduled actions that are due remain.
+    pub fn filter_scheduled(&self, state: &ScheduleState, due_only: bool) -> ExecutionPlan {
+        let now = now();
+        let actions = self
+            .actions
+            .iter()
+            .filter(|(_, action)| match action.schedule() {
+                Some(schedule) => schedule.is_due(state.last_run.get(&action.id()).copied(), now),
+                None => !due_only,
+            })
+            .cloned()
+            .collect();
+        ExecutionPlan { actions }
+    }
+
+    /// The serialized form of every action keyed by its short description
+    pub fn fingerprints(&self) -> Result<BTreeMap<String, String>, serde_json::Error> {
+        self.actions
+            .iter()
+            .map(|(_, action)| Ok((action.short_description(), serde_json::to_string(action)?)))
+            .collect()
+    }
+
     pub fn iter(&self) -> std::slice::Iter<'_, ArcConfigAction> {
         self.actions.iter()
     }
+    /// Keeps the actions of the selected types, see [`ActionType::is_selected`]
+    pub fn filter_actions_by_type(
+        &self,
+        types: &[ActionType],
+        skip_types: &[ActionType],
+    ) -> ExecutionPlan {
+        let actions = self
+            .actions
+            .iter()
+            .filter(|(_, action)| action.action_type().is_selected(types, skip_types))
+            .cloned()
+            .collect();
+        ExecutionPlan { actions }
+    }
+
     pub fn filter_actions_by_tags(&self, active_tags: &BTreeSet<Tag>) -> ExecutionPlan {
         let mut filtered: Vec<ArcConfigAction> = Vec::new();
         for (cfg, action) in self.actions.iter() {
@@ -80,23 +178,28 @@ impl ExecutionPlan {
         let mut results = Vec::new();
         for (_, actions) in actions_by_order {
             let mut tasks = JoinSet::new();
-            for (cfg, action) in actions {
+            for (index, (cfg, action)) in actions.into_iter().enumerate() {
                 let observer = observer.clone();
                 tasks.spawn(async move {
                     observer.action_started(&action);
                     let result = action.execute(&observer, &cfg);
                     observer.action_finished(&action, &result);
-                    ActionResult { action, result }
+                    (index, ActionResult { action, result })
                 });
             }
+            let mut finished = Vec::new();
             while let Some(res) = tasks.join_next().await {
                 match res {
-                    Ok(action_result) => results.push(action_result),
+                    Ok(action_result) => finished.push(action_result),
                     Err(e) => {
                         crate::error!("Error executing action: {e}");
                     }
                 }
             }
+            // The results are in the order of the plan like a sequential run, not in the
+            // order the actions happened to finish
+            finished.sort_by_key(|(index, _)| *index);
+            results.extend(finished.into_iter().map(|(_, result)| result));
         }
         results
     }
@@ -115,15 +218,21 @@ pub fn create_execution_plan(
     global_config: &Arc<GlobalConfig>,
     cli: &CliOptions,
 ) -> Result<ExecutionPlan, ApplyError> {
+    let mut render_tags = global_config.get_active_tags(&cli.tags, &cli.profile)?;
+    render_tags.retain(|t| !cli.exclude_tags.iter().any(|e| e == t.name()));
+    global_config.set_render_tags(render_tags);
     let mut actions: Vec<(ArcHermitConfig, ArcAction)> = Vec::new();
     for (path, cfg) in global_config.subconfigs() {
         for item in cfg.config_items() {
-            match item.as_action(cfg, cli) {
-                Ok(action) => {
-                    actions.push((cfg.clone(), action));
+            match item.as_actions(cfg, cli) {
+                Ok(item_actions) => {
+                    actions.extend(item_actions.into_iter().map(|a| (cfg.clone(), a)));
                 }
                 Err(e) => match e {
                     HermitConfigNotAction => {}
+                    e @ SourceOutsideHermitDir(..) if cli.strict => return Err(e.into()),
+                    // Linking into the hermit directory would modify the repository
+                    e @ TargetInsideHermitDir(..) => return Err(e.into()),
                     e => {
                         crate::error!(
                             "An error occured when preparing action in {path} for {}: {e}",
@@ -134,6 +243,11 @@ pub fn create_execution_plan(
             }
         }
     }
+    let disabled = DisabledActions::load().unwrap_or_else(|e| {
+        crate::warn!("Ignoring unreadable list of disabled actions: {e}");
+        DisabledActions::default()
+    });
+    actions.retain(|(_, action)| !disabled.ids.contains(&action.id()));
     actions.sort_by_key(|(_, action)| action.get_order());
     Ok(ExecutionPlan { actions })
 }
@@ -2,10 +2,17 @@
 //
 // SPDX-License-Identifier: GPL-3.0-only
 
+use std::collections::BTreeMap;
 use std::ffi::OsString;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
 
-use crate::config::{FallbackOperation, FileStatus};
+use rayon::prelude::*;
+
+use crate::config::{DirMode, FallbackOperation, FileStatus, HardlinkFallback, Ownership};
+use crate::hermitgrab_error::StateError;
+use crate::state::{HashCache, HashEntry};
 use crate::{FileOpsError, LinkType};
 
 pub fn link_files<P: AsRef<Path>, Q: AsRef<Path>>(
@@ -13,23 +20,30 @@ pub fn link_files<P: AsRef<Path>, Q: AsRef<Path>>(
     dst: Q,
     link_type: &LinkType,
     fall_back: &FallbackOperation,
+    hardlink_fallback: &HardlinkFallback,
 ) -> Result<(), FileOpsError> {
     let src = src
         .as_ref()
         .canonicalize()
         .unwrap_or(src.as_ref().to_path_buf());
+    // Only the parent is resolved, an existing symlink at the destination is handled by the
+    // fallback instead of its target
     let dst = dst
         .as_ref()
-        .canonicalize()
+        .parent()
+        .and_then(|parent| parent.canonicalize().ok())
+        .zip(dst.as_ref().file_name())
+        .map(|(parent, name)| parent.join(name))
         .unwrap_or(dst.as_ref().to_path_buf());
     if !src.exists() {
         return Err(FileOpsError::SourceNotFound(src.display().to_string()));
     }
     let dst_clone = dst.clone();
     if dst.exists() || dst.is_symlink() {
-        if src == dst {
+        if dst.canonicalize().is_ok_and(|resolved| resolved == src) {
             return Ok(());
         }
+        let is_dir = dst.is_dir() && !dst.is_symlink();
         match fall_back {
             FallbackOperation::Abort => {
                 return Err(FileOpsError::DestinationExists(dst.display().to_string()));
@@ -53,14 +67,14 @@ pub fn link_files<P: AsRef<Path>, Q: AsRef<Path>>(
                     .map_err(|e| FileOpsError::Io(backup_file, e))?;
             }
             FallbackOperation::Delete => {
-                if dst.is_dir() {
+                if is_dir {
                     std::fs::remove_dir(&dst).map_err(|e| FileOpsError::Io(dst, e))?;
                 } else {
                     std::fs::remove_file(&dst).map_err(|e| FileOpsError::Io(dst, e))?;
                 }
             }
             FallbackOperation::DeleteDir => {
-                if dst.is_dir() {
+                if is_dir {
                     std::fs::remove_dir_all(&dst).map_err(|e| FileOpsError::Io(dst, e))?;
                 } else {
                     std::fs::remove_file(&dst).map_err(|e| FileOpsError::Io(dst, e))?;
@@ -90,9 +104,28 @@ pub fn link_files<P: AsRef<Path>, Q: AsRef<Path>>(
                 symlink_file(src, &dst_clone).map_err(|e| FileOpsError::Io(dst_clone.into(), e))?;
             }
         }
-        LinkType::Hard => {
-            std::fs::hard_link(src, &dst_clone).map_err(|e| FileOpsError::Io(dst_clone, e))?;
-        }
+        LinkType::Hard => match std::fs::hard_link(&src, &dst_clone) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => match hardlink_fallback {
+                HardlinkFallback::Copy => {
+                    crate::warn!(
+                        "{} and {} are on different file systems, copying instead of hard linking",
+                        src.display(),
+                        dst_clone.display()
+                    );
+                    copy(&src, &dst_clone)?;
+                }
+                HardlinkFallback::Fail => {
+                    return Err(FileOpsError::CrossDeviceHardlink(
+                        src.display().to_string(),
+                        dst_clone.display().to_string(),
+                        mount_point(&src).display().to_string(),
+                        mount_point(&dst_clone).display().to_string(),
+                    ));
+                }
+            },
+            Err(e) => return Err(FileOpsError::Io(dst_clone, e)),
+        },
         LinkType::Copy => {
             copy(&src, &dst_clone)?;
         }
@@ -100,7 +133,166 @@ pub fn link_files<P: AsRef<Path>, Q: AsRef<Path>>(
     Ok(())
 }
 
+/// Creates the missing parents of `path`. With a mode the created directories and the
+/// direct parent of `path` get this mode, the umask does not apply.
+pub fn create_parents(path: &Path, mode: Option<DirMode>) -> Result<(), FileOpsError> {
+    let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) else {
+        return Ok(());
+    };
+    let mut dirs: Vec<PathBuf> = parent
+        .ancestors()
+        .take_while(|p| !p.as_os_str().is_empty() && !p.exists())
+        .map(Path::to_path_buf)
+        .collect();
+    if !dirs.is_empty() {
+        std::fs::create_dir_all(parent).map_err(|e| FileOpsError::Io(parent.into(), e))?;
+    } else {
+        dirs.push(parent.to_path_buf());
+    }
+    let Some(mode) = mode else {
+        return Ok(());
+    };
+    #[cfg(unix)]
+    for dir in dirs {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(mode.0))
+            .map_err(|e| FileOpsError::Io(dir, e))?;
+    }
+    #[cfg(not(unix))]
+    let _ = (mode, dirs);
+    Ok(())
+}
+
+/// Verifies the mode of the directory containing `path`
+pub fn check_dir_mode(path: &Path, mode: Option<DirMode>) -> FileStatus {
+    let (Some(mode), Some(parent)) = (mode, path.parent()) else {
+        return FileStatus::Ok;
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        match parent.metadata() {
+            Ok(meta) => {
+                let actual = DirMode(meta.permissions().mode() & 0o7777);
+                if actual != mode {
+                    return FileStatus::DirModeMismatch(parent.to_path_buf(), actual, mode);
+                }
+            }
+            Err(e) => return FileStatus::FailedToGetMetadata(parent.to_path_buf(), e),
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (mode, parent);
+    FileStatus::Ok
+}
+
+/// Replaces the content of `dst` atomically: the content is written to a temporary file
+/// in the same directory that is renamed over the destination. The permissions of an existing
+/// destination are kept and a symlinked destination is written through the link.
+pub fn write_atomic(dst: &Path, content: impl AsRef<[u8]>) -> std::io::Result<()> {
+    write_atomic_with(dst, content.as_ref(), false)
+}
+
+/// Same as [write_atomic], but the written file is only accessible by the owner, e.g. for
+/// rendered content with secret values
+pub fn write_private(dst: &Path, content: impl AsRef<[u8]>) -> std::io::Result<()> {
+    write_atomic_with(dst, content.as_ref(), true)
+}
+
+fn write_atomic_with(dst: &Path, content: &[u8], private: bool) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let dst = if dst.is_symlink() {
+        dst.canonicalize().unwrap_or_else(|_| dst.to_path_buf())
+    } else {
+        dst.to_path_buf()
+    };
+    let dir = match dst.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    std::fs::create_dir_all(dir)?;
+    let mut builder = tempfile::Builder::new();
+    builder.prefix(".hermitgrab").suffix(".tmp");
+    match std::fs::metadata(&dst) {
+        // Temporary files are created with 0600
+        _ if private => {}
+        Ok(metadata) => {
+            builder.permissions(metadata.permissions());
+        }
+        #[cfg(unix)]
+        Err(_) => {
+            // Same mode as std::fs::write, the umask still applies
+            use std::os::unix::fs::PermissionsExt;
+            builder.permissions(std::fs::Permissions::from_mode(0o666));
+        }
+        #[cfg(not(unix))]
+        Err(_) => {}
+    }
+    let mut file = builder.tempfile_in(dir)?;
+    file.write_all(content)?;
+    file.as_file().sync_all()?;
+    file.persist(&dst).map_err(|e| e.error)?;
+    Ok(())
+}
+
+/// Returns true if both paths are on the same file system, paths that don't exist are
+/// checked by their nearest existing ancestor
+pub fn same_device(a: &Path, b: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let device = |path: &Path| {
+            path.ancestors()
+                .find_map(|p| p.metadata().ok())
+                .map(|m| m.dev())
+        };
+        device(a) == device(b)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (a, b);
+        true
+    }
+}
+
+/// The mount point of the file system containing `path`
+fn mount_point(path: &Path) -> PathBuf {
+    let mut mount = path.to_path_buf();
+    for ancestor in path.ancestors().skip(1) {
+        if !same_device(ancestor, path) {
+            break;
+        }
+        mount = ancestor.to_path_buf();
+    }
+    mount
+}
+
+#[cfg(unix)]
+type DirId = (u64, u64);
+#[cfg(not(unix))]
+type DirId = PathBuf;
+
+/// Identity of a directory after following symlinks, used to detect symlink loops
+fn dir_id(path: &Path) -> std::io::Result<DirId> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let meta = path.metadata()?;
+        Ok((meta.dev(), meta.ino()))
+    }
+    #[cfg(not(unix))]
+    {
+        path.canonicalize()
+    }
+}
+
+/// Copies a file or a directory recursively, symlinks are followed
 pub fn copy(src: &Path, dst: &Path) -> Result<(), FileOpsError> {
+    copy_tree(src, dst, &mut Vec::new())
+}
+
+fn copy_tree(src: &Path, dst: &Path, parents: &mut Vec<DirId>) -> Result<(), FileOpsError> {
     if src.is_file() {
         if let Some(parent) = dst.parent()
             && !parent.exists()
@@ -109,18 +301,40 @@ pub fn copy(src: &Path, dst: &Path) -> Result<(), FileOpsError> {
         }
         std::fs::copy(src, dst).map_err(|e| FileOpsError::Io(dst.into(), e))?;
     } else {
+        let id = dir_id(src).map_err(|e| FileOpsError::Io(src.into(), e))?;
+        if parents.contains(&id) {
+            return Err(FileOpsError::SymlinkLoop(src.display().to_string()));
+        }
+        parents.push(id);
         for file in src
             .read_dir()
             .map_err(|e| FileOpsError::Io(src.into(), e))?
         {
             let entry = file.map_err(|e| FileOpsError::Io(src.into(), e))?;
-            copy(&entry.path(), dst.join(entry.file_name()).as_path())?;
+            copy_tree(
+                &entry.path(),
+                dst.join(entry.file_name()).as_path(),
+                parents,
+            )?;
         }
+        parents.pop();
     }
     Ok(())
 }
 
 pub fn check_copied(quick: bool, src_file: &Path, actual_dst: &Path) -> FileStatus {
+    if !quick && src_file.is_dir() {
+        prehash_copied(&[(src_file, actual_dst)]);
+    }
+    check_copied_tree(quick, src_file, actual_dst, &mut Vec::new())
+}
+
+fn check_copied_tree(
+    quick: bool,
+    src_file: &Path,
+    actual_dst: &Path,
+    parents: &mut Vec<DirId>,
+) -> FileStatus {
     match actual_dst.try_exists() {
         Ok(exists) => {
             if !exists {
@@ -145,11 +359,11 @@ pub fn check_copied(quick: bool, src_file: &Path, actual_dst: &Path) -> FileStat
             return FileStatus::SizeDiffers(actual_dst.into(), src_meta.len(), dst_meta.len());
         }
         if !quick {
-            let src_hash = match hash_file(src_file) {
+            let src_hash = match cached_hash_file(src_file) {
                 Ok(hash) => hash,
                 Err(e) => return FileStatus::FailedToHashFile(src_file.into(), e),
             };
-            let dst_hash = match hash_file(actual_dst) {
+            let dst_hash = match cached_hash_file(actual_dst) {
                 Ok(hash) => hash,
                 Err(e) => return FileStatus::FailedToHashFile(actual_dst.into(), e),
             };
@@ -162,13 +376,24 @@ pub fn check_copied(quick: bool, src_file: &Path, actual_dst: &Path) -> FileStat
         if !src_file.is_dir() {
             return FileStatus::SrcIsFileButTargetIsDir(actual_dst.into());
         }
+        let id = match dir_id(src_file) {
+            Ok(id) => id,
+            Err(e) => return FileStatus::FailedToGetMetadata(src_file.into(), e),
+        };
+        if parents.contains(&id) {
+            return FileStatus::SymlinkLoop(src_file.into());
+        }
+        parents.push(id);
         match src_file.read_dir() {
             Ok(e) => {
                 for f in e {
                     let fs = match f {
-                        Ok(file) => {
-                            check_copied(quick, &file.path(), &actual_dst.join(file.file_name()))
-                        }
+                        Ok(file) => check_copied_tree(
+                            quick,
+                            &file.path(),
+                            &actual_dst.join(file.file_name()),
+                            parents,
+                        ),
                         Err(e) => return FileStatus::FailedToTraverseDir(src_file.into(), e),
                     };
                     if !fs.is_ok() {
@@ -180,8 +405,180 @@ pub fn check_copied(quick: bool, src_file: &Path, actual_dst: &Path) -> FileStat
                 return FileStatus::FailedToTraverseDir(src_file.into(), e);
             }
         }
+        parents.pop();
+        FileStatus::Ok
+    }
+}
+
+/// Recursively searches `root` for symlinks that resolve into `target_dir`.
+/// Symlinked directories are not followed, unreadable directories are skipped.
+/// Returns the links together with their resolved destination.
+pub fn find_links_into(
+    root: &Path,
+    target_dir: &Path,
+    max_depth: usize,
+) -> Vec<(PathBuf, PathBuf)> {
+    let mut found = Vec::new();
+    let mut pending = vec![(root.to_path_buf(), 0)];
+    while let Some((dir, depth)) = pending.pop() {
+        if dir.starts_with(target_dir) {
+            continue;
+        }
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            let path = entry.path();
+            if file_type.is_symlink() {
+                let Ok(link) = std::fs::read_link(&path) else {
+                    continue;
+                };
+                // Joining an absolute link replaces the directory
+                let resolved = normalize_lexically(&dir.join(link));
+                if resolved.starts_with(target_dir) {
+                    found.push((path, resolved));
+                }
+            } else if file_type.is_dir() && depth < max_depth {
+                pending.push((path, depth + 1));
+            }
+        }
+    }
+    found.sort();
+    found
+}
+
+/// Changes owner and group of `path`, symlinks themselves are changed instead of their target.
+/// With `recursive` the content of directories is changed as well.
+pub fn apply_ownership(
+    path: &Path,
+    ownership: &Ownership,
+    recursive: bool,
+) -> Result<(), FileOpsError> {
+    if ownership.is_default() {
+        return Ok(());
+    }
+    #[cfg(unix)]
+    {
+        let (uid, gid) = resolve_ownership(ownership)?;
+        chown_path(path, uid, gid, recursive)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = recursive;
+        Err(FileOpsError::OwnershipNotSupported(
+            path.display().to_string(),
+        ))
+    }
+}
+
+/// Verifies owner and group of `path`, the target of symlinks is not considered
+pub fn check_ownership(path: &Path, ownership: &Ownership) -> FileStatus {
+    if ownership.is_default() {
+        return FileStatus::Ok;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let (uid, gid) = match resolve_ownership(ownership) {
+            Ok(ids) => ids,
+            Err(e) => return FileStatus::OwnershipMismatch(path.to_path_buf(), e.to_string()),
+        };
+        let meta = match path.symlink_metadata() {
+            Ok(meta) => meta,
+            Err(e) => return FileStatus::FailedToGetMetadata(path.to_path_buf(), e),
+        };
+        if uid.is_some_and(|uid| uid != meta.uid()) || gid.is_some_and(|gid| gid != meta.gid()) {
+            return FileStatus::OwnershipMismatch(
+                path.to_path_buf(),
+                format!(
+                    "owned by {}:{} instead of {ownership}",
+                    meta.uid(),
+                    meta.gid()
+                ),
+            );
+        }
         FileStatus::Ok
     }
+    #[cfg(not(unix))]
+    {
+        FileStatus::OwnershipMismatch(
+            path.to_path_buf(),
+            "ownership is not supported on this platform".to_string(),
+        )
+    }
+}
+
+#[cfg(unix)]
+fn resolve_ownership(ownership: &Ownership) -> Result<(Option<u32>, Option<u32>), FileOpsError> {
+    use nix::unistd::{Group, User};
+    let uid = match &ownership.owner {
+        None => None,
+        Some(owner) => Some(match owner.parse::<u32>() {
+            Ok(uid) => uid,
+            Err(_) => User::from_name(owner)
+                .ok()
+                .flatten()
+                .ok_or_else(|| FileOpsError::UnknownOwner(owner.clone()))?
+                .uid
+                .as_raw(),
+        }),
+    };
+    let gid = match &ownership.group {
+        None => None,
+        Some(group) => Some(match group.parse::<u32>() {
+            Ok(gid) => gid,
+            Err(_) => Group::from_name(group)
+                .ok()
+                .flatten()
+                .ok_or_else(|| FileOpsError::UnknownOwner(group.clone()))?
+                .gid
+                .as_raw(),
+        }),
+    };
+    Ok((uid, gid))
+}
+
+#[cfg(unix)]
+fn chown_path(
+    path: &Path,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    recursive: bool,
+) -> Result<(), FileOpsError> {
+    std::os::unix::fs::lchown(path, uid, gid).map_err(|e| {
+        if e.kind() == std::io::ErrorKind::PermissionDenied {
+            FileOpsError::OwnershipPermissionDenied(path.display().to_string())
+        } else {
+            FileOpsError::Io(path.to_path_buf(), e)
+        }
+    })?;
+    if recursive && path.is_dir() && !path.is_symlink() {
+        let entries =
+            std::fs::read_dir(path).map_err(|e| FileOpsError::Io(path.to_path_buf(), e))?;
+        for entry in entries {
+            let entry = entry.map_err(|e| FileOpsError::Io(path.to_path_buf(), e))?;
+            chown_path(&entry.path(), uid, gid, recursive)?;
+        }
+    }
+    Ok(())
+}
+
+/// Resolves `.` and `..` components without touching the file system
+fn normalize_lexically(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            std::path::Component::CurDir => {}
+            std::path::Component::ParentDir => {
+                result.pop();
+            }
+            c => result.push(c),
+        }
+    }
+    result
 }
 
 pub fn hash_file(path: &Path) -> Result<blake3::Hash, std::io::Error> {
@@ -190,6 +587,233 @@ pub fn hash_file(path: &Path) -> Result<blake3::Hash, std::io::Error> {
     Ok(hasher.finalize())
 }
 
+/// Hashes of files, loaded from the state directory on first use
+static HASH_CACHE: std::sync::LazyLock<Mutex<HashCache>> = std::sync::LazyLock::new(|| {
+    Mutex::new(HashCache::load().unwrap_or_else(|e| {
+        crate::debug!("Ignoring unreadable hash cache: {e}");
+        HashCache::default()
+    }))
+});
+
+/// Like [`hash_file`], but uses the hash cache if size and modification time are unchanged
+pub fn cached_hash_file(path: &Path) -> Result<blake3::Hash, std::io::Error> {
+    let meta = path.metadata()?;
+    let mtime = meta
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let cached = HASH_CACHE
+        .lock()
+        .expect("Hash cache lock poisoned")
+        .files
+        .get(path)
+        .filter(|e| e.size == meta.len() && e.mtime == mtime)
+        .and_then(|e| blake3::Hash::from_hex(&e.hash).ok());
+    if let Some(hash) = cached {
+        return Ok(hash);
+    }
+    let hash = hash_file(path)?;
+    HASH_CACHE
+        .lock()
+        .expect("Hash cache lock poisoned")
+        .files
+        .insert(
+            path.to_path_buf(),
+            HashEntry {
+                size: meta.len(),
+                mtime,
+                hash: hash.to_hex().to_string(),
+            },
+        );
+    Ok(hash)
+}
+
+/// Hashes the files of copied sources and their destinations in parallel, which fills the
+/// hash cache for the following checks
+pub fn prehash_copied(pairs: &[(&Path, &Path)]) {
+    let mut files = Vec::new();
+    for (src, dst) in pairs {
+        collect_copied_files(src, dst, &mut Vec::new(), &mut files);
+    }
+    files.par_iter().for_each(|file| {
+        if let Err(e) = cached_hash_file(file) {
+            crate::debug!("Failed to hash {file:?}: {e}");
+        }
+    });
+}
+
+fn collect_copied_files(
+    src: &Path,
+    dst: &Path,
+    parents: &mut Vec<DirId>,
+    files: &mut Vec<PathBuf>,
+) {
+    if src.is_file() {
+        if dst.is_file() {
+            files.push(src.to_path_buf());
+            files.push(dst.to_path_buf());
+        }
+        return;
+    }
+    let Ok(id) = dir_id(src) else {
+        return;
+    };
+    let Ok(entries) = src.read_dir() else {
+        return;
+    };
+    if parents.contains(&id) {
+        return;
+    }
+    parents.push(id);
+    for entry in entries.flatten() {
+        collect_copied_files(&entry.path(), &dst.join(entry.file_name()), parents, files);
+    }
+    parents.pop();
+}
+
+/// Writes the hash cache to the state directory, entries of removed files are dropped
+pub fn save_hash_cache() -> Result<(), StateError> {
+    let mut cache = HASH_CACHE.lock().expect("Hash cache lock poisoned");
+    cache.files.retain(|path, _| path.exists());
+    cache.save()
+}
+
+/// Size and modification time of the entries below some directories, to find what a
+/// command changed. Directories are only recorded by their existence.
+#[derive(Debug, Default, PartialEq)]
+pub struct Snapshot {
+    entries: BTreeMap<PathBuf, (u64, u128)>,
+}
+
+impl Snapshot {
+    /// Records the entries of every directory down to its depth, 1 only records the entries
+    /// of the directory itself
+    pub fn take(roots: &[(PathBuf, usize)]) -> Self {
+        let mut snapshot = Self::default();
+        for (root, depth) in roots {
+            snapshot.collect(root, *depth);
+        }
+        snapshot
+    }
+
+    fn collect(&mut self, dir: &Path, depth: usize) {
+        if depth == 0 {
+            return;
+        }
+        let Ok(entries) = dir.read_dir() else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Ok(meta) = path.symlink_metadata() else {
+                continue;
+            };
+            if meta.is_dir() {
+                self.entries.insert(path.clone(), (0, 0));
+                self.collect(&path, depth - 1);
+            } else {
+                let mtime = meta
+                    .modified()
+                    .ok()
+                    .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+                    .unwrap_or_default()
+                    .as_nanos();
+                self.entries.insert(path, (meta.len(), mtime));
+            }
+        }
+    }
+
+    /// The entries that were added (+), removed (-) or changed (~) in `after`
+    pub fn changes(&self, after: &Snapshot) -> Vec<String> {
+        let added_or_changed =
+            after
+                .entries
+                .iter()
+                .filter_map(|(path, meta)| match self.entries.get(path) {
+                    None => Some(format!("+ {}", path.display())),
+                    Some(before) if before != meta => Some(format!("~ {}", path.display())),
+                    Some(_) => None,
+                });
+        let removed = self
+            .entries
+            .keys()
+            .filter(|path| !after.entries.contains_key(*path))
+            .map(|path| format!("- {}", path.display()));
+        added_or_changed.chain(removed).collect()
+    }
+}
+
+/// Files with a NUL byte in the first 8000 bytes are treated as binary, like git does
+pub fn is_binary(path: &Path) -> Result<bool, std::io::Error> {
+    use std::io::Read;
+    let mut buffer = Vec::with_capacity(8000);
+    std::fs::File::open(path)?
+        .take(8000)
+        .read_to_end(&mut buffer)?;
+    Ok(buffer.contains(&0))
+}
+
+/// Size delta and modification times of a copied file, to tell which side was changed
+pub fn binary_diff_hint(src: &Path, dst: &Path) -> Result<String, std::io::Error> {
+    let src_meta = src.metadata()?;
+    let dst_meta = dst.metadata()?;
+    let delta = dst_meta.len() as i128 - src_meta.len() as i128;
+    let size = if delta == 0 {
+        format!("same size of {} bytes", dst_meta.len())
+    } else {
+        format!("{delta:+} bytes in the target")
+    };
+    let (src_time, dst_time) = (src_meta.modified()?, dst_meta.modified()?);
+    let time = match dst_time.duration_since(src_time) {
+        Ok(newer) if newer.as_secs() > 0 => format!(
+            "the target is {} newer than the source",
+            human_duration(newer.as_secs())
+        ),
+        Ok(_) => "both were modified at the same time".to_string(),
+        Err(e) => format!(
+            "the source is {} newer than the target",
+            human_duration(e.duration().as_secs())
+        ),
+    };
+    Ok(format!("binary file, {size}, {time}"))
+}
+
+/// Unified diff from the source to the copied target
+pub fn unified_diff(src: &Path, dst: &Path) -> Result<String, std::io::Error> {
+    let old = std::fs::read_to_string(src)?;
+    let new = std::fs::read_to_string(dst)?;
+    let (src, dst) = (src.display().to_string(), dst.display().to_string());
+    Ok(similar::TextDiff::from_lines(&old, &new)
+        .unified_diff()
+        .header(&src, &dst)
+        .to_string())
+}
+
+/// Formats a duration in seconds with its largest unit, like `3d` or `5m`
+pub fn human_duration(secs: u64) -> String {
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m", secs / 60),
+        3600..86400 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    }
+}
+
+/// 1-based line and column of a byte offset
+pub fn line_and_column(content: &str, offset: usize) -> (usize, usize) {
+    let before = &content[..offset.min(content.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rsplit('\n')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .count()
+        + 1;
+    (line, column)
+}
+
 #[cfg(not(debug_assertions))]
 pub use std::sync::LazyLock;
 
@@ -227,9 +851,11 @@ where
 }
 
 pub mod dirs {
-    use std::path::PathBuf;
+    use std::collections::BTreeMap;
+    use std::path::{Path, PathBuf};
 
     use super::LazyLock;
+    use crate::hermitgrab_error::ConfigError;
 
     pub static HERMIT_EXE: LazyLock<PathBuf> = LazyLock::new(|| {
         std::env::current_exe()
@@ -238,9 +864,18 @@ pub mod dirs {
             .expect("Should canonicalize exe path")
     });
 
+    /// Panics without a home directory, commands check for it with [home_dir] first
     pub static BASE_DIRS: LazyLock<directories::BaseDirs, fn() -> directories::BaseDirs> =
         LazyLock::new(|| directories::BaseDirs::new().expect("BaseDirs should init"));
 
+    /// The home directory of the user, an error if it can not be determined, e.g. in
+    /// minimal containers without HOME
+    pub fn home_dir() -> Result<PathBuf, ConfigError> {
+        directories::BaseDirs::new()
+            .map(|dirs| dirs.home_dir().to_path_buf())
+            .ok_or(ConfigError::NoHomeDirectory)
+    }
+
     pub static HOME_DIR: LazyLock<String> =
         LazyLock::new(|| BASE_DIRS.home_dir().to_path_buf().display().to_string());
     /// XDG_HOME env or $HOME
@@ -326,4 +961,332 @@ pub mod dirs {
     /// XDG_RUNTIME_DIR env or None
     pub static XDG_RUNTIME_DIR: LazyLock<Option<String>> =
         LazyLock::new(|| std::env::var("XDG_RUNTIME_DIR").ok());
+    /// The XDG base directories by their name in `{{ dir.* }}`
+    pub fn xdg_bases() -> BTreeMap<String, String> {
+        let mut bases = BTreeMap::from([
+            ("xdg_config".to_string(), XDG_CONFIG_HOME.clone()),
+            ("xdg_data".to_string(), XDG_DATA_HOME.clone()),
+            ("xdg_cache".to_string(), XDG_CACHE_HOME.clone()),
+            ("xdg_bin".to_string(), XDG_BIN_HOME.clone()),
+            ("xdg_state".to_string(), XDG_STATE_HOME.clone()),
+        ]);
+        if let Some(runtime) = XDG_RUNTIME_DIR.clone() {
+            bases.insert("xdg_runtime".to_string(), runtime);
+        }
+        bases
+    }
+
+    /// Maps `path` below a base directory of `current` to the same location below the base
+    /// directory with the same name in `recorded`, None if the bases are the same
+    pub fn relocate(
+        path: &Path,
+        current: &BTreeMap<String, String>,
+        recorded: &BTreeMap<String, String>,
+    ) -> Option<(String, PathBuf)> {
+        current
+            .iter()
+            .filter_map(|(name, base)| {
+                let rest = path.strip_prefix(base).ok()?;
+                let recorded = recorded.get(name).filter(|r| *r != base)?;
+                Some((base.len(), name, Path::new(recorded).join(rest)))
+            })
+            .max_by_key(|(len, _, _)| *len)
+            .map(|(_, name, path)| (name.clone(), path))
+    }
+
+    /// Whether we run inside of the Windows Subsystem for Linux
+    pub static IS_WSL: std::sync::LazyLock<bool> = std::sync::LazyLock::new(|| {
+        cfg!(target_os = "linux")
+            && (std::env::var_os("WSL_DISTRO_NAME").is_some()
+                || Path::new("/proc/sys/fs/binfmt_misc/WSLInterop").exists())
+    });
+
+    /// The home directory of the Windows user as seen from WSL, e.g. /mnt/c/Users/me
+    pub static WIN_HOME: std::sync::LazyLock<Option<String>> =
+        std::sync::LazyLock::new(|| if *IS_WSL { detect_win_home() } else { None });
+
+    fn detect_win_home() -> Option<String> {
+        let run = |program: &str, args: &[&str]| {
+            let mut command = std::process::Command::new(program);
+            command.args(args);
+            // cmd.exe complains about UNC paths when started in the Linux file system
+            if Path::new("/mnt/c").is_dir() {
+                command.current_dir("/mnt/c");
+            }
+            let output = command.output().ok().filter(|o| o.status.success())?;
+            let output = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            (!output.is_empty()).then_some(output)
+        };
+        let profile = run("cmd.exe", &["/c", "echo %USERPROFILE%"])?;
+        run("wslpath", &["-u", &profile])
+    }
+
+    /// Whether the path is on a Windows drive mounted into WSL, like /mnt/c
+    pub fn on_windows_drive(path: &Path) -> bool {
+        let mut components = path
+            .components()
+            .skip(1)
+            .map(|c| c.as_os_str().to_string_lossy());
+        components.next().as_deref() == Some("mnt")
+            && components.next().is_some_and(|drive| {
+                drive.len() == 1 && drive.chars().all(|c| c.is_ascii_alphabetic())
+            })
+    }
+
+    /// Machine local state of hermitgrab, $XDG_STATE_HOME/hermitgrab
+    pub static HERMIT_STATE_DIR: LazyLock<PathBuf> =
+        LazyLock::new(|| PathBuf::from(XDG_STATE_HOME.as_str()).join("hermitgrab"));
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    #[test]
+    fn test_on_windows_drive() {
+        use super::dirs::on_windows_drive;
+        assert!(on_windows_drive(Path::new("/mnt/c/Users/me/AppData")));
+        assert!(on_windows_drive(Path::new("/mnt/D")));
+        assert!(!on_windows_drive(Path::new("/mnt/wsl/shared")));
+        assert!(!on_windows_drive(Path::new("/home/me/mnt/c")));
+        assert!(!on_windows_drive(Path::new("mnt/c")));
+    }
+
+    #[test]
+    fn test_create_parents_with_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp = tempfile::TempDir::new().unwrap();
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o7777;
+        std::fs::set_permissions(temp.path(), std::fs::Permissions::from_mode(0o755)).unwrap();
+        let file = temp.path().join(".ssh/keys/config");
+        assert!(matches!(
+            check_dir_mode(&file, Some(DirMode(0o700))),
+            FileStatus::FailedToGetMetadata(..)
+        ));
+        create_parents(&file, Some(DirMode(0o700))).unwrap();
+        assert_eq!(mode(&temp.path().join(".ssh")), 0o700);
+        assert_eq!(mode(&temp.path().join(".ssh/keys")), 0o700);
+        assert_eq!(mode(temp.path()), 0o755);
+        assert!(check_dir_mode(&file, Some(DirMode(0o700))).is_ok());
+        let existing = temp.path().join("existing");
+        create_parents(&existing, Some(DirMode(0o700))).unwrap();
+        assert_eq!(mode(temp.path()), 0o700);
+        assert!(matches!(
+            check_dir_mode(&file, Some(DirMode(0o750))),
+            FileStatus::DirModeMismatch(..)
+        ));
+        let parsed: crate::config::LinkConfig =
+            toml::from_str("source = \"a\"\ntarget = \"b\"\ndir_mode = \"0700\"").unwrap();
+        assert_eq!(parsed.dir_mode, Some(DirMode(0o700)));
+        let parsed: crate::config::LinkConfig =
+            toml::from_str("source = \"a\"\ntarget = \"b\"\ndir_mode = 0o750").unwrap();
+        assert_eq!(parsed.dir_mode, Some(DirMode(0o750)));
+    }
+
+    #[test]
+    fn test_write_atomic_keeps_permissions_and_links() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp = tempfile::TempDir::new().unwrap();
+        let file = temp.path().join("config");
+        std::fs::write(&file, "old").unwrap();
+        std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o600)).unwrap();
+        let link = temp.path().join("link");
+        std::os::unix::fs::symlink(&file, &link).unwrap();
+        write_atomic(&link, "new").unwrap();
+        assert!(link.is_symlink());
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "new");
+        let mode = std::fs::metadata(&file).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        write_atomic(&temp.path().join("sub/created"), "x").unwrap();
+        let leftovers = std::fs::read_dir(temp.path())
+            .unwrap()
+            .filter(|e| {
+                e.as_ref()
+                    .unwrap()
+                    .file_name()
+                    .to_string_lossy()
+                    .ends_with(".tmp")
+            })
+            .count();
+        assert_eq!(leftovers, 0);
+    }
+
+    #[test]
+    fn test_relocate_xdg_path() {
+        let bases = |config: &str| {
+            BTreeMap::from([
+                ("xdg_config".to_string(), config.to_string()),
+                ("xdg_data".to_string(), "/home/u/.local/share".to_string()),
+            ])
+        };
+        let current = bases("/home/u/.config");
+        let recorded = bases("/data/cfg");
+        assert_eq!(
+            dirs::relocate(Path::new("/home/u/.config/git/config"), &current, &recorded),
+            Some((
+                "xdg_config".to_string(),
+                PathBuf::from("/data/cfg/git/config")
+            ))
+        );
+        assert_eq!(
+            dirs::relocate(Path::new("/home/u/.local/share/x"), &current, &recorded),
+            None
+        );
+        assert_eq!(
+            dirs::relocate(Path::new("/home/u/.zshrc"), &current, &recorded),
+            None
+        );
+    }
+
+    #[test]
+    fn test_find_links_into() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let home = temp.path().join("home");
+        let hermit = home.join(".hermitgrab");
+        std::fs::create_dir_all(hermit.join("zsh")).unwrap();
+        std::fs::write(hermit.join("zsh/zshrc"), "").unwrap();
+        std::fs::create_dir_all(home.join(".config")).unwrap();
+        std::os::unix::fs::symlink(hermit.join("zsh/zshrc"), home.join(".zshrc")).unwrap();
+        std::os::unix::fs::symlink("../.hermitgrab/gone", home.join(".config/gone")).unwrap();
+        std::os::unix::fs::symlink("/etc/hosts", home.join("hosts")).unwrap();
+        let found = find_links_into(&home, &hermit, 4);
+        let links = found.iter().map(|(l, _)| l.clone()).collect::<Vec<_>>();
+        assert_eq!(links, vec![home.join(".config/gone"), home.join(".zshrc")]);
+    }
+
+    #[test]
+    fn test_symlink_loop() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let src = temp.path().join("src");
+        std::fs::create_dir_all(src.join("sub")).unwrap();
+        std::fs::write(src.join("sub/file"), "content").unwrap();
+        std::os::unix::fs::symlink("..", src.join("sub/parent")).unwrap();
+        let dst = temp.path().join("dst");
+        let err = copy(&src, &dst).unwrap_err();
+        assert!(
+            matches!(&err, FileOpsError::SymlinkLoop(path) if path.ends_with("parent")),
+            "{err}"
+        );
+        std::fs::remove_file(src.join("sub/parent")).unwrap();
+        copy(&src, &dst).unwrap();
+        assert!(check_copied(false, &src, &dst).is_ok());
+        std::os::unix::fs::symlink("..", src.join("sub/parent")).unwrap();
+        std::os::unix::fs::symlink("..", dst.join("sub/parent")).unwrap();
+        let status = check_copied(false, &src, &dst);
+        assert!(matches!(status, FileStatus::SymlinkLoop(_)), "{status}");
+    }
+
+    #[test]
+    fn test_cached_hash_invalidation() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let file = temp.path().join("file");
+        std::fs::write(&file, "first").unwrap();
+        assert_eq!(cached_hash_file(&file).unwrap(), blake3::hash(b"first"));
+        assert_eq!(cached_hash_file(&file).unwrap(), blake3::hash(b"first"));
+        std::fs::write(&file, "second content").unwrap();
+        assert_eq!(
+            cached_hash_file(&file).unwrap(),
+            blake3::hash(b"second content")
+        );
+    }
+
+    #[test]
+    fn test_same_device_and_mount_point() {
+        let temp = tempfile::TempDir::new().unwrap();
+        assert!(same_device(temp.path(), &temp.path().join("missing/file")));
+        assert_eq!(mount_point(Path::new("/")), PathBuf::from("/"));
+        assert!(temp.path().starts_with(mount_point(temp.path())));
+    }
+
+    #[test]
+    fn test_ownership() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let file = temp.path().join("owned");
+        std::fs::write(&file, "").unwrap();
+        let uid = nix::unistd::getuid().as_raw();
+        let gid = nix::unistd::getgid().as_raw();
+        let own = Ownership {
+            owner: Some(uid.to_string()),
+            group: Some(gid.to_string()),
+        };
+        apply_ownership(&file, &own, false).unwrap();
+        assert!(check_ownership(&file, &own).is_ok());
+        let other = Ownership {
+            owner: Some((uid + 1).to_string()),
+            group: None,
+        };
+        assert!(matches!(
+            check_ownership(&file, &other),
+            FileStatus::OwnershipMismatch(_, _)
+        ));
+        let unknown = Ownership {
+            owner: Some("hermitgrab-no-such-user".to_string()),
+            group: None,
+        };
+        assert!(matches!(
+            apply_ownership(&file, &unknown, false),
+            Err(FileOpsError::UnknownOwner(_))
+        ));
+    }
+
+    #[test]
+    fn test_diff_hints() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let (src, dst) = (temp.path().join("src"), temp.path().join("dst"));
+        std::fs::write(&src, "a\nb\n").unwrap();
+        std::fs::write(&dst, "a\nc\n").unwrap();
+        assert!(!is_binary(&src).unwrap());
+        let diff = unified_diff(&src, &dst).unwrap();
+        assert!(diff.contains("-b\n+c\n"), "{diff}");
+
+        std::fs::write(&src, [0u8, 1, 2]).unwrap();
+        std::fs::write(&dst, [0u8, 1, 2, 3, 4]).unwrap();
+        let now = std::time::SystemTime::now();
+        for (file, time) in [
+            (&src, now - std::time::Duration::from_secs(7200)),
+            (&dst, now),
+        ] {
+            std::fs::File::options()
+                .write(true)
+                .open(file)
+                .unwrap()
+                .set_modified(time)
+                .unwrap();
+        }
+        assert!(is_binary(&src).unwrap());
+        assert_eq!(
+            binary_diff_hint(&src, &dst).unwrap(),
+            "binary file, +2 bytes in the target, the target is 2h newer than the source"
+        );
+    }
+
+    #[test]
+    fn test_snapshot_changes() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let config = temp.path().join("config");
+        std::fs::create_dir_all(config.join("app")).unwrap();
+        std::fs::write(config.join("app").join("kept"), "a").unwrap();
+        std::fs::write(config.join("app").join("changed"), "a").unwrap();
+        std::fs::write(config.join("removed"), "a").unwrap();
+        let roots = [(config.clone(), 2)];
+        let before = Snapshot::take(&roots);
+        std::fs::write(config.join("app").join("changed"), "ab").unwrap();
+        std::fs::remove_file(config.join("removed")).unwrap();
+        std::fs::create_dir_all(config.join("new").join("deep")).unwrap();
+        std::fs::write(config.join("new").join("deep").join("ignored"), "a").unwrap();
+        let changes = before.changes(&Snapshot::take(&roots));
+        assert_eq!(
+            changes,
+            vec![
+                format!("~ {}", config.join("app").join("changed").display()),
+                format!("+ {}", config.join("new").display()),
+                format!("+ {}", config.join("new").join("deep").display()),
+                format!("- {}", config.join("removed").display()),
+            ]
+        );
+    }
 }
@@ -2,20 +2,10 @@
 //
 // SPDX-License-Identifier: GPL-3.0-only
 
-use std::path::{PathBuf, StripPrefixError};
+use std::path::{Path, PathBuf, StripPrefixError};
 
 use thiserror::Error;
 
-#[derive(Debug, Error)]
-pub enum HermitGrabError {
-    #[error(transparent)]
-    FileOps(#[from] FileOpsError),
-    #[error(transparent)]
-    Config(#[from] ConfigError),
-    #[error(transparent)]
-    Apply(#[from] ApplyError),
-}
-
 #[derive(Debug, Error)]
 pub enum FileOpsError {
     #[error("Source does not exist: {0}")]
@@ -26,10 +16,24 @@ pub enum FileOpsError {
     Io(PathBuf, std::io::Error),
     #[error("Failed to find a backup file name for {0}")]
     BackupAlreadyExists(String),
+    #[error("Unknown user or group: {0}")]
+    UnknownOwner(String),
+    #[error("Changing the ownership of {0} requires elevated privileges")]
+    OwnershipPermissionDenied(String),
+    #[error("Changing the ownership of {0} is not supported on this platform")]
+    OwnershipNotSupported(String),
+    #[error(
+        "Can not hard link {0} to {1}, they are on different file systems (mounted at {2} and {3}). Use link = \"copy\" or hardlink_fallback = \"copy\""
+    )]
+    CrossDeviceHardlink(String, String, String, String),
+    #[error("Symlink loop detected at {0}")]
+    SymlinkLoop(String),
 }
 
 #[derive(Debug, Error)]
 pub enum ConfigError {
+    #[error("Could not determine the home directory, set HOME or use --hermit-dir")]
+    NoHomeDirectory,
     #[error("Redeclaration of source: {0} found in file {1}")]
     DuplicateSource(String, PathBuf),
     #[error("An error occurred while handling the file {1}: {0}")]
@@ -40,6 +44,10 @@ pub enum ConfigError {
     SerializeToml(toml::ser::Error, PathBuf),
     #[error("Duplicate profile found: {0} in file {1}")]
     DuplicateProfile(String, PathBuf),
+    #[error("Duplicate tag alias found: {0} in file {1}")]
+    DuplicateTagAlias(String, PathBuf),
+    #[error("Duplicate variable found: {0} in file {1}")]
+    DuplicateVariable(String, PathBuf),
     #[error("Failed to deserialize document in TOML format: {0} in file {1}")]
     DeserializeDocumentToml(toml_edit::TomlError, PathBuf),
     #[error(transparent)]
@@ -50,10 +58,51 @@ pub enum ConfigError {
     HermitConfigNotAction,
     #[error("The tag {0} was not found in the configuration")]
     TagNotFound(String),
+    #[error("None of the target tags [{0}] is active and no default target is set")]
+    NoMatchingTarget(String),
     #[error(transparent)]
     PatchAction(#[from] PatchActionError),
     #[error(transparent)]
     LinkAction(#[from] LinkActionError),
+    #[error(transparent)]
+    FetchAction(#[from] FetchActionError),
+    #[error("Custom detector caused error: {0}")]
+    Detector(std::io::Error),
+    #[error(
+        "The configuration contains {0} error(s) and strict config loading is enabled, affected files: [{1}]"
+    )]
+    StrictConfig(usize, String),
+    #[error("The link source {0} in {1} is outside of the hermit directory")]
+    SourceOutsideHermitDir(PathBuf, PathBuf),
+    #[error("Invalid fallback for {0} in file {1}")]
+    InvalidFallback(String, PathBuf),
+    #[error("manage_children requires a directory as source: {0} in file {1}")]
+    ManageChildrenWithoutDirectory(String, PathBuf),
+    #[error(
+        "The target {1} of {0} in file {2} is inside of the hermit directory, set allow_in_repo = true if this is intended"
+    )]
+    TargetInsideHermitDir(String, PathBuf, PathBuf),
+}
+
+impl ConfigError {
+    /// The file the error refers to, if known
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            ConfigError::DuplicateSource(_, path)
+            | ConfigError::Io(_, path)
+            | ConfigError::DeserializeToml(_, path)
+            | ConfigError::SerializeToml(_, path)
+            | ConfigError::DuplicateProfile(_, path)
+            | ConfigError::DuplicateTagAlias(_, path)
+            | ConfigError::DuplicateVariable(_, path)
+            | ConfigError::DeserializeDocumentToml(_, path)
+            | ConfigError::SourceOutsideHermitDir(_, path)
+            | ConfigError::InvalidFallback(_, path)
+            | ConfigError::ManageChildrenWithoutDirectory(_, path)
+            | ConfigError::TargetInsideHermitDir(_, _, path) => Some(path),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Error)]
@@ -86,6 +135,8 @@ pub enum PatchActionError {
     SerdecParse(#[from] jsonc_parser::errors::ParseError),
     #[error(transparent)]
     Render(#[from] handlebars::RenderError),
+    #[error(transparent)]
+    FileOps(#[from] FileOpsError),
 }
 
 #[derive(Debug, Error)]
@@ -120,10 +171,32 @@ pub enum AddError {
     StripPrefix(#[from] StripPrefixError),
     #[error("A source with the file {0} already exists")]
     SourceAlreadyExists(PathBuf),
+    #[error("A target is required when the source is not a file")]
+    MissingTarget,
     #[error("The configuration file {0} already exists")]
     ConfigFileAlreadyExists(PathBuf),
     #[error("The configuration file {0} does not exist")]
     ConfigFileNotFound(PathBuf),
+    #[error("No link manages {0}, pass the file to compare against to --from-diff")]
+    NoDiffBase(PathBuf),
+    #[error("Only JSON, YAML and TOML files can be diffed: {0}")]
+    UnsupportedDiffFormat(PathBuf),
+    #[error(transparent)]
+    Patch(#[from] PatchActionError),
+    #[error(transparent)]
+    JsonSerialization(#[from] serde_json::Error),
+    #[error(transparent)]
+    Plan(#[from] ApplyError),
+}
+
+#[derive(Debug, Error)]
+pub enum SnippetError {
+    #[error("Snippet not found: {0}")]
+    NotFound(String),
+    #[error(transparent)]
+    Render(#[from] handlebars::RenderError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
 }
 
 #[derive(Debug, Error)]
@@ -142,6 +215,22 @@ pub enum ApplyError {
     ConfigLoad(#[from] ConfigError),
     #[error(transparent)]
     SerdeJson(#[from] serde_json::Error),
+    #[error(transparent)]
+    State(#[from] StateError),
+    #[error("No action with id {0} in the plan, `get plan --json -` lists the ids of all actions")]
+    UnknownAction(String),
+    #[error(transparent)]
+    ReportTemplate(#[from] ReportTemplateError),
+}
+
+#[derive(Debug, Error)]
+pub enum StateError {
+    #[error("An error occurred while handling the state file {1}: {0}")]
+    Io(std::io::Error, PathBuf),
+    #[error("Failed to parse the state file {1}: {0}")]
+    Parse(serde_json::Error, PathBuf),
+    #[error("No run with id {0} found in the history")]
+    UnknownHistoryEntry(String),
 }
 
 #[derive(Debug, Error)]
@@ -152,6 +241,8 @@ pub enum ActionError {
     Install(#[from] InstallActionError),
     #[error(transparent)]
     Patch(#[from] PatchActionError),
+    #[error(transparent)]
+    Fetch(#[from] FetchActionError),
 }
 
 #[derive(Debug, Error)]
@@ -164,6 +255,28 @@ pub enum LinkActionError {
     Render(#[from] handlebars::RenderError),
 }
 
+#[derive(Debug, Error)]
+pub enum FetchActionError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Render(#[from] handlebars::RenderError),
+    #[error(transparent)]
+    Http(reqwest::Error),
+    #[error(
+        "The certificate of the server was not accepted ({0}). If a proxy intercepts TLS, add its CA certificate to the system trust store or use --insecure"
+    )]
+    UntrustedCertificate(String),
+    #[error(transparent)]
+    Zip(#[from] zip::result::ZipError),
+    #[error("The download of {0} was aborted unexpectedly")]
+    DownloadPanicked(String),
+    #[error("Checksum mismatch for {0}: expected {1}, got {2}")]
+    ChecksumMismatch(String, String, String),
+    #[error("The archive entry {0} would be unpacked outside of the target directory")]
+    EntryOutsideTarget(String),
+}
+
 #[derive(Debug, Error)]
 pub enum InstallActionError {
     #[error(transparent)]
@@ -176,6 +289,124 @@ pub enum InstallActionError {
     PreCommandFailedLaunch(String, std::io::Error),
     #[error("Failed to launch post-command: {0} due to IO error: {1}")]
     PostCommandFailedLaunch(String, std::io::Error),
+    #[error("Install of {0} stopped, the time budget for installs is exhausted")]
+    TimeBudgetExceeded(String),
+    #[error("{0} was installed, but its check still fails")]
+    PostInstallCheckFailed(String),
+}
+
+#[derive(Debug, Error)]
+pub enum HookError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Write(#[from] PatchActionError),
+    #[error("Unable to determine the shell from '{0}', please specify --shell")]
+    UnknownShell(String),
+    #[error("The profile '{0}' can not be quoted for the shell")]
+    InvalidProfile(String),
+}
+
+#[derive(Debug, Error)]
+pub enum CompletionError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Hook(#[from] HookError),
+    #[error("{0} exists and is not managed by hermitgrab, remove it to install the completions")]
+    Occupied(PathBuf),
+}
+
+#[derive(Debug, Error)]
+pub enum SelfInstallError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Completion(#[from] CompletionError),
+    #[error(transparent)]
+    Hook(#[from] HookError),
+    #[error("The SHA-256 of the executable is {1}, expected {0}")]
+    ChecksumMismatch(String, String),
+    #[error("{0} does not start with a SHA-256 checksum")]
+    InvalidChecksumFile(PathBuf),
+    #[error("{0} has no file name")]
+    NoFileName(PathBuf),
+}
+
+#[derive(Debug, Error)]
+pub enum DoctorError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error("The doctor found {0} error(s)")]
+    Findings(usize),
+}
+
+#[derive(Debug, Error)]
+pub enum RenderCommandError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Render(#[from] handlebars::RenderError),
+    #[error("Neither a file nor a snippet named '{0}' was found")]
+    NotFound(String),
+    #[error("No configuration found to render with")]
+    NoConfig,
+}
+
+#[derive(Debug, Error)]
+pub enum RefactorError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("Failed to parse {1}: {0}")]
+    Parse(toml_edit::TomlError, PathBuf),
+    #[error("{1}: {0}")]
+    InFile(Box<RefactorError>, PathBuf),
+    #[error("Can not rename {0} to {1}, an entry for {1} exists already")]
+    KeyExists(String, String),
+    #[error("Invalid tag name '{0}'")]
+    InvalidTagName(String),
+}
+
+#[derive(Debug, Error)]
+pub enum ReportCommitError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Git(#[from] git2::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error("The dotfiles repository is bare, reports can only be committed with a work tree")]
+    BareRepository,
+    #[error("The report {0} is outside of the dotfiles repository")]
+    OutsideRepository(PathBuf),
+    #[error("The remote rejected the push of {0}: {1}")]
+    PushRejected(String, String),
+}
+
+#[derive(Debug, Error)]
+pub enum ReportTemplateError {
+    #[error("Failed to read the report template {1}: {0}")]
+    Io(std::io::Error, PathBuf),
+    #[error("Failed to render the report template {1}: {0}")]
+    Render(handlebars::RenderError, PathBuf),
+}
+
+#[derive(Debug, Error)]
+pub enum PlanError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Git(#[from] git2::Error),
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    #[error(transparent)]
+    Apply(#[from] ApplyError),
+    #[error("Invalid arguments: {0}")]
+    InvalidArguments(String),
 }
 
 #[derive(Debug, Error)]
@@ -183,7 +414,13 @@ pub enum DiscoverError {
     #[error(transparent)]
     Git(#[from] git2::Error),
     #[error(transparent)]
-    Octocrab(#[from] octocrab::Error),
+    Octocrab(octocrab::Error),
+    #[error(
+        "The certificate of GitHub was not accepted ({0}). If a proxy intercepts TLS, add its CA certificate to the system trust store or use --insecure"
+    )]
+    UntrustedCertificate(String),
+    #[error("Failed to create the HTTP client: {0}")]
+    HttpClient(Box<dyn std::error::Error + Send + Sync>),
     #[error("No Git clone URL in Github response for repository: {0}")]
     NoGitCloneUrl(String),
     #[error(transparent)]
@@ -192,4 +429,24 @@ pub enum DiscoverError {
     InvalidInput(String),
     #[error("Repository already exists at path: {0}")]
     RepoAlreadyExists(std::path::PathBuf),
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+}
+
+impl From<octocrab::Error> for DiscoverError {
+    fn from(e: octocrab::Error) -> Self {
+        match crate::integrations::http::certificate_error(&e) {
+            Some(message) => DiscoverError::UntrustedCertificate(message),
+            None => DiscoverError::Octocrab(e),
+        }
+    }
+}
+
+impl From<reqwest::Error> for FetchActionError {
+    fn from(e: reqwest::Error) -> Self {
+        match crate::integrations::http::certificate_error(&e) {
+            Some(message) => FetchActionError::UntrustedCertificate(message),
+            None => FetchActionError::Http(e),
+        }
+    }
 }
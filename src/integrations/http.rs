@@ -0,0 +1,328 @@
+// SPDX-FileCopyrightText: 2025 Karsten Becker
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! HTTP clients that honor the proxy settings. The proxy is taken from the `[http]` user
+//! settings, or else from the `HTTPS_PROXY`, `HTTP_PROXY` and `NO_PROXY` environment
+//! variables. With `--insecure` or `insecure = true` certificates are not verified, for
+//! proxies that intercept TLS with a self-signed certificate.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock};
+use std::task::{Context, Poll};
+
+use http::header::{HeaderName, USER_AGENT};
+use http::{HeaderValue, Uri};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::connect::proxy::Tunnel;
+use hyper_util::client::proxy::matcher::Matcher;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use octocrab::auth::OAuth;
+use octocrab::service::middleware::auth_header::AuthHeaderLayer;
+use octocrab::service::middleware::base_uri::BaseUriLayer;
+use octocrab::service::middleware::extra_headers::ExtraHeadersLayer;
+use octocrab::{AuthState, Octocrab, OctocrabBuilder};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{CryptoProvider, verify_tls12_signature, verify_tls13_signature};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{DigitallySignedStruct, SignatureScheme};
+use secrecy::ExposeSecret;
+use tokio::net::TcpStream;
+use tower_service::Service;
+
+use crate::config::user_settings::{HttpSettings, UserSettings};
+use crate::hermitgrab_error::DiscoverError;
+
+const GITHUB_API: &str = "https://api.github.com";
+const GITHUB_UPLOADS: &str = "https://uploads.github.com";
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+static INSECURE: AtomicBool = AtomicBool::new(false);
+
+static SETTINGS: LazyLock<HttpSettings> =
+    LazyLock::new(|| UserSettings::load().unwrap_or_default().http);
+
+/// Disables the verification of certificates for all clients created afterwards
+pub fn set_insecure(insecure: bool) {
+    INSECURE.store(insecure, Ordering::Relaxed);
+}
+
+fn insecure() -> bool {
+    INSECURE.load(Ordering::Relaxed) || SETTINGS.insecure
+}
+
+/// The proxy of the user settings, the environment is used by the clients if none is set
+fn configured_proxy() -> Option<reqwest::Proxy> {
+    let proxy = SETTINGS.proxy.as_deref()?;
+    match reqwest::Proxy::all(proxy) {
+        Ok(p) => Some(
+            p.no_proxy(
+                SETTINGS
+                    .no_proxy
+                    .as_deref()
+                    .and_then(reqwest::NoProxy::from_string),
+            ),
+        ),
+        Err(e) => {
+            crate::warn!("Ignoring the invalid proxy {proxy}: {e}");
+            None
+        }
+    }
+}
+
+pub fn client() -> reqwest::Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder().danger_accept_invalid_certs(insecure());
+    if let Some(proxy) = configured_proxy() {
+        builder = builder.proxy(proxy);
+    }
+    builder.build()
+}
+
+pub fn blocking_client() -> reqwest::Result<reqwest::blocking::Client> {
+    let mut builder = reqwest::blocking::Client::builder().danger_accept_invalid_certs(insecure());
+    if let Some(proxy) = configured_proxy() {
+        builder = builder.proxy(proxy);
+    }
+    builder.build()
+}
+
+/// Applies the proxy and certificate settings to git network operations
+pub fn git_options<'a>(
+    fetch_options: &mut git2::FetchOptions<'a>,
+    callbacks: &mut git2::RemoteCallbacks<'a>,
+) {
+    let mut proxy = git2::ProxyOptions::new();
+    match SETTINGS.proxy.as_deref() {
+        Some(url) => proxy.url(url),
+        None => proxy.auto(),
+    };
+    fetch_options.proxy_options(proxy);
+    if insecure() {
+        callbacks.certificate_check(|_, _| Ok(git2::CertificateCheckStatus::CertificateOk));
+    }
+}
+
+/// Applies the proxy and certificate settings to a git push
+pub fn git_push_options<'a>(
+    push_options: &mut git2::PushOptions<'a>,
+    callbacks: &mut git2::RemoteCallbacks<'a>,
+) {
+    let mut proxy = git2::ProxyOptions::new();
+    match SETTINGS.proxy.as_deref() {
+        Some(url) => proxy.url(url),
+        None => proxy.auto(),
+    };
+    push_options.proxy_options(proxy);
+    if insecure() {
+        callbacks.certificate_check(|_, _| Ok(git2::CertificateCheckStatus::CertificateOk));
+    }
+}
+
+fn matcher() -> Matcher {
+    match &SETTINGS.proxy {
+        Some(proxy) => Matcher::builder()
+            .all(proxy.clone())
+            .no(SETTINGS.no_proxy.clone().unwrap_or_default())
+            .build(),
+        None => Matcher::from_env(),
+    }
+}
+
+/// Authentication of a GitHub client
+pub enum GitHubAuth {
+    None,
+    Token(String),
+    OAuth(OAuth),
+}
+
+/// Creates a GitHub client. The default client of octocrab is used unless the API has to
+/// be reached through a proxy or certificates must not be verified.
+pub fn github(
+    auth: GitHubAuth,
+    base_uri: Option<&str>,
+    headers: &[(HeaderName, String)],
+) -> Result<Octocrab, DiscoverError> {
+    let base_uri = Uri::try_from(base_uri.unwrap_or(GITHUB_API))
+        .map_err(|e| DiscoverError::HttpClient(e.into()))?;
+    let matcher = matcher();
+    if !insecure() && matcher.intercept(&base_uri).is_none() {
+        let mut builder = Octocrab::builder().base_uri(base_uri)?;
+        for (name, value) in headers {
+            builder = builder.add_header(name.clone(), value.clone());
+        }
+        let octocrab = match auth {
+            GitHubAuth::None => builder.build()?,
+            GitHubAuth::Token(token) => builder.personal_token(token).build()?,
+            GitHubAuth::OAuth(oauth) => builder.oauth(oauth).build()?,
+        };
+        return Ok(octocrab);
+    }
+    let auth_header = match auth {
+        GitHubAuth::None => None,
+        GitHubAuth::Token(token) => Some(format!("Bearer {token}")),
+        GitHubAuth::OAuth(oauth) => Some(format!(
+            "{} {}",
+            oauth.token_type,
+            oauth.access_token.expose_secret()
+        )),
+    }
+    .map(HeaderValue::try_from)
+    .transpose()
+    .map_err(|e| DiscoverError::HttpClient(e.into()))?;
+    let mut extra = vec![(USER_AGENT, HeaderValue::from_static("octocrab"))];
+    for (name, value) in headers {
+        let value = HeaderValue::try_from(value.as_str())
+            .map_err(|e| DiscoverError::HttpClient(e.into()))?;
+        extra.push((name.clone(), value));
+    }
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = hyper_rustls::HttpsConnectorBuilder::new();
+    let builder = if insecure() {
+        let tls = rustls::ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| DiscoverError::HttpClient(e.into()))?
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate(provider)))
+            .with_no_client_auth();
+        builder.with_tls_config(tls)
+    } else {
+        builder.with_provider_and_native_roots(provider)?
+    };
+    let mut http = HttpConnector::new();
+    http.enforce_http(false);
+    let connector = builder
+        .https_or_http()
+        .enable_http1()
+        .wrap_connector(ProxyConnector {
+            matcher: Arc::new(matcher),
+            http,
+        });
+    let client = hyper_util::client::legacy::Client::builder(TokioExecutor::new()).build(connector);
+    Ok(OctocrabBuilder::new_empty()
+        .with_service(client)
+        .with_layer(&BaseUriLayer::new(base_uri.clone()))
+        .with_layer(&ExtraHeadersLayer::new(Arc::new(extra)))
+        .with_layer(&AuthHeaderLayer::new(
+            auth_header,
+            base_uri,
+            Uri::from_static(GITHUB_UPLOADS),
+        ))
+        .with_auth(AuthState::None)
+        .build()
+        .unwrap_or_else(|never| match never {}))
+}
+
+/// Accepts any server certificate, but still checks that the handshake is signed by it
+#[derive(Debug)]
+struct AcceptAnyCertificate(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// Connects directly or, if the proxy matcher intercepts the destination, through an
+/// HTTP CONNECT tunnel. Only HTTPS destinations are tunneled.
+#[derive(Clone)]
+struct ProxyConnector {
+    matcher: Arc<Matcher>,
+    http: HttpConnector,
+}
+
+impl Service<Uri> for ProxyConnector {
+    type Response = TokioIo<TcpStream>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.http.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, dst: Uri) -> Self::Future {
+        match self.matcher.intercept(&dst) {
+            Some(intercept) if dst.scheme() == Some(&http::uri::Scheme::HTTPS) => {
+                let mut tunnel = Tunnel::new(intercept.uri().clone(), self.http.clone());
+                if let Some(auth) = intercept.basic_auth() {
+                    tunnel = tunnel.with_auth(auth.clone());
+                }
+                Box::pin(async move { tunnel.call(dst).await.map_err(Into::into) })
+            }
+            _ => {
+                let connecting = self.http.call(dst);
+                Box::pin(async move { connecting.await.map_err(Into::into) })
+            }
+        }
+    }
+}
+
+/// A hint for errors caused by an untrusted certificate, e.g. of a proxy that intercepts TLS
+pub fn certificate_error(err: &(dyn std::error::Error + 'static)) -> Option<String> {
+    let mut source = Some(err);
+    while let Some(err) = source {
+        let message = err.to_string();
+        if message.to_lowercase().contains("certificate") {
+            return Some(message);
+        }
+        source = err.source();
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_certificate_error() {
+        let io = std::io::Error::other("invalid peer certificate: UnknownIssuer");
+        let err = std::io::Error::new(std::io::ErrorKind::ConnectionRefused, io);
+        assert_eq!(
+            certificate_error(&err).as_deref(),
+            Some("invalid peer certificate: UnknownIssuer")
+        );
+        assert_eq!(certificate_error(&std::io::Error::other("refused")), None);
+    }
+}
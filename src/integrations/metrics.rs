@@ -0,0 +1,126 @@
+// SPDX-FileCopyrightText: 2025 Karsten Becker
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::action::{ActionOutcome, ActionType};
+use crate::execution_plan::ActionResult;
+use crate::file_ops::write_atomic;
+
+/// Status label of an action in the metrics
+const STATUSES: [&str; 3] = ["ok", "skipped", "failed"];
+
+/// Metrics of an apply run in the Prometheus textfile format
+#[derive(Debug, Default, PartialEq)]
+pub struct RunMetrics {
+    /// Number of actions by type and status
+    pub actions: BTreeMap<(String, &'static str), usize>,
+    pub duration: Duration,
+    /// End of the run in seconds since the epoch
+    pub timestamp: u64,
+}
+
+impl RunMetrics {
+    pub fn from_results(results: &[ActionResult], duration: Duration, timestamp: u64) -> Self {
+        // All combinations are written, so that a series does not vanish when its count is 0
+        let mut actions = ActionType::ALL
+            .iter()
+            .flat_map(|t| STATUSES.map(|status| ((t.to_string(), status), 0)))
+            .collect::<BTreeMap<_, _>>();
+        for result in results {
+            let status = match &result.result {
+                Ok(ActionOutcome::Done) => "ok",
+                Ok(ActionOutcome::Skipped(_)) => "skipped",
+                Err(_) => "failed",
+            };
+            *actions
+                .entry((result.action.action_type().to_string(), status))
+                .or_default() += 1;
+        }
+        Self {
+            actions,
+            duration,
+            timestamp,
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        self.write_to(&mut out)
+            .expect("Writing to a String can not fail");
+        out
+    }
+
+    fn write_to(&self, out: &mut String) -> std::fmt::Result {
+        writeln!(
+            out,
+            "# HELP hermitgrab_actions Actions of the last apply by type and status"
+        )?;
+        writeln!(out, "# TYPE hermitgrab_actions gauge")?;
+        for ((action_type, status), count) in &self.actions {
+            writeln!(
+                out,
+                "hermitgrab_actions{{type=\"{action_type}\",status=\"{status}\"}} {count}"
+            )?;
+        }
+        writeln!(
+            out,
+            "# HELP hermitgrab_apply_duration_seconds Duration of the last apply"
+        )?;
+        writeln!(out, "# TYPE hermitgrab_apply_duration_seconds gauge")?;
+        writeln!(
+            out,
+            "hermitgrab_apply_duration_seconds {:.3}",
+            self.duration.as_secs_f64()
+        )?;
+        writeln!(
+            out,
+            "# HELP hermitgrab_last_apply_timestamp End of the last apply in seconds since the epoch"
+        )?;
+        writeln!(out, "# TYPE hermitgrab_last_apply_timestamp gauge")?;
+        writeln!(out, "hermitgrab_last_apply_timestamp {}", self.timestamp)
+    }
+
+    /// Replaces the file atomically, a scraper never reads a partially written file
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        write_atomic(path, self.render())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_metrics() {
+        let mut metrics = RunMetrics::from_results(&[], Duration::from_millis(1500), 1700000000);
+        *metrics
+            .actions
+            .get_mut(&("link".to_string(), "failed"))
+            .unwrap() = 2;
+        let rendered = metrics.render();
+        assert!(rendered.contains("hermitgrab_actions{type=\"link\",status=\"failed\"} 2\n"));
+        assert!(rendered.contains("hermitgrab_actions{type=\"fetch\",status=\"ok\"} 0\n"));
+        assert_eq!(
+            rendered
+                .lines()
+                .filter(|l| l.starts_with("hermitgrab_actions"))
+                .count(),
+            12
+        );
+        assert!(rendered.contains("hermitgrab_apply_duration_seconds 1.500\n"));
+        assert!(rendered.ends_with("hermitgrab_last_apply_timestamp 1700000000\n"));
+
+        let temp = tempfile::TempDir::new().unwrap();
+        let file = temp.path().join("textfile").join("hermitgrab.prom");
+        metrics.write(&file).unwrap();
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), rendered);
+    }
+}
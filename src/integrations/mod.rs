@@ -2,5 +2,10 @@
 //
 // SPDX-License-Identifier: GPL-3.0-only
 
+pub mod http;
+pub mod metrics;
+pub mod notify;
+pub mod report_commit;
+pub mod telemetry;
 #[cfg(feature = "ubi")]
 pub mod ubi_int;
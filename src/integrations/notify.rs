@@ -0,0 +1,76 @@
+// SPDX-FileCopyrightText: 2025 Karsten Becker
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::io::Write;
+use std::process::Command;
+use std::time::Duration;
+
+use crate::config::Shell;
+use crate::config::user_settings::NotifySettings;
+use crate::state::HistoryEntry;
+use crate::{debug, warn};
+
+/// Rings the bell and runs the `on_finish` command when the run took longer than the
+/// configured threshold. Failures are only warned about.
+pub fn notify_finished(settings: &NotifySettings, elapsed: Duration, entry: &HistoryEntry) {
+    if elapsed < settings.threshold() {
+        return;
+    }
+    if settings.bell {
+        let mut stderr = std::io::stderr();
+        let _ = stderr.write_all(b"\x07").and_then(|_| stderr.flush());
+    }
+    if let Some(cmd) = &settings.on_finish {
+        debug!("Running the on_finish command: {cmd}");
+        match on_finish_command(cmd, elapsed, entry).status() {
+            Ok(status) if status.success() => {}
+            Ok(status) => warn!("The on_finish command failed with {status}"),
+            Err(e) => warn!("Failed to run the on_finish command: {e}"),
+        }
+    }
+}
+
+/// The `on_finish` command with the summary of the run in its environment
+fn on_finish_command(cmd: &str, elapsed: Duration, entry: &HistoryEntry) -> Command {
+    let shell = Shell::Auto;
+    let result = if entry.failed > 0 { "failed" } else { "ok" };
+    let mut command = Command::new(shell.program());
+    command
+        .args(shell.command_args())
+        .arg(cmd)
+        .env("HERMIT_COMMAND", &entry.command)
+        .env("HERMIT_RESULT", result)
+        .env("HERMIT_OK_COUNT", entry.ok.to_string())
+        .env("HERMIT_SKIPPED_COUNT", entry.skipped.to_string())
+        .env("HERMIT_FAILED_COUNT", entry.failed.to_string())
+        .env("HERMIT_DURATION_SECS", elapsed.as_secs().to_string());
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsStr;
+
+    use super::*;
+
+    #[test]
+    fn test_on_finish_command() {
+        let mut entry = HistoryEntry::new("apply", None, vec![]);
+        entry.add_result("link:a".to_string(), true, "Link a".to_string(), None);
+        entry.add_result("link:b".to_string(), false, "Link b".to_string(), None);
+        let command = on_finish_command("notify-send done", Duration::from_secs(75), &entry);
+        let env = command
+            .get_envs()
+            .filter_map(|(k, v)| Some((k.to_str()?, v?.to_str()?)))
+            .collect::<std::collections::BTreeMap<_, _>>();
+        assert_eq!(env["HERMIT_RESULT"], "failed");
+        assert_eq!(env["HERMIT_OK_COUNT"], "1");
+        assert_eq!(env["HERMIT_FAILED_COUNT"], "1");
+        assert_eq!(env["HERMIT_DURATION_SECS"], "75");
+        assert_eq!(
+            command.get_args().last(),
+            Some(OsStr::new("notify-send done"))
+        );
+    }
+}
@@ -0,0 +1,340 @@
+// SPDX-FileCopyrightText: 2025 Karsten Becker
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::cell::{Cell, RefCell};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use git2::{
+    Cred, CredentialType, IndexEntry, IndexTime, Oid, PushOptions, RemoteCallbacks, Repository,
+    Signature,
+};
+use serde_json::Value;
+
+use crate::config::GlobalConfig;
+use crate::config::secrets::redact;
+use crate::hermitgrab_error::ReportCommitError;
+use crate::integrations::http;
+use crate::state::HistoryEntry;
+use crate::{debug, info, warn};
+
+/// Directory in the dotfiles repository that holds the reports of all machines
+pub const REPORTS_DIR: &str = "reports";
+
+/// Name of the machine, reduced to characters that are safe in a path
+fn machine_name() -> String {
+    let name = hostname::get()
+        .map(|h| h.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if name.is_empty() || name.chars().all(|c| c == '.') {
+        "unknown".to_string()
+    } else {
+        name
+    }
+}
+
+/// Commits and pushes the report of an apply when `report_commit` is enabled in the root
+/// hermit.toml. Failures are only warned about, the run itself is not affected
+pub fn commit_report_if_enabled(global_config: &Arc<GlobalConfig>, entry: &HistoryEntry) {
+    let enabled = global_config
+        .root_config()
+        .is_some_and(|cfg| cfg.settings.report_commit);
+    if !enabled || entry.command != "apply" {
+        return;
+    }
+    match commit_report(global_config.hermit_dir(), &machine_name(), entry) {
+        Ok(Some(file)) => info!("Committed the report to {}", file.display()),
+        Ok(None) => {
+            debug!("The result did not change since the last report");
+            return;
+        }
+        Err(e) => {
+            warn!("Failed to commit the report: {e}");
+            return;
+        }
+    }
+    match push_report(global_config.hermit_dir()) {
+        Ok(Some(remote)) => info!("Pushed the report to {remote}"),
+        Ok(None) => warn!("The branch has no upstream, the report was committed but not pushed"),
+        Err(e) => warn!("Failed to push the report: {e}"),
+    }
+}
+
+/// Whether two reports have the same result, the time of the run is not compared
+fn same_result(old: &[u8], new: &str) -> bool {
+    let without_time = |value: &mut Value| {
+        if let Some(map) = value.as_object_mut() {
+            map.remove("id");
+            map.remove("timestamp");
+        }
+    };
+    let (Ok(mut old), Ok(mut new)) = (
+        serde_json::from_slice::<Value>(old),
+        serde_json::from_str::<Value>(new),
+    ) else {
+        return false;
+    };
+    without_time(&mut old);
+    without_time(&mut new);
+    old == new
+}
+
+/// Writes the redacted report to `reports/<machine>/<command>.json` below the hermit directory
+/// and commits only that file on top of HEAD. Other staged or modified files are not touched.
+/// Returns None without writing anything if the committed report has the same result.
+pub fn commit_report(
+    hermit_dir: &Path,
+    machine: &str,
+    entry: &HistoryEntry,
+) -> Result<Option<PathBuf>, ReportCommitError> {
+    let repo = Repository::discover(hermit_dir)?;
+    let workdir = repo.workdir().ok_or(ReportCommitError::BareRepository)?;
+    let workdir = workdir.canonicalize()?;
+    let hermit_dir = hermit_dir.canonicalize()?;
+    let file = hermit_dir
+        .join(REPORTS_DIR)
+        .join(machine)
+        .join(format!("{}.json", entry.command));
+    let relative = file
+        .strip_prefix(&workdir)
+        .map_err(|_| ReportCommitError::OutsideRepository(file.clone()))?
+        .to_path_buf();
+    let content = redact(&serde_json::to_string_pretty(entry)?) + "\n";
+    let head = repo.head().ok().and_then(|h| h.peel_to_commit().ok());
+    let committed = head
+        .as_ref()
+        .and_then(|head| head.tree().ok())
+        .and_then(|tree| tree.get_path(&relative).ok())
+        .and_then(|entry| repo.find_blob(entry.id()).ok());
+    if committed.is_some_and(|blob| same_result(blob.content(), &content)) {
+        return Ok(None);
+    }
+    if let Some(parent) = file.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&file, &content)?;
+
+    let blob = repo.blob(content.as_bytes())?;
+    // An in-memory index based on HEAD, so that only the report ends up in the commit
+    let mut index = git2::Index::new()?;
+    if let Some(head) = &head {
+        index.read_tree(&head.tree()?)?;
+    }
+    index.add(&index_entry(&relative, blob, content.len()))?;
+    let tree = repo.find_tree(index.write_tree_to(&repo)?)?;
+    let signature = repo
+        .signature()
+        .or_else(|_| Signature::now("hermitgrab", "hermitgrab@localhost"))?;
+    let message = format!(
+        "Report of {} on {machine}: {} ok, {} failed",
+        entry.command, entry.ok, entry.failed
+    );
+    let parents = head.iter().collect::<Vec<_>>();
+    repo.commit(
+        Some("HEAD"),
+        &signature,
+        &signature,
+        &message,
+        &tree,
+        &parents,
+    )?;
+    // Stage the report in the index of the work tree as well, it matches HEAD now
+    let mut work_index = repo.index()?;
+    work_index.add_path(&relative)?;
+    work_index.write()?;
+    Ok(Some(file))
+}
+
+/// Pushes the current branch to its upstream, so the report can be seen from other machines.
+/// Returns the name of the remote, None if the branch has no upstream.
+pub fn push_report(hermit_dir: &Path) -> Result<Option<String>, ReportCommitError> {
+    let repo = Repository::discover(hermit_dir)?;
+    let head = repo.head()?;
+    let Some(branch) = head.name().filter(|_| head.is_branch()) else {
+        return Ok(None);
+    };
+    let Ok(remote_name) = repo.branch_upstream_remote(branch) else {
+        return Ok(None);
+    };
+    let remote_name = remote_name.as_str().unwrap_or("origin").to_string();
+    let merge = repo.branch_upstream_merge(branch)?;
+    let refspec = format!("{branch}:{}", merge.as_str().unwrap_or(branch));
+    let mut remote = repo.find_remote(&remote_name)?;
+    let config = repo.config()?;
+    let tried = Cell::new(false);
+    let rejected = RefCell::new(None);
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.credentials(|url, username_from_url, allowed_types| {
+        // libgit2 asks again as long as the credentials are rejected
+        if tried.replace(true) {
+            return Err(git2::Error::from_str("The credentials were rejected"));
+        }
+        if allowed_types.contains(CredentialType::SSH_KEY) {
+            Cred::ssh_key_from_agent(username_from_url.unwrap_or("git"))
+        } else if allowed_types.contains(CredentialType::USER_PASS_PLAINTEXT) {
+            Cred::credential_helper(&config, url, username_from_url)
+        } else {
+            Cred::default()
+        }
+    });
+    callbacks.push_update_reference(|refname, status| {
+        if let Some(status) = status {
+            *rejected.borrow_mut() = Some((refname.to_string(), status.to_string()));
+        }
+        Ok(())
+    });
+    let mut push_options = PushOptions::new();
+    http::git_push_options(&mut push_options, &mut callbacks);
+    push_options.remote_callbacks(callbacks);
+    remote.push(&[refspec], Some(&mut push_options))?;
+    drop(push_options);
+    if let Some((refname, status)) = rejected.into_inner() {
+        return Err(ReportCommitError::PushRejected(refname, status));
+    }
+    Ok(Some(remote_name))
+}
+
+fn index_entry(path: &Path, id: Oid, size: usize) -> IndexEntry {
+    let path = path.to_string_lossy().replace('\\', "/").into_bytes();
+    IndexEntry {
+        ctime: IndexTime::new(0, 0),
+        mtime: IndexTime::new(0, 0),
+        dev: 0,
+        ino: 0,
+        mode: 0o100644,
+        uid: 0,
+        gid: 0,
+        file_size: size as u32,
+        id,
+        flags: path.len().min(0xfff) as u16,
+        flags_extended: 0,
+        path,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commit_report() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let repo = Repository::init(temp.path()).unwrap();
+        let mut config = repo.config().unwrap();
+        config.set_str("user.name", "Test").unwrap();
+        config.set_str("user.email", "test@example.com").unwrap();
+        std::fs::write(temp.path().join("staged.txt"), "staged").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("staged.txt")).unwrap();
+        index.write().unwrap();
+
+        let mut entry = HistoryEntry::new("apply", None, vec!["work".to_string()]);
+        entry.add_result("link:foo".to_string(), false, "Link foo".to_string(), None);
+        let file = commit_report(temp.path(), "box", &entry).unwrap().unwrap();
+        assert!(file.ends_with("reports/box/apply.json"));
+
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(
+            head.message(),
+            Some("Report of apply on box: 0 ok, 1 failed")
+        );
+        let tree = head.tree().unwrap();
+        assert!(tree.get_path(Path::new("reports/box/apply.json")).is_ok());
+        assert!(tree.get_path(Path::new("staged.txt")).is_err());
+        let statuses = repo.statuses(None).unwrap();
+        let changed = statuses
+            .iter()
+            .filter_map(|s| s.path().map(str::to_string))
+            .collect::<Vec<_>>();
+        assert_eq!(changed, vec!["staged.txt"]);
+
+        entry.id = "later".to_string();
+        entry.timestamp += 60;
+        assert!(commit_report(temp.path(), "box", &entry).unwrap().is_none());
+        entry.ok = 1;
+        assert!(commit_report(temp.path(), "box", &entry).unwrap().is_some());
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(head.parent_count(), 1);
+    }
+
+    #[test]
+    fn test_commit_report_if_enabled_pushes() {
+        use crate::config::{CONF_FILE_NAME, find_hermit_files};
+
+        let temp = tempfile::TempDir::new().unwrap();
+        let remote = Repository::init_bare(temp.path().join("remote.git")).unwrap();
+        let hermit_dir = temp.path().join("hermit");
+        let repo = Repository::init(&hermit_dir).unwrap();
+        let mut config = repo.config().unwrap();
+        config.set_str("user.name", "Test").unwrap();
+        config.set_str("user.email", "test@example.com").unwrap();
+        std::fs::write(
+            hermit_dir.join(CONF_FILE_NAME),
+            "[settings]\nstrict_mode = false\nreport_commit = true\n",
+        )
+        .unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new(CONF_FILE_NAME)).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = repo.signature().unwrap();
+        repo.commit(Some("HEAD"), &signature, &signature, "init", &tree, &[])
+            .unwrap();
+        let branch = repo.head().unwrap().shorthand().unwrap().to_string();
+        repo.remote("origin", &temp.path().join("remote.git").to_string_lossy())
+            .unwrap();
+        config
+            .set_str(&format!("branch.{branch}.remote"), "origin")
+            .unwrap();
+        config
+            .set_str(
+                &format!("branch.{branch}.merge"),
+                &format!("refs/heads/{branch}"),
+            )
+            .unwrap();
+        let paths = find_hermit_files(&hermit_dir, &[CONF_FILE_NAME]);
+        let global = GlobalConfig::from_paths(&hermit_dir, &paths).unwrap();
+        let head = || repo.head().unwrap().peel_to_commit().unwrap().id();
+        let initial = head();
+
+        commit_report_if_enabled(&global, &HistoryEntry::new("status", None, vec![]));
+        assert_eq!(head(), initial);
+
+        let entry = HistoryEntry::new("apply", None, vec![]);
+        commit_report_if_enabled(&global, &entry);
+        let committed = head();
+        assert_ne!(committed, initial);
+        let pushed = remote
+            .find_reference(&format!("refs/heads/{branch}"))
+            .unwrap()
+            .target()
+            .unwrap();
+        assert_eq!(pushed, committed);
+        let report = Path::new(REPORTS_DIR)
+            .join(machine_name())
+            .join("apply.json");
+        assert!(
+            remote
+                .find_commit(pushed)
+                .unwrap()
+                .tree()
+                .unwrap()
+                .get_path(&report)
+                .is_ok()
+        );
+
+        // The same result is not committed again
+        commit_report_if_enabled(&global, &HistoryEntry::new("apply", None, vec![]));
+        assert_eq!(head(), committed);
+    }
+}
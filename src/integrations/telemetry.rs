@@ -0,0 +1,122 @@
+// SPDX-FileCopyrightText: 2025 Karsten Becker
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::user_settings::TelemetrySettings;
+use crate::file_ops::dirs::HERMIT_STATE_DIR;
+
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A single run of hermitgrab. Only the subcommand is recorded, never arguments or paths.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TelemetryEvent {
+    /// Seconds since the unix epoch
+    pub timestamp: u64,
+    pub command: String,
+    pub duration_ms: u64,
+    pub success: bool,
+    pub version: String,
+    pub os: String,
+}
+
+impl TelemetryEvent {
+    pub fn new(command: &str, duration: Duration, success: bool) -> Self {
+        Self {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            command: command.to_string(),
+            duration_ms: duration.as_millis() as u64,
+            success,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            os: std::env::consts::OS.to_string(),
+        }
+    }
+}
+
+/// Events are stored as JSON lines in $XDG_STATE_HOME/hermitgrab/telemetry.jsonl
+pub fn telemetry_file() -> PathBuf {
+    HERMIT_STATE_DIR.join("telemetry.jsonl")
+}
+
+fn append_event(file: &Path, event: &TelemetryEvent) -> std::io::Result<()> {
+    if let Some(parent) = file.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut out = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(file)?;
+    writeln!(out, "{}", serde_json::to_string(event)?)
+}
+
+/// Reads all stored events, lines that fail to parse are skipped
+pub fn read_events(file: &Path) -> std::io::Result<Vec<TelemetryEvent>> {
+    if !file.exists() {
+        return Ok(Vec::new());
+    }
+    Ok(std::fs::read_to_string(file)?
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+/// Sends all stored events as JSON array to the endpoint and removes them on success
+async fn upload_events(file: &Path, endpoint: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let events = read_events(file)?;
+    if events.is_empty() {
+        return Ok(());
+    }
+    crate::integrations::http::client()?
+        .post(endpoint)
+        .timeout(UPLOAD_TIMEOUT)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(serde_json::to_string(&events)?)
+        .send()
+        .await?
+        .error_for_status()?;
+    std::fs::remove_file(file)?;
+    Ok(())
+}
+
+/// Records a run if telemetry is enabled in the user settings and uploads the stored
+/// events if an endpoint is configured. Failures never affect the command itself.
+pub async fn record_run(settings: &TelemetrySettings, command: &str, duration: Duration, ok: bool) {
+    if !settings.enabled {
+        return;
+    }
+    let file = telemetry_file();
+    if let Err(e) = append_event(&file, &TelemetryEvent::new(command, duration, ok)) {
+        log::debug!("Failed to record telemetry in {}: {e}", file.display());
+        return;
+    }
+    if let Some(endpoint) = &settings.endpoint
+        && let Err(e) = upload_events(&file, endpoint).await
+    {
+        log::debug!("Failed to upload telemetry to {endpoint}: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append_and_read_events() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let file = temp.path().join("state").join("telemetry.jsonl");
+        assert!(read_events(&file).unwrap().is_empty());
+        let ok = TelemetryEvent::new("apply", Duration::from_millis(1500), true);
+        let failed = TelemetryEvent::new("status", Duration::from_millis(20), false);
+        append_event(&file, &ok).unwrap();
+        append_event(&file, &failed).unwrap();
+        assert_eq!(read_events(&file).unwrap(), vec![ok, failed]);
+    }
+}
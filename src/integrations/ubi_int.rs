@@ -64,7 +64,7 @@ pub async fn main(args: &[String]) {
 const MAX_TERM_WIDTH: usize = 100;
 
 #[allow(clippy::too_many_lines)]
-fn cmd() -> Command {
+pub(crate) fn cmd() -> Command {
     Command::new("ubi")
         .version(env!("CARGO_PKG_VERSION"))
         .author("Dave Rolsky <autarch@urth.org>")
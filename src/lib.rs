@@ -11,9 +11,13 @@ pub mod execution_plan;
 pub mod file_ops;
 pub mod hermitgrab_error;
 pub mod integrations;
+pub mod state;
+#[cfg(feature = "testkit")]
+pub mod testkit;
 
 // Re-export key types for compatibility with main.rs and all modules
-pub use config::{HermitConfig, InstallConfig, LinkConfig, LinkType, RequireTag};
+pub use config::options::CliOptionsBuilder;
+pub use config::{CliOptions, HermitConfig, InstallConfig, LinkConfig, LinkType, RequireTag};
 pub use hermitgrab_error::{
     AddError, ApplyError, ConfigError, DiscoverError, FileOpsError, StatusError,
 };
@@ -3,18 +3,22 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
 use std::path::PathBuf;
+use std::time::Instant;
 
 use anyhow::Result;
 use clap::Parser;
 
+use crate::commands::exit_report::ExitCode;
 use crate::commands::{Cli, Commands};
-use crate::common_cli::{hermitgrab_info, info};
+use crate::common_cli::{hermitgrab_info, info, warn};
+use crate::config::options::CliOptionsBuilder;
+use crate::config::user_settings::UserSettings;
 use crate::config::{
     CONF_FILE_NAME, GlobalConfig, HermitConfig, InstallConfig, LinkConfig, LinkType, RequireTag,
-    find_hermit_files,
+    config_file_names, find_hermit_files,
 };
-use crate::file_ops::dirs::BASE_DIRS;
-use crate::hermitgrab_error::FileOpsError;
+use crate::file_ops::dirs::home_dir;
+use crate::hermitgrab_error::{ConfigError, FileOpsError};
 
 mod action;
 mod build_doc;
@@ -26,13 +30,14 @@ mod execution_plan;
 mod file_ops;
 mod hermitgrab_error;
 mod integrations;
+mod state;
 
-fn init_hermit_dir(cli_path: &Option<PathBuf>) -> std::path::PathBuf {
+fn init_hermit_dir(cli_path: &Option<PathBuf>) -> Result<PathBuf, ConfigError> {
     if let Some(path) = cli_path {
         hermitgrab_info!("Using hermit directory from CLI: {}", path.display());
-        return path.clone();
+        return Ok(path.clone());
     }
-    let dotfiles_dir = BASE_DIRS.home_dir().join(".hermitgrab");
+    let dotfiles_dir = home_dir()?.join(".hermitgrab");
     if !dotfiles_dir.exists() {
         let path_buf = std::env::current_exe().ok();
         if let Some(exe) = path_buf {
@@ -44,7 +49,7 @@ fn init_hermit_dir(cli_path: &Option<PathBuf>) -> std::path::PathBuf {
                     "Using hermit directory beside executable {}",
                     dotfiles_dir.display()
                 );
-                return exe_dir.to_path_buf();
+                return Ok(exe_dir.to_path_buf());
             }
         }
     }
@@ -52,7 +57,7 @@ fn init_hermit_dir(cli_path: &Option<PathBuf>) -> std::path::PathBuf {
         "Using hermit directory from user dirs: {}",
         dotfiles_dir.display()
     );
-    dotfiles_dir
+    Ok(dotfiles_dir)
 }
 
 #[tokio::main]
@@ -61,30 +66,80 @@ async fn main() -> Result<()> {
         build_doc::build_doc();
         return Ok(());
     }
-    let cli = Cli::parse();
+    let aliases = UserSettings::load().unwrap_or_default().alias;
+    let cli = Cli::parse_from(commands::expand_alias(
+        std::env::args_os().collect(),
+        &aliases,
+    ));
+    if cli.json.as_deref() == Some(std::path::Path::new("-")) {
+        common_cli::reserve_stdout();
+    }
     let command = cli.command;
+    let options = CliOptionsBuilder::new()
+        .confirm(cli.confirm)
+        .verbose(cli.verbose)
+        .quiet(cli.quiet)
+        .json(cli.json)
+        .build();
+    common_cli::set_verbosity(options.verbosity);
+    integrations::http::set_insecure(cli.insecure);
+    if cli.insecure {
+        warn!("TLS certificates are not verified, only use --insecure on trusted networks");
+    }
     if !matches!(command, Commands::Ubi { .. }) {
         simple_logger::SimpleLogger::new()
             .with_level(log::LevelFilter::Error)
             .env()
             .init()?;
     }
-    let search_root = init_hermit_dir(&cli.hermit_dir);
-    let yaml_files = find_hermit_files(&search_root);
-    let global_config = GlobalConfig::from_paths(&search_root, &yaml_files)?;
+    let search_root = match init_hermit_dir(&cli.hermit_dir) {
+        Ok(path) => path,
+        Err(e) => {
+            common_cli::error(&e.to_string());
+            std::process::exit(ExitCode::ConfigInvalid.code());
+        }
+    };
+    let conf_names = config_file_names(&search_root, &cli.conf_name);
+    let yaml_files = find_hermit_files(&search_root, &conf_names);
+    let global_config = if cli.strict_config {
+        GlobalConfig::from_paths_strict(&search_root, &yaml_files)
+    } else {
+        GlobalConfig::from_paths(&search_root, &yaml_files)
+    };
+    let global_config = match global_config {
+        Ok(global_config) => global_config,
+        Err(e) => {
+            common_cli::error(&e.to_string());
+            std::process::exit(ExitCode::ConfigInvalid.code());
+        }
+    };
     #[cfg(not(feature = "interactive"))]
     let interactive = false;
     #[cfg(feature = "interactive")]
     let interactive = cli.interactive;
-    commands::execute(
+    let command_name = command.name();
+    let start = Instant::now();
+    let report = commands::execute(
         command,
         global_config,
-        cli.confirm,
-        cli.verbose,
+        options.confirm,
+        options.verbosity,
         interactive,
-        cli.json,
+        options.json,
     )
-    .await?;
-
-    Ok(())
+    .await;
+    if command_name != "ubi" {
+        commands::cmd_completions::refresh_completions();
+    }
+    let telemetry = UserSettings::load().unwrap_or_default().telemetry;
+    integrations::telemetry::record_run(&telemetry, command_name, start.elapsed(), report.is_ok())
+        .await;
+    let exit_code = report.exit_code();
+    if options.verbosity.is_verbose() {
+        info!("{report}");
+    }
+    if let Err(e) = report.into_result() {
+        common_cli::error(&format!("{e:#}"));
+    }
+    std::process::exit(exit_code.code())
 }
@@ -0,0 +1,409 @@
+// SPDX-FileCopyrightText: 2025 Karsten Becker
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::file_ops::dirs::HERMIT_STATE_DIR;
+use crate::hermitgrab_error::StateError;
+
+/// Reads a JSON state file, a missing file results in the default value
+pub fn load_state<T: DeserializeOwned + Default>(path: &Path) -> Result<T, StateError> {
+    if !path.exists() {
+        return Ok(T::default());
+    }
+    let content =
+        std::fs::read_to_string(path).map_err(|e| StateError::Io(e, path.to_path_buf()))?;
+    serde_json::from_str(&content).map_err(|e| StateError::Parse(e, path.to_path_buf()))
+}
+
+/// Writes a JSON state file, creating the state directory if required
+pub fn save_state<T: Serialize>(path: &Path, state: &T) -> Result<(), StateError> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| StateError::Io(e, parent.to_path_buf()))?;
+    }
+    let content = serde_json::to_string_pretty(state)
+        .map_err(|e| StateError::Parse(e, path.to_path_buf()))?;
+    std::fs::write(path, content).map_err(|e| StateError::Io(e, path.to_path_buf()))
+}
+
+/// Progress of an apply run, used to resume interrupted runs of the same plan
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ApplyProgress {
+    pub plan_hash: String,
+    pub completed: BTreeSet<String>,
+}
+
+impl ApplyProgress {
+    pub fn file() -> PathBuf {
+        HERMIT_STATE_DIR.join("apply_progress.json")
+    }
+
+    pub fn load() -> Result<Self, StateError> {
+        load_state(&Self::file())
+    }
+
+    pub fn save(&self) -> Result<(), StateError> {
+        save_state(&Self::file(), self)
+    }
+
+    pub fn clear() -> Result<(), StateError> {
+        let file = Self::file();
+        match std::fs::remove_file(&file) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(StateError::Io(e, file)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the completed actions if the progress belongs to the given plan
+    pub fn completed_for(&self, plan_hash: &str) -> Option<&BTreeSet<String>> {
+        if self.plan_hash == plan_hash && !self.completed.is_empty() {
+            Some(&self.completed)
+        } else {
+            None
+        }
+    }
+}
+
+/// Seconds since the unix epoch
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Last successful run of scheduled actions
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ScheduleState {
+    /// Seconds since the unix epoch by action id
+    pub last_run: BTreeMap<String, u64>,
+}
+
+impl ScheduleState {
+    pub fn file() -> PathBuf {
+        HERMIT_STATE_DIR.join("schedule.json")
+    }
+
+    pub fn load() -> Result<Self, StateError> {
+        load_state(&Self::file())
+    }
+
+    pub fn save(&self) -> Result<(), StateError> {
+        save_state(&Self::file(), self)
+    }
+}
+
+/// The XDG base directories of the last apply, to find links applied with other overrides
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct XdgBases {
+    /// Resolved directory by the name used in `{{ dir.* }}`
+    pub bases: BTreeMap<String, String>,
+}
+
+impl XdgBases {
+    pub fn file() -> PathBuf {
+        HERMIT_STATE_DIR.join("xdg_bases.json")
+    }
+
+    pub fn load() -> Result<Self, StateError> {
+        load_state(&Self::file())
+    }
+
+    pub fn save(&self) -> Result<(), StateError> {
+        save_state(&Self::file(), self)
+    }
+}
+
+/// Actions that are left out of every plan on this machine
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct DisabledActions {
+    pub ids: BTreeSet<String>,
+}
+
+impl DisabledActions {
+    pub fn file() -> PathBuf {
+        HERMIT_STATE_DIR.join("disabled.json")
+    }
+
+    pub fn load() -> Result<Self, StateError> {
+        load_state(&Self::file())
+    }
+
+    pub fn save(&self) -> Result<(), StateError> {
+        save_state(&Self::file(), self)
+    }
+}
+
+/// Content hash of a file, valid as long as size and modification time are unchanged
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct HashEntry {
+    pub size: u64,
+    /// Nanoseconds since the unix epoch
+    pub mtime: u128,
+    pub hash: String,
+}
+
+/// Content hashes of copied files, used to speed up repeated extensive status checks
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct HashCache {
+    pub files: BTreeMap<PathBuf, HashEntry>,
+}
+
+impl HashCache {
+    pub fn file() -> PathBuf {
+        HERMIT_STATE_DIR.join("hash_cache.json")
+    }
+
+    pub fn load() -> Result<Self, StateError> {
+        load_state(&Self::file())
+    }
+
+    pub fn save(&self) -> Result<(), StateError> {
+        save_state(&Self::file(), self)
+    }
+}
+
+/// Number of runs kept in the history, older runs are removed
+const HISTORY_LIMIT: usize = 500;
+
+/// An apply or status run, stored as one file per run in the history directory
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct HistoryEntry {
+    /// Start of the run in milliseconds since the epoch, sorts like the runs
+    pub id: String,
+    /// Start of the run in seconds since the epoch
+    pub timestamp: u64,
+    pub command: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub plan_hash: String,
+    /// Number of actions that were ok
+    pub ok: usize,
+    /// Number of actions that failed or were not ok
+    pub failed: usize,
+    /// Number of actions that had nothing to do, they are not counted as ok
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub skipped: usize,
+    /// Result of every action in the order of the plan
+    #[serde(default, deserialize_with = "deserialize_results")]
+    pub results: Vec<HistoryResult>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct HistoryResult {
+    /// Id of the action, empty in entries that stored the results by id
+    #[serde(default)]
+    pub id: String,
+    pub ok: bool,
+    pub description: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// Reason why the action had nothing to do
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub skipped: Option<String>,
+}
+
+/// Older entries stored the results in a map by action id
+fn deserialize_results<'de, D>(deserializer: D) -> Result<Vec<HistoryResult>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Results {
+        Ordered(Vec<HistoryResult>),
+        ById(BTreeMap<String, HistoryResult>),
+    }
+    Ok(match Results::deserialize(deserializer)? {
+        Results::Ordered(results) => results,
+        Results::ById(results) => results
+            .into_iter()
+            .map(|(id, result)| HistoryResult { id, ..result })
+            .collect(),
+    })
+}
+
+fn is_zero(count: &usize) -> bool {
+    *count == 0
+}
+
+impl HistoryEntry {
+    /// Starts an entry for a run that starts now
+    pub fn new(command: &str, profile: Option<String>, tags: Vec<String>) -> Self {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        Self {
+            id: millis.to_string(),
+            timestamp: now(),
+            command: command.to_string(),
+            profile,
+            tags,
+            ..Default::default()
+        }
+    }
+
+    pub fn add_result(
+        &mut self,
+        id: String,
+        ok: bool,
+        description: String,
+        message: Option<String>,
+    ) {
+        if ok {
+            self.ok += 1;
+        } else {
+            self.failed += 1;
+        }
+        self.results.push(HistoryResult {
+            id,
+            ok,
+            description,
+            message,
+            skipped: None,
+        });
+    }
+
+    pub fn add_skipped(&mut self, id: String, description: String, reason: String) {
+        self.skipped += 1;
+        self.results.push(HistoryResult {
+            id,
+            ok: true,
+            description,
+            message: None,
+            skipped: Some(reason),
+        });
+    }
+
+    pub fn dir() -> PathBuf {
+        HERMIT_STATE_DIR.join("history")
+    }
+
+    /// Directory the output of the commands of this run is streamed to
+    pub fn output_dir(&self) -> PathBuf {
+        Self::dir().join(&self.id)
+    }
+
+    /// Stores the entry and removes the oldest entries beyond the history limit
+    pub fn save(&self) -> Result<(), StateError> {
+        Self::save_in(&Self::dir(), self)
+    }
+
+    fn save_in(dir: &Path, entry: &Self) -> Result<(), StateError> {
+        save_state(&dir.join(format!("{}.json", entry.id)), entry)?;
+        let mut files = Self::files_in(dir)?;
+        files.sort();
+        for file in files.iter().rev().skip(HISTORY_LIMIT) {
+            std::fs::remove_file(file).map_err(|e| StateError::Io(e, file.clone()))?;
+            let output_dir = file.with_extension("");
+            if output_dir.is_dir() {
+                std::fs::remove_dir_all(&output_dir)
+                    .map_err(|e| StateError::Io(e, output_dir.clone()))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn files_in(dir: &Path) -> Result<Vec<PathBuf>, StateError> {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(StateError::Io(e, dir.to_path_buf())),
+        };
+        Ok(entries
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|ext| ext == "json"))
+            .collect())
+    }
+
+    /// All stored runs, the most recent first
+    pub fn list() -> Result<Vec<Self>, StateError> {
+        Self::list_in(&Self::dir())
+    }
+
+    fn list_in(dir: &Path) -> Result<Vec<Self>, StateError> {
+        let mut entries = Self::files_in(dir)?
+            .iter()
+            .map(|file| load_state::<Self>(file))
+            .collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by(|a, b| b.timestamp.cmp(&a.timestamp).then(b.id.cmp(&a.id)));
+        Ok(entries)
+    }
+
+    /// The stored run with the given id
+    pub fn load(id: &str) -> Result<Self, StateError> {
+        let file = Self::dir().join(format!("{id}.json"));
+        if !file.exists() {
+            return Err(StateError::UnknownHistoryEntry(id.to_string()));
+        }
+        load_state(&file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_roundtrip() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let file = temp.path().join("nested").join("progress.json");
+        let missing: ApplyProgress = load_state(&file).unwrap();
+        assert_eq!(missing, ApplyProgress::default());
+        let progress = ApplyProgress {
+            plan_hash: "abc".to_string(),
+            completed: BTreeSet::from(["a".to_string()]),
+        };
+        save_state(&file, &progress).unwrap();
+        let loaded: ApplyProgress = load_state(&file).unwrap();
+        assert_eq!(loaded, progress);
+        assert!(loaded.completed_for("abc").is_some());
+        assert!(loaded.completed_for("other").is_none());
+    }
+
+    #[test]
+    fn test_history_limit_and_order() {
+        let temp = tempfile::TempDir::new().unwrap();
+        for i in 0..HISTORY_LIMIT + 2 {
+            let entry = HistoryEntry {
+                id: format!("{i:05}"),
+                timestamp: i as u64,
+                command: "apply".to_string(),
+                ..Default::default()
+            };
+            HistoryEntry::save_in(temp.path(), &entry).unwrap();
+        }
+        let entries = HistoryEntry::list_in(temp.path()).unwrap();
+        assert_eq!(entries.len(), HISTORY_LIMIT);
+        assert_eq!(entries[0].id, format!("{:05}", HISTORY_LIMIT + 1));
+        assert_eq!(entries.last().unwrap().id, "00002");
+    }
+
+    #[test]
+    fn test_history_counts_skipped() {
+        let mut entry = HistoryEntry::new("apply", None, Vec::new());
+        entry.add_result("a".to_string(), true, "A".to_string(), None);
+        entry.add_skipped("b".to_string(), "B".to_string(), "up to date".to_string());
+        assert_eq!((entry.ok, entry.skipped, entry.failed), (1, 1, 0));
+        assert_eq!(entry.results[1].skipped.as_deref(), Some("up to date"));
+        let json = serde_json::to_value(&entry).unwrap();
+        assert_eq!(json["results"][0]["id"], "a");
+        assert!(json["results"][0].get("skipped").is_none());
+        let old: HistoryEntry = serde_json::from_str(
+            r#"{"id": "1", "timestamp": 1, "command": "apply", "plan_hash": "", "ok": 1, "failed": 0,
+                "results": {"a": {"ok": true, "description": "A"}}}"#,
+        )
+        .unwrap();
+        assert_eq!(old.skipped, 0);
+        assert_eq!(old.results[0].id, "a");
+    }
+}
@@ -0,0 +1,129 @@
+// SPDX-FileCopyrightText: 2025 Karsten Becker
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Helpers for integration tests of hermitgrab, enabled by the `testkit` feature.
+//!
+//! ```no_run
+//! # async fn example() {
+//! use hermitgrab::testkit::{TestHome, read_global_config};
+//!
+//! let home = TestHome::new().await;
+//! let global_config = read_global_config(&home.hermit_dir());
+//! # }
+//! ```
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use tempfile::TempDir;
+use tokio::sync::{Mutex, MutexGuard};
+
+use crate::config::{CONF_FILE_NAME, GlobalConfig, find_hermit_files};
+
+/// This lock will be used for preventing modification of the HOME variable
+/// when multiple tests are started simultaneously. This also means that
+/// only one test holding it can run at a time.
+pub static ENV_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// A temporary directory that is used as $HOME while the value is alive.
+/// It holds the [ENV_LOCK], so tests using it run one after the other.
+pub struct TestHome {
+    path: PathBuf,
+    _temp: TempDir,
+    _env_lock: MutexGuard<'static, ()>,
+}
+
+impl TestHome {
+    pub async fn new() -> Self {
+        let env_lock = ENV_LOCK.lock().await;
+        let temp = TempDir::new().expect("Failed to create temporary directory");
+        let path = temp
+            .path()
+            .canonicalize()
+            .expect("Failed to canonicalize temporary directory");
+        // SAFETY: Other tests modifying the environment have to hold the ENV_LOCK
+        unsafe {
+            std::env::set_var("HOME", &path);
+        }
+        Self {
+            path,
+            _temp: temp,
+            _env_lock: env_lock,
+        }
+    }
+
+    /// The temporary home directory
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The home directory as string, as used for [read_json]
+    pub fn path_str(&self) -> &str {
+        self.path.to_str().expect("Temporary path should be utf-8")
+    }
+
+    /// The default hermit directory in the temporary home
+    pub fn hermit_dir(&self) -> PathBuf {
+        self.path.join(".hermitgrab")
+    }
+}
+
+/// Reads a JSON file, replacing all occurrences of `temp_dir` with TEMP_DIR so that
+/// results can be compared across runs
+pub fn read_json<P: AsRef<Path>>(path: P, temp_dir: &str) -> serde_json::Value {
+    assert_file_exists(&path);
+    let data = fs::read_to_string(path).expect("Failed to read file");
+    let data = data
+        .replace(temp_dir, "TEMP_DIR")
+        .replace("/privateTEMP_DIR", "TEMP_DIR");
+    serde_json::from_str(&data).expect("Failed to parse JSON")
+}
+
+pub fn assert_json_eq(expected: &Path, actual: &Path, temp_dir: &str) {
+    let exp = read_json(expected, temp_dir);
+    let act = read_json(actual, temp_dir);
+    pretty_assertions::assert_eq!(
+        exp,
+        act,
+        "JSON files differ: {} != {}",
+        expected.display(),
+        actual.display()
+    );
+}
+
+pub fn assert_file_exists<P: AsRef<Path>>(path: P) {
+    assert!(
+        path.as_ref().exists(),
+        "File does not exist: {}",
+        path.as_ref().display()
+    );
+}
+
+pub fn assert_symlink_points_to(link: &Path, target: &Path) {
+    assert!(link.exists(), "Symlink does not exist: {}", link.display());
+    let link_target = fs::read_link(link).expect("Not a symlink");
+    pretty_assertions::assert_eq!(
+        link_target,
+        target.canonicalize().unwrap(),
+        "Symlink {} does not point to {}",
+        link.display(),
+        target.display()
+    );
+}
+
+pub fn assert_file_equals<P: AsRef<Path>>(path: P, content: &str) {
+    assert_file_exists(&path);
+    let data = fs::read_to_string(path).expect("Failed to read file");
+    pretty_assertions::assert_eq!(content, data);
+}
+
+/// Loads all hermit.toml files below the hermit directory
+pub fn read_global_config(hermit_root: &Path) -> Arc<GlobalConfig> {
+    GlobalConfig::from_paths(
+        hermit_root,
+        &find_hermit_files(hermit_root, &[CONF_FILE_NAME]),
+    )
+    .expect("Failed to load the configuration")
+}
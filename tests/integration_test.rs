@@ -1,85 +1,24 @@
 //! Integration test ported from test.sh using Commands enum
 
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 use std::str::FromStr;
-use std::sync::Arc;
 
-use hermitgrab::commands::{self, AddCommand, Commands, GetCommand};
-use hermitgrab::config::{
-    FallbackOperation, GlobalConfig, PatchType, RequireTag, find_hermit_files,
+use hermitgrab::commands::{self, AddCommand, ApplyArgs, Commands, GetCommand};
+use hermitgrab::common_cli::Verbosity;
+use hermitgrab::config::{FallbackOperation, PatchType, RequireTag};
+use hermitgrab::testkit::{
+    TestHome, assert_file_equals, assert_file_exists, assert_json_eq, assert_symlink_points_to,
+    read_global_config,
 };
 use pretty_assertions::assert_eq;
-use tempfile::TempDir;
-use tokio::sync::Mutex;
-
-/// This lock will be used for preventing modification of the HOME variable
-/// when multiple tests are started simultaenously. This also means that
-/// only one test can run at a time. This is due to the usage of the $HOME
-/// variable in the ordered test.
-static ENV_LOCK: Mutex<()> = Mutex::const_new(());
-
-fn read_json<P: AsRef<Path>>(path: P, temp_dir: &str) -> serde_json::Value {
-    assert_file_exists(&path);
-    let data = fs::read_to_string(path).expect("Failed to read file");
-    let data = data
-        .replace(temp_dir, "TEMP_DIR")
-        .replace("/privateTEMP_DIR", "TEMP_DIR");
-    serde_json::from_str(&data).expect("Failed to parse JSON")
-}
-
-fn assert_json_eq(expected: &Path, actual: &Path, temp_dir: &str) {
-    let exp = read_json(expected, temp_dir);
-    let act = read_json(actual, temp_dir);
-    assert_eq!(
-        exp,
-        act,
-        "JSON files differ: {} != {}",
-        expected.display(),
-        actual.display()
-    );
-}
-
-fn assert_file_exists<P: AsRef<Path>>(path: P) {
-    assert!(
-        path.as_ref().exists(),
-        "File does not exist: {}",
-        path.as_ref().display()
-    );
-}
-
-fn assert_symlink_points_to(link: &Path, target: &Path) {
-    assert!(link.exists(), "Symlink does not exist: {}", link.display());
-    let link_target = fs::read_link(link).expect("Not a symlink");
-    assert_eq!(
-        link_target,
-        target.canonicalize().unwrap(),
-        "Symlink {} does not point to {}",
-        link.display(),
-        target.display()
-    );
-}
-
-fn assert_file_equals<P: AsRef<Path>>(path: P, content: &str) {
-    assert_file_exists(&path);
-    let data = fs::read_to_string(path).expect("Failed to read file");
-    assert_eq!(content, data);
-}
-
-fn read_global_config(hermit_root: &Path) -> Arc<GlobalConfig> {
-    GlobalConfig::from_paths(hermit_root, &find_hermit_files(hermit_root)).unwrap()
-}
 
 #[tokio::test]
 async fn smoke_test() {
-    let temp = TempDir::new().unwrap();
-    let temp_path = temp.path().canonicalize().unwrap();
-    let temp_str = temp_path.to_str().unwrap();
-    let _env_lock = ENV_LOCK.lock().await;
-    unsafe {
-        std::env::set_var("HOME", &temp_path);
-    }
-    let hermit_root = temp_path.join(".hermitgrab");
+    let home = TestHome::new().await;
+    let temp_path = home.path();
+    let temp_str = home.path_str();
+    let hermit_root = home.hermit_dir();
     let cargo_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
     let test_results = cargo_root.join("test_results");
 
@@ -90,11 +29,12 @@ async fn smoke_test() {
         },
         read_global_config(&hermit_root),
         true,
-        false,
+        Verbosity::Normal,
         false,
         None,
     )
     .await
+    .into_result()
     .unwrap();
     assert_file_exists(hermit_root.join(".git/HEAD"));
 
@@ -113,11 +53,12 @@ async fn smoke_test() {
         },
         read_global_config(&hermit_root),
         true,
-        false,
+        Verbosity::Normal,
         false,
         None,
     )
     .await
+    .into_result()
     .unwrap();
     assert_file_exists(hermit_root.join("test1/hermit.toml"));
     // Compare config
@@ -125,15 +66,16 @@ async fn smoke_test() {
     let actual = test_results.join("add_config_test1_actual.json");
     commands::execute(
         Commands::Get {
-            get_command: GetCommand::Config,
+            get_command: GetCommand::Config { origin: false },
         },
         read_global_config(&hermit_root),
         true,
-        false,
+        Verbosity::Normal,
         false,
         Some(actual.clone()),
     )
     .await
+    .into_result()
     .unwrap();
     assert_json_eq(&expected, &actual, temp_str);
 
@@ -148,30 +90,33 @@ async fn smoke_test() {
                 required_tags: vec![],
                 fallback: FallbackOperation::Abort,
                 order: None,
+                relative: false,
             },
         },
         read_global_config(&hermit_root),
         true,
-        false,
+        Verbosity::Normal,
         false,
         None,
     )
     .await
+    .into_result()
     .unwrap();
     assert_file_exists(hermit_root.join("test1/testfile.txt"));
     let expected = test_results.join("add_testfile_link_expected.json");
     let actual = test_results.join("add_testfile_link_actual.json");
     commands::execute(
         Commands::Get {
-            get_command: GetCommand::Config,
+            get_command: GetCommand::Config { origin: false },
         },
         read_global_config(&hermit_root),
         true,
-        false,
+        Verbosity::Normal,
         false,
         Some(actual.clone()),
     )
     .await
+    .into_result()
     .unwrap();
     assert_json_eq(&expected, &actual, temp_str);
 
@@ -188,30 +133,33 @@ async fn smoke_test() {
                 required_tags: vec![RequireTag::from_str("~another").unwrap()],
                 fallback: FallbackOperation::BackupOverwrite,
                 order: None,
+                relative: false,
             },
         },
         read_global_config(&hermit_root),
         true,
-        false,
+        Verbosity::Normal,
         false,
         None,
     )
     .await
+    .into_result()
     .unwrap();
     assert_file_exists(hermit_root.join("test1/anotherfile.txt"));
     let expected = test_results.join("add_anotherfile_link_expected.json");
     let actual = test_results.join("add_anotherfile_link_actual.json");
     commands::execute(
         Commands::Get {
-            get_command: GetCommand::Config,
+            get_command: GetCommand::Config { origin: false },
         },
         read_global_config(&hermit_root),
         true,
-        false,
+        Verbosity::Normal,
         false,
         Some(actual.clone()),
     )
     .await
+    .into_result()
     .unwrap();
     assert_json_eq(&expected, &actual, temp_str);
 
@@ -223,14 +171,20 @@ async fn smoke_test() {
             tags: vec!["test1".to_string()],
             profile: None,
             extensive: false,
+            scan_home: false,
+            diff: false,
+            target_prefix: None,
+            types: vec![],
+            skip_types: vec![],
         },
         read_global_config(&hermit_root),
         true,
-        false,
+        Verbosity::Normal,
         false,
         Some(actual.clone()),
     )
     .await
+    .into_result()
     .unwrap();
     assert_json_eq(&expected, &actual, temp_str);
 
@@ -240,30 +194,33 @@ async fn smoke_test() {
             add_command: AddCommand::Profile {
                 name: "testProfile".to_string(),
                 tags: vec!["hello".parse().unwrap(), "test1".parse().unwrap()],
+                from_active: false,
             },
         },
         read_global_config(&hermit_root),
         true,
-        false,
+        Verbosity::Normal,
         false,
         None,
     )
     .await
+    .into_result()
     .unwrap();
     assert_file_exists(hermit_root.join("hermit.toml"));
     let expected = test_results.join("add_profile_test1_expected.json");
     let actual = test_results.join("add_profile_test1_actual.json");
     commands::execute(
         Commands::Get {
-            get_command: GetCommand::Config,
+            get_command: GetCommand::Config { origin: false },
         },
         read_global_config(&hermit_root),
         true,
-        false,
+        Verbosity::Normal,
         false,
         Some(actual.clone()),
     )
     .await
+    .into_result()
     .unwrap();
     assert_json_eq(&expected, &actual, temp_str);
 
@@ -275,11 +232,12 @@ async fn smoke_test() {
         },
         read_global_config(&hermit_root),
         true,
-        false,
+        Verbosity::Normal,
         false,
         None,
     )
     .await
+    .into_result()
     .unwrap();
 
     // Step 9: Get profiles
@@ -289,11 +247,12 @@ async fn smoke_test() {
         },
         read_global_config(&hermit_root),
         true,
-        false,
+        Verbosity::Normal,
         false,
         None,
     )
     .await
+    .into_result()
     .unwrap();
 
     // Step 10: Apply expecting failure
@@ -301,19 +260,19 @@ async fn smoke_test() {
     let actual = test_results.join("failed_apply_actual.json");
     commands::execute(
         Commands::Apply {
-            tags: vec![],
-            profile: Some("testProfile".to_string()),
-            fallback: None,
-            force: false,
-            parallel: false,
+            args: ApplyArgs {
+                profile: Some("testProfile".to_string()),
+                ..Default::default()
+            },
         },
         read_global_config(&hermit_root),
         true,
-        false,
+        Verbosity::Normal,
         false,
         Some(actual.clone()),
     )
     .await
+    .into_result()
     .unwrap();
     assert_json_eq(&expected, &actual, temp_str);
 
@@ -322,19 +281,21 @@ async fn smoke_test() {
     let actual = test_results.join("forced_apply_actual.json");
     commands::execute(
         Commands::Apply {
-            tags: vec![],
-            profile: Some("testProfile".to_string()),
-            fallback: None,
-            force: true,
-            parallel: true,
+            args: ApplyArgs {
+                profile: Some("testProfile".to_string()),
+                force: true,
+                parallel: true,
+                ..Default::default()
+            },
         },
         read_global_config(&hermit_root),
         true,
-        false,
+        Verbosity::Normal,
         false,
         Some(actual.clone()),
     )
     .await
+    .into_result()
     .unwrap();
     assert_json_eq(&expected, &actual, temp_str);
     assert_file_exists(temp_path.join("anotherfile.txt.bak"));
@@ -354,14 +315,20 @@ async fn smoke_test() {
             tags: vec![],
             profile: Some("testProfile".to_string()),
             extensive: false,
+            scan_home: false,
+            diff: false,
+            target_prefix: None,
+            types: vec![],
+            skip_types: vec![],
         },
         read_global_config(&hermit_root),
         true,
-        false,
+        Verbosity::Normal,
         false,
         Some(actual.clone()),
     )
     .await
+    .into_result()
     .unwrap();
     assert_json_eq(&expected, &actual, temp_str);
 
@@ -375,7 +342,10 @@ async fn smoke_test() {
     commands::execute(
         Commands::Add {
             add_command: AddCommand::Patch {
-                source: patch_toml.clone(),
+                source: Some(patch_toml.clone()),
+                snippet: None,
+                content: None,
+                from_diff: None,
                 config_dir: Some(PathBuf::from("cargo")),
                 patch_type: PatchType::JsonMerge,
                 target: Some(cargo_config.clone()),
@@ -385,25 +355,27 @@ async fn smoke_test() {
         },
         read_global_config(&hermit_root),
         true,
-        false,
+        Verbosity::Normal,
         false,
         None,
     )
     .await
+    .into_result()
     .unwrap();
     let expected = test_results.join("add_patch_expected.json");
     let actual = test_results.join("add_patch_actual.json");
     commands::execute(
         Commands::Get {
-            get_command: GetCommand::Config,
+            get_command: GetCommand::Config { origin: false },
         },
         read_global_config(&hermit_root),
         true,
-        false,
+        Verbosity::Normal,
         false,
         Some(actual.clone()),
     )
     .await
+    .into_result()
     .unwrap();
     assert_json_eq(&expected, &actual, temp_str);
     assert_file_exists(hermit_root.join("cargo/hermit.toml"));
@@ -414,22 +386,22 @@ async fn smoke_test() {
     let actual = test_results.join("applied_patch_actual.json");
     commands::execute(
         Commands::Apply {
-            tags: vec!["cargo".to_string()],
-            profile: None,
-            fallback: None,
-            force: false,
-            parallel: false,
+            args: ApplyArgs {
+                tags: vec!["cargo".to_string()],
+                ..Default::default()
+            },
         },
         read_global_config(&hermit_root),
         true,
-        false,
+        Verbosity::Normal,
         false,
         Some(actual.clone()),
     )
     .await
+    .into_result()
     .unwrap();
     assert_json_eq(&expected, &actual, temp_str);
-    let expected = test_results.join("patched_config_actual.toml");
+    let expected = test_results.join("patched_config.toml");
     let actual = cargo_config;
     let exp = fs::read_to_string(&expected).unwrap();
     let act = fs::read_to_string(&actual).unwrap();
@@ -446,14 +418,10 @@ async fn sequential_ordered() {
     ordered_test(false).await
 }
 async fn ordered_test(parallel: bool) {
-    let temp = TempDir::new().unwrap();
-    let temp_path = temp.path();
-    let temp_str = temp_path.to_str().unwrap();
-    let _env_lock = ENV_LOCK.lock().await;
-    unsafe {
-        std::env::set_var("HOME", temp_path);
-    }
-    let hermit_root = temp_path.join(".hermitgrab");
+    let home = TestHome::new().await;
+    let temp_path = home.path();
+    let temp_str = home.path_str();
+    let hermit_root = home.hermit_dir();
     let cargo_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
     let test_results = cargo_root.join("test_results");
     let config_under_test = cargo_root.join("tests/test_exec_order/hermit.toml");
@@ -464,20 +432,83 @@ async fn ordered_test(parallel: bool) {
     let actual = test_results.join("exec_ordered_actual.json");
     commands::execute(
         Commands::Apply {
-            tags: vec!["ordered".to_string()],
-            profile: None,
-            fallback: None,
-            force: false,
-            parallel,
+            args: ApplyArgs {
+                tags: vec!["ordered".to_string()],
+                parallel,
+                deterministic: true,
+                ..Default::default()
+            },
         },
         read_global_config(&hermit_root),
         true,
-        true,
+        Verbosity::Verbose,
         false,
         Some(actual.clone()),
     )
     .await
+    .into_result()
     .unwrap();
     assert_file_equals(temp_path.join("test_exec_order.log"), "0\n1\n2\n10\n");
     assert_json_eq(&expected, &actual, temp_str);
 }
+
+#[tokio::test]
+async fn sequential_ordered_max() {
+    let home = TestHome::new().await;
+    let temp_path = home.path();
+    let hermit_root = home.hermit_dir();
+    let cargo_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let config =
+        std::fs::read_to_string(cargo_root.join("tests/test_exec_order/hermit.toml")).unwrap();
+    std::fs::create_dir_all(&hermit_root).unwrap();
+    std::fs::write(
+        hermit_root.join("hermit.toml"),
+        format!("{config}\n[settings]\nstrict_mode = false\norder_mode = \"max\"\n"),
+    )
+    .unwrap();
+    let report = commands::execute(
+        Commands::Apply {
+            args: ApplyArgs {
+                tags: vec!["ordered".to_string()],
+                ..Default::default()
+            },
+        },
+        read_global_config(&hermit_root),
+        true,
+        Verbosity::Verbose,
+        false,
+        None,
+    )
+    .await;
+    report.into_result().unwrap();
+    // All items are raised to the order 10 of the config and run in declaration order
+    assert_file_equals(temp_path.join("test_exec_order.log"), "1\n10\n2\n0\n");
+}
+
+#[test]
+fn strict_config_exit_code() {
+    let temp = tempfile::TempDir::new().unwrap();
+    let hermit_dir = temp.path().join("hermit");
+    fs::create_dir_all(&hermit_dir).unwrap();
+    fs::write(hermit_dir.join("hermit.toml"), "[[link]]\nsource = ").unwrap();
+    let run = |strict: bool| {
+        let mut cmd = std::process::Command::new(env!("CARGO_BIN_EXE_hermitgrab"));
+        cmd.env("HOME", temp.path())
+            .env_remove("HERMIT_STRICT_CONFIG")
+            .arg("--hermit-dir")
+            .arg(&hermit_dir)
+            .arg("status");
+        if strict {
+            cmd.arg("--strict-config");
+        }
+        let output = cmd.output().unwrap();
+        (
+            output.status.code(),
+            String::from_utf8_lossy(&output.stderr).to_string(),
+        )
+    };
+    let (code, stderr) = run(true);
+    assert_eq!(code, Some(3), "{stderr}");
+    assert!(!stderr.contains("Stack backtrace"), "{stderr}");
+    assert_eq!(run(false).0, Some(3));
+}
@@ -1,50 +1,8 @@
-use std::fs;
-use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::path::PathBuf;
 
-use hermitgrab::commands::{self, Commands};
-use hermitgrab::config::{GlobalConfig, find_hermit_files};
-use tempfile::TempDir;
-use tokio::sync::Mutex;
-
-/// This lock will be used for preventing modification of the HOME variable
-/// when multiple tests are started simultaenously. This also means that
-/// only one test can run at a time. This is due to the usage of the $HOME
-/// variable in the ordered test.
-static ENV_LOCK: Mutex<()> = Mutex::const_new(());
-
-fn read_json<P: AsRef<Path>>(path: P, temp_dir: &str) -> serde_json::Value {
-    assert_file_exists(&path);
-    let data = fs::read_to_string(path).expect("Failed to read file");
-    let data = data
-        .replace(temp_dir, "TEMP_DIR")
-        .replace("/privateTEMP_DIR", "TEMP_DIR");
-    serde_json::from_str(&data).expect("Failed to parse JSON")
-}
-
-fn assert_json_eq(expected: &Path, actual: &Path, temp_dir: &str) {
-    let exp = read_json(expected, temp_dir);
-    let act = read_json(actual, temp_dir);
-    pretty_assertions::assert_eq!(
-        exp,
-        act,
-        "JSON files differ: {} != {}",
-        expected.display(),
-        actual.display()
-    );
-}
-
-fn assert_file_exists<P: AsRef<Path>>(path: P) {
-    assert!(
-        path.as_ref().exists(),
-        "File does not exist: {}",
-        path.as_ref().display()
-    );
-}
-
-fn read_global_config(hermit_root: &Path) -> Arc<GlobalConfig> {
-    GlobalConfig::from_paths(hermit_root, &find_hermit_files(hermit_root)).unwrap()
-}
+use hermitgrab::commands::{self, ApplyArgs, Commands};
+use hermitgrab::common_cli::Verbosity;
+use hermitgrab::testkit::{TestHome, assert_json_eq, read_global_config};
 
 #[tokio::test]
 async fn xdg_cache() {
@@ -101,12 +59,10 @@ async fn xdg_state_clean() {
 }
 
 async fn test_env_subst(env_name: &str, clean: bool) {
-    let temp = TempDir::new().unwrap();
-    let temp_path = temp.path();
-    let temp_str = temp_path.to_str().unwrap();
-    let _env_lock = ENV_LOCK.lock().await;
+    let home = TestHome::new().await;
+    let temp_path = home.path();
+    let temp_str = home.path_str();
     unsafe {
-        std::env::set_var("HOME", temp_path);
         if clean {
             std::env::remove_var("XDG_HOME");
             std::env::remove_var("XDG_CACHE_HOME");
@@ -144,19 +100,19 @@ async fn test_env_subst(env_name: &str, clean: bool) {
     let actual = test_results.join(format!("exec_env_{env_name}_{clean}_actual.json"));
     commands::execute(
         Commands::Apply {
-            tags: vec!["xdg_test".to_string()],
-            profile: None,
-            fallback: None,
-            force: false,
-            parallel: false,
+            args: ApplyArgs {
+                tags: vec!["xdg_test".to_string()],
+                ..Default::default()
+            },
         },
         read_global_config(&hermit_root),
         true,
-        true,
+        Verbosity::Verbose,
         false,
         Some(actual.clone()),
     )
     .await
+    .into_result()
     .unwrap();
     assert_json_eq(&expected, &actual, temp_str);
     unsafe { std::env::remove_var(env_name) };